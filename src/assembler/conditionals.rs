@@ -0,0 +1,240 @@
+use super::diagnostic::{Diagnostic, Span};
+use super::expression::{self, Constants, Scope};
+use super::tokenizer::{self, Token, TokenInfoType};
+
+struct Block {
+    // Whether the lines in the current branch are kept.
+    active: bool,
+    // Whether the lines around the block are kept.
+    outer: bool,
+    has_else: bool,
+    span: Span,
+}
+
+/*
+Removes the lines `.if`, `.ifdef` and `.ifndef` blocks leave out, before anything else reads the
+source, so they don't have to be valid:
+
+.ifdef DEBUG
+.equ LOG_LEVEL 3
+.else
+.equ LOG_LEVEL 0
+.endif
+
+.if LOG_LEVEL > 5
+.error "LOG_LEVEL only goes up to 5"
+.endif
+
+Lines are read in order, so a condition can only use constants defined above it or given with
+`-D`. This also goes for blocks inside a macro, which are decided where the macro is defined.
+ */
+pub fn filter(
+    tokens: &[TokenInfoType],
+    defines: &Constants,
+) -> (Vec<TokenInfoType>, Vec<Diagnostic>) {
+    let mut conditions = Conditions::new(defines);
+    let mut out = Vec::new();
+
+    for line in tokens.split_inclusive(|token| token.0 == Token::NewLine) {
+        if conditions.keep(line) {
+            out.extend_from_slice(line);
+        }
+    }
+
+    (out, conditions.finish())
+}
+
+// The blocks around the line being read, for reading the source a line at a time.
+pub struct Conditions {
+    constants: Constants,
+    blocks: Vec<Block>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Conditions {
+    pub fn new(defines: &Constants) -> Conditions {
+        Conditions {
+            constants: defines.clone(),
+            blocks: Vec::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    // Whether `line` stays in the source. The directives themselves never do.
+    pub fn keep(&mut self, line: &[TokenInfoType]) -> bool {
+        let active = self.blocks.last().is_none_or(|block| block.active);
+        let directive = &line[0];
+        let mut keep = false;
+
+        let res = match directive.0 {
+            Token::If | Token::IfDefined | Token::IfNotDefined => {
+                // Nothing inside a block that is left out is looked at, not even its conditions.
+                let taken = if active {
+                    condition(line, &self.constants)
+                } else {
+                    Ok(false)
+                };
+
+                self.blocks.push(Block {
+                    active: matches!(taken, Ok(true)),
+                    outer: active,
+                    has_else: false,
+                    span: directive.2.clone(),
+                });
+
+                taken.map(|_| ())
+            }
+            Token::Else => match self.blocks.last_mut() {
+                Some(block) if block.has_else => Err(Diagnostic::error(
+                    "this `.if` already has an `.else`",
+                    Some(directive.2.clone()),
+                )
+                .with_help(format!("the `.if` is on line {}", block.span.origin().line))),
+                Some(block) => {
+                    block.active = block.outer && !block.active;
+                    block.has_else = true;
+                    Ok(())
+                }
+                None => Err(Diagnostic::error(
+                    "`.else` without a matching `.if`",
+                    Some(directive.2.clone()),
+                )),
+            },
+            Token::EndIf => match self.blocks.pop() {
+                Some(_) => Ok(()),
+                None => Err(Diagnostic::error(
+                    "`.endif` without a matching `.if`",
+                    Some(directive.2.clone()),
+                )),
+            },
+            _ if !active => Ok(()),
+            Token::UserError | Token::UserWarning => message(line).map(|diagnostic| {
+                self.diagnostics.push(diagnostic);
+            }),
+            Token::Constant => {
+                // Kept for `collect_constants`, which reports any problem with the definition.
+                if let (Some((Token::Identifier, name, _)), Ok(value)) = (line.get(1), value(line))
+                {
+                    self.constants.entry(name.clone()).or_insert(value);
+                }
+
+                keep = true;
+                Ok(())
+            }
+            _ => {
+                keep = true;
+                Ok(())
+            }
+        };
+
+        if let Err(diagnostic) = res {
+            self.diagnostics.push(diagnostic);
+        }
+
+        keep
+    }
+
+    // Everything found along the way, and the blocks that were never closed.
+    pub fn finish(mut self) -> Vec<Diagnostic> {
+        for block in self.blocks {
+            self.diagnostics.push(
+                Diagnostic::error("`.if` is never closed", Some(block.span))
+                    .with_help("add `.endif` after the last line of the block"),
+            );
+        }
+
+        self.diagnostics
+    }
+}
+
+fn condition(line: &[TokenInfoType], constants: &Constants) -> Result<bool, Diagnostic> {
+    let directive = &line[0];
+
+    if directive.0 != Token::If {
+        let name = match line.get(1) {
+            Some(token) if token.0 == Token::Identifier => &token.1,
+            _ => {
+                return Err(Diagnostic::error(
+                    format!("`{}` expects a constant name", directive.1),
+                    Some(directive.2.clone()),
+                )
+                .with_help(format!("for example `{} DEBUG`", directive.1)))
+            }
+        };
+
+        end_of_line(line, 2)?;
+
+        return Ok(constants.contains_key(name) == (directive.0 == Token::IfDefined));
+    }
+
+    if matches!(line.get(1), None | Some((Token::NewLine, _, _))) {
+        return Err(
+            Diagnostic::error("`.if` expects a condition", Some(directive.2.clone()))
+                .with_help("for example `.if WIDTH > 40`"),
+        );
+    }
+
+    let mut pos = 1;
+    let (expr, span) = expression::parse(line, &mut pos)?;
+    end_of_line(line, pos)?;
+
+    let scope = Scope {
+        constants,
+        labels: None,
+        here: None,
+    };
+
+    match expr.evaluate(&scope, &span, &mut Vec::new())? {
+        Some(value) => Ok(value != 0),
+        None => Err(Diagnostic::error(
+            "the `.if` condition can't depend on a label or `$`",
+            Some(span),
+        )
+        .with_help("constants in a condition have to be defined above it, or with `-D`")),
+    }
+}
+
+// The value of a `.equ NAME value` line, which may have a comma after the name.
+fn value(line: &[TokenInfoType]) -> Result<(expression::Expr, Span), Diagnostic> {
+    let mut pos = match line.get(2) {
+        Some(token) if token.0 == Token::Comma => 3,
+        _ => 2,
+    };
+
+    expression::parse(line, &mut pos)
+}
+
+fn message(line: &[TokenInfoType]) -> Result<Diagnostic, Diagnostic> {
+    let directive = &line[0];
+
+    let text = match line.get(1) {
+        Some(token) if token.0 == Token::String => tokenizer::unescape(token)?,
+        _ => {
+            return Err(Diagnostic::error(
+                format!("`{}` expects a message in quotes", directive.1),
+                Some(directive.2.clone()),
+            )
+            .with_help(format!("for example `{} \"unsupported\"`", directive.1)))
+        }
+    };
+
+    end_of_line(line, 2)?;
+
+    let text = String::from_utf8_lossy(&text).into_owned();
+    let span = Some(directive.2.to(&line[1].2));
+
+    Ok(match directive.0 {
+        Token::UserError => Diagnostic::error(text, span),
+        _ => Diagnostic::warning(text, span),
+    })
+}
+
+fn end_of_line(line: &[TokenInfoType], pos: usize) -> Result<(), Diagnostic> {
+    match line.get(pos) {
+        Some(token) if token.0 != Token::NewLine => Err(Diagnostic::error(
+            format!("unexpected `{}`", token.1),
+            Some(token.2.clone()),
+        )),
+        _ => Ok(()),
+    }
+}
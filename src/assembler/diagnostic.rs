@@ -0,0 +1,227 @@
+use std::ops::Range;
+
+use colored::{ColoredString, Colorize};
+
+use super::source::SourceMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    // Index of the file in the `SourceMap`.
+    pub file: usize,
+    pub line: usize,
+    pub column: usize,
+    pub range: Range<usize>,
+    // For tokens that came out of a macro, the span of the macro call they were expanded from.
+    pub expansion: Option<Box<Span>>,
+}
+
+impl Span {
+    pub fn new(file: usize, line: usize, column: usize, range: Range<usize>) -> Span {
+        Span {
+            file,
+            line,
+            column,
+            range,
+            expansion: None,
+        }
+    }
+
+    // The span of the outermost macro call that produced this one, or the span itself.
+    pub fn origin(&self) -> &Span {
+        match &self.expansion {
+            Some(call) => call.origin(),
+            None => self,
+        }
+    }
+
+    // Creates a span covering both `self` and `other`. When they aren't next to each other in
+    // the source, such as a macro body token and an argument from the call, only `self` is kept.
+    pub fn to(&self, other: &Span) -> Span {
+        if self.file != other.file || self.line != other.line || self.expansion != other.expansion {
+            return self.clone();
+        }
+
+        Span {
+            file: self.file,
+            line: self.line,
+            column: self.column,
+            range: self.range.start..other.range.end,
+            expansion: self.expansion.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Option<Span>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.into(),
+            span,
+            help: None,
+        }
+    }
+
+    pub fn warning(message: impl Into<String>, span: Option<Span>) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Warning,
+            message: message.into(),
+            span,
+            help: None,
+        }
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Diagnostic {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    // Drops repeats of the same problem, such as a bad constant found again at each of its uses.
+    pub fn dedup(diagnostics: &mut Vec<Diagnostic>) {
+        let mut seen: Vec<Diagnostic> = Vec::new();
+
+        diagnostics.retain(|diagnostic| {
+            if seen.contains(diagnostic) {
+                return false;
+            }
+
+            seen.push(diagnostic.clone());
+            true
+        });
+    }
+
+    /*
+    error: unknown instruction `mvo`
+     --> examples/box.yuasm:9:5
+      |
+    9 |     mvo r6, 201
+      |     ^^^
+      |
+      = help: ...
+     */
+    pub fn render_title(&self) -> String {
+        let title = match self.severity {
+            Severity::Error => "error".red().bold(),
+            Severity::Warning => "warning".yellow().bold(),
+        };
+
+        format!("{}{} {}", title, ":".bold(), self.message.bold())
+    }
+
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let mut out = format!("{}\n", self.render_title());
+
+        let span = match &self.span {
+            Some(span) => span,
+            None => {
+                out += &format!(" {} {}\n", "-->".blue().bold(), sources.name(0));
+
+                if let Some(help) = &self.help {
+                    out += &format!("  {} {}: {}\n", "=".blue().bold(), "help".bold(), help);
+                }
+
+                return out;
+            }
+        };
+
+        let caret = |width: usize| {
+            let caret = "^".repeat(width);
+
+            match self.severity {
+                Severity::Error => caret.red().bold(),
+                Severity::Warning => caret.yellow().bold(),
+            }
+        };
+
+        out += &Self::render_snippet(sources, span, caret);
+
+        // Errors inside a macro also show every call that led to them, innermost first.
+        let mut expansion = &span.expansion;
+
+        while let Some(call) = expansion {
+            out += &format!("{}{} in this macro invocation\n", "note".bold(), ":".bold());
+            out += &Self::render_snippet(sources, call, |width| "-".repeat(width).blue().bold());
+
+            expansion = &call.expansion;
+        }
+
+        let gutter = " ".repeat(span.line.to_string().len());
+        let pipe = "|".blue().bold();
+
+        if let Some(help) = &self.help {
+            out += &format!("{} {}\n", gutter, pipe);
+            out += &format!(
+                "{} {} {}: {}\n",
+                gutter,
+                "=".blue().bold(),
+                "help".bold(),
+                help
+            );
+        }
+
+        out
+    }
+
+    fn render_snippet(
+        sources: &SourceMap,
+        span: &Span,
+        marker: impl Fn(usize) -> ColoredString,
+    ) -> String {
+        let file_name = sources.name(span.file);
+        let source = sources.content(span.file);
+        let line_text = source.lines().nth(span.line - 1).unwrap_or("");
+        let gutter = " ".repeat(span.line.to_string().len());
+        let pipe = "|".blue().bold();
+
+        // The caret covers the span, but never runs past the end of the line it starts on.
+        let width = source[span.range.clone()]
+            .lines()
+            .next()
+            .map(|text| text.chars().count())
+            .unwrap_or(0)
+            .max(1);
+
+        // Keep tabs in the padding so the caret lines up with the source line above it.
+        let padding: String = line_text
+            .chars()
+            .take(span.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        let mut out = format!(
+            "{}{} {}:{}:{}\n",
+            gutter,
+            "-->".blue().bold(),
+            file_name,
+            span.line,
+            span.column
+        );
+        out += &format!("{} {}\n", gutter, pipe);
+        out += &format!(
+            "{} {} {}\n",
+            span.line.to_string().blue().bold(),
+            pipe,
+            line_text
+        );
+        out += &format!("{} {} {}{}\n", gutter, pipe, padding, marker(width));
+
+        out
+    }
+}
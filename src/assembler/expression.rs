@@ -0,0 +1,501 @@
+use std::collections::HashMap;
+
+use super::diagnostic::{Diagnostic, Span};
+use super::tokenizer::{self, Token, TokenInfoType};
+use crate::common::object::RelocationTarget;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Negate,
+    Not,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Shl,
+    Shr,
+    And,
+    Or,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl BinaryOp {
+    fn from_token(token: Token) -> Option<BinaryOp> {
+        match token {
+            Token::Plus => Some(BinaryOp::Add),
+            Token::Minus => Some(BinaryOp::Sub),
+            Token::Star => Some(BinaryOp::Mul),
+            Token::Slash => Some(BinaryOp::Div),
+            Token::Percent => Some(BinaryOp::Mod),
+            Token::ShiftLeft => Some(BinaryOp::Shl),
+            Token::ShiftRight => Some(BinaryOp::Shr),
+            Token::Ampersand => Some(BinaryOp::And),
+            Token::Pipe => Some(BinaryOp::Or),
+            Token::Equal => Some(BinaryOp::Equal),
+            Token::NotEqual => Some(BinaryOp::NotEqual),
+            Token::Less => Some(BinaryOp::Less),
+            Token::LessEqual => Some(BinaryOp::LessEqual),
+            Token::Greater => Some(BinaryOp::Greater),
+            Token::GreaterEqual => Some(BinaryOp::GreaterEqual),
+            _ => None,
+        }
+    }
+
+    // Same order as C: `|` binds loosest, then `&`, `== !=`, `< <= > >=`, shifts, `+ -` and
+    // finally `* / %`.
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Equal | BinaryOp::NotEqual => 3,
+            BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual => 4,
+            BinaryOp::Shl | BinaryOp::Shr => 5,
+            BinaryOp::Add | BinaryOp::Sub => 6,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 7,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Number(u32),
+    Address(u32),
+    Here,
+    Symbol(String),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+// Whether an expression ends up as an immediate value or as a memory address. Labels, `$` and
+// `$addresses` are addresses, and the distance between two addresses is a plain number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    Number,
+    Address,
+}
+
+pub type Constants = HashMap<String, (Expr, Span)>;
+
+// A value of `offset` plus multiples of addresses that are only known once the program is
+// linked. A label at offset 4 in the text section is `4 + 1 * text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Linear {
+    pub offset: i64,
+    pub bases: Vec<(RelocationTarget, i64)>,
+}
+
+impl Linear {
+    pub fn number(value: i64) -> Linear {
+        Linear {
+            offset: value,
+            bases: Vec::new(),
+        }
+    }
+
+    pub fn at(target: RelocationTarget, offset: i64) -> Linear {
+        Linear {
+            offset,
+            bases: vec![(target, 1)],
+        }
+    }
+
+    pub fn is_number(&self) -> bool {
+        self.bases.is_empty()
+    }
+
+    fn scale(mut self, by: i64) -> Option<Linear> {
+        self.offset = self.offset.checked_mul(by)?;
+
+        for (_, factor) in &mut self.bases {
+            *factor = factor.checked_mul(by)?;
+        }
+
+        self.bases.retain(|(_, factor)| *factor != 0);
+
+        Some(self)
+    }
+
+    fn add(mut self, other: Linear) -> Option<Linear> {
+        self.offset = self.offset.checked_add(other.offset)?;
+
+        for (target, factor) in other.bases {
+            match self.bases.iter_mut().find(|(base, _)| *base == target) {
+                Some((_, existing)) => *existing = existing.checked_add(factor)?,
+                None => self.bases.push((target, factor)),
+            }
+        }
+
+        // `end - start` cancels out to a plain number.
+        self.bases.retain(|(_, factor)| *factor != 0);
+
+        Some(self)
+    }
+}
+
+pub struct Scope<'a> {
+    pub constants: &'a Constants,
+    // Where each label is. Only the assembler knows this, so the parser evaluates without them.
+    pub labels: Option<&'a HashMap<String, Linear>>,
+    pub here: Option<Linear>,
+}
+
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse::<u32>().ok(),
+    }
+}
+
+// A constant given before the source is read, like `-D NAME=VALUE` on the command line.
+pub fn defined(value: i64) -> (Expr, Span) {
+    let expr = match u32::try_from(value) {
+        Ok(value) => Expr::Number(value),
+        Err(_) => Expr::Unary(
+            UnaryOp::Negate,
+            Box::new(Expr::Number(value.unsigned_abs() as u32)),
+        ),
+    };
+
+    // Never shown, a number can't produce a diagnostic.
+    (expr, Span::new(0, 0, 0, 0..0))
+}
+
+pub fn not_relocatable(span: &Span) -> Diagnostic {
+    Diagnostic::error(
+        "this expression uses a label in a way the linker can't fill in",
+        Some(span.clone()),
+    )
+    .with_help("a label can only have numbers added to or subtracted from it")
+}
+
+fn unexpected(tokens: &[TokenInfoType], pos: usize) -> Diagnostic {
+    match tokens.get(pos) {
+        Some(token) if token.0 != Token::NewLine => Diagnostic::error(
+            format!("expected a value, found `{}`", token.1.escape_default()),
+            Some(token.2.clone()),
+        ),
+        _ => Diagnostic::error(
+            "expected a value, found end of line",
+            tokens
+                .get(pos.saturating_sub(1))
+                .map(|token| token.2.clone()),
+        ),
+    }
+}
+
+fn parse_primary(tokens: &[TokenInfoType], pos: &mut usize) -> Result<Expr, Diagnostic> {
+    let token = match tokens.get(*pos) {
+        Some(token) => token,
+        None => return Err(unexpected(tokens, *pos)),
+    };
+
+    let expr = match token.0 {
+        Token::Number => match parse_number(&token.1) {
+            Some(value) => Expr::Number(value),
+            None => {
+                return Err(Diagnostic::error(
+                    format!("number `{}` is too large", token.1),
+                    Some(token.2.clone()),
+                ))
+            }
+        },
+        Token::Address => match parse_number(&token.1[1..]) {
+            Some(value) => Expr::Address(value),
+            None => {
+                return Err(Diagnostic::error(
+                    format!("address `{}` is too large", token.1),
+                    Some(token.2.clone()),
+                ))
+            }
+        },
+        Token::Char => match tokenizer::unescape(token)?.as_slice() {
+            [byte] => Expr::Number(*byte as u32),
+            _ => {
+                return Err(Diagnostic::error(
+                    format!("`{}` must hold exactly one character", token.1),
+                    Some(token.2.clone()),
+                )
+                .with_help("use double quotes for strings"))
+            }
+        },
+        Token::Dollar => Expr::Here,
+        Token::Identifier => Expr::Symbol(token.1.clone()),
+        Token::Minus | Token::Tilde => {
+            let op = if token.0 == Token::Minus {
+                UnaryOp::Negate
+            } else {
+                UnaryOp::Not
+            };
+
+            *pos += 1;
+            return Ok(Expr::Unary(op, Box::new(parse_primary(tokens, pos)?)));
+        }
+        Token::LParen => {
+            *pos += 1;
+            let expr = parse_binary(tokens, pos, 0)?;
+
+            match tokens.get(*pos) {
+                Some(close) if close.0 == Token::RParen => (),
+                _ => {
+                    return Err(Diagnostic::error("unclosed `(`", Some(token.2.clone()))
+                        .with_help("add a `)` to the end of the expression"))
+                }
+            }
+
+            expr
+        }
+        _ => return Err(unexpected(tokens, *pos)),
+    };
+
+    *pos += 1;
+
+    Ok(expr)
+}
+
+fn parse_binary(
+    tokens: &[TokenInfoType],
+    pos: &mut usize,
+    min_precedence: u8,
+) -> Result<Expr, Diagnostic> {
+    let mut lhs = parse_primary(tokens, pos)?;
+
+    while let Some(op) = tokens
+        .get(*pos)
+        .and_then(|token| BinaryOp::from_token(token.0))
+    {
+        if op.precedence() <= min_precedence {
+            break;
+        }
+
+        *pos += 1;
+
+        let rhs = parse_binary(tokens, pos, op.precedence())?;
+        lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+    }
+
+    Ok(lhs)
+}
+
+// Parses an expression starting at `pos`, leaving `pos` on the first token after it. Returns the
+// expression with a span covering all of it.
+pub fn parse(tokens: &[TokenInfoType], pos: &mut usize) -> Result<(Expr, Span), Diagnostic> {
+    let start = *pos;
+    let expr = parse_binary(tokens, pos, 0)?;
+    let span = tokens[start].2.to(&tokens[*pos - 1].2);
+
+    Ok((expr, span))
+}
+
+impl Expr {
+    // Follows constants to find out what kind of value the expression produces. Anything that
+    // isn't a constant is assumed to be a label, which the assembler checks later.
+    pub fn kind(
+        &self,
+        scope: &Scope,
+        span: &Span,
+        visiting: &mut Vec<String>,
+    ) -> Result<ValueKind, Diagnostic> {
+        match self {
+            Expr::Number(_) => Ok(ValueKind::Number),
+            Expr::Address(_) | Expr::Here => Ok(ValueKind::Address),
+            Expr::Symbol(name) => match scope.constants.get(name) {
+                Some((expr, definition)) => {
+                    Self::enter(name, scope, visiting)?;
+                    let kind = expr.kind(scope, definition, visiting);
+                    visiting.pop();
+                    kind
+                }
+                None => Ok(ValueKind::Address),
+            },
+            Expr::Unary(_, _) => Ok(ValueKind::Number),
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = lhs.kind(scope, span, visiting)?;
+                let rhs = rhs.kind(scope, span, visiting)?;
+
+                match (op, lhs, rhs) {
+                    (BinaryOp::Add, ValueKind::Address, ValueKind::Address) => Err(
+                        Diagnostic::error("cannot add two addresses together", Some(span.clone())),
+                    ),
+                    (BinaryOp::Sub, ValueKind::Number, ValueKind::Address) => {
+                        Err(Diagnostic::error(
+                            "cannot subtract an address from a number",
+                            Some(span.clone()),
+                        ))
+                    }
+                    (BinaryOp::Add, ValueKind::Address, _)
+                    | (BinaryOp::Add, _, ValueKind::Address)
+                    | (BinaryOp::Sub, ValueKind::Address, ValueKind::Number) => {
+                        Ok(ValueKind::Address)
+                    }
+                    _ => Ok(ValueKind::Number),
+                }
+            }
+        }
+    }
+
+    // Returns `None` when the value depends on a label or `$`, which only the linker can place.
+    pub fn evaluate(
+        &self,
+        scope: &Scope,
+        span: &Span,
+        visiting: &mut Vec<String>,
+    ) -> Result<Option<i64>, Diagnostic> {
+        match self {
+            Expr::Number(value) | Expr::Address(value) => Ok(Some(*value as i64)),
+            Expr::Here => Ok(None),
+            Expr::Symbol(name) => match scope.constants.get(name) {
+                Some((expr, definition)) => {
+                    Self::enter(name, scope, visiting)?;
+                    let value = expr.evaluate(scope, definition, visiting);
+                    visiting.pop();
+                    value
+                }
+                None => Ok(None),
+            },
+            Expr::Unary(op, expr) => {
+                Ok(expr.evaluate(scope, span, visiting)?.map(|value| match op {
+                    UnaryOp::Negate => -value,
+                    UnaryOp::Not => !value,
+                }))
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = lhs.evaluate(scope, span, visiting)?;
+                let rhs = rhs.evaluate(scope, span, visiting)?;
+
+                match (lhs, rhs) {
+                    (Some(lhs), Some(rhs)) => Self::apply(*op, lhs, rhs, span).map(Some),
+                    _ => Ok(None),
+                }
+            }
+        }
+    }
+
+    fn apply(op: BinaryOp, lhs: i64, rhs: i64, span: &Span) -> Result<i64, Diagnostic> {
+        let value = match op {
+            BinaryOp::Add => lhs.checked_add(rhs),
+            BinaryOp::Sub => lhs.checked_sub(rhs),
+            BinaryOp::Mul => lhs.checked_mul(rhs),
+            BinaryOp::Div | BinaryOp::Mod if rhs == 0 => {
+                return Err(Diagnostic::error(
+                    "division by zero in expression",
+                    Some(span.clone()),
+                ))
+            }
+            BinaryOp::Div => lhs.checked_div(rhs),
+            BinaryOp::Mod => lhs.checked_rem(rhs),
+            BinaryOp::Shl => u32::try_from(rhs).ok().and_then(|rhs| lhs.checked_shl(rhs)),
+            BinaryOp::Shr => u32::try_from(rhs).ok().and_then(|rhs| lhs.checked_shr(rhs)),
+            BinaryOp::And => Some(lhs & rhs),
+            BinaryOp::Or => Some(lhs | rhs),
+            // Comparisons are 1 when they hold and 0 when they don't.
+            BinaryOp::Equal => Some((lhs == rhs) as i64),
+            BinaryOp::NotEqual => Some((lhs != rhs) as i64),
+            BinaryOp::Less => Some((lhs < rhs) as i64),
+            BinaryOp::LessEqual => Some((lhs <= rhs) as i64),
+            BinaryOp::Greater => Some((lhs > rhs) as i64),
+            BinaryOp::GreaterEqual => Some((lhs >= rhs) as i64),
+        };
+
+        value.ok_or_else(|| Self::overflow(span))
+    }
+
+    fn overflow(span: &Span) -> Diagnostic {
+        Diagnostic::error("expression overflows", Some(span.clone()))
+    }
+
+    // Like `evaluate`, but labels and `$` are kept as the addresses the linker fills in.
+    pub fn relocate(
+        &self,
+        scope: &Scope,
+        span: &Span,
+        visiting: &mut Vec<String>,
+    ) -> Result<Linear, Diagnostic> {
+        match self {
+            Expr::Number(value) | Expr::Address(value) => Ok(Linear::number(*value as i64)),
+            Expr::Here => scope
+                .here
+                .clone()
+                .ok_or_else(|| Diagnostic::error("`$` can't be used here", Some(span.clone()))),
+            Expr::Symbol(name) => {
+                if let Some((expr, definition)) = scope.constants.get(name) {
+                    Self::enter(name, scope, visiting)?;
+                    let value = expr.relocate(scope, definition, visiting);
+                    visiting.pop();
+                    return value;
+                }
+
+                match scope.labels.and_then(|labels| labels.get(name)) {
+                    Some(label) => Ok(label.clone()),
+                    None => Err(Diagnostic::error(
+                        format!("no label or constant named `{}`", name),
+                        Some(span.clone()),
+                    )),
+                }
+            }
+            Expr::Unary(op, expr) => {
+                let value = expr.relocate(scope, span, visiting)?;
+
+                match op {
+                    UnaryOp::Negate => value.scale(-1).ok_or_else(|| Self::overflow(span)),
+                    UnaryOp::Not if value.is_number() => Ok(Linear::number(!value.offset)),
+                    UnaryOp::Not => Err(not_relocatable(span)),
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = lhs.relocate(scope, span, visiting)?;
+                let rhs = rhs.relocate(scope, span, visiting)?;
+
+                let value = match op {
+                    BinaryOp::Add => lhs.add(rhs),
+                    BinaryOp::Sub => rhs.scale(-1).and_then(|rhs| lhs.add(rhs)),
+                    BinaryOp::Mul if lhs.is_number() => rhs.scale(lhs.offset),
+                    BinaryOp::Mul if rhs.is_number() => lhs.scale(rhs.offset),
+                    _ if lhs.is_number() && rhs.is_number() => {
+                        return Self::apply(*op, lhs.offset, rhs.offset, span).map(Linear::number)
+                    }
+                    _ => return Err(not_relocatable(span)),
+                };
+
+                value.ok_or_else(|| Self::overflow(span))
+            }
+        }
+    }
+
+    /*
+    Errors in a constant's definition are reported at the definition, however the constant was
+    reached, so each one comes out the same every time and is only shown once. A cycle is named
+    from its first constant alphabetically, wherever it was entered.
+     */
+    fn enter(name: &str, scope: &Scope, visiting: &mut Vec<String>) -> Result<(), Diagnostic> {
+        if let Some(start) = visiting.iter().position(|visited| visited == name) {
+            let mut cycle = visiting[start..].to_vec();
+            let first = (0..cycle.len()).min_by_key(|&i| &cycle[i]).unwrap();
+
+            cycle.rotate_left(first);
+            cycle.push(cycle[0].clone());
+
+            return Err(Diagnostic::error(
+                format!("constant `{}` is defined in terms of itself", cycle[0]),
+                scope.constants.get(&cycle[0]).map(|(_, span)| span.clone()),
+            )
+            .with_help(format!(
+                "the definitions form a cycle: {}",
+                cycle.join(" -> ")
+            )));
+        }
+
+        visiting.push(name.to_string());
+
+        Ok(())
+    }
+}
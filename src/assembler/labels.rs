@@ -0,0 +1,126 @@
+use super::diagnostic::Diagnostic;
+use super::tokenizer::{Token, TokenInfoType};
+
+/*
+Gives local and anonymous labels the names the parser and the linker see:
+
+print:              print:
+    ...                 ...
+.loop:              print.loop:
+    jnz .loop           jnz print.loop
+1:                  1@0:
+    jmp 1b              jmp 1@0
+
+A local label belongs to the last global label before it, and can be referenced from anywhere
+else as `print.loop`. Anonymous labels can only be referenced with `1f` (the next `1:`) or `1b`
+(the previous one), so their names contain a character no label can be written with, and they are
+left out of the symbol table.
+
+This runs after macros are expanded, so every call to a macro gets its own anonymous labels.
+ */
+pub fn resolve(tokens: &[TokenInfoType]) -> (Vec<TokenInfoType>, Vec<Diagnostic>) {
+    let mut diagnostics = Vec::new();
+
+    let numbered: Vec<(usize, &str)> = tokens
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| token.0 == Token::NumericLabel)
+        .map(|(i, token)| (i, token.1.trim_end_matches(':')))
+        .collect();
+
+    let anonymous = |n: usize| format!("{}@{}", numbered[n].1, n);
+
+    let mut out = Vec::with_capacity(tokens.len());
+    let mut scope: Option<&str> = None;
+
+    for (i, token) in tokens.iter().enumerate() {
+        let at_line_start = i == 0 || tokens[i - 1].0 == Token::NewLine;
+
+        let resolved = match token.0 {
+            Token::Label => {
+                let name = token.1.trim_end_matches(':');
+
+                if !name.contains('.') {
+                    scope = Some(name);
+                }
+
+                None
+            }
+            Token::LocalLabel => match scope {
+                Some(scope) => Some((Token::Label, format!("{}{}", scope, token.1))),
+                None => {
+                    diagnostics.push(
+                        Diagnostic::error(
+                            format!(
+                                "local label `{}` does not follow a global label",
+                                token.1.trim_end_matches(':')
+                            ),
+                            Some(token.2.clone()),
+                        )
+                        .with_help("add a label such as `start:` before it"),
+                    );
+
+                    Some((Token::Label, token.1.clone()))
+                }
+            },
+            // Directives such as `.main` start a line, local labels are only referenced after an
+            // instruction or directive.
+            Token::Metadata if !at_line_start => match scope {
+                Some(scope) => Some((Token::Identifier, format!("{}{}", scope, token.1))),
+                None => Some((Token::Identifier, token.1.clone())),
+            },
+            Token::NumericLabel => {
+                let n = numbered.iter().position(|(j, _)| *j == i).unwrap();
+                Some((Token::Label, format!("{}:", anonymous(n))))
+            }
+            Token::NumericReference => {
+                let (number, direction) = token.1.split_at(token.1.len() - 1);
+
+                let found = if direction == "f" {
+                    numbered
+                        .iter()
+                        .position(|(j, name)| *j > i && *name == number)
+                } else {
+                    numbered
+                        .iter()
+                        .rposition(|(j, name)| *j < i && *name == number)
+                };
+
+                match found {
+                    Some(n) => Some((Token::Identifier, anonymous(n))),
+                    None => {
+                        let place = if direction == "f" { "after" } else { "before" };
+                        let mut diagnostic = Diagnostic::error(
+                            format!("there is no `{}:` label {} `{}`", number, place, token.1),
+                            Some(token.2.clone()),
+                        );
+
+                        if numbered.iter().any(|(_, name)| *name == number) {
+                            let other = if direction == "f" { "b" } else { "f" };
+                            diagnostic = diagnostic
+                                .with_help(format!("did you mean `{}{}`?", number, other));
+                        }
+
+                        diagnostics.push(diagnostic);
+
+                        // Parsed as a label name so it's only reported once.
+                        Some((Token::Identifier, token.1.clone()))
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        match resolved {
+            Some((kind, text)) => out.push((kind, text, token.2.clone())),
+            None => out.push(token.clone()),
+        }
+    }
+
+    (out, diagnostics)
+}
+
+// Anonymous labels have no name that can be referenced from another object.
+pub fn is_anonymous(name: &str) -> bool {
+    name.contains('@')
+}
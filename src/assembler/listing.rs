@@ -0,0 +1,164 @@
+use std::collections::BTreeMap;
+
+use super::diagnostic::Span;
+use super::source::SourceMap;
+use crate::common::{
+    executable::{Executable, SectionKind},
+    symbols::SymbolTable,
+};
+
+// The longest instruction is 5 bytes, so every instruction fits on a single row.
+const BYTES_PER_ROW: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListingEntry {
+    pub section: SectionKind,
+    // An offset into the section until the listing is placed.
+    pub addr: u32,
+    pub bytes: Vec<u8>,
+    // What a pseudo-instruction expanded to, see `ParserInstruction::expansion`.
+    pub expansion: Option<String>,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Listing {
+    // Keyed by file and line.
+    pub lines: BTreeMap<(usize, usize), Vec<ListingEntry>>,
+    pub symbols: SymbolTable,
+    pub interrupts: Vec<(u8, u32, String)>,
+}
+
+impl Listing {
+    pub fn new() -> Listing {
+        Listing::default()
+    }
+
+    pub fn add(
+        &mut self,
+        span: &Span,
+        section: SectionKind,
+        offset: u32,
+        bytes: &[u8],
+        expansion: Option<String>,
+    ) {
+        self.lines
+            .entry((span.file, span.line))
+            .or_default()
+            .push(ListingEntry {
+                section,
+                addr: offset,
+                bytes: bytes.to_vec(),
+                expansion,
+            });
+    }
+
+    // Moves every entry to its address in `executable`, and takes its bytes from there so
+    // operands the linker filled in show their final value.
+    pub fn place(&mut self, executable: &Executable) {
+        for entry in self.lines.values_mut().flatten() {
+            let section = match executable.section(entry.section) {
+                Some(section) => section,
+                None => continue,
+            };
+
+            let start = entry.addr as usize;
+
+            if let Some(bytes) = section.bytes.get(start..start + entry.bytes.len()) {
+                entry.bytes = bytes.to_vec();
+            }
+
+            entry.addr += section.load;
+        }
+    }
+
+    /*
+    Renders every line of `source` next to the address and bytes it assembled to:
+
+     line  addr   bytes
+        9  04402  00 50 C9         mov r6, 0xC9
+
+    Data that doesn't fit on one row carries on below without the source text. A pseudo-instruction
+    gets a line of its own, with the instructions it expanded to below it:
+
+       12                         push r1, r2
+           04405  03 10               psh r1
+           04407  03 20               psh r2
+
+    Included files are
+    listed after the main file, each under its name. The symbol table and the interrupt table
+    follow the source.
+     */
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let mut out = String::from(" line  addr   bytes\n");
+
+        for (file, source) in sources.files.iter().enumerate() {
+            if file > 0 {
+                out += &format!("\n{}\n", source.name);
+            }
+
+            self.render_file(file, &source.content, &mut out);
+        }
+
+        out += "\nSymbols\n";
+
+        let mut symbols: Vec<(&String, &u32)> = self.symbols.symbols.iter().collect();
+        symbols.sort_by_key(|(name, addr)| (**addr, *name));
+
+        for (name, addr) in symbols {
+            out += &format!("  {:05X}  {}\n", addr, name);
+        }
+
+        out += "\nInterrupts\n";
+
+        for (irq, addr, name) in &self.interrupts {
+            out += &format!("  0x{:02X}  {:05X}  {}\n", irq, addr, name);
+        }
+
+        out
+    }
+
+    fn render_file(&self, file: usize, source: &str, out: &mut String) {
+        for (i, text) in source.lines().enumerate() {
+            let line = i + 1;
+            let mut rows: Vec<(u32, &[u8], Option<&String>)> = Vec::new();
+
+            for entry in self.lines.get(&(file, line)).into_iter().flatten() {
+                for (j, chunk) in entry.bytes.chunks(BYTES_PER_ROW).enumerate() {
+                    let expansion = entry.expansion.as_ref().filter(|_| j == 0);
+                    rows.push((entry.addr + (j * BYTES_PER_ROW) as u32, chunk, expansion));
+                }
+            }
+
+            let expanded = rows.iter().any(|(_, _, expansion)| expansion.is_some());
+
+            if rows.is_empty() || expanded {
+                *out += format!("{:>5}{:<25}{}", line, "", text).trim_end();
+                *out += "\n";
+            }
+
+            let indent = &text[..text.len() - text.trim_start().len()];
+
+            for (j, (addr, bytes, expansion)) in rows.iter().enumerate() {
+                let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+
+                let row = match expansion {
+                    Some(expansion) => format!(
+                        "{:>5}  {:05X}  {:<16}{}    {}",
+                        "",
+                        addr,
+                        hex.join(" "),
+                        indent,
+                        expansion
+                    ),
+                    None if j == 0 && !expanded => {
+                        format!("{:>5}  {:05X}  {:<16}{}", line, addr, hex.join(" "), text)
+                    }
+                    None => format!("{:>5}  {:05X}  {}", "", addr, hex.join(" ")),
+                };
+
+                *out += row.trim_end();
+                *out += "\n";
+            }
+        }
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::{HashMap, HashSet};
+
+use super::diagnostic::{Diagnostic, Span};
+use super::expression::{self, Constants, Scope};
+use super::tokenizer::{Token, TokenInfoType};
+
+const MAX_REPEAT: i64 = 0xFFFF;
+
+#[derive(Debug, Clone)]
+pub struct Macro {
+    pub params: Vec<String>,
+    pub body: Vec<TokenInfoType>,
+    pub span: Span,
+}
+
+struct Expander<'a> {
+    macros: HashMap<String, Macro>,
+    diagnostics: Vec<Diagnostic>,
+    constants: &'a Constants,
+    // Counts every macro call so labels inside each expansion get a name of their own.
+    expansions: usize,
+}
+
+/*
+Replaces `.macro` definitions, macro calls and `.rept` blocks with the tokens they stand for, so
+the parser only ever sees plain instructions:
+
+.macro putc char, color
+    mov r1, char
+    stl r1, r3
+.endm
+
+A label defined inside a macro is local to each call. `loop:` becomes `loop__1:` in the first
+expansion, `loop__2:` in the second, and so on, and `.loop:` becomes `.loop__1:`.
+ */
+pub fn expand(
+    tokens: &[TokenInfoType],
+    constants: &Constants,
+) -> (Vec<TokenInfoType>, Vec<Diagnostic>) {
+    let mut expander = Expander {
+        macros: HashMap::new(),
+        diagnostics: Vec::new(),
+        constants,
+        expansions: 0,
+    };
+
+    let tokens = expander.expand(tokens, &mut Vec::new());
+
+    (tokens, expander.diagnostics)
+}
+
+impl Expander<'_> {
+    fn expand(&mut self, tokens: &[TokenInfoType], stack: &mut Vec<String>) -> Vec<TokenInfoType> {
+        let lines: Vec<&[TokenInfoType]> = tokens
+            .split_inclusive(|token| token.0 == Token::NewLine)
+            .collect();
+
+        let mut out = Vec::new();
+        let mut i = 0;
+
+        while i < lines.len() {
+            let line = lines[i];
+            i += 1;
+
+            match line[0].0 {
+                Token::MacroStart | Token::Repeat => {
+                    let (close, name) = match line[0].0 {
+                        Token::MacroStart => (Token::MacroEnd, ".endm"),
+                        _ => (Token::RepeatEnd, ".endr"),
+                    };
+
+                    let body = match Self::block(&lines, &mut i, line[0].0, close) {
+                        Some(body) => body,
+                        None => {
+                            self.diagnostics.push(
+                                Diagnostic::error(
+                                    format!("`{}` is never closed", line[0].1),
+                                    Some(line[0].2.clone()),
+                                )
+                                .with_help(format!(
+                                    "add `{}` after the last line of the block",
+                                    name
+                                )),
+                            );
+                            continue;
+                        }
+                    };
+
+                    if line[0].0 == Token::MacroStart {
+                        if let Err(diagnostic) = self.define(line, body) {
+                            self.diagnostics.push(diagnostic);
+                        }
+                    } else {
+                        match self.repeat_count(line) {
+                            Ok(count) => {
+                                for _ in 0..count {
+                                    out.extend(self.expand(&body, stack));
+                                }
+                            }
+                            Err(diagnostic) => self.diagnostics.push(diagnostic),
+                        }
+                    }
+                }
+                Token::MacroEnd | Token::RepeatEnd => {
+                    let open = match line[0].0 {
+                        Token::MacroEnd => ".macro",
+                        _ => ".rept",
+                    };
+
+                    self.diagnostics.push(Diagnostic::error(
+                        format!("`{}` without a matching `{}`", line[0].1, open),
+                        Some(line[0].2.clone()),
+                    ));
+                }
+                _ => {
+                    // A call can follow a label on the same line.
+                    let pos = match line[0].0 {
+                        Token::Label if line.len() > 1 => 1,
+                        _ => 0,
+                    };
+
+                    if line[pos].0 == Token::Identifier && self.macros.contains_key(&line[pos].1) {
+                        out.extend_from_slice(&line[..pos]);
+                        out.extend(self.call(line, pos, stack));
+
+                        if let Some(newline) = line.last().filter(|t| t.0 == Token::NewLine) {
+                            out.push(newline.clone());
+                        }
+                    } else {
+                        out.extend_from_slice(line);
+                    }
+                }
+            }
+        }
+
+        out
+    }
+
+    // Collects the lines up to the `close` that matches the block opened on the line before `i`,
+    // leaving `i` on the line after it. Blocks of the same kind can be nested.
+    fn block(
+        lines: &[&[TokenInfoType]],
+        i: &mut usize,
+        open: Token,
+        close: Token,
+    ) -> Option<Vec<TokenInfoType>> {
+        let mut depth = 0;
+        let mut body = Vec::new();
+
+        for (j, line) in lines.iter().enumerate().skip(*i) {
+            if line[0].0 == open {
+                depth += 1;
+            } else if line[0].0 == close {
+                if depth == 0 {
+                    *i = j + 1;
+                    return Some(body);
+                }
+
+                depth -= 1;
+            }
+
+            body.extend_from_slice(line);
+        }
+
+        *i = lines.len();
+
+        None
+    }
+
+    fn define(
+        &mut self,
+        line: &[TokenInfoType],
+        body: Vec<TokenInfoType>,
+    ) -> Result<(), Diagnostic> {
+        let name = match line.get(1) {
+            Some(token) if token.0 == Token::Identifier => token,
+            Some(token) if token.0 != Token::NewLine => {
+                return Err(Diagnostic::error(
+                    format!("expected macro name, found `{}`", token.1),
+                    Some(token.2.clone()),
+                ))
+            }
+            _ => {
+                return Err(
+                    Diagnostic::error("`.macro` expects a name", Some(line[0].2.clone()))
+                        .with_help("for example `.macro putc char, color`"),
+                )
+            }
+        };
+
+        let mut params: Vec<String> = Vec::new();
+
+        for token in &line[2..] {
+            match token.0 {
+                Token::Comma | Token::NewLine => (),
+                Token::Identifier if params.contains(&token.1) => {
+                    return Err(Diagnostic::error(
+                        format!("parameter `{}` is already defined", token.1),
+                        Some(token.2.clone()),
+                    ))
+                }
+                Token::Identifier => params.push(token.1.clone()),
+                _ => {
+                    return Err(Diagnostic::error(
+                        format!("expected parameter name, found `{}`", token.1),
+                        Some(token.2.clone()),
+                    ))
+                }
+            }
+        }
+
+        // A macro defined inside another one is defined again by every call to the outer macro.
+        if let Some(previous) = self.macros.get(&name.1) {
+            if previous.span.file != name.2.file || previous.span.range != name.2.range {
+                return Err(Diagnostic::error(
+                    format!(
+                        "macro `{}` is already defined on line {}",
+                        name.1, previous.span.line
+                    ),
+                    Some(name.2.clone()),
+                ));
+            }
+        }
+
+        self.macros.insert(
+            name.1.clone(),
+            Macro {
+                params,
+                body,
+                span: name.2.clone(),
+            },
+        );
+
+        Ok(())
+    }
+
+    fn repeat_count(&self, line: &[TokenInfoType]) -> Result<i64, Diagnostic> {
+        if line.len() < 2 || line[1].0 == Token::NewLine {
+            return Err(
+                Diagnostic::error("`.rept` expects a count", Some(line[0].2.clone()))
+                    .with_help("for example `.rept 4`"),
+            );
+        }
+
+        let mut pos = 1;
+        let (expr, span) = expression::parse(line, &mut pos)?;
+
+        if let Some(token) = line.get(pos).filter(|token| token.0 != Token::NewLine) {
+            return Err(Diagnostic::error(
+                format!("unexpected `{}` after the count", token.1),
+                Some(token.2.clone()),
+            ));
+        }
+
+        let scope = Scope {
+            constants: self.constants,
+            labels: None,
+            here: None,
+        };
+
+        match expr.evaluate(&scope, &span, &mut Vec::new())? {
+            Some(count) if (0..=MAX_REPEAT).contains(&count) => Ok(count),
+            Some(count) => Err(Diagnostic::error(
+                format!("`.rept` count {} is out of range", count),
+                Some(span),
+            )
+            .with_help(format!("the count must be between 0 and {}", MAX_REPEAT))),
+            None => Err(Diagnostic::error(
+                "the `.rept` count can't depend on a label or `$`",
+                Some(span),
+            )),
+        }
+    }
+
+    // Splits the arguments of a call on commas outside of parentheses.
+    fn arguments(tokens: &[TokenInfoType]) -> Vec<Vec<TokenInfoType>> {
+        let mut args: Vec<Vec<TokenInfoType>> = Vec::new();
+        let mut current = Vec::new();
+        let mut depth = 0;
+
+        for token in tokens.iter().take_while(|token| token.0 != Token::NewLine) {
+            match token.0 {
+                Token::Comma if depth == 0 => {
+                    args.push(current);
+                    current = Vec::new();
+                    continue;
+                }
+                Token::LParen => depth += 1,
+                Token::RParen => depth -= 1,
+                _ => (),
+            }
+
+            current.push(token.clone());
+        }
+
+        if !current.is_empty() || !args.is_empty() {
+            args.push(current);
+        }
+
+        args
+    }
+
+    fn call(
+        &mut self,
+        line: &[TokenInfoType],
+        pos: usize,
+        stack: &mut Vec<String>,
+    ) -> Vec<TokenInfoType> {
+        let name = &line[pos];
+        let definition = self.macros[&name.1].clone();
+
+        if stack.contains(&name.1) {
+            let mut cycle = stack.clone();
+            cycle.push(name.1.clone());
+
+            self.diagnostics.push(
+                Diagnostic::error(
+                    format!("macro `{}` expands into itself", name.1),
+                    Some(name.2.clone()),
+                )
+                .with_help(format!("the calls form a cycle: {}", cycle.join(" -> "))),
+            );
+
+            return Vec::new();
+        }
+
+        let args = Self::arguments(&line[pos + 1..]);
+
+        if let Some(empty) = args.iter().position(|arg| arg.is_empty()) {
+            self.diagnostics.push(Diagnostic::error(
+                format!("argument {} of `{}` is empty", empty + 1, name.1),
+                Some(name.2.clone()),
+            ));
+
+            return Vec::new();
+        }
+
+        if args.len() != definition.params.len() {
+            self.diagnostics.push(
+                Diagnostic::error(
+                    format!(
+                        "macro `{}` takes {} argument(s), but {} were given",
+                        name.1,
+                        definition.params.len(),
+                        args.len()
+                    ),
+                    Some(name.2.clone()),
+                )
+                .with_help(format!(
+                    "`{}` is defined on line {}",
+                    name.1, definition.span.line
+                )),
+            );
+
+            return Vec::new();
+        }
+
+        self.expansions += 1;
+
+        let locals: HashSet<String> = definition
+            .body
+            .iter()
+            .filter(|token| matches!(token.0, Token::Label | Token::LocalLabel))
+            .map(|token| token.1.replace(':', ""))
+            .collect();
+
+        let mut tokens = Vec::new();
+
+        for token in &definition.body {
+            // Arguments keep the spans from the call, everything else points into the body.
+            if token.0 == Token::Identifier {
+                if let Some(i) = definition.params.iter().position(|p| *p == token.1) {
+                    tokens.extend_from_slice(&args[i]);
+                    continue;
+                }
+            }
+
+            let mut span = token.2.clone();
+            span.expansion = Some(Box::new(name.2.clone()));
+
+            let text = match token.0 {
+                Token::Identifier | Token::Metadata if locals.contains(&token.1) => {
+                    format!("{}__{}", token.1, self.expansions)
+                }
+                Token::Label | Token::LocalLabel if locals.contains(&token.1.replace(':', "")) => {
+                    format!("{}__{}:", token.1.replace(':', ""), self.expansions)
+                }
+                _ => token.1.clone(),
+            };
+
+            tokens.push((token.0, text, span));
+        }
+
+        stack.push(name.1.clone());
+        let tokens = self.expand(&tokens, stack);
+        stack.pop();
+
+        tokens
+    }
+}
@@ -1,20 +1,54 @@
+#[cfg(test)]
+mod tests;
+
+pub mod conditionals;
+pub mod diagnostic;
+pub mod expression;
+pub mod labels;
+pub mod listing;
+pub mod macros;
 pub mod parser;
+pub mod pseudo;
+pub mod source;
 pub mod tokenizer;
 
 use std::collections::HashMap;
 
-use crate::common::instruction::opcode::Instruction;
-
-use self::parser::{DefineByteData, InstructionArg, InstructionType, Label, ParserResult};
+use crate::common::{
+    executable::{Executable, ExecutableError, SectionKind},
+    instruction::opcode::{Instruction, Opcode},
+    object::{ObjectFile, Relocation, RelocationSize, RelocationTarget, Symbol},
+    symbols::{SourceLine, SymbolTable},
+};
+use crate::linker::{LinkError, Linker};
+
+use self::diagnostic::{Diagnostic, Span};
+use self::expression::{Linear, Scope, ValueKind};
+use self::listing::Listing;
+use self::parser::{
+    DataValue, InstructionArg, InstructionType, Label, ParserInstruction, ParserResult,
+};
+use self::source::SourceMap;
 
 pub struct Assembler {
     parser_res: ParserResult,
+    file_names: Vec<String>,
+}
+
+// An object along with the span of the operand behind each of its relocations.
+struct Encoded {
+    object: ObjectFile,
+    listing: Listing,
+    relocation_spans: Vec<Span>,
 }
 
 impl Assembler {
-    pub fn new(parser_res: ParserResult) -> Assembler {
+    pub fn new(parser_res: ParserResult, sources: &SourceMap) -> Assembler {
         // println!("Parser result: {:?}", parser_res);
-        Assembler { parser_res }
+        Assembler {
+            parser_res,
+            file_names: sources.files.iter().map(|file| file.name.clone()).collect(),
+        }
     }
 
     fn find_label(name: &String, labels: &Vec<Label>) -> Option<Label> {
@@ -27,305 +61,770 @@ impl Assembler {
         None
     }
 
-    fn find_data_label(
-        name: &String,
-        labels: &HashMap<String, Vec<DefineByteData>>,
-    ) -> Option<(String, Vec<DefineByteData>)> {
-        for (label, values) in labels {
-            if label == name {
-                return Some((label.clone(), values.clone()));
+    fn text_section(label: &Label) -> SectionKind {
+        if label.far {
+            SectionKind::FarText
+        } else {
+            SectionKind::Text
+        }
+    }
+
+    // Where every label and `.extern` symbol is, relative to the start of its section.
+    // `text_offsets` has an entry for each text label.
+    fn locations(
+        &self,
+        data_offsets: &HashMap<String, u32>,
+        text_offsets: &[u32],
+    ) -> HashMap<String, Linear> {
+        let mut labels = HashMap::new();
+
+        for name in self.parser_res.externs.keys() {
+            labels.insert(
+                name.clone(),
+                Linear::at(RelocationTarget::Symbol(name.clone()), 0),
+            );
+        }
+
+        for (label, offset) in self.parser_res.text_labels.iter().zip(text_offsets) {
+            labels.insert(
+                label.name.clone(),
+                Linear::at(
+                    RelocationTarget::Section(Self::text_section(label)),
+                    *offset as i64,
+                ),
+            );
+        }
+
+        for (name, offset) in data_offsets {
+            labels.insert(
+                name.clone(),
+                Linear::at(RelocationTarget::Section(SectionKind::Data), *offset as i64),
+            );
+        }
+
+        labels
+    }
+
+    // The operand that decides an instruction's addressing mode, unless it is a register.
+    fn operand(instruction: &ParserInstruction) -> Option<&InstructionArg> {
+        let arg = match instruction.instruction_type {
+            InstructionType::Zero => return None,
+            InstructionType::One => &instruction.args[0],
+            InstructionType::Two => &instruction.args[1],
+        };
+
+        match arg {
+            InstructionArg::Register(_) => None,
+            arg => Some(arg),
+        }
+    }
+
+    // The value of an operand that refers to labels, as far as it's known before linking.
+    fn relocated(
+        arg: &InstructionArg,
+        section: SectionKind,
+        span: &Span,
+        labels: &HashMap<String, Linear>,
+        scope: &Scope,
+    ) -> Option<Linear> {
+        match arg {
+            InstructionArg::Identifier(ident) => labels.get(ident).cloned(),
+            InstructionArg::Expression(expr, _) => {
+                let scope = Scope {
+                    here: Some(Linear::at(RelocationTarget::Section(section), 0)),
+                    ..*scope
+                };
+
+                expr.relocate(&scope, span, &mut Vec::new()).ok()
             }
+            _ => None,
         }
+    }
 
-        None
+    // Whether an operand refers to a label in far text, so it needs a 20-bit address. Only the
+    // section a label is in matters here, not its offset.
+    fn is_far(
+        arg: &InstructionArg,
+        section: SectionKind,
+        span: &Span,
+        labels: &HashMap<String, Linear>,
+        scope: &Scope,
+    ) -> bool {
+        let far = RelocationTarget::Section(SectionKind::FarText);
+
+        Self::relocated(arg, section, span, labels, scope)
+            .is_some_and(|value| value.bases.iter().any(|(target, _)| *target == far))
+    }
+
+    // Turns the value of an operand into the word itself. When the value depends on where the
+    // linker puts a label, the word is left as zero and a relocation of `size` is added for it.
+    // Only words are ever plain numbers here.
+    #[allow(clippy::too_many_arguments)]
+    fn word_operand(
+        value: Linear,
+        kind: ValueKind,
+        section: SectionKind,
+        offset: usize,
+        size: RelocationSize,
+        span: &Span,
+        relocations: &mut Vec<(Relocation, Span)>,
+    ) -> Result<u16, Diagnostic> {
+        if value.is_number() {
+            return match (kind, value.offset) {
+                (ValueKind::Number, -0x8000..=0xFFFF) => Ok(value.offset as u16),
+                (ValueKind::Address, 0..=0xFFFF) => Ok(value.offset as u16),
+                (ValueKind::Number, _) => Err(Diagnostic::error(
+                    format!(
+                        "expression evaluates to {}, which does not fit in 16 bits",
+                        value.offset
+                    ),
+                    Some(span.clone()),
+                )),
+                (ValueKind::Address, _) => Err(Diagnostic::error(
+                    format!(
+                        "expression evaluates to address 0x{:X}, which does not fit in 16 bits",
+                        value.offset
+                    ),
+                    Some(span.clone()),
+                )
+                .with_help("operands that refer to labels are stored as a word")),
+            };
+        }
+
+        match value.bases.as_slice() {
+            [(target, 1)] => {
+                let addend = match i32::try_from(value.offset) {
+                    Ok(addend) => addend,
+                    Err(_) => {
+                        return Err(Diagnostic::error(
+                            "expression overflows",
+                            Some(span.clone()),
+                        ))
+                    }
+                };
+
+                relocations.push((
+                    Relocation {
+                        section,
+                        offset: offset as u32,
+                        size,
+                        target: target.clone(),
+                        addend,
+                    },
+                    span.clone(),
+                ));
+
+                Ok(0)
+            }
+            _ => Err(expression::not_relocatable(span)),
+        }
     }
 
-    // fn data_label_len(byte_data: Vec<DefineByteData>) -> usize {
-    //     let mut ret = 0;
+    // Encodes the value of an operand after its meta byte has been worked out. Only registers
+    // are handled by the caller, since where they go depends on the instruction.
+    #[allow(clippy::too_many_arguments)]
+    fn encode_value(
+        arg: &InstructionArg,
+        mut meta: u8,
+        section: SectionKind,
+        start: usize,
+        span: &Span,
+        labels: &HashMap<String, Linear>,
+        scope: &Scope,
+        output: &mut Vec<u8>,
+        relocations: &mut Vec<(Relocation, Span)>,
+    ) -> Result<(), Diagnostic> {
+        let value = match arg {
+            InstructionArg::Register(reg) => {
+                output.push(meta);
+                output.push(*reg);
+                return Ok(());
+            }
+            InstructionArg::Number(num) => {
+                if num > &255 {
+                    meta |= 0b0000_0100;
+                    output.push(meta);
+                    output.push((num >> 8) as u8);
+                    output.push(*num as u8);
+                } else {
+                    output.push(meta);
+                    output.push(*num as u8);
+                }
+                return Ok(());
+            }
+            InstructionArg::Address(addr) => {
+                if *addr <= 255 {
+                    output.push(meta);
+                    output.push(*addr as u8);
+                } else if *addr <= 65535 {
+                    meta |= 0b0000_0100;
+                    output.push(meta);
+                    output.push((addr >> 8) as u8);
+                    output.push(*addr as u8);
+                } else {
+                    meta |= 0b0000_1000;
+                    output.push(meta);
+                    output.push(((addr & 0xF0000) >> 16) as u8);
+                    output.push(((addr & 0x0FF00) >> 8) as u8);
+                    output.push(*addr as u8);
+                }
+                return Ok(());
+            }
+            InstructionArg::Identifier(ident) => match labels.get(ident) {
+                Some(label) => Ok((label.clone(), ValueKind::Address)),
+                None => Err(Diagnostic::error(
+                    format!("no label named `{}`", ident),
+                    Some(span.clone()),
+                )),
+            },
+            InstructionArg::Expression(expr, kind) => {
+                let scope = Scope {
+                    here: Some(Linear::at(RelocationTarget::Section(section), start as i64)),
+                    ..*scope
+                };
+
+                expr.relocate(&scope, span, &mut Vec::new())
+                    .map(|value| (value, *kind))
+            }
+        };
 
-    //     for data in byte_data {
-    //         ret += data.len();
-    //     }
+        // Labels and expressions are always a word, even when they can't be encoded, unless they
+        // refer to far text. Then they take the 20-bit form and the linker fills in all of it.
+        let size = if Self::is_far(arg, section, span, labels, scope) {
+            meta |= 0b0000_1000;
+            output.push(meta);
+            output.push(0);
+            RelocationSize::Double
+        } else {
+            meta |= 0b0000_0100;
+            output.push(meta);
+            RelocationSize::Word
+        };
+
+        let word = value.and_then(|(value, kind)| {
+            Self::word_operand(
+                value,
+                kind,
+                section,
+                output.len() + 2 - size.width(),
+                size,
+                span,
+                relocations,
+            )
+        });
+        output.extend_from_slice(&word.as_ref().map_or(0, |word| *word).to_be_bytes());
+
+        word.map(|_| ())
+    }
 
-    //     ret
-    // }
+    // Encodes a value from the data section that starts at `offset`. Bytes have to be plain
+    // numbers, words and doubles can also hold the address of a label.
+    fn encode_data(
+        value: &DataValue,
+        offset: usize,
+        scope: &Scope,
+        relocations: &mut Vec<(Relocation, Span)>,
+    ) -> Result<Vec<u8>, Diagnostic> {
+        let (expr, span) = match value {
+            DataValue::Bytes(bytes) => return Ok(bytes.clone()),
+            DataValue::Byte(expr, span)
+            | DataValue::Word(expr, span)
+            | DataValue::Double(expr, span) => (expr, span),
+        };
 
-    pub fn assemble(&self) -> Vec<u8> {
-        let mut output: Vec<u8> = Vec::new();
+        let scope = Scope {
+            here: Some(Linear::at(
+                RelocationTarget::Section(SectionKind::Data),
+                offset as i64,
+            )),
+            ..*scope
+        };
 
-        let mut data_section_len = 0;
+        let linear = expr.relocate(&scope, span, &mut Vec::new())?;
+
+        match value {
+            DataValue::Byte(_, _) if !linear.is_number() => Err(Diagnostic::error(
+                "the address of a label does not fit in a byte",
+                Some(span.clone()),
+            )
+            .with_help("use `dw` to store an address")),
+            DataValue::Byte(_, _) => match linear.offset {
+                -0x80..=0xFF => Ok(vec![linear.offset as u8]),
+                value => Err(Diagnostic::error(
+                    format!(
+                        "expression evaluates to {}, which does not fit in a byte",
+                        value
+                    ),
+                    Some(span.clone()),
+                )
+                .with_help("use `dw` for 16-bit values")),
+            },
+            DataValue::Double(_, _) if linear.is_number() => match linear.offset {
+                -0x80000..=0xFFFFF => {
+                    let value = linear.offset as u32 & 0xFFFFF;
+                    Ok(vec![(value >> 16) as u8, (value >> 8) as u8, value as u8])
+                }
+                value => Err(Diagnostic::error(
+                    format!(
+                        "expression evaluates to {}, which does not fit in 20 bits",
+                        value
+                    ),
+                    Some(span.clone()),
+                )),
+            },
+            DataValue::Double(_, _) => {
+                let word = Self::word_operand(
+                    linear,
+                    ValueKind::Address,
+                    SectionKind::Data,
+                    offset,
+                    RelocationSize::Double,
+                    span,
+                    relocations,
+                )?;
+
+                Ok(vec![0, (word >> 8) as u8, word as u8])
+            }
+            _ => Self::word_operand(
+                linear,
+                ValueKind::Number,
+                SectionKind::Data,
+                offset,
+                RelocationSize::Word,
+                span,
+                relocations,
+            )
+            .map(|word| word.to_be_bytes().to_vec()),
+        }
+    }
 
-        for (data_label_name, data_label_values) in &self.parser_res.data_labels {
+    fn encode(&self) -> Result<Encoded, Vec<Diagnostic>> {
+        let mut data: Vec<u8> = Vec::new();
+        let mut text: Vec<u8> = Vec::new();
+        let mut far_text: Vec<u8> = Vec::new();
+        let mut diagnostics: Vec<Diagnostic> = Vec::new();
+        let mut object = ObjectFile::new();
+        let mut listing = Listing::new();
+        let mut relocations: Vec<(Relocation, Span)> = Vec::new();
+        let mut data_offsets: HashMap<String, u32> = HashMap::new();
+
+        // Data labels are laid out in the order they appear in the source. Every value has a fixed
+        // size, so labels can be placed before any data is encoded.
+        for data_label in &self.parser_res.data_labels {
             // The parser already checks for existing data labels and text labels.
 
             // But just in case...
-            if Assembler::find_label(data_label_name, &self.parser_res.text_labels).is_some() {
-                panic!(
-                    "Label {} already defined for a text label.",
-                    data_label_name
-                );
+            if let Some(label) =
+                Assembler::find_label(&data_label.name, &self.parser_res.text_labels)
+            {
+                diagnostics.push(Diagnostic::error(
+                    format!(
+                        "label `{}` is defined as both a text and a data label",
+                        data_label.name
+                    ),
+                    Some(label.span),
+                ));
             }
 
-            for value in data_label_values {
-                match value.clone() {
-                    parser::DefineByteData::String(string, _) => {
-                        data_section_len += string.len();
+            let align = data_label.align as usize;
+            let start = data.len().next_multiple_of(align);
 
-                        for char in string.chars() {
-                            output.push(char.try_into().unwrap())
-                        }
-                    }
-                    parser::DefineByteData::Byte(byte, _) => {
-                        data_section_len += 1;
+            data.resize(start + data_label.len(), 0);
+            data_offsets.insert(data_label.name.clone(), start as u32);
+            object.data_align = object.data_align.max(data_label.align);
+        }
 
-                        output.push(byte);
-                    }
+        // Operands that refer to far labels take an extra byte, so text labels can only be placed
+        // once it is known which operands do. That only depends on the section of each label.
+        let text_labels = &self.parser_res.text_labels;
+        let sections = self.locations(&data_offsets, &vec![0; text_labels.len()]);
+        let scope = Scope {
+            constants: &self.parser_res.constants,
+            labels: Some(&sections),
+            here: None,
+        };
+
+        let mut text_offsets = Vec::new();
+        let mut ends = [0, 0];
+
+        for label in text_labels {
+            let end = &mut ends[label.far as usize];
+            text_offsets.push(*end);
+
+            for instruction in &label.instructions {
+                let far = Self::operand(instruction).is_some_and(|arg| {
+                    let span = instruction.arg_spans.last().unwrap_or(&instruction.span);
+                    Self::is_far(arg, Self::text_section(label), span, &sections, &scope)
+                });
+
+                *end += instruction.len() as u32 + far as u32;
+            }
+        }
+
+        let labels = self.locations(&data_offsets, &text_offsets);
+        let scope = Scope {
+            labels: Some(&labels),
+            ..scope
+        };
+
+        for data_label in &self.parser_res.data_labels {
+            let mut offset = data_offsets[&data_label.name] as usize;
 
-                    parser::DefineByteData::Short(short, _) => {
-                        data_section_len += 2;
+            for line in &data_label.lines {
+                let start = offset;
 
-                        output.push(((short & 0xFF00) >> 8) as u8);
-                        output.push(short as u8);
+                for value in &line.values {
+                    match Self::encode_data(value, offset, &scope, &mut relocations) {
+                        Ok(bytes) => data[offset..offset + bytes.len()].copy_from_slice(&bytes),
+                        Err(diagnostic) => diagnostics.push(diagnostic),
                     }
+
+                    offset += value.len();
                 }
+
+                listing.add(
+                    line.span.origin(),
+                    SectionKind::Data,
+                    start as u32,
+                    &data[start..offset],
+                    None,
+                );
             }
         }
 
-        let mut code_len = 0;
+        for label in text_labels {
+            let section = Self::text_section(label);
+            let text = if label.far { &mut far_text } else { &mut text };
 
-        for label in &self.parser_res.text_labels {
-            code_len += label.len();
-            // println!("Label \"{}\" @ addr $0x{:05x}", label.name, label.addr);
             for instruction in &label.instructions {
+                let start = text.len();
+
+                object.lines.push((
+                    section,
+                    start as u32,
+                    SourceLine {
+                        file: self.file_names[instruction.span.file].clone(),
+                        line: instruction.span.line,
+                    },
+                ));
+
                 let opcode =
                     Instruction::create_opcode(instruction.opcode, instruction.addressing_mode);
-                output.push(opcode);
+                text.push(opcode);
 
                 let mut meta: u8 = 0x00;
 
-                match instruction.instruction_type {
+                if let (Opcode::MOV, Some(arg)) = (instruction.opcode, Self::operand(instruction)) {
+                    let span = instruction.arg_spans.last().unwrap_or(&instruction.span);
+
+                    if Self::is_far(arg, section, span, &labels, &scope) {
+                        diagnostics.push(
+                            Diagnostic::error(
+                                "the address of far text does not fit in a register",
+                                Some(span.clone()),
+                            )
+                            .with_help("only jumps, calls and loads can refer to `.far` labels"),
+                        );
+                    }
+                }
+
+                // `ret` only pops the low 16 bits of the return address, so a near call can't leave
+                // the 64K it's made from. Plain addresses and symbols from other objects are taken as
+                // given.
+                if let (Opcode::JSR, Some(arg)) = (instruction.opcode, Self::operand(instruction)) {
+                    let span = instruction.arg_spans.last().unwrap_or(&instruction.span);
+                    let far = RelocationTarget::Section(SectionKind::FarText);
+
+                    let crosses = Self::relocated(arg, section, span, &labels, &scope)
+                        .filter(|value| {
+                            !value.is_number()
+                                && value.bases.iter().all(|(target, _)| {
+                                    matches!(target, RelocationTarget::Section(_))
+                                })
+                        })
+                        .is_some_and(|value| {
+                            value.bases.iter().any(|(target, _)| *target == far)
+                                != (section == SectionKind::FarText)
+                        });
+
+                    if crosses {
+                        diagnostics.push(
+                            Diagnostic::error(
+                                "`jsr` can't call between near and far text",
+                                Some(span.clone()),
+                            )
+                            .with_help("use `jsrf` and return with `retf`"),
+                        );
+                    }
+                }
+
+                let res = match instruction.instruction_type {
                     InstructionType::Zero => {
                         meta |= 0b0000_1100;
-                        output.push(meta);
+                        text.push(meta);
+                        Ok(())
                     }
                     InstructionType::One => match &instruction.args[0] {
                         InstructionArg::Register(reg) => {
                             meta |= reg << 4;
                             meta |= 0b0000_1100;
-                            output.push(meta);
-                        }
-                        InstructionArg::Number(num) => {
-                            if num > &255 {
-                                meta |= 0b0000_0100;
-                                output.push(meta);
-                                output.push((num >> 8) as u8);
-                                output.push(*num as u8);
-                            } else {
-                                output.push(meta);
-                                output.push(*num as u8);
-                            }
-                        }
-                        InstructionArg::Address(addr) => {
-                            let new_addr = addr + data_section_len as u32;
-
-                            if new_addr <= 255 {
-                                output.push(meta);
-                                output.push(new_addr as u8);
-                            } else if new_addr <= 65535 {
-                                meta |= 0b0000_0100;
-                                output.push(meta);
-                                output.push((new_addr >> 8) as u8);
-                                output.push(new_addr as u8);
-                            } else {
-                                meta |= 0b0000_1000;
-                                output.push(meta);
-                                output.push(((new_addr & 0xF0000) >> 16) as u8);
-                                output.push(((new_addr & 0x0FF00) >> 8) as u8);
-                                output.push(new_addr as u8);
-                            }
-                        }
-                        InstructionArg::Identifier(ident) => {
-                            meta |= 0b0000_0100;
-
-                            match Assembler::find_label(ident, &self.parser_res.text_labels) {
-                                Some(label) => {
-                                    let addr = label.addr + data_section_len;
-                                    output.push(meta);
-                                    output.push((addr >> 8) as u8);
-                                    output.push(addr as u8);
-                                }
-                                None => {
-                                    // Label may be a data label
-                                    // Let's check
-
-                                    if Assembler::find_data_label(
-                                        ident,
-                                        &self.parser_res.data_labels,
-                                    )
-                                    .is_none()
-                                    {
-                                        panic!("No label named {}", ident);
-                                    }
-
-                                    let data_label = Assembler::find_data_label(
-                                        ident,
-                                        &self.parser_res.data_labels,
-                                    )
-                                    .unwrap();
-                                    let data_label_start = match data_label.1[0] {
-                                        DefineByteData::String(_, offset) => offset + 0x4402,
-                                        DefineByteData::Byte(_, offset) => offset + 0x4402,
-                                        DefineByteData::Short(_, offset) => offset + 0x4402,
-                                    };
-
-                                    output.push(meta);
-
-                                    output.push((data_label_start >> 8) as u8);
-                                    output.push(data_label_start as u8);
-                                }
-                            };
-
-                            // let label = match Assembler::find_label(ident, &self.parser_res.text_labels) {
-                            //     Some(label) => label,
-                            //     None => panic!("Label {} does not exist!", ident),
-                            // };
-
-                            // meta |= 0b0000_0100;
-                            // output.push(meta);
-                            // output.push((label.addr >> 8) as u8);
-                            // output.push(label.addr as u8);
+                            text.push(meta);
+                            Ok(())
                         }
+                        arg => Self::encode_value(
+                            arg,
+                            meta,
+                            section,
+                            start,
+                            &instruction.arg_spans[0],
+                            &labels,
+                            &scope,
+                            text,
+                            &mut relocations,
+                        ),
                     },
                     InstructionType::Two => {
-                        match &instruction.args[0] {
-                            InstructionArg::Register(reg) => meta |= reg << 4,
-                            _ => panic!("Argument 1 must be a register."),
+                        // The parser only accepts two argument instructions that start with a register.
+                        if let InstructionArg::Register(reg) = &instruction.args[0] {
+                            meta |= reg << 4;
                         }
 
-                        match &instruction.args[1] {
-                            InstructionArg::Register(reg) => {
-                                output.push(meta);
-                                output.push(*reg);
-                            }
-                            InstructionArg::Number(num) => {
-                                if num > &255 {
-                                    meta |= 0b0000_0100;
-                                    output.push(meta);
-                                    output.push((num >> 8) as u8);
-                                    output.push(*num as u8);
-                                } else {
-                                    output.push(meta);
-                                    output.push(*num as u8);
-                                }
-                            }
-                            InstructionArg::Address(addr) => {
-                                let new_addr = addr + data_section_len as u32;
-
-                                if new_addr <= 255 {
-                                    output.push(meta);
-                                    output.push(new_addr as u8);
-                                } else if new_addr <= 65535 {
-                                    meta |= 0b0000_0100;
-                                    output.push(meta);
-                                    output.push((new_addr >> 8) as u8);
-                                    output.push(new_addr as u8);
-                                } else {
-                                    meta |= 0b0000_1000;
-                                    output.push(meta);
-                                    output.push(((new_addr & 0xF0000) >> 16) as u8);
-                                    output.push(((new_addr & 0x0FF00) >> 8) as u8);
-                                    output.push(new_addr as u8);
-                                }
-                            }
-                            InstructionArg::Identifier(ident) => {
-                                meta |= 0b0000_0100;
-
-                                match Assembler::find_label(ident, &self.parser_res.text_labels) {
-                                    Some(label) => {
-                                        let addr = label.addr + data_section_len;
-                                        output.push(meta);
-                                        output.push((addr >> 8) as u8);
-                                        output.push(addr as u8);
-                                    }
-                                    None => {
-                                        // Label may be a data label
-                                        // Let's check
-
-                                        if Assembler::find_data_label(
-                                            ident,
-                                            &self.parser_res.data_labels,
-                                        )
-                                        .is_none()
-                                        {
-                                            panic!("No label named {}", ident);
-                                        }
-
-                                        let data_label = Assembler::find_data_label(
-                                            ident,
-                                            &self.parser_res.data_labels,
-                                        )
-                                        .unwrap();
-                                        let data_label_start = match data_label.1[0] {
-                                            DefineByteData::String(_, offset) => offset + 0x4402,
-                                            DefineByteData::Byte(_, offset) => offset + 0x4402,
-                                            DefineByteData::Short(_, offset) => offset + 0x4402,
-                                        };
-
-                                        output.push(meta);
-
-                                        output.push((data_label_start >> 8) as u8);
-                                        output.push(data_label_start as u8);
-                                    }
-                                };
-                            }
-                        }
+                        Self::encode_value(
+                            &instruction.args[1],
+                            meta,
+                            section,
+                            start,
+                            &instruction.arg_spans[1],
+                            &labels,
+                            &scope,
+                            text,
+                            &mut relocations,
+                        )
                     }
+                };
+
+                if let Err(diagnostic) = res {
+                    diagnostics.push(diagnostic);
                 }
+
+                // Expanded macros are listed on the line that called them.
+                listing.add(
+                    instruction.span.origin(),
+                    section,
+                    start as u32,
+                    &text[start..],
+                    instruction.expansion.clone(),
+                );
             }
         }
 
-        output.insert(0, data_section_len as u8);
-        output.insert(0, ((data_section_len & 0xFF00) >> 8) as u8);
-
-        output.insert(0, code_len as u8);
-        output.insert(0, ((code_len & 0xFF00) >> 8) as u8);
-
-        let start_index = match self.parser_res.metadata.get("main") {
-            None => panic!("No main label defined."),
-            Some(label_value) => match label_value.to_owned() {
+        if let Some((value, span)) = self.parser_res.metadata.get("main") {
+            match value {
+                parser::MetadataValue::String(label_name) if labels.contains_key(label_name) => {
+                    object.entry = Some(label_name.clone());
+                }
                 parser::MetadataValue::String(label_name) => {
-                    match Assembler::find_label(&label_name, &self.parser_res.text_labels) {
-                        None => panic!("Label {} does not exist.", label_name),
-                        Some(label) => label.addr + data_section_len,
-                    }
+                    diagnostics.push(Diagnostic::error(
+                        format!("main label `{}` does not exist", label_name),
+                        Some(span.clone()),
+                    ));
                 }
                 parser::MetadataValue::Number(_) => {
-                    panic!("Defining main label cannot have a number as a value.")
+                    diagnostics.push(Diagnostic::error(
+                        "main label cannot be a number",
+                        Some(span.clone()),
+                    ));
                 }
-            },
-        };
+            }
+        }
 
-        output.insert(0, start_index as u8);
-        output.insert(0, ((start_index & 0xFF00) >> 8) as u8);
+        let mut interrupts: Vec<(&u8, &(String, Span))> =
+            self.parser_res.interrupts.iter().collect();
+        interrupts.sort_by_key(|(interrupt, _)| **interrupt);
 
-        for interrupt in 0..255 {
-            // println!("{}", interrupt);
-            if self.parser_res.interrupts.get(&interrupt).is_some() {
-                // The interrupt is defined, put the address in the output
-                let label = Self::find_label(
-                    &self.parser_res.interrupts[&interrupt],
-                    &self.parser_res.text_labels,
-                )
-                .unwrap();
-                output.push((((label.addr as u16) & 0xFF00) >> 8) as u8);
-                output.push(label.addr as u8);
+        for (interrupt, (label_name, span)) in interrupts {
+            if labels.contains_key(label_name) {
+                object.interrupts.push((*interrupt, label_name.clone()));
             } else {
-                // The interrupt is not defined, fill with zero's
-                output.push(0);
-                output.push(0);
+                diagnostics.push(Diagnostic::error(
+                    format!("interrupt handler `{}` does not exist", label_name),
+                    Some(span.clone()),
+                ));
             }
         }
 
-        output
+        for (name, span) in &self.parser_res.globals {
+            if Self::find_label(name, &self.parser_res.text_labels).is_none()
+                && !data_offsets.contains_key(name)
+            {
+                diagnostics.push(Diagnostic::error(
+                    format!("global label `{}` is not defined", name),
+                    Some(span.clone()),
+                ));
+            }
+        }
+
+        for (name, span) in &self.parser_res.externs {
+            if Self::find_label(name, &self.parser_res.text_labels).is_some()
+                || data_offsets.contains_key(name)
+            {
+                diagnostics.push(Diagnostic::error(
+                    format!(
+                        "`{}` is defined in this file, so it can't be `.extern`",
+                        name
+                    ),
+                    Some(span.clone()),
+                ));
+            }
+        }
+
+        Diagnostic::dedup(&mut diagnostics);
+
+        if !diagnostics.is_empty() {
+            return Err(diagnostics);
+        }
+
+        let mut data_symbols: Vec<(&String, &u32)> = data_offsets.iter().collect();
+        data_symbols.sort_by_key(|(name, offset)| (**offset, *name));
+
+        let symbols = data_symbols
+            .into_iter()
+            .map(|(name, offset)| (name, SectionKind::Data, *offset))
+            .chain(
+                text_labels
+                    .iter()
+                    .zip(text_offsets)
+                    .map(|(label, offset)| (&label.name, Self::text_section(label), offset)),
+            );
+
+        for (name, section, offset) in symbols {
+            if labels::is_anonymous(name) {
+                continue;
+            }
+
+            listing.symbols.insert(name, offset);
+
+            object.symbols.push(Symbol {
+                name: name.clone(),
+                section,
+                offset,
+                global: self.parser_res.globals.contains_key(name),
+            });
+        }
+
+        let mut externs: Vec<&String> = self.parser_res.externs.keys().collect();
+        externs.sort();
+        object.externs = externs.into_iter().cloned().collect();
+
+        let (relocations, relocation_spans) = relocations.into_iter().unzip();
+        object.relocations = relocations;
+        object.data = data;
+        object.text = text;
+        object.far_text = far_text;
+
+        for (interrupt, label_name) in &object.interrupts {
+            let offset = object.symbol(label_name).map_or(0, |symbol| symbol.offset);
+            listing
+                .interrupts
+                .push((*interrupt, offset, label_name.clone()));
+        }
+
+        Ok(Encoded {
+            object,
+            listing,
+            relocation_spans,
+        })
+    }
+
+    // Assembles into an object for `link`. Addresses in the listing are offsets into the
+    // section each line ends up in.
+    pub fn assemble_object(&self) -> Result<(ObjectFile, Listing), Vec<Diagnostic>> {
+        let encoded = self.encode()?;
+
+        Ok((encoded.object, encoded.listing))
+    }
+
+    // Assembles a whole program, by linking it as the only object.
+    pub fn assemble(&self) -> Result<(Executable, Listing), Vec<Diagnostic>> {
+        let encoded = self.encode();
+
+        if !self.parser_res.metadata.contains_key("main") {
+            let diagnostic = Diagnostic::error("no main label defined", None)
+                .with_help("add `.main <label>` to choose where the program starts");
+
+            let mut diagnostics = encoded.err().unwrap_or_default();
+            diagnostics.push(diagnostic);
+
+            return Err(diagnostics);
+        }
+
+        let Encoded {
+            object,
+            mut listing,
+            relocation_spans,
+        } = encoded?;
+
+        let interrupts = object.interrupts.clone();
+        let name = self.file_names.first().cloned().unwrap_or_default();
+
+        let executable = Linker::new(vec![(name, object)]).link().map_err(|errors| {
+            errors
+                .into_iter()
+                .map(|error| match error {
+                    LinkError::RelocationOutOfRange {
+                        index, value, bits, ..
+                    } => Diagnostic::error(
+                        format!(
+                            "expression evaluates to address 0x{:X}, which does not fit in {} bits",
+                            value, bits
+                        ),
+                        Some(relocation_spans[index].clone()),
+                    )
+                    .with_help("operands that refer to labels are stored as a word"),
+                    LinkError::UndefinedSymbol { name, .. } => Diagnostic::error(
+                        format!("`{}` is declared `.extern`, but nothing defines it", name),
+                        self.parser_res.externs.get(&name).cloned(),
+                    )
+                    .with_help(
+                        "assemble with --object and link it with the object that defines it",
+                    ),
+                    LinkError::FarInterruptHandler {
+                        interrupt, handler, ..
+                    } => Diagnostic::error(
+                        format!("interrupt handler `{}` is in far text", handler),
+                        self.parser_res
+                            .interrupts
+                            .get(&interrupt)
+                            .map(|(_, span)| span.clone()),
+                    )
+                    .with_help(
+                        "the interrupt table only holds 16-bit addresses, move it to `.text`",
+                    ),
+                    LinkError::InvalidExecutable(
+                        error @ ExecutableError::InvalidLoadAddress { kind, .. },
+                    ) if kind != SectionKind::FarText => {
+                        Diagnostic::error(format!("the program is too large: {}", error), None)
+                            .with_help("move code to `.far` to make room")
+                    }
+                    error => Diagnostic::error(error.to_string(), None),
+                })
+                .collect::<Vec<Diagnostic>>()
+        })?;
+
+        listing.place(&executable);
+
+        if let Some(section) = executable.section(SectionKind::Symbols) {
+            listing.symbols = SymbolTable::from_bytes(&section.bytes).unwrap_or_default();
+        }
+
+        listing.interrupts = interrupts
+            .into_iter()
+            .map(|(interrupt, name)| {
+                let addr = listing.symbols.get(&name).unwrap_or(0);
+                (interrupt, addr, name)
+            })
+            .collect();
+
+        Ok((executable, listing))
     }
 }
@@ -1,10 +1,18 @@
-use std::{collections::HashMap, str::FromStr};
-
-use super::tokenizer::Token;
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+};
+
+use super::conditionals;
+use super::diagnostic::{Diagnostic, Span};
+use super::expression::{self, Constants, Expr, Scope, ValueKind};
+use super::labels;
+use super::macros;
+use super::pseudo::{self, Arg, Operand};
+pub use super::tokenizer::TokenInfoType;
+use super::tokenizer::{self, Token};
 use crate::common::instruction::opcode::{AddressingMode, Instruction, Opcode};
 
-pub type TokenInfoType = (Token, String);
-
 use regex::Regex;
 
 #[derive(Debug, Clone)]
@@ -13,6 +21,9 @@ pub enum InstructionArg {
     Number(u16),
     Address(u32),
     Identifier(String),
+    // An expression that refers to labels or `$`, so it can only be evaluated once the program
+    // is laid out. It is always encoded as a word.
+    Expression(Expr, ValueKind),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -27,28 +38,43 @@ pub enum Sections {
     None,
     Text,
     Data,
+    Far,
 }
 
 #[derive(Debug)]
 pub struct ParserResult {
-    pub metadata: HashMap<String, MetadataValue>,
-    pub interrupts: HashMap<u8, String>,
+    pub metadata: HashMap<String, (MetadataValue, Span)>,
+    pub interrupts: HashMap<u8, (String, Span)>,
     pub text_labels: Vec<Label>,
-    pub data_labels: HashMap<String, Vec<DefineByteData>>,
+    pub data_labels: Vec<DataLabel>,
+    pub constants: Constants,
+    // Labels other objects can use, and labels this one expects another object to define.
+    pub globals: HashMap<String, Span>,
+    pub externs: HashMap<String, Span>,
+    pub warnings: Vec<Diagnostic>,
 }
 
 impl ParserResult {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        metadata: HashMap<String, MetadataValue>,
+        metadata: HashMap<String, (MetadataValue, Span)>,
         text_labels: Vec<Label>,
-        data_labels: HashMap<String, Vec<DefineByteData>>,
-        interrupts: HashMap<u8, String>,
+        data_labels: Vec<DataLabel>,
+        interrupts: HashMap<u8, (String, Span)>,
+        constants: Constants,
+        globals: HashMap<String, Span>,
+        externs: HashMap<String, Span>,
+        warnings: Vec<Diagnostic>,
     ) -> ParserResult {
         ParserResult {
             metadata,
             text_labels,
             data_labels,
+            constants,
             interrupts,
+            globals,
+            externs,
+            warnings,
         }
     }
 }
@@ -60,131 +86,115 @@ pub struct ParserInstruction {
     pub addressing_mode: AddressingMode,
     pub instruction_type: InstructionType,
     pub args: Vec<InstructionArg>,
+    pub span: Span,
+    pub arg_spans: Vec<Span>,
+    // The instruction as text when it came from a pseudo-instruction, for listings.
+    pub expansion: Option<String>,
 }
 
 impl ParserInstruction {
-    pub fn get_instruction(opcode: Opcode, args: Vec<InstructionArg>) -> ParserInstruction {
+    fn accepted_modes(opcode: Opcode) -> String {
+        let modes: Vec<String> = Instruction::get_variants(opcode)
+            .iter()
+            .map(|mode| format!("{:?}", mode))
+            .collect();
+
+        format!(
+            "`{}` accepts these addressing modes: {}",
+            format!("{:?}", opcode).to_lowercase(),
+            modes.join(", ")
+        )
+    }
+
+    fn arg_mode(arg: &InstructionArg) -> AddressingMode {
+        match arg {
+            InstructionArg::Register(_) => AddressingMode::Register,
+            InstructionArg::Number(_) => AddressingMode::Immediate,
+            InstructionArg::Address(_) => AddressingMode::Direct,
+            InstructionArg::Identifier(_) => AddressingMode::Direct,
+            InstructionArg::Expression(_, ValueKind::Number) => AddressingMode::Immediate,
+            InstructionArg::Expression(_, ValueKind::Address) => AddressingMode::Direct,
+        }
+    }
+
+    pub fn get_instruction(
+        opcode: Opcode,
+        args: Vec<InstructionArg>,
+        span: Span,
+        arg_spans: Vec<Span>,
+    ) -> Result<ParserInstruction, Diagnostic> {
+        let name = format!("{:?}", opcode).to_lowercase();
+
         let instruction_type = match args.len() {
             0 => InstructionType::Zero,
             1 => InstructionType::One,
             2 => InstructionType::Two,
-            _ => panic!("Too many arguments for instruction."),
+            _ => {
+                return Err(Diagnostic::error(
+                    format!("too many arguments for `{}`", name),
+                    Some(span),
+                ))
+            }
         };
 
-        let map = Instruction::hashmap();
-
-        match args.len() {
-            0 => {
-                let full_opcode = Instruction::create_opcode(opcode, AddressingMode::Discard);
-
-                if map.contains_key(&full_opcode) {
-                    let (_, _, _, arg_count) = map.get(&full_opcode).unwrap();
-
-                    if arg_count < &0 {
-                        panic!(
-                            "Too many arguments for instruction {:?} with arg {:?}",
-                            opcode, args[0]
-                        );
-                    }
-
-                    return ParserInstruction {
-                        opcode,
-                        addressing_mode: AddressingMode::Discard,
-                        instruction_type,
-                        args,
-                    };
+        if instruction_type == InstructionType::Two {
+            match args[0] {
+                InstructionArg::Register(_) => (),
+                _ => {
+                    return Err(Diagnostic::error(
+                        format!("first argument of `{}` must be a register", name),
+                        Some(arg_spans[0].clone()),
+                    ))
                 }
-
-                panic!("Invalid instruction {:?}", opcode);
             }
-            1 => {
-                let mode = match args[0] {
-                    InstructionArg::Register(_) => AddressingMode::Register,
-                    InstructionArg::Number(_) => AddressingMode::Immediate,
-                    InstructionArg::Address(_) => AddressingMode::Direct,
-                    InstructionArg::Identifier(_) => AddressingMode::Direct,
-                };
-
-                let full_opcode = Instruction::create_opcode(opcode, mode);
-
-                if map.contains_key(&full_opcode) {
-                    let (_, _, _, arg_count) = map.get(&full_opcode).unwrap();
-
-                    match arg_count.cmp(&1) {
-                        std::cmp::Ordering::Less => panic!(
-                            "Too many arguments for instruction {:?} with arg {:?}",
-                            opcode, args[0]
-                        ),
-                        std::cmp::Ordering::Equal => (),
-                        std::cmp::Ordering::Greater => panic!(
-                            "Too few arguments for instruction {:?} with arg {:?}",
-                            opcode, args[0]
-                        ),
-                    }
+        }
 
-                    return ParserInstruction {
-                        opcode,
-                        addressing_mode: mode,
-                        instruction_type,
-                        args,
-                    };
-                }
+        let (mode, mode_span) = match instruction_type {
+            InstructionType::Zero => (AddressingMode::Discard, span.clone()),
+            InstructionType::One => (Self::arg_mode(&args[0]), arg_spans[0].clone()),
+            InstructionType::Two => (Self::arg_mode(&args[1]), arg_spans[1].clone()),
+        };
 
-                panic!("Invalid instruction {:?} with arg {:?} (opcode doesn't accept mode / type {:?})", opcode, args[0], mode);
-            }
-            2 => {
-                match args[0] {
-                    InstructionArg::Register(_) => (),
-                    _ => panic!(
-                        "Invalid instruction {:?} with args {:?} (first arg must be register)",
-                        opcode, args
-                    ),
-                }
+        let full_opcode = Instruction::create_opcode(opcode, mode);
 
-                let mode = match args[1] {
-                    InstructionArg::Register(_) => AddressingMode::Register,
-                    InstructionArg::Number(_) => AddressingMode::Immediate,
-                    InstructionArg::Address(_) => AddressingMode::Direct,
-                    InstructionArg::Identifier(_) => AddressingMode::Direct,
+        let arg_count = match Instruction::lookup(full_opcode) {
+            Some((_, _, _, arg_count, _)) => *arg_count as usize,
+            None => {
+                let message = if mode == AddressingMode::Discard {
+                    format!("`{}` expects arguments", name)
+                } else {
+                    format!("`{}` does not accept a {:?} operand", name, mode)
                 };
 
-                let full_opcode = Instruction::create_opcode(opcode, mode);
-
-                if map.contains_key(&full_opcode) {
-                    let (_, _, _, arg_count) = map.get(&full_opcode).unwrap();
-
-                    match arg_count.cmp(&2) {
-                        std::cmp::Ordering::Less => panic!(
-                            "Too many arguments for instruction {:?} with args {:?}",
-                            opcode, args
-                        ),
-                        std::cmp::Ordering::Equal => (),
-                        std::cmp::Ordering::Greater => panic!(
-                            "Too few arguments for instruction {:?} with args {:?}",
-                            opcode, args
-                        ),
-                    }
-
-                    return ParserInstruction {
-                        opcode,
-                        addressing_mode: mode,
-                        instruction_type,
-                        args,
-                    };
-                }
-
-                panic!("Invalid instruction {:?} with args {:?} (opcode doesn't accept mode / type {:?})", opcode, args, mode);
-            }
-            _ => {
-                panic!(
-                    "Invalid instruction {:?} with args {:?}. Too many args.",
-                    opcode, args
-                );
+                return Err(Diagnostic::error(message, Some(mode_span))
+                    .with_help(Self::accepted_modes(opcode)));
             }
+        };
+
+        if arg_count != args.len() {
+            return Err(Diagnostic::error(
+                format!(
+                    "`{}` takes {} argument(s) but {} were given",
+                    name,
+                    arg_count,
+                    args.len()
+                ),
+                Some(span),
+            ));
         }
+
+        Ok(ParserInstruction {
+            opcode,
+            addressing_mode: mode,
+            instruction_type,
+            args,
+            span,
+            arg_spans,
+            expansion: None,
+        })
     }
 
-    fn len(&self) -> usize {
+    pub fn len(&self) -> usize {
         let mut init_len: usize = 2;
 
         match self.instruction_type {
@@ -207,7 +217,7 @@ impl ParserInstruction {
                         init_len += 3;
                     }
                 }
-                InstructionArg::Identifier(_) => init_len += 2,
+                InstructionArg::Identifier(_) | InstructionArg::Expression(_, _) => init_len += 2,
             },
             InstructionType::Two => {
                 match self.args[0] {
@@ -235,7 +245,9 @@ impl ParserInstruction {
                             init_len += 3;
                         }
                     }
-                    InstructionArg::Identifier(_) => init_len += 2,
+                    InstructionArg::Identifier(_) | InstructionArg::Expression(_, _) => {
+                        init_len += 2
+                    }
                 }
             }
         }
@@ -254,59 +266,89 @@ pub enum MetadataValue {
 pub struct Label {
     pub name: String,
     pub instructions: Vec<ParserInstruction>,
-    pub addr: usize,
+    // Set for labels in `.far`. The assembler works out where labels go, since an operand that
+    // refers to a far label takes an extra byte.
+    pub far: bool,
+    pub span: Span,
 }
 
 impl Label {
-    pub fn new(name: String, addr: usize) -> Label {
+    pub fn new(name: String, far: bool, span: Span) -> Label {
         Label {
             name,
             instructions: Vec::new(),
-            addr,
+            far,
+            span,
         }
     }
 
     fn add(&mut self, instruction: ParserInstruction) {
         self.instructions.push(instruction);
     }
+}
 
-    pub fn len(&self) -> usize {
-        let mut size: usize = 0;
-
-        for instruction in &self.instructions {
-            size += instruction.len();
-        }
+#[derive(Debug, Clone)]
+pub struct DataLabel {
+    pub name: String,
+    pub lines: Vec<DataLine>,
+    // Set by an `.align` before the label, so the label starts on a multiple of it.
+    pub align: u32,
+    pub span: Span,
+}
 
-        size
+impl DataLabel {
+    pub fn len(&self) -> usize {
+        self.lines
+            .iter()
+            .flat_map(|line| &line.values)
+            .map(DataValue::len)
+            .sum()
     }
 }
 
+// The values from one line of data, such as `dw 1, 2, 3`.
 #[derive(Debug, Clone)]
-pub enum DefineByteData {
-    String(String, usize),
-    Byte(u8, usize),
-    Short(u16, usize),
+pub struct DataLine {
+    pub values: Vec<DataValue>,
+    pub span: Span,
 }
 
-impl DefineByteData {
+// Values are expressions until the assembler knows where every label is.
+#[derive(Debug, Clone)]
+pub enum DataValue {
+    Bytes(Vec<u8>),
+    Byte(Expr, Span),
+    Word(Expr, Span),
+    Double(Expr, Span),
+}
+
+impl DataValue {
     pub fn len(&self) -> usize {
-        match self.clone() {
-            DefineByteData::String(string, _) => string.len(),
-            DefineByteData::Byte(_, _) => 1,
-            DefineByteData::Short(_, _) => 2,
+        match self {
+            DataValue::Bytes(bytes) => bytes.len(),
+            DataValue::Byte(_, _) => 1,
+            DataValue::Word(_, _) => 2,
+            DataValue::Double(_, _) => 3,
         }
     }
 }
 
 pub struct Parser {
     tokens: Vec<TokenInfoType>,
-    pub metadata: HashMap<String, MetadataValue>,
-    pub interrupts: HashMap<u8, String>,
+    pub metadata: HashMap<String, (MetadataValue, Span)>,
+    pub interrupts: HashMap<u8, (String, Span)>,
     pub text_labels: Vec<Label>,
-    pub data_labels: HashMap<String, Vec<DefineByteData>>,
+    pub data_labels: Vec<DataLabel>,
+    pub constants: Constants,
+    pub globals: HashMap<String, Span>,
+    pub externs: HashMap<String, Span>,
+    pub diagnostics: Vec<Diagnostic>,
+    // Constants given with `define`, which the source can't define again.
+    pub defines: HashSet<String>,
     current_token_index: u32,
-    label_offset: usize,
     current_section: Sections,
+    // An `.align` waiting for the data label after it.
+    pending_align: Option<(u32, Span)>,
 }
 
 impl Parser {
@@ -317,13 +359,25 @@ impl Parser {
             metadata: HashMap::new(),
             interrupts: HashMap::new(),
             text_labels: Vec::new(),
-            data_labels: HashMap::new(),
+            data_labels: Vec::new(),
+            constants: HashMap::new(),
+            globals: HashMap::new(),
+            externs: HashMap::new(),
+            diagnostics: Vec::new(),
+            defines: HashSet::new(),
             current_token_index: 0,
-            label_offset: 0,
             current_section: Sections::None,
+            pending_align: None,
         }
     }
 
+    // Defines a constant before the source is read, like `-D NAME=VALUE` on the command line.
+    pub fn define(&mut self, name: &str, value: i64) {
+        self.constants
+            .insert(name.to_string(), expression::defined(value));
+        self.defines.insert(name.to_string());
+    }
+
     fn get_token(&self) -> Option<TokenInfoType> {
         if self.current_token_index == self.tokens.len() as u32 {
             return None;
@@ -332,65 +386,111 @@ impl Parser {
         Some(self.tokens[self.current_token_index as usize].clone())
     }
 
-    fn peek_token(&self) -> Option<&TokenInfoType> {
-        if self.current_token_index + 1 == self.tokens.len() as u32 {
-            return None;
+    // Like `get_token`, but running out of tokens is an error pointing at the end of the file.
+    fn expect_token(&self, expected: &str) -> Result<TokenInfoType, Diagnostic> {
+        match self.get_token() {
+            Some(token) => Ok(token),
+            None => Err(Diagnostic::error(
+                format!("expected {}, found end of file", expected),
+                self.tokens.last().map(|token| token.2.clone()),
+            )),
         }
-
-        Some(&self.tokens[(self.current_token_index + 1) as usize])
     }
 
-    fn convert_byte_to_base(byte: String) -> u8 {
-        let reg = Regex::new(r"^0[xX][0-9A-Fa-f]+$").unwrap();
+    // Skips the rest of the current line so parsing can continue after an error.
+    fn skip_line(&mut self) {
+        while let Some(token) = self.get_token() {
+            self.current_token_index += 1;
 
-        if reg.is_match(&byte) {
-            u8::from_str_radix(&byte[2..], 16).unwrap()
-        } else {
-            byte.parse::<u8>().unwrap()
+            if token.0 == Token::NewLine {
+                break;
+            }
         }
     }
 
-    fn convert_short_to_base(short: String) -> u16 {
+    fn convert_byte_to_base(byte: &str) -> Option<u8> {
         let reg = Regex::new(r"^0[xX][0-9A-Fa-f]+$").unwrap();
 
-        if reg.is_match(&short) {
-            u16::from_str_radix(&short[2..], 16).unwrap()
+        if reg.is_match(byte) {
+            u8::from_str_radix(&byte[2..], 16).ok()
         } else {
-            short.parse::<u16>().unwrap()
+            byte.parse::<u8>().ok()
         }
     }
 
-    fn convert_int_to_base(short: String) -> u32 {
+    fn convert_short_to_base(short: &str) -> Option<u16> {
         let reg = Regex::new(r"^0[xX][0-9A-Fa-f]+$").unwrap();
 
-        if reg.is_match(&short) {
-            u32::from_str_radix(&short[2..], 16).unwrap()
+        if reg.is_match(short) {
+            u16::from_str_radix(&short[2..], 16).ok()
         } else {
-            short.parse::<u32>().unwrap()
+            short.parse::<u16>().ok()
         }
     }
 
-    fn label_exists(&mut self, name: &String) -> bool {
-        if self.text_labels.is_empty() && self.data_labels.is_empty() {
-            return false;
+    fn parse_short(token: &TokenInfoType) -> Result<u16, Diagnostic> {
+        match Self::convert_short_to_base(&token.1) {
+            Some(value) => Ok(value),
+            None => Err(Diagnostic::error(
+                format!("number `{}` does not fit in 16 bits", token.1),
+                Some(token.2.clone()),
+            )
+            .with_help("numbers must be between 0 and 0xFFFF")),
+        }
+    }
+
+    fn parse_register(token: &TokenInfoType) -> Result<u8, Diagnostic> {
+        let mut chars = token.1.chars();
+        chars.next();
+        let cleaned = chars.as_str();
+
+        match cleaned.to_lowercase().as_str() {
+            "1" => Ok(0),
+            "2" => Ok(1),
+            "3" => Ok(2),
+            "4" => Ok(3),
+            "5" => Ok(4),
+            "6" => Ok(5),
+            "pc" => Ok(6),
+            "sp" => Ok(7),
+            "bp" => Ok(8),
+            _ => Err(Diagnostic::error(
+                format!("unknown register `{}`", token.1),
+                Some(token.2.clone()),
+            )),
         }
+    }
 
+    fn label_exists(&mut self, name: &String) -> bool {
         for label in &self.text_labels {
             if &label.name == name {
                 return true;
             }
         }
 
-        for label in self.data_labels.keys() {
-            if label == name {
+        for label in &self.data_labels {
+            if &label.name == name {
                 return true;
             }
         }
 
-        false
+        self.constants.contains_key(name)
     }
 
-    pub fn parse(&mut self) -> ParserResult {
+    pub fn parse(&mut self) -> Result<ParserResult, Vec<Diagnostic>> {
+        let (tokens, diagnostics) = conditionals::filter(&self.tokens, &self.constants);
+        self.tokens = tokens;
+        self.diagnostics.extend(diagnostics);
+
+        self.collect_constants();
+
+        let (tokens, diagnostics) = macros::expand(&self.tokens, &self.constants);
+        self.diagnostics.extend(diagnostics);
+
+        let (tokens, diagnostics) = labels::resolve(&tokens);
+        self.tokens = tokens;
+        self.diagnostics.extend(diagnostics);
+
         while self.current_token_index < self.tokens.len() as u32 {
             // println!("Tokens");
             if self.get_token().is_none() {
@@ -399,80 +499,204 @@ impl Parser {
 
             let token = self.get_token().unwrap();
 
-            match token.0 {
+            let res = match token.0 {
                 Token::Metadata => self.parse_metadata(),
                 Token::InterruptDefine => self.set_interrupt(),
+                Token::Global | Token::Extern => self.parse_linkage(),
+                Token::Align => self.parse_align(),
+                kind if Self::is_data_directive(kind) => Err(Diagnostic::error(
+                    format!("`{}` must follow a data label", token.1),
+                    Some(token.2.clone()),
+                )
+                .with_help("for example `msg: db \"Hi\", 0`")),
+                Token::Constant => {
+                    // Already read by `collect_constants`.
+                    self.skip_line();
+                    Ok(())
+                }
                 Token::Label => match self.current_section {
-                    Sections::Text => self.parse_text_label(),
+                    Sections::Text | Sections::Far => self.parse_text_label(),
                     Sections::Data => self.parse_data_label(),
-                    Sections::None => panic!("No section defined for label {}", token.1),
+                    Sections::None => Err(Diagnostic::error(
+                        format!(
+                            "label `{}` is outside of a section",
+                            token.1.replace(':', "")
+                        ),
+                        Some(token.2.clone()),
+                    )
+                    .with_help("add `.text`, `.far` or `.data` before the label")),
                 },
-                Token::Error => panic!("Unknown symbol \"{}\"", token.1),
-                Token::NewLine => self.current_token_index += 1,
+                Token::Error => Err(Diagnostic::error(
+                    format!("unknown symbol `{}`", token.1),
+                    Some(token.2.clone()),
+                )),
+                Token::NewLine => {
+                    self.current_token_index += 1;
+                    Ok(())
+                }
                 Token::TextSection => {
                     self.current_section = Sections::Text;
-                    self.current_token_index += 1
+                    self.current_token_index += 1;
+                    Ok(())
                 }
                 Token::DataSection => {
                     self.current_section = Sections::Data;
-                    self.current_token_index += 1
+                    self.current_token_index += 1;
+                    Ok(())
+                }
+                Token::FarSection => {
+                    self.current_section = Sections::Far;
+                    self.current_token_index += 1;
+                    Ok(())
                 }
-                _ => panic!("Unknown token \"{:?}\"", token.0),
+                Token::Identifier
+                    if matches!(self.current_section, Sections::Text | Sections::Far) =>
+                {
+                    Err(Diagnostic::error(
+                        format!("instruction `{}` is not inside a label", token.1),
+                        Some(token.2.clone()),
+                    )
+                    .with_help("every instruction must follow a label, such as `start:`"))
+                }
+                _ => Err(Diagnostic::error(
+                    format!("unexpected `{}`", token.1.escape_default()),
+                    Some(token.2.clone()),
+                )
+                .with_help("expected a label, a section or a directive")),
+            };
+
+            if let Err(diagnostic) = res {
+                self.diagnostics.push(diagnostic);
+                self.skip_line();
             }
         }
 
-        ParserResult::new(
+        if let Some((_, span)) = self.pending_align.take() {
+            self.diagnostics.push(Diagnostic::error(
+                "`.align` is not followed by a data label",
+                Some(span),
+            ));
+        }
+
+        Diagnostic::dedup(&mut self.diagnostics);
+
+        if self
+            .diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.is_error())
+        {
+            return Err(self.diagnostics.clone());
+        }
+
+        Ok(ParserResult::new(
             self.metadata.clone(),
             self.text_labels.clone(),
             self.data_labels.clone(),
             self.interrupts.clone(),
-        )
+            self.constants.clone(),
+            self.globals.clone(),
+            self.externs.clone(),
+            self.diagnostics.clone(),
+        ))
     }
 
-    fn set_interrupt(&mut self) {
-        if self.get_token().is_none() {
-            panic!("No tokens left, expecting interrupt definition.");
-        }
+    fn set_interrupt(&mut self) -> Result<(), Diagnostic> {
+        let define_token = self.expect_token("interrupt definition")?;
 
         self.current_token_index += 1;
 
-        if self.get_token().is_none() {
-            panic!("No tokens left, expected Number");
-        }
-
-        let interrupt_number_token = self.get_token().unwrap();
+        let interrupt_number_token = self.expect_token("interrupt number")?;
 
         match interrupt_number_token.0 {
             Token::Number => (),
-            _ => panic!("Expecting number, got {:?}", interrupt_number_token.0),
+            _ => {
+                return Err(Diagnostic::error(
+                    format!(
+                        "expected interrupt number, found `{}`",
+                        interrupt_number_token.1
+                    ),
+                    Some(interrupt_number_token.2),
+                ))
+            }
         }
 
         self.current_token_index += 1;
 
-        if self.get_token().is_none() {
-            panic!("No tokens left, expected Identifier");
-        }
-
-        let label_name_token = self.get_token().unwrap();
+        let label_name_token = self.expect_token("label name")?;
 
         match label_name_token.0 {
             Token::Identifier => (),
-            _ => panic!("Expected Identifier, got {:?}", label_name_token.0),
+            _ => {
+                return Err(Diagnostic::error(
+                    format!("expected label name, found `{}`", label_name_token.1),
+                    Some(label_name_token.2),
+                ))
+            }
         }
 
-        let k = Self::convert_byte_to_base(interrupt_number_token.1);
+        let k = match Self::convert_byte_to_base(&interrupt_number_token.1) {
+            Some(k) if k < 0xFF => k,
+            _ => {
+                return Err(Diagnostic::error(
+                    format!("interrupt `{}` is out of range", interrupt_number_token.1),
+                    Some(interrupt_number_token.2),
+                )
+                .with_help("interrupts must be between 0x00 and 0xFE"))
+            }
+        };
 
-        self.interrupts.insert(k, label_name_token.1);
+        self.interrupts.insert(
+            k,
+            (label_name_token.1, define_token.2.to(&label_name_token.2)),
+        );
 
         self.current_token_index += 1;
+
+        Ok(())
     }
 
-    fn parse_metadata(&mut self) {
-        if self.get_token().is_none() {
-            panic!("No tokens left, expecting metadata.");
+    // Reads `.global` or `.extern` followed by one or more label names.
+    fn parse_linkage(&mut self) -> Result<(), Diagnostic> {
+        let directive = self.expect_token("`.global` or `.extern`")?;
+        self.current_token_index += 1;
+
+        let mut names = Vec::new();
+
+        while let Some(token) = self.get_token() {
+            match token.0 {
+                Token::Identifier => names.push((token.1, token.2)),
+                Token::Comma => (),
+                Token::NewLine => break,
+                _ => {
+                    return Err(Diagnostic::error(
+                        format!("expected label name, found `{}`", token.1),
+                        Some(token.2),
+                    ))
+                }
+            }
+
+            self.current_token_index += 1;
         }
 
-        let token = self.get_token().unwrap();
+        if names.is_empty() {
+            return Err(Diagnostic::error(
+                format!("`{}` expects at least one label name", directive.1),
+                Some(directive.2),
+            ));
+        }
+
+        let table = match directive.0 {
+            Token::Global => &mut self.globals,
+            _ => &mut self.externs,
+        };
+
+        table.extend(names);
+
+        Ok(())
+    }
+
+    fn parse_metadata(&mut self) -> Result<(), Diagnostic> {
+        let token = self.expect_token("metadata")?;
 
         // 0 should be the metadata name, 1 should be the value.
         let metadata_name = token.1.replace('.', "");
@@ -482,49 +706,52 @@ impl Parser {
 
         self.current_token_index += 1;
 
-        if self.get_token().is_none() {
-            panic!("No tokens left, expecting Number or Identifier");
-        }
-
-        let ident_token = self.get_token().unwrap();
+        let ident_token = self.expect_token("number or identifier")?;
+        let span = token.2.to(&ident_token.2);
 
         match ident_token.0 {
             Token::Identifier => {
                 self.metadata
-                    .insert(metadata_name, MetadataValue::String(ident_token.1));
+                    .insert(metadata_name, (MetadataValue::String(ident_token.1), span));
             }
             Token::Number => {
                 self.metadata.insert(
                     metadata_name,
-                    MetadataValue::Number(Self::convert_short_to_base(ident_token.1)),
+                    (
+                        MetadataValue::Number(Self::parse_short(&ident_token)?),
+                        span,
+                    ),
                 );
             }
-            _ => panic!("Expected Number or Identifier, got {:?}", ident_token.0),
+            _ => {
+                return Err(Diagnostic::error(
+                    format!("expected number or identifier, found `{}`", ident_token.1),
+                    Some(ident_token.2),
+                ))
+            }
         }
 
         self.current_token_index += 1;
-    }
 
-    fn parse_text_label(&mut self) {
-        if self.get_token().is_none() {
-            panic!("No tokens left, expecting label.");
-        }
+        Ok(())
+    }
 
-        let label_token = self.get_token().unwrap();
+    fn parse_text_label(&mut self) -> Result<(), Diagnostic> {
+        let label_token = self.expect_token("label")?;
         let label_name = label_token.1.replace(':', "");
 
         if self.label_exists(&label_name) {
-            panic!("Label {} already defined.", label_name);
+            return Err(Diagnostic::error(
+                format!("label `{}` is already defined", label_name),
+                Some(label_token.2),
+            ));
         }
 
         self.current_token_index += 1;
 
-        // println!(
-        //     "Label {} addr 0x{:x}",
-        //     label_name,
-        //     self.label_offset + 0x4402
-        // );
-        let mut label = Label::new(label_name, self.label_offset + 0x4402);
+        let far = self.current_section == Sections::Far;
+        let mut label = Label::new(label_name, far, label_token.2);
+        let mut had_error = false;
 
         loop {
             if self.get_token().is_none() {
@@ -541,243 +768,669 @@ impl Parser {
                 break;
             }
 
-            let instruction = self.make_instruction();
-
-            label.add(instruction);
+            match self.make_instructions() {
+                Ok(instructions) => instructions.into_iter().for_each(|i| label.add(i)),
+                Err(diagnostic) => {
+                    // Keep going so every bad instruction in the label gets reported.
+                    self.diagnostics.push(diagnostic);
+                    self.skip_line();
+                    had_error = true;
+                }
+            }
         }
 
-        self.label_offset += label.len();
-
-        if label.instructions.is_empty() {
-            panic!("Label {} has no body.", label.name);
+        // A label can share its address with the one after it, as in `print:` followed by `.loop:`.
+        if label.instructions.is_empty() && !had_error && !self.next_is_label() {
+            return Err(Diagnostic::error(
+                format!("label `{}` has no body", label.name),
+                Some(label.span),
+            ));
         }
 
         self.text_labels.push(label);
+
+        Ok(())
     }
 
-    fn make_instruction(&mut self) -> ParserInstruction {
-        if self.get_token().is_none() {
-            panic!("No tokens left, expecting instruction.");
+    fn next_is_label(&self) -> bool {
+        matches!(self.get_token(), Some((Token::Label, _, _)))
+    }
+
+    fn scope(&self) -> Scope<'_> {
+        Scope {
+            constants: &self.constants,
+            labels: None,
+            here: None,
         }
+    }
 
-        let instruction_token = self.get_token().unwrap();
-        let opcode = match Opcode::from_str(&instruction_token.1.to_ascii_lowercase()) {
-            Ok(opcode) => opcode,
-            Err(_) => panic!("Unknown instruction: {}", instruction_token.1),
-        };
+    // Constants can be used before they are defined, so they are all read before anything else.
+    fn collect_constants(&mut self) {
+        let mut pos = 0;
 
-        self.current_token_index += 1;
+        while pos < self.tokens.len() {
+            if self.tokens[pos].0 == Token::Constant {
+                if let Err(diagnostic) = self.parse_constant(&mut pos) {
+                    self.diagnostics.push(diagnostic);
+                }
+            }
 
-        if self.get_token().is_none() {
-            // Depends on what the instruction is, we may not need any args.
-            return ParserInstruction::get_instruction(opcode, vec![]);
+            // Carry on from the next line, whether or not the definition was valid.
+            while pos < self.tokens.len() && self.tokens[pos].0 != Token::NewLine {
+                pos += 1;
+            }
+
+            pos += 1;
         }
 
-        let mut args: Vec<InstructionArg> = Vec::new();
+        let mut names: Vec<&String> = self.constants.keys().collect();
+        names.sort();
 
-        let token = self.get_token().unwrap();
+        for name in names {
+            let (expr, span) = &self.constants[name];
+            let mut visiting = vec![name.clone()];
 
-        if token.0 == Token::NewLine {
-            // This instruction has no arguments.
-            return ParserInstruction::get_instruction(opcode, vec![]);
+            let res = expr
+                .kind(&self.scope(), span, &mut visiting)
+                .and_then(|_| expr.evaluate(&self.scope(), span, &mut vec![name.clone()]));
+
+            if let Err(diagnostic) = res {
+                self.diagnostics.push(diagnostic);
+            }
         }
+    }
 
-        if token.0 == Token::Comma {
-            panic!("Expected argument, got comma.");
+    fn parse_constant(&mut self, pos: &mut usize) -> Result<(), Diagnostic> {
+        let directive = self.tokens[*pos].clone();
+        *pos += 1;
+
+        let name = match self.tokens.get(*pos) {
+            Some(token) if token.0 == Token::Identifier => token.clone(),
+            Some(token) if token.0 != Token::NewLine => {
+                return Err(Diagnostic::error(
+                    format!("expected constant name, found `{}`", token.1),
+                    Some(token.2.clone()),
+                ))
+            }
+            _ => {
+                return Err(Diagnostic::error(
+                    format!("`{}` expects a name and a value", directive.1),
+                    Some(directive.2),
+                )
+                .with_help("for example `.equ WIDTH 80`"))
+            }
+        };
+
+        *pos += 1;
+
+        if let Some(token) = self.tokens.get(*pos) {
+            if token.0 == Token::Comma {
+                *pos += 1;
+            }
+        }
+
+        let (expr, span) = expression::parse(&self.tokens, pos)?;
+
+        if self.defines.contains(&name.1) {
+            return Err(Diagnostic::error(
+                format!("constant `{}` is already defined with `-D`", name.1),
+                Some(name.2),
+            )
+            .with_help(format!(
+                "to give it a default value, wrap the definition in `.ifndef {}`",
+                name.1
+            )));
         }
 
+        if let Some((_, previous)) = self.constants.get(&name.1) {
+            return Err(Diagnostic::error(
+                format!(
+                    "constant `{}` is already defined on line {}",
+                    name.1, previous.line
+                ),
+                Some(name.2),
+            ));
+        }
+
+        self.constants.insert(name.1, (expr, span));
+
+        Ok(())
+    }
+
+    fn parse_arg(&mut self) -> Result<(InstructionArg, Span), Diagnostic> {
+        let token = self.expect_token("argument")?;
+
         match token.0 {
             Token::Register => {
-                // We need to take into account that instructions that has two arguments always have a register as the first argument.
-                // So we need to check if the next token is a comma, if it is, then we know that the next token is the second argument.
-
-                let mut chars = token.1.chars();
-                chars.next();
-                let cleaned = chars.as_str();
-
-                let reg_num: u8 = match cleaned.to_lowercase().as_str() {
-                    "1" => 0,
-                    "2" => 1,
-                    "3" => 2,
-                    "4" => 3,
-                    "5" => 4,
-                    "6" => 5,
-                    "pc" => 6,
-                    "sp" => 7,
-                    "bp" => 8,
-                    _ => panic!("Unknown register \"{}\"", cleaned),
-                };
-
                 self.current_token_index += 1;
+                return Ok((
+                    InstructionArg::Register(Self::parse_register(&token)?),
+                    token.2,
+                ));
+            }
+            Token::Error => {
+                return Err(Diagnostic::error(
+                    format!("unknown symbol `{}`", token.1),
+                    Some(token.2),
+                ))
+            }
+            _ => (),
+        }
 
-                args.push(InstructionArg::Register(reg_num));
-
-                if self.peek_token().is_none() {
-                    return ParserInstruction::get_instruction(opcode, args);
+        let mut pos = self.current_token_index as usize;
+
+        let (expr, span) = expression::parse(&self.tokens, &mut pos).map_err(|diagnostic| {
+            diagnostic.with_help("arguments are registers, numbers, $addresses or label names")
+        })?;
+
+        let text: Vec<&str> = self.tokens[self.current_token_index as usize..pos]
+            .iter()
+            .map(|token| token.1.as_str())
+            .collect();
+        let text = text.join(" ");
+
+        self.current_token_index = pos as u32;
+
+        let kind = expr.kind(&self.scope(), &span, &mut Vec::new())?;
+
+        let arg = match (expr.evaluate(&self.scope(), &span, &mut Vec::new())?, kind) {
+            (Some(value), ValueKind::Number) => match value {
+                // Negative numbers are stored as two's complement.
+                -0x8000..=-1 => InstructionArg::Number(value as u16),
+                0..=0xFFFF => InstructionArg::Number(value as u16),
+                _ => {
+                    return Err(Diagnostic::error(
+                        format!("number `{}` does not fit in 16 bits", text),
+                        Some(span),
+                    )
+                    .with_help("numbers must be between 0 and 0xFFFF"))
+                }
+            },
+            (Some(value), ValueKind::Address) => match value {
+                0..=0xFFFFF => InstructionArg::Address(value as u32),
+                _ => {
+                    return Err(Diagnostic::error(
+                        format!("address `{}` does not fit in 20 bits", text),
+                        Some(span),
+                    )
+                    .with_help("addresses must be between $0 and $0xFFFFF"))
+                }
+            },
+            (None, kind) => match expr {
+                Expr::Symbol(name) if !self.constants.contains_key(&name) => {
+                    InstructionArg::Identifier(name)
                 }
+                expr => InstructionArg::Expression(expr, kind),
+            },
+        };
+
+        Ok((arg, span))
+    }
 
-                let sec_arg_test = self.get_token().unwrap();
+    // Reads an instruction, or a pseudo-instruction and the instructions it expands to.
+    fn make_instructions(&mut self) -> Result<Vec<ParserInstruction>, Diagnostic> {
+        let token = self.expect_token("instruction")?;
+        let name = token.1.to_ascii_lowercase();
 
-                // See if we need to store a second argument.
-                // If we don't have a comma, then we don't need to store a second argument.
+        if !pseudo::is_pseudo(&name, &self.tokens[self.current_token_index as usize + 1..]) {
+            return Ok(vec![self.make_instruction()?]);
+        }
+
+        self.current_token_index += 1;
+
+        let operands = self.parse_operands()?;
+        let span = match operands.last() {
+            Some(operand) => token.2.to(operand.span()),
+            None => token.2,
+        };
 
-                if sec_arg_test.0 != Token::Comma {
-                    return ParserInstruction::get_instruction(opcode, args);
+        pseudo::expand(&name, operands, &span)?
+            .into_iter()
+            .map(|(opcode, args)| {
+                let text: Vec<&str> = args.iter().map(|arg| arg.text.as_str()).collect();
+                let mnemonic = format!("{:?}", opcode).to_lowercase();
+                let expansion = format!("{} {}", mnemonic, text.join(", "));
+                let arg_spans = args.iter().map(|arg| arg.span.clone()).collect();
+                let args = args.into_iter().map(|arg| arg.value).collect();
+
+                let mut instruction =
+                    ParserInstruction::get_instruction(opcode, args, span.clone(), arg_spans)?;
+                instruction.expansion = Some(expansion);
+
+                Ok(instruction)
+            })
+            .collect()
+    }
+
+    // Reads any number of comma separated operands, which can include `[r2+4]`.
+    fn parse_operands(&mut self) -> Result<Vec<Operand>, Diagnostic> {
+        let mut operands = Vec::new();
+
+        while let Some(token) = self.get_token() {
+            if token.0 == Token::NewLine {
+                break;
+            }
+
+            if !operands.is_empty() {
+                if token.0 != Token::Comma {
+                    return Err(Diagnostic::error(
+                        format!("expected `,`, found `{}`", token.1),
+                        Some(token.2),
+                    ));
                 }
 
                 self.current_token_index += 1;
 
-                if self.get_token().is_none() {
-                    panic!("Expected second argument, got nothing.");
+                if matches!(self.get_token(), None | Some((Token::NewLine, _, _))) {
+                    return Err(Diagnostic::error(
+                        "expected argument after `,`",
+                        Some(token.2),
+                    ));
                 }
+            }
 
-                let sec_arg = self.get_token().unwrap();
-
-                match sec_arg.0 {
-                    Token::Register => {
-                        let mut chars = sec_arg.1.chars();
-                        chars.next();
-                        let cleaned = chars.as_str();
-
-                        let reg_num: u8 = match cleaned.to_lowercase().as_str() {
-                            "1" => 0,
-                            "2" => 1,
-                            "3" => 2,
-                            "4" => 3,
-                            "5" => 4,
-                            "6" => 5,
-                            "pc" => 6,
-                            "sp" => 7,
-                            "bp" => 8,
-                            _ => panic!("Unknown register \"{}\"", cleaned),
-                        };
-
-                        args.push(InstructionArg::Register(reg_num));
-                        self.current_token_index += 1;
+            let operand = match self.get_token() {
+                Some((Token::LBracket, _, _)) => self.parse_indexed()?,
+                _ => Operand::Plain(self.parse_pseudo_arg()?),
+            };
 
-                        ParserInstruction::get_instruction(opcode, args)
-                    }
-                    Token::Number => {
-                        let value = Self::convert_short_to_base(sec_arg.1);
+            operands.push(operand);
+        }
 
-                        args.push(InstructionArg::Number(value));
-                        self.current_token_index += 1;
+        Ok(operands)
+    }
 
-                        ParserInstruction::get_instruction(opcode, args)
-                    }
-                    Token::Address => {
-                        let cleaned = sec_arg.1.replace('$', "");
-                        let value = Self::convert_int_to_base(cleaned);
+    fn parse_pseudo_arg(&mut self) -> Result<Arg, Diagnostic> {
+        let start = self.current_token_index as usize;
+        let (value, span) = self.parse_arg()?;
 
-                        args.push(InstructionArg::Address(value));
-                        self.current_token_index += 1;
+        let text: Vec<&str> = self.tokens[start..self.current_token_index as usize]
+            .iter()
+            .map(|token| token.1.as_str())
+            .collect();
 
-                        ParserInstruction::get_instruction(opcode, args)
-                    }
-                    Token::Identifier => {
-                        args.push(InstructionArg::Identifier(sec_arg.1));
-                        self.current_token_index += 1;
+        Ok(Arg {
+            value,
+            span,
+            text: text.join(" "),
+        })
+    }
 
-                        ParserInstruction::get_instruction(opcode, args)
-                    }
-                    _ => todo!(),
-                }
-            }
-            Token::Address => {
-                let cleaned = token.1.replace('$', "");
-                let value = Self::convert_int_to_base(cleaned);
+    // Reads `[r2]`, `[r2+4]` or `[r2-4]`.
+    fn parse_indexed(&mut self) -> Result<Operand, Diagnostic> {
+        let open = self.expect_token("`[`")?;
+        self.current_token_index += 1;
+
+        let base = self.parse_pseudo_arg()?;
 
-                args.push(InstructionArg::Address(value));
+        if !matches!(base.value, InstructionArg::Register(_)) {
+            return Err(Diagnostic::error(
+                format!("expected a register after `[`, found `{}`", base.text),
+                Some(base.span),
+            ));
+        }
 
+        let offset = match self.get_token() {
+            Some((sign @ (Token::Plus | Token::Minus), _, _)) => {
                 self.current_token_index += 1;
+                Some((self.parse_pseudo_arg()?, sign == Token::Minus))
+            }
+            _ => None,
+        };
 
-                ParserInstruction::get_instruction(opcode, args)
+        let close = match self.get_token() {
+            Some(token) if token.0 == Token::RBracket => token,
+            Some(token) if token.0 != Token::NewLine => {
+                return Err(Diagnostic::error(
+                    format!("expected `]`, found `{}`", token.1),
+                    Some(token.2),
+                ))
             }
-            Token::Number => {
-                let value = Self::convert_short_to_base(token.1);
+            _ => {
+                return Err(Diagnostic::error(
+                    "`[` is never closed",
+                    Some(open.2.to(&base.span)),
+                ))
+            }
+        };
 
-                args.push(InstructionArg::Number(value));
+        self.current_token_index += 1;
 
-                self.current_token_index += 1;
+        Ok(Operand::Indexed {
+            base,
+            offset,
+            span: open.2.to(&close.2),
+        })
+    }
 
-                ParserInstruction::get_instruction(opcode, args)
+    fn make_instruction(&mut self) -> Result<ParserInstruction, Diagnostic> {
+        let instruction_token = self.expect_token("instruction")?;
+        let opcode = match Opcode::from_str(&instruction_token.1.to_ascii_lowercase()) {
+            Ok(opcode) => opcode,
+            Err(_) => {
+                return Err(Diagnostic::error(
+                    format!("unknown instruction `{}`", instruction_token.1),
+                    Some(instruction_token.2),
+                ))
             }
-            Token::Identifier => {
-                args.push(InstructionArg::Identifier(token.1));
+        };
+
+        self.current_token_index += 1;
+
+        let span = instruction_token.2;
+
+        if self.get_token().is_none() {
+            // Depends on what the instruction is, we may not need any args.
+            return ParserInstruction::get_instruction(opcode, vec![], span, vec![]);
+        }
+
+        let mut args: Vec<InstructionArg> = Vec::new();
+        let mut arg_spans: Vec<Span> = Vec::new();
+
+        let token = self.get_token().unwrap();
+
+        if token.0 == Token::NewLine {
+            // This instruction has no arguments.
+            return ParserInstruction::get_instruction(opcode, vec![], span, vec![]);
+        }
+
+        if token.0 == Token::Comma {
+            return Err(Diagnostic::error(
+                "expected argument, found `,`",
+                Some(token.2),
+            ));
+        }
+
+        let (arg, arg_span) = self.parse_arg()?;
+        args.push(arg);
+        arg_spans.push(arg_span);
+
+        // Instructions that have two arguments always have a register as the first argument,
+        // but that is checked by `get_instruction` so the error points at the right argument.
+        // If the next token is a comma, we know that the token after it is the second argument.
+        if let Some(sec_arg_test) = self.get_token() {
+            if sec_arg_test.0 == Token::Comma {
                 self.current_token_index += 1;
 
-                ParserInstruction::get_instruction(opcode, args)
+                let sec_arg = match self.get_token() {
+                    Some(sec_arg) if sec_arg.0 != Token::NewLine => sec_arg,
+                    _ => {
+                        return Err(Diagnostic::error(
+                            "expected second argument after `,`",
+                            Some(sec_arg_test.2),
+                        ))
+                    }
+                };
+
+                if sec_arg.0 == Token::Comma {
+                    return Err(Diagnostic::error(
+                        "expected argument, found `,`",
+                        Some(sec_arg.2),
+                    ));
+                }
+
+                let (arg, arg_span) = self.parse_arg()?;
+                args.push(arg);
+                arg_spans.push(arg_span);
             }
-            Token::Error => panic!("Error token found: {:?}", token.0),
-            _ => panic!("Expected argument, got {:?}", token.0),
         }
+
+        let span = span.to(arg_spans.last().unwrap());
+
+        ParserInstruction::get_instruction(opcode, args, span, arg_spans)
     }
 
-    fn parse_data_label(&mut self) {
-        if self.get_token().is_none() {
-            panic!("No tokens left, expecting label.");
-        }
+    fn is_data_directive(token: Token) -> bool {
+        matches!(
+            token,
+            Token::DefineByte
+                | Token::DefineWord
+                | Token::DefineDouble
+                | Token::ReserveByte
+                | Token::ReserveWord
+                | Token::Times
+                | Token::Asciz
+        )
+    }
 
-        let label_token = self.get_token().unwrap();
+    fn parse_data_label(&mut self) -> Result<(), Diagnostic> {
+        let label_token = self.expect_token("label")?;
         let label_name = label_token.1.replace(':', "");
 
         if self.label_exists(&label_name) {
-            panic!("Label {} already defined.", label_name);
+            return Err(Diagnostic::error(
+                format!("label `{}` is already defined", label_name),
+                Some(label_token.2),
+            ));
         }
 
         self.current_token_index += 1;
 
-        println!("Data label is {}", label_name);
+        let mut label = DataLabel {
+            name: label_name,
+            lines: Vec::new(),
+            align: self.pending_align.take().map_or(1, |(align, _)| align),
+            span: label_token.2,
+        };
+        let mut had_error = false;
 
-        if self.get_token().is_none() {
-            panic!("No tokens left, expecting DefineByte.")
+        while let Some(token) = self.get_token() {
+            if token.0 == Token::NewLine {
+                self.current_token_index += 1;
+                continue;
+            } else if !Self::is_data_directive(token.0) {
+                break;
+            }
+
+            match self.parse_data_line() {
+                Ok(line) => label.lines.push(line),
+                Err(diagnostic) => {
+                    self.diagnostics.push(diagnostic);
+                    self.skip_line();
+                    had_error = true;
+                }
+            }
         }
 
-        let def_byte = self.get_token().unwrap();
+        if label.lines.is_empty() && !had_error && !self.next_is_label() {
+            return Err(Diagnostic::error(
+                format!("data label `{}` has no data", label.name),
+                Some(label.span),
+            )
+            .with_help("for example `msg: db \"Hi\", 0`"));
+        }
+
+        self.data_labels.push(label);
+
+        Ok(())
+    }
+
+    // Reads a count for `times`, `resb`, `resw` or `.align`, which has to be known right away.
+    fn parse_count(&mut self, directive: &TokenInfoType) -> Result<usize, Diagnostic> {
+        if matches!(self.get_token(), None | Some((Token::NewLine, _, _))) {
+            return Err(Diagnostic::error(
+                format!("`{}` expects a count", directive.1),
+                Some(directive.2.clone()),
+            ));
+        }
 
-        if def_byte.0 != Token::DefineByte {
-            panic!("Found {:?}, expecting DefineByte", def_byte.0);
+        let mut pos = self.current_token_index as usize;
+        let (expr, span) = expression::parse(&self.tokens, &mut pos)?;
+        self.current_token_index = pos as u32;
+
+        match expr.evaluate(&self.scope(), &span, &mut Vec::new())? {
+            Some(count) if (0..=0xFFFF).contains(&count) => Ok(count as usize),
+            Some(count) => Err(Diagnostic::error(
+                format!("`{}` count {} is out of range", directive.1, count),
+                Some(span),
+            )
+            .with_help("the count must be between 0 and 65535")),
+            None => Err(Diagnostic::error(
+                format!("the `{}` count can't depend on a label or `$`", directive.1),
+                Some(span),
+            )),
         }
+    }
 
+    fn parse_align(&mut self) -> Result<(), Diagnostic> {
+        let token = self.expect_token("`.align`")?;
         self.current_token_index += 1;
 
-        let mut data: Vec<DefineByteData> = Vec::new();
+        if self.current_section != Sections::Data {
+            return Err(Diagnostic::error(
+                "`.align` can only be used in the data section",
+                Some(token.2),
+            ));
+        }
 
-        let mut offset = 0;
+        let align = self.parse_count(&token)?;
 
-        loop {
-            if self.get_token().is_none() {
-                break;
+        if !align.is_power_of_two() {
+            return Err(Diagnostic::error(
+                format!("cannot align to {} bytes", align),
+                Some(token.2),
+            )
+            .with_help("the alignment must be a power of two, like 2 or 16"));
+        }
+
+        self.expect_end_of_line()?;
+        self.pending_align = Some((align as u32, token.2));
+
+        Ok(())
+    }
+
+    fn expect_end_of_line(&mut self) -> Result<(), Diagnostic> {
+        match self.get_token() {
+            Some(token) if token.0 != Token::NewLine => Err(Diagnostic::error(
+                format!("unexpected `{}`", token.1.escape_default()),
+                Some(token.2),
+            )
+            .with_help("values are separated by commas")),
+            _ => Ok(()),
+        }
+    }
+
+    /*
+    Reads a line of data:
+
+    db "Hi\n", 0       bytes, strings or characters
+    dw 1, table        16-bit words
+    dd far_label       20-bit addresses, as three bytes
+    .asciz "Hi"        strings, each followed by a zero byte
+    resb 16            zero filled bytes, `resw` for words
+    times 4 dw 0       any of the above, repeated
+     */
+    fn parse_data_line(&mut self) -> Result<DataLine, Diagnostic> {
+        let first = self.expect_token("data")?;
+        let mut directive = first.clone();
+        let mut repeat = 1;
+
+        if directive.0 == Token::Times {
+            self.current_token_index += 1;
+            repeat = self.parse_count(&directive)?;
+            directive = self.expect_token("data directive after the `times` count")?;
+
+            if !Self::is_data_directive(directive.0) || directive.0 == Token::Times {
+                return Err(Diagnostic::error(
+                    format!(
+                        "expected a data directive, found `{}`",
+                        directive.1.escape_default()
+                    ),
+                    Some(directive.2),
+                )
+                .with_help("for example `times 4 db 0`"));
             }
+        }
 
-            let token = self.get_token().unwrap();
+        self.current_token_index += 1;
 
-            match token.0 {
-                Token::String => {
-                    let byte_data = DefineByteData::String(token.1.replace('"', ""), offset);
-                    data.push(byte_data.clone());
-                    offset += byte_data.len();
-                }
-                Token::Number => {
-                    let num = Parser::convert_short_to_base(token.1);
-                    if num > 255 {
-                        // Num is a short
-                        data.push(DefineByteData::Short(num, offset));
-                        offset += 2;
-                    } else {
-                        // Num is a byte
-                        data.push(DefineByteData::Byte(num as u8, offset));
-                        offset += 1;
+        let mut values = Vec::new();
+
+        match directive.0 {
+            Token::ReserveByte | Token::ReserveWord => {
+                let size = if directive.0 == Token::ReserveWord {
+                    2
+                } else {
+                    1
+                };
+                values.push(DataValue::Bytes(vec![
+                    0;
+                    self.parse_count(&directive)? * size
+                ]));
+            }
+            _ => loop {
+                values.push(self.parse_data_value(&directive)?);
+
+                match self.get_token() {
+                    Some(comma) if comma.0 == Token::Comma => {
+                        self.current_token_index += 1;
+
+                        if matches!(self.get_token(), None | Some((Token::NewLine, _, _))) {
+                            return Err(Diagnostic::error(
+                                "expected a value after `,`",
+                                Some(comma.2),
+                            ));
+                        }
                     }
+                    _ => break,
+                }
+            },
+        }
+
+        self.expect_end_of_line()?;
+
+        let last = &self.tokens[self.current_token_index as usize - 1];
+        let span = first.2.to(&last.2);
+        let values: Vec<DataValue> = (0..repeat).flat_map(|_| values.clone()).collect();
+
+        if values.iter().map(DataValue::len).sum::<usize>() == 0 {
+            self.diagnostics.push(Diagnostic::warning(
+                "this line doesn't add any data",
+                Some(span.clone()),
+            ));
+        }
+
+        Ok(DataLine { values, span })
+    }
+
+    fn parse_data_value(&mut self, directive: &TokenInfoType) -> Result<DataValue, Diagnostic> {
+        let token = self.expect_token("value")?;
+
+        if token.0 == Token::String {
+            let mut bytes = tokenizer::unescape(&token)?;
+
+            match directive.0 {
+                Token::DefineByte => (),
+                Token::Asciz => bytes.push(0),
+                _ => {
+                    return Err(Diagnostic::error(
+                        format!("`{}` can't hold a string", directive.1),
+                        Some(token.2),
+                    )
+                    .with_help("strings can only be used with `db` and `.asciz`"))
                 }
-                Token::Comma => (),
-                Token::NewLine => break,
-                _ => panic!("Cannot turn token {:?} into bytes", token.0),
             }
 
             self.current_token_index += 1;
+            return Ok(DataValue::Bytes(bytes));
         }
 
-        self.data_labels.insert(label_name, data);
+        if directive.0 == Token::Asciz {
+            return Err(Diagnostic::error(
+                format!("expected a string, found `{}`", token.1.escape_default()),
+                Some(token.2),
+            )
+            .with_help("use `db` for numbers"));
+        }
+
+        let mut pos = self.current_token_index as usize;
+        let (expr, span) = expression::parse(&self.tokens, &mut pos)?;
+        self.current_token_index = pos as u32;
+
+        Ok(match directive.0 {
+            Token::DefineWord => DataValue::Word(expr, span),
+            Token::DefineDouble => DataValue::Double(expr, span),
+            _ => DataValue::Byte(expr, span),
+        })
     }
 }
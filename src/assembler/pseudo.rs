@@ -0,0 +1,236 @@
+use super::diagnostic::{Diagnostic, Span};
+use super::parser::InstructionArg;
+use super::tokenizer::{Token, TokenInfoType};
+use crate::common::instruction::opcode::Opcode;
+
+/*
+Pseudo-instructions the assembler expands into real ones:
+
+inc r1              add r1, 1
+dec r1              sub r1, 1
+not r1              xor r1, 0xFFFF
+neg r1              xor r1, 0xFFFF
+                    add r1, 1
+clr r1              mov r1, 0
+call print          jsr print
+la r1, msg          mov r1, msg
+push r1, r2         psh r1
+                    psh r2
+pop r1, r2          pop r2
+                    pop r1
+ld r1, [r2+4]       mov r6, r2
+                    add r6, 4
+                    ld r1, r6
+
+`pop` takes the registers in the same order as `push`, so the same list restores them. `ld` works
+out the address in r6, the scratch register, so the base is left alone but r6 and the flags are
+overwritten. That also means r6 can't be the base when there's an offset.
+ */
+const NAMES: [&str; 8] = ["inc", "dec", "not", "neg", "clr", "call", "la", "push"];
+
+// r6, which `ld` is free to overwrite.
+pub const SCRATCH: u8 = 5;
+
+#[derive(Debug, Clone)]
+pub struct Arg {
+    pub value: InstructionArg,
+    pub span: Span,
+    // As written, for listings.
+    pub text: String,
+}
+
+impl Arg {
+    fn number(value: u16, span: &Span) -> Arg {
+        let text = if value > 9 {
+            format!("0x{:X}", value)
+        } else {
+            value.to_string()
+        };
+
+        Arg {
+            value: InstructionArg::Number(value),
+            span: span.clone(),
+            text,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Operand {
+    Plain(Arg),
+    // `[r2+4]`, the offset is subtracted when the flag is set.
+    Indexed {
+        base: Arg,
+        offset: Option<(Arg, bool)>,
+        span: Span,
+    },
+}
+
+impl Operand {
+    pub fn span(&self) -> &Span {
+        match self {
+            Operand::Plain(arg) => &arg.span,
+            Operand::Indexed { span, .. } => span,
+        }
+    }
+}
+
+// `pop` and `ld` are real instructions, unless they are given several registers or `[...]`.
+pub fn is_pseudo(name: &str, line: &[TokenInfoType]) -> bool {
+    let mut line = line.iter().take_while(|token| token.0 != Token::NewLine);
+
+    match name {
+        "pop" => line.any(|token| token.0 == Token::Comma),
+        "ld" => line.any(|token| token.0 == Token::LBracket),
+        name => NAMES.contains(&name),
+    }
+}
+
+pub fn expand(
+    name: &str,
+    operands: Vec<Operand>,
+    span: &Span,
+) -> Result<Vec<(Opcode, Vec<Arg>)>, Diagnostic> {
+    if name == "push" || name == "pop" {
+        if operands.is_empty() {
+            return Err(Diagnostic::error(
+                format!("`{}` expects at least one argument", name),
+                Some(span.clone()),
+            ));
+        }
+
+        let mut args = operands
+            .into_iter()
+            .map(|operand| plain(name, operand))
+            .collect::<Result<Vec<Arg>, Diagnostic>>()?;
+
+        let opcode = if name == "push" {
+            Opcode::PSH
+        } else {
+            args.reverse();
+            Opcode::POP
+        };
+
+        return Ok(args.into_iter().map(|arg| (opcode, vec![arg])).collect());
+    }
+
+    let expected = match name {
+        "la" | "ld" => 2,
+        _ => 1,
+    };
+
+    if operands.len() != expected {
+        return Err(Diagnostic::error(
+            format!(
+                "`{}` takes {} argument(s) but {} were given",
+                name,
+                expected,
+                operands.len()
+            ),
+            Some(span.clone()),
+        ));
+    }
+
+    let mut operands = operands.into_iter();
+    let first = operands.next().unwrap();
+
+    if name == "call" {
+        return Ok(vec![(Opcode::JSR, vec![plain(name, first)?])]);
+    }
+
+    let reg = register(name, first)?;
+    let one = Arg::number(1, span);
+    let ones = Arg::number(0xFFFF, span);
+
+    let expansion = match name {
+        "inc" => vec![(Opcode::ADD, vec![reg, one])],
+        "dec" => vec![(Opcode::SUB, vec![reg, one])],
+        "not" => vec![(Opcode::XOR, vec![reg, ones])],
+        "neg" => vec![
+            (Opcode::XOR, vec![reg.clone(), ones]),
+            (Opcode::ADD, vec![reg, one]),
+        ],
+        "clr" => vec![(Opcode::MOV, vec![reg, Arg::number(0, span)])],
+        "la" => {
+            let addr = plain(name, operands.next().unwrap())?;
+
+            match addr.value {
+                InstructionArg::Address(_)
+                | InstructionArg::Identifier(_)
+                | InstructionArg::Expression(_, _) => (),
+                _ => {
+                    return Err(Diagnostic::error(
+                        format!("`la` expects a label or an $address, found `{}`", addr.text),
+                        Some(addr.span),
+                    ))
+                }
+            }
+
+            vec![(Opcode::MOV, vec![reg, addr])]
+        }
+        _ => return load(reg, operands.next().unwrap()),
+    };
+
+    Ok(expansion)
+}
+
+fn load(dest: Arg, operand: Operand) -> Result<Vec<(Opcode, Vec<Arg>)>, Diagnostic> {
+    let (base, offset) = match operand {
+        Operand::Indexed { base, offset, .. } => (base, offset),
+        Operand::Plain(arg) => {
+            return Err(Diagnostic::error(
+                format!("expected `[register+offset]`, found `{}`", arg.text),
+                Some(arg.span),
+            ))
+        }
+    };
+
+    let (offset, negative) = match offset {
+        Some(offset) => offset,
+        None => return Ok(vec![(Opcode::LD, vec![dest, base])]),
+    };
+
+    if matches!(base.value, InstructionArg::Register(SCRATCH)) {
+        return Err(Diagnostic::error(
+            "r6 can't be the base of `[...]` with an offset",
+            Some(base.span),
+        )
+        .with_help("`ld` works out the address in r6, copy the base to another register first"));
+    }
+
+    let scratch = Arg {
+        value: InstructionArg::Register(SCRATCH),
+        span: base.span.clone(),
+        text: String::from("r6"),
+    };
+    let add = if negative { Opcode::SUB } else { Opcode::ADD };
+
+    Ok(vec![
+        (Opcode::MOV, vec![scratch.clone(), base]),
+        (add, vec![scratch.clone(), offset]),
+        (Opcode::LD, vec![dest, scratch]),
+    ])
+}
+
+fn plain(name: &str, operand: Operand) -> Result<Arg, Diagnostic> {
+    match operand {
+        Operand::Plain(arg) => Ok(arg),
+        operand => Err(Diagnostic::error(
+            format!("`{}` can't read from `[...]`", name),
+            Some(operand.span().clone()),
+        )
+        .with_help("only `ld` accepts a register and an offset")),
+    }
+}
+
+fn register(name: &str, operand: Operand) -> Result<Arg, Diagnostic> {
+    let arg = plain(name, operand)?;
+
+    match arg.value {
+        InstructionArg::Register(_) => Ok(arg),
+        _ => Err(Diagnostic::error(
+            format!("`{}` expects a register, found `{}`", name, arg.text),
+            Some(arg.span),
+        )),
+    }
+}
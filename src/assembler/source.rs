@@ -0,0 +1,218 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::conditionals::Conditions;
+use super::diagnostic::Diagnostic;
+use super::expression::{self, Constants};
+use super::tokenizer::{tokenize, Token, TokenInfoType};
+
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub name: String,
+    pub content: String,
+}
+
+// Every file that went into a program. Spans refer to files by their index in `files`, and the
+// file being assembled is always the first one.
+#[derive(Debug, Default, Clone)]
+pub struct SourceMap {
+    pub files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap::default()
+    }
+
+    pub fn add(&mut self, name: &str, content: &str) -> usize {
+        self.files.push(SourceFile {
+            name: name.to_string(),
+            content: content.to_string(),
+        });
+
+        self.files.len() - 1
+    }
+
+    pub fn name(&self, file: usize) -> &str {
+        self.files.get(file).map_or("", |file| &file.name)
+    }
+
+    pub fn content(&self, file: usize) -> &str {
+        self.files.get(file).map_or("", |file| &file.content)
+    }
+}
+
+// Reads a file with every line ending in a single `\n`, whatever the file used.
+pub fn read_source(path: &Path) -> io::Result<String> {
+    let content = fs::read_to_string(path)?;
+
+    Ok(content.lines().map(|line| format!("{}\n", line)).collect())
+}
+
+struct Loader<'a> {
+    sources: SourceMap,
+    diagnostics: Vec<Diagnostic>,
+    include_dirs: &'a [PathBuf],
+    // Canonical paths of every file read so far, so each one is only included once.
+    included: HashSet<PathBuf>,
+    // The chain of files currently being read, with the name each one is shown as.
+    stack: Vec<(PathBuf, String)>,
+    // Follows the `.if` blocks so includes they leave out aren't read. Their problems are
+    // reported by the parser, which filters the blocks for real.
+    conditions: Conditions,
+}
+
+/*
+Reads `root` and every file it includes into a single token stream. `.include "file"` is looked
+up next to the file that includes it first, then in each of `include_dirs` in order. A file that
+was already included is skipped, and a file that ends up including itself is an error. Includes
+inside `.if` blocks that are left out are never looked up, so `defines` has to hold the constants
+the parser is given with `-D`.
+
+Only failing to read `root` is an `Err`, problems with includes are returned as diagnostics.
+ */
+pub fn load(
+    root: &Path,
+    include_dirs: &[PathBuf],
+    defines: &[(String, i64)],
+) -> io::Result<(SourceMap, Vec<TokenInfoType>, Vec<Diagnostic>)> {
+    let content = read_source(root)?;
+
+    let defines: Constants = defines
+        .iter()
+        .map(|(name, value)| (name.clone(), expression::defined(*value)))
+        .collect();
+
+    let mut loader = Loader {
+        sources: SourceMap::new(),
+        diagnostics: Vec::new(),
+        include_dirs,
+        included: HashSet::new(),
+        stack: Vec::new(),
+        conditions: Conditions::new(&defines),
+    };
+
+    let tokens = loader.load_file(root, &content);
+
+    Ok((loader.sources, tokens, loader.diagnostics))
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+impl Loader<'_> {
+    fn load_file(&mut self, path: &Path, content: &str) -> Vec<TokenInfoType> {
+        let name = path.display().to_string();
+        let canonical = canonical(path);
+
+        self.included.insert(canonical.clone());
+        self.stack.push((canonical, name.clone()));
+
+        let file = self.sources.add(&name, content);
+        let tokens = tokenize(content, file);
+        let mut out = Vec::new();
+
+        for line in tokens.split_inclusive(|token| token.0 == Token::NewLine) {
+            let kept = self.conditions.keep(line);
+
+            if line[0].0 != Token::Include || !kept {
+                out.extend_from_slice(line);
+                continue;
+            }
+
+            match self.include(line, path) {
+                Ok(tokens) => out.extend(tokens),
+                Err(diagnostic) => self.diagnostics.push(diagnostic),
+            }
+        }
+
+        self.stack.pop();
+
+        out
+    }
+
+    fn resolve(&self, name: &str, from: &Path) -> Option<PathBuf> {
+        let relative = from.parent().unwrap_or_else(|| Path::new("")).join(name);
+
+        std::iter::once(relative)
+            .chain(self.include_dirs.iter().map(|dir| dir.join(name)))
+            .find(|path| path.is_file())
+    }
+
+    fn include(
+        &mut self,
+        line: &[TokenInfoType],
+        from: &Path,
+    ) -> Result<Vec<TokenInfoType>, Diagnostic> {
+        let token = match line.get(1) {
+            Some(token) if token.0 == Token::String => token,
+            _ => {
+                return Err(Diagnostic::error(
+                    "`.include` expects a file name in quotes",
+                    Some(line[0].2.clone()),
+                )
+                .with_help("for example `.include \"print.yuasm\"`"))
+            }
+        };
+
+        if let Some(extra) = line.get(2).filter(|token| token.0 != Token::NewLine) {
+            return Err(Diagnostic::error(
+                format!("unexpected `{}` after the file name", extra.1),
+                Some(extra.2.clone()),
+            ));
+        }
+
+        let name = &token.1[1..token.1.len() - 1];
+
+        let path = match self.resolve(name, from) {
+            Some(path) => path,
+            None => {
+                let dir = from.parent().map(|dir| dir.display().to_string());
+                let mut searched = vec![dir.filter(|dir| !dir.is_empty()).unwrap_or(".".into())];
+                searched.extend(
+                    self.include_dirs
+                        .iter()
+                        .map(|dir| dir.display().to_string()),
+                );
+
+                return Err(Diagnostic::error(
+                    format!("cannot find `{}` to include", name),
+                    Some(token.2.clone()),
+                )
+                .with_help(format!("searched in {}", searched.join(", "))));
+            }
+        };
+
+        let canonical = canonical(&path);
+
+        if let Some(start) = self.stack.iter().position(|(file, _)| *file == canonical) {
+            let mut cycle: Vec<&str> = self.stack[start..]
+                .iter()
+                .map(|(_, name)| name.as_str())
+                .collect();
+            cycle.push(&self.stack[start].1);
+
+            return Err(Diagnostic::error(
+                format!("`{}` includes itself", self.stack[start].1),
+                Some(token.2.clone()),
+            )
+            .with_help(format!("the includes form a cycle: {}", cycle.join(" -> "))));
+        }
+
+        // Every file is included once, however many files ask for it.
+        if self.included.contains(&canonical) {
+            return Ok(Vec::new());
+        }
+
+        match read_source(&path) {
+            Ok(content) => Ok(self.load_file(&path, &content)),
+            Err(error) => Err(Diagnostic::error(
+                format!("cannot read `{}`: {}", path.display(), error),
+                Some(token.2.clone()),
+            )),
+        }
+    }
+}
@@ -0,0 +1,900 @@
+use std::{env, fs, path::PathBuf, process};
+
+use crate::common::{
+    executable::{Executable, SectionKind},
+    symbols::SymbolTable,
+};
+
+use super::diagnostic::{Diagnostic, Severity};
+use super::parser::Parser;
+use super::source::{self, SourceMap};
+use super::tokenizer::tokenize;
+use super::Assembler;
+
+fn sources(source: &str) -> SourceMap {
+    let mut sources = SourceMap::new();
+    sources.add("test.yuasm", source);
+    sources
+}
+
+fn assemble(source: &str) -> Result<Executable, Vec<Diagnostic>> {
+    let mut parser = Parser::new(tokenize(source, 0));
+    let parser_res = parser.parse()?;
+
+    Assembler::new(parser_res, &sources(source))
+        .assemble()
+        .map(|(executable, _)| executable)
+}
+
+#[test]
+fn test_assemble_ok() {
+    let executable = assemble(".main start\n.text\nstart:\n    mov r1, 5\n    hlt\n").unwrap();
+    let text = executable.section(SectionKind::Text).unwrap();
+
+    assert_eq!(executable.entry, 0x4402);
+    assert_eq!(text.load, 0x4402);
+    assert_eq!(text.bytes, vec![0x00, 0x00, 0x05, 0xFE, 0x0C]);
+    assert_eq!(
+        executable.section(SectionKind::Ivt).unwrap().bytes.len(),
+        510
+    );
+    assert_eq!(
+        Executable::parse(&executable.to_bytes()).unwrap(),
+        executable
+    );
+}
+
+#[test]
+fn test_span_line_and_column() {
+    let diagnostics = assemble(".main start\n.text\nstart:\n    mvo r1, 5\n").unwrap_err();
+    let span = diagnostics[0].span.clone().unwrap();
+
+    assert_eq!(diagnostics[0].message, "unknown instruction `mvo`");
+    assert_eq!(span.line, 4);
+    assert_eq!(span.column, 5);
+    assert_eq!(span.range, 29..32);
+}
+
+#[test]
+fn test_collects_multiple_errors() {
+    let diagnostics =
+        assemble(".main start\n.text\nstart:\n    mvo r1, 5\n    mov 5, r1\n    hlt\n")
+            .unwrap_err();
+
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[1].span.clone().unwrap().line, 5);
+}
+
+#[test]
+fn test_unknown_label() {
+    let diagnostics = assemble(".main start\n.text\nstart:\n    jmp nowhere\n").unwrap_err();
+
+    assert_eq!(diagnostics[0].message, "no label named `nowhere`");
+    assert_eq!(diagnostics[0].span.clone().unwrap().column, 9);
+}
+
+#[test]
+fn test_missing_main() {
+    let diagnostics = assemble(".text\nstart:\n    hlt\n").unwrap_err();
+
+    assert_eq!(diagnostics[0].message, "no main label defined");
+    assert!(diagnostics[0].span.is_none());
+}
+
+#[test]
+fn test_data_warning() {
+    let mut parser = Parser::new(tokenize(".data\nvalue: resb 0\n", 0));
+    let parser_res = parser.parse().unwrap();
+
+    assert_eq!(parser_res.warnings.len(), 1);
+    assert_eq!(parser_res.warnings[0].severity, Severity::Warning);
+}
+
+#[test]
+fn test_render() {
+    colored::control::set_override(false);
+
+    let source = ".text\nstart:\n\tmvo r1, 5\n";
+    let diagnostics = assemble(source).unwrap_err();
+
+    assert_eq!(
+        diagnostics[0].render(&sources(source)),
+        "error: unknown instruction `mvo`\n --> test.yuasm:3:2\n  |\n3 | \tmvo r1, 5\n  | \t^^^\n"
+    );
+}
+
+#[test]
+fn test_symbols() {
+    let executable = assemble(
+        ".main start\n.data\nmsg: db \"Hi\"\n.text\nstart:\n    mov r1, 5\nend:\n    hlt\n",
+    )
+    .unwrap();
+
+    let section = executable.section(SectionKind::Symbols).unwrap();
+    let symbols = SymbolTable::from_bytes(&section.bytes).unwrap();
+
+    assert_eq!(symbols.get("msg"), Some(0x4402));
+    assert_eq!(symbols.get("start"), Some(0x4404));
+    assert_eq!(symbols.get("end"), Some(0x4407));
+    assert_eq!(symbols.line(0x4404).unwrap().to_string(), "test.yuasm:6");
+    assert_eq!(symbols.line(0x4407).unwrap().to_string(), "test.yuasm:8");
+}
+
+#[test]
+fn test_listing() {
+    let source =
+        ".main start\n.data\nmsg: db \"Hello\", 0\n.text\nstart:\n    mov r1, msg\n    hlt\n";
+    let mut parser = Parser::new(tokenize(source, 0));
+    let (_, listing) = Assembler::new(parser.parse().unwrap(), &sources(source))
+        .assemble()
+        .unwrap();
+
+    let output = listing.render(&sources(source));
+
+    assert!(
+        output.contains("\n    3  04402  48 65 6C 6C 6F  msg: db \"Hello\", 0\n       04407  00\n")
+    );
+    assert!(output.contains("\n    6  04408  80 04 44 02         mov r1, msg\n"));
+    assert!(output.contains("\n    5                         start:\n"));
+    assert!(output.contains("\nSymbols\n  04402  msg\n  04408  start\n"));
+}
+
+fn text(source: &str) -> Vec<u8> {
+    assemble(source)
+        .unwrap()
+        .section(SectionKind::Text)
+        .unwrap()
+        .bytes
+        .clone()
+}
+
+#[test]
+fn test_constants() {
+    let bytes = text(
+        ".main start\n.equ WIDTH 80\n.define VGA 0xA000\n.equ CELL VGA + WIDTH * 2\n.equ CURSOR $0x401\n\
+         .text\nstart:\n    mov r1, WIDTH\n    mov r2, CELL\n    stl r1, CURSOR + 1\n",
+    );
+
+    assert_eq!(
+        bytes,
+        vec![0x00, 0x00, 0x50, 0x00, 0x14, 0xA0, 0xA0, 0x86, 0x04, 0x04, 0x02]
+    );
+}
+
+#[test]
+fn test_operator_precedence() {
+    let bytes = text(
+        ".main start\n.text\nstart:\n    mov r1, 2 + 3 * 4\n    mov r1, (2 + 3) * 4\n    mov r1, 1 << 4 | 1\n    mov r1, ~0 & 0xF0 >> 4\n    mov r1, -1\n",
+    );
+
+    assert_eq!(bytes[2], 14);
+    assert_eq!(bytes[5], 20);
+    assert_eq!(bytes[8], 17);
+    assert_eq!(bytes[11], 0x0F);
+    assert_eq!(&bytes[12..16], &[0x00, 0x04, 0xFF, 0xFF]);
+
+    let bytes = text(
+        ".main start\n.text\nstart:\n    mov r1, 1 + 1 == 2\n    mov r1, 2 < 1 | 3 >= 3 & 4 != 4\n    mov r1, 1 << 2 > 3\n",
+    );
+
+    assert_eq!(bytes[2], 1);
+    assert_eq!(bytes[5], 0);
+    assert_eq!(bytes[8], 1);
+}
+
+#[test]
+fn test_label_math() {
+    // `end - start` is a number, `$ + 8` and `start + 4` are addresses.
+    let bytes = text(
+        ".main start\n.text\nstart:\n    mov r1, end - start\n    jmp $ + 8\n    jmp start + 4\nend:\n    hlt\n",
+    );
+
+    assert_eq!(&bytes[..4], &[0x00, 0x04, 0x00, 0x0C]);
+    assert_eq!(&bytes[4..8], &[0x8E, 0x04, 0x44, 0x0E]);
+    assert_eq!(&bytes[8..12], &[0x8E, 0x04, 0x44, 0x06]);
+}
+
+#[test]
+fn test_constant_errors() {
+    // A cycle and a bad definition are each reported once, at a definition, not at every use.
+    let diagnostics = assemble(
+        ".main start\n.equ B A\n.equ A B + 1\n.equ X 1 / 0\n.equ Y X + 1\n.text\nstart:\n\
+         \x20   mov r1, A\n    mov r2, X\n    mov r3, Y\n    hlt\n",
+    )
+    .unwrap_err();
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(
+        diagnostics[0].message,
+        "constant `A` is defined in terms of itself"
+    );
+    assert_eq!(diagnostics[0].span.clone().unwrap().line, 3);
+    assert_eq!(diagnostics[1].message, "division by zero in expression");
+    assert_eq!(diagnostics[1].span.clone().unwrap().line, 4);
+
+    let diagnostics =
+        assemble(".main start\n.text\nstart:\n    mov r1, nowhere + 1\n").unwrap_err();
+    assert_eq!(
+        diagnostics[0].message,
+        "no label or constant named `nowhere`"
+    );
+    assert_eq!(diagnostics[0].span.clone().unwrap().column, 13);
+
+    let diagnostics =
+        assemble(".main start\n.text\nstart:\n    mov r1, 4 / (2 - 2)\n").unwrap_err();
+    assert_eq!(diagnostics[0].message, "division by zero in expression");
+
+    let diagnostics = assemble(".main start\n.text\nstart:\n    mov r1, 0xFFFF + 1\n").unwrap_err();
+    assert_eq!(
+        diagnostics[0].message,
+        "number `0xFFFF + 1` does not fit in 16 bits"
+    );
+
+    let diagnostics =
+        assemble(".main start\n.equ A 1\n.equ A 2\n.text\nstart:\n    hlt\n").unwrap_err();
+    assert_eq!(
+        diagnostics[0].message,
+        "constant `A` is already defined on line 2"
+    );
+}
+
+// The bytes from `label` to the end of the data section.
+fn data_at(executable: &Executable, label: &str) -> Vec<u8> {
+    let data = executable.section(SectionKind::Data).unwrap();
+    let symbols =
+        SymbolTable::from_bytes(&executable.section(SectionKind::Symbols).unwrap().bytes).unwrap();
+
+    data.bytes[(symbols.get(label).unwrap() - data.load) as usize..].to_vec()
+}
+
+#[test]
+fn test_data_directives() {
+    let executable = assemble(
+        ".main start\n.equ COUNT 3\n.data\nmsg: .asciz \"Hi\\n\"\n.align 4\ntable:\n\
+         \x20   db 'A', '\\x42', \"\\\"\\\\\", -1\n    dw 0x1234, msg, $ - table\n\
+         \x20   dd 0x12345, msg + 1\n    resb 2\n    resw 1\n    times 2 db 7\n\
+         \x20   times 2 dw COUNT\n.text\nstart:\n    mov r1, 'A'\n    hlt\n",
+    )
+    .unwrap();
+
+    let symbols =
+        SymbolTable::from_bytes(&executable.section(SectionKind::Symbols).unwrap().bytes).unwrap();
+    let [msg_high, msg_low] = (symbols.get("msg").unwrap() as u16).to_be_bytes();
+    let [next_high, next_low] = (symbols.get("msg").unwrap() as u16 + 1).to_be_bytes();
+
+    assert_eq!(symbols.get("table").unwrap() & 3, 0);
+    assert_eq!(&data_at(&executable, "msg")[..4], b"Hi\n\0");
+    assert_eq!(
+        data_at(&executable, "table")[..27],
+        [
+            0x41, 0x42, 0x22, 0x5C, 0xFF, 0x12, 0x34, msg_high, msg_low, 0x00, 0x09, 0x01, 0x23,
+            0x45, 0x00, next_high, next_low, 0x00, 0x00, 0x00, 0x00, 0x07, 0x07, 0x00, 0x03, 0x00,
+            0x03
+        ]
+    );
+    assert_eq!(
+        executable.section(SectionKind::Text).unwrap().bytes[..3],
+        [0x00, 0x00, 0x41]
+    );
+}
+
+#[test]
+fn test_data_errors() {
+    let messages = |source: &str| -> Vec<String> {
+        let source = format!(".main start\n.data\n{}\n.text\nstart:\n    hlt\n", source);
+
+        assemble(&source)
+            .unwrap_err()
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect()
+    };
+
+    assert_eq!(
+        messages("value: db 300"),
+        vec!["expression evaluates to 300, which does not fit in a byte"]
+    );
+    assert_eq!(
+        messages("value: db value"),
+        vec!["the address of a label does not fit in a byte"]
+    );
+    assert_eq!(
+        messages("value: dd 0x100000"),
+        vec!["expression evaluates to 1048576, which does not fit in 20 bits"]
+    );
+    assert_eq!(
+        messages("value: dw \"Hi\""),
+        vec!["`dw` can't hold a string"]
+    );
+    assert_eq!(
+        messages("value: .asciz 1"),
+        vec!["expected a string, found `1`"]
+    );
+    assert_eq!(messages("value: db \"\\q\""), vec!["unknown escape `\\q`"]);
+    assert_eq!(
+        messages(".align 3\nvalue: db 0"),
+        vec!["cannot align to 3 bytes"]
+    );
+    assert_eq!(
+        messages("value: db 0\n.align 2"),
+        vec!["`.align` is not followed by a data label"]
+    );
+    assert_eq!(
+        messages("value: times label db 0"),
+        vec!["the `times` count can't depend on a label or `$`"]
+    );
+    assert_eq!(messages("db 1"), vec!["`db` must follow a data label"]);
+}
+
+#[test]
+fn test_data_layout() {
+    let executable = assemble(
+        ".main start\n.data\nfirst: db \"abc\"\nsecond: dw 0x1234\nthird: db 7\nfourth: dw third\n\
+         .text\nstart:\n    hlt\n",
+    )
+    .unwrap();
+
+    let data = executable.section(SectionKind::Data).unwrap();
+    let symbols =
+        SymbolTable::from_bytes(&executable.section(SectionKind::Symbols).unwrap().bytes).unwrap();
+
+    // Data labels are laid out in source order, one after the other.
+    assert_eq!(data.load, 0x4402);
+    assert_eq!(
+        data.bytes,
+        vec![0x61, 0x62, 0x63, 0x12, 0x34, 0x07, 0x44, 0x07]
+    );
+    assert_eq!(symbols.get("first"), Some(0x4402));
+    assert_eq!(symbols.get("second"), Some(0x4405));
+    assert_eq!(symbols.get("third"), Some(0x4407));
+    assert_eq!(symbols.get("fourth"), Some(0x4408));
+}
+
+// Every example assembles to the same bytes every time, and to the binary checked in next to it.
+// The symbol table holds the path the example was assembled from, so this has to run from the
+// root of the repository like `compile_all.py` does.
+#[test]
+fn test_examples_reproducible() {
+    let examples = PathBuf::from("examples");
+    let mut checked = 0;
+
+    for entry in fs::read_dir(&examples).unwrap() {
+        let path = entry.unwrap().path();
+
+        if path.extension().and_then(|extension| extension.to_str()) != Some("yuasm") {
+            continue;
+        }
+
+        let build = || {
+            let (sources, tokens, diagnostics) = source::load(&path, &[], &[]).unwrap();
+            assert!(diagnostics.is_empty(), "{}", path.display());
+
+            let parser_res = Parser::new(tokens).parse().unwrap();
+            let (executable, _) = Assembler::new(parser_res, &sources).assemble().unwrap();
+
+            executable.to_bytes()
+        };
+
+        let bytes = build();
+        let compiled = examples
+            .join("compiled")
+            .join(path.with_extension("bin").file_name().unwrap());
+
+        assert!(bytes == build(), "{} is not reproducible", path.display());
+        assert!(
+            bytes == fs::read(&compiled).unwrap(),
+            "{} is out of date, run compile_all.py",
+            compiled.display()
+        );
+
+        checked += 1;
+    }
+
+    assert!(checked > 0);
+}
+
+#[test]
+fn test_macros() {
+    let expanded = text(
+        ".main start\n.macro putc char, cell\n    mov r1, char\n    stl r1, cell\n.endm\n\
+         .macro twice char\n    putc char, $0xA001\n    putc char + 1, $0xA003\n.endm\n\
+         .text\nstart:\n    twice 65\n.rept 2\n.rept 2\n    nop\n.endr\n.endr\n",
+    );
+    let written = text(
+        ".main start\n.text\nstart:\n    mov r1, 65\n    stl r1, $0xA001\n    mov r1, 66\n    stl r1, $0xA003\n\
+         nop\n    nop\n    nop\n    nop\n",
+    );
+
+    assert_eq!(expanded, written);
+}
+
+#[test]
+fn test_macro_local_labels() {
+    let executable = assemble(
+        ".main start\n.macro spin n\n    mov r2, 0\nloop:\n    add r2, 1\n    cmp r2, n\n    bne loop\n.endm\n\
+         .text\nstart:\n    spin 3\n    spin 4\n",
+    )
+    .unwrap();
+
+    let section = executable.section(SectionKind::Symbols).unwrap();
+    let symbols = SymbolTable::from_bytes(&section.bytes).unwrap();
+
+    assert_eq!(symbols.get("loop__1"), Some(0x4405));
+    assert_eq!(symbols.get("loop__2"), Some(0x4412));
+    assert_eq!(symbols.get("loop"), None);
+}
+
+#[test]
+fn test_macro_errors() {
+    colored::control::set_override(false);
+
+    let source = ".main start\n.macro bad\n    mvo r1, 5\n.endm\n.text\nstart:\n    bad\n";
+    let diagnostics = assemble(source).unwrap_err();
+    let span = diagnostics[0].span.clone().unwrap();
+
+    assert_eq!(span.line, 3);
+    assert_eq!(span.expansion.unwrap().line, 7);
+    assert!(diagnostics[0].render(&sources(source)).ends_with(
+        "note: in this macro invocation\n --> test.yuasm:7:5\n  |\n7 |     bad\n  |     ---\n"
+    ));
+
+    let diagnostics =
+        assemble(".main start\n.macro m a, b\n    hlt\n.endm\n.text\nstart:\n    m 1\n")
+            .unwrap_err();
+    assert_eq!(
+        diagnostics[0].message,
+        "macro `m` takes 2 argument(s), but 1 were given"
+    );
+
+    let diagnostics = assemble(
+        ".main start\n.macro a\n    b\n.endm\n.macro b\n    a\n.endm\n.text\nstart:\n    a\n",
+    )
+    .unwrap_err();
+    assert_eq!(diagnostics[0].message, "macro `a` expands into itself");
+    assert_eq!(
+        diagnostics[0].help.as_deref(),
+        Some("the calls form a cycle: a -> b -> a")
+    );
+
+    let diagnostics = assemble(".main start\n.text\nstart:\n.rept 2\n    hlt\n").unwrap_err();
+    assert_eq!(diagnostics[0].message, "`.rept` is never closed");
+}
+
+#[test]
+fn test_local_labels() {
+    let executable = assemble(
+        ".main start\n.text\nstart:\n    mov r1, 3\n.loop:\n    sub r1, 1\n    cmp r1, 0\n    bne .loop\n\
+         jmp print.loop\nprint:\n.loop:\n    jmp .loop\n.data\nmsg:\n.end: db 0\n",
+    )
+    .unwrap();
+
+    let section = executable.section(SectionKind::Symbols).unwrap();
+    let symbols = SymbolTable::from_bytes(&section.bytes).unwrap();
+
+    let start = symbols.get("start").unwrap();
+
+    assert_eq!(symbols.get("start.loop"), Some(start + 3));
+    assert_eq!(symbols.get("print"), Some(start + 0x11));
+    assert_eq!(symbols.get("print.loop"), Some(start + 0x11));
+    assert_eq!(symbols.get("msg.end"), symbols.get("msg"));
+    assert_eq!(symbols.get("loop"), None);
+
+    let written = text(
+        ".main start\n.text\nstart:\n    mov r1, 3\nloop:\n    sub r1, 1\n    cmp r1, 0\n    bne loop\n\
+         jmp print\nprint:\n    jmp print\n.data\nmsg: db 0\n",
+    );
+    assert_eq!(
+        executable.section(SectionKind::Text).unwrap().bytes,
+        written
+    );
+}
+
+#[test]
+fn test_anonymous_labels() {
+    let anonymous = text(
+        ".main start\n.text\nstart:\n    jmp 1f\n1:\n    jmp 1f\n1:\n    jmp 1b\n\
+         .macro spin\n1:\n    bne 1b\n.endm\nother:\n    spin\n    spin\n",
+    );
+    let named = text(
+        ".main start\n.text\nstart:\n    jmp a\na:\n    jmp b\nb:\n    jmp b\n\
+         other:\n    bne other\nc:\n    bne c\n",
+    );
+
+    assert_eq!(anonymous, named);
+
+    let executable = assemble(".main start\n.text\nstart:\n1:\n    jmp 1b\n").unwrap();
+    let section = executable.section(SectionKind::Symbols).unwrap();
+    let symbols = SymbolTable::from_bytes(&section.bytes).unwrap();
+
+    assert_eq!(symbols.get("start"), Some(0x4402));
+    assert_eq!(symbols.by_address().len(), 1);
+}
+
+#[test]
+fn test_label_errors() {
+    let diagnostics =
+        assemble(".main start\n.text\n.loop:\n    hlt\nstart:\n    hlt\n").unwrap_err();
+    assert_eq!(
+        diagnostics[0].message,
+        "local label `.loop` does not follow a global label"
+    );
+
+    let diagnostics =
+        assemble(".main start\n.text\nstart:\n1:\n    jmp 1f\n    jmp 2b\n").unwrap_err();
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(diagnostics[0].message, "there is no `1:` label after `1f`");
+    assert_eq!(diagnostics[0].help.as_deref(), Some("did you mean `1b`?"));
+    assert_eq!(diagnostics[1].message, "there is no `2:` label before `2b`");
+    assert_eq!(diagnostics[1].help, None);
+
+    let diagnostics =
+        assemble(".main start\n.text\nstart:\n.loop:\n    hlt\nother:\n    jmp .loop\n")
+            .unwrap_err();
+    assert_eq!(diagnostics[0].message, "no label named `other.loop`");
+
+    // Constants count even before the first label.
+    let diagnostics =
+        assemble(".main start\n.equ foo 1\n.text\nfoo:\n    hlt\nstart:\n    hlt\n").unwrap_err();
+    assert_eq!(diagnostics[0].message, "label `foo` is already defined");
+
+    let diagnostics =
+        assemble(".main start\n.equ msg 1\n.data\nmsg: db 0\n.text\nstart:\n    hlt\n")
+            .unwrap_err();
+    assert_eq!(diagnostics[0].message, "label `msg` is already defined");
+}
+
+#[test]
+fn test_pseudo_instructions() {
+    let pseudo = text(
+        ".main start\n.equ FIELD 4\n.text\nstart:\n    inc r1\n    dec r2\n    not r3\n    neg r4\n\
+         clr r5\n    call start\n    la r1, start\n    push r1, 5, r2\n    pop r1, r2\n    pop r3\n\
+         ld r1, [r2+FIELD]\n    ld r1, [r2 - 1]\n    ld r2, [r2+2]\n    ld r1, [r2]\n    ld r1, r2\n",
+    );
+    let real = text(
+        ".main start\n.text\nstart:\n    add r1, 1\n    sub r2, 1\n    xor r3, 0xFFFF\n    xor r4, 0xFFFF\n\
+         add r4, 1\n    mov r5, 0\n    jsr start\n    mov r1, start\n    psh r1\n    psh 5\n    psh r2\n\
+         pop r2\n    pop r1\n    pop r3\n    mov r6, r2\n    add r6, 4\n    ld r1, r6\n    mov r6, r2\n\
+         sub r6, 1\n    ld r1, r6\n    mov r6, r2\n    add r6, 2\n    ld r2, r6\n    ld r1, r2\n\
+         ld r1, r2\n",
+    );
+
+    assert_eq!(pseudo, real);
+
+    let source = ".main start\n.text\nstart:\n    push r1, r2\n    hlt\n";
+    let mut parser = Parser::new(tokenize(source, 0));
+    let (_, listing) = Assembler::new(parser.parse().unwrap(), &sources(source))
+        .assemble()
+        .unwrap();
+
+    assert!(listing.render(&sources(source)).contains(
+        "\n    4                             push r1, r2\n       04402  43 0C                   psh r1\n\
+         \x20      04404  43 1C                   psh r2\n    5  04406  FE 0C               hlt\n"
+    ));
+}
+
+#[test]
+fn test_pseudo_errors() {
+    let messages = |source: &str| -> Vec<String> {
+        assemble(&format!(
+            ".main start\n.text\nstart:\n{}\n    hlt\n",
+            source
+        ))
+        .unwrap_err()
+        .into_iter()
+        .map(|diagnostic| diagnostic.message)
+        .collect()
+    };
+
+    assert_eq!(
+        messages("    inc 5"),
+        ["`inc` expects a register, found `5`"]
+    );
+    assert_eq!(
+        messages("    clr r1, r2"),
+        ["`clr` takes 1 argument(s) but 2 were given"]
+    );
+    assert_eq!(
+        messages("    la r1, 5"),
+        ["`la` expects a label or an $address, found `5`"]
+    );
+    assert_eq!(
+        messages("    push"),
+        ["`push` expects at least one argument"]
+    );
+    assert_eq!(messages("    push r1,"), ["expected argument after `,`"]);
+    assert_eq!(
+        messages("    push [r1]"),
+        ["`push` can't read from `[...]`"]
+    );
+    assert_eq!(
+        messages("    ld r1, [start+2]"),
+        ["expected a register after `[`, found `start + 2`"]
+    );
+    assert_eq!(messages("    ld r1, [r2+2"), ["`[` is never closed"]);
+    assert_eq!(
+        messages("    ld r1, [r6+2]"),
+        ["r6 can't be the base of `[...]` with an offset"]
+    );
+    assert_eq!(messages("    ld r1, [r2*2]"), ["expected `]`, found `*`"]);
+}
+
+fn assemble_with(source: &str, defines: &[(&str, i64)]) -> Result<Executable, Vec<Diagnostic>> {
+    let mut parser = Parser::new(tokenize(source, 0));
+
+    for (name, value) in defines {
+        parser.define(name, *value);
+    }
+
+    Assembler::new(parser.parse()?, &sources(source))
+        .assemble()
+        .map(|(executable, _)| executable)
+}
+
+#[test]
+fn test_conditionals() {
+    let source = ".main start\n.ifdef DEBUG\n.equ LEVEL 3\n.else\n.equ LEVEL 0\n.endif\n\
+                  .ifndef WIDTH\n.equ WIDTH 40\n.endif\n.text\nstart:\n    mov r1, LEVEL\n\
+                  .if WIDTH > 20\n    mov r2, WIDTH\n.if 0\n    not an instruction\n.else\n\
+                  \x20   mov r3, 1\n.endif\n.else\n.if 1\n    mov r4, 1\n.endif\n.endif\n    hlt\n";
+    let text = |defines: &[(&str, i64)]| {
+        assemble_with(source, defines)
+            .unwrap()
+            .section(SectionKind::Text)
+            .unwrap()
+            .bytes
+            .clone()
+    };
+
+    assert_eq!(
+        text(&[]),
+        [0x00, 0x00, 0x00, 0x00, 0x10, 0x28, 0x00, 0x20, 0x01, 0xFE, 0x0C]
+    );
+    assert_eq!(
+        text(&[("DEBUG", 1), ("WIDTH", 20)]),
+        [0x00, 0x00, 0x03, 0x00, 0x30, 0x01, 0xFE, 0x0C]
+    );
+}
+
+#[test]
+fn test_conditional_errors() {
+    let source = ".main start\n.if WIDTH > 80\n.error \"too wide\"\n.endif\n\
+                  .warning \"deprecated\"\n.text\nstart:\n    hlt\n";
+    let diagnostics = assemble_with(source, &[("WIDTH", 100)]).unwrap_err();
+
+    assert_eq!(diagnostics[0].message, "too wide");
+    assert_eq!(diagnostics[1].message, "deprecated");
+    assert_eq!(diagnostics[1].severity, Severity::Warning);
+
+    let mut parser = Parser::new(tokenize(source, 0));
+    parser.define("WIDTH", 80);
+    let warnings = parser.parse().unwrap().warnings;
+    assert_eq!(warnings.len(), 1);
+
+    let messages = |source: &str| -> Vec<String> {
+        assemble_with(source, &[("LEVEL", 1)])
+            .unwrap_err()
+            .into_iter()
+            .map(|diagnostic| diagnostic.message)
+            .collect()
+    };
+
+    assert_eq!(
+        messages(".main start\n.if start\n.endif\n.text\nstart:\n    hlt\n"),
+        ["the `.if` condition can't depend on a label or `$`"]
+    );
+    assert_eq!(
+        messages(".main start\n.if 1\n.else\n.else\n.endif\n.endif\n.text\nstart:\n    hlt\n"),
+        [
+            "this `.if` already has an `.else`",
+            "`.endif` without a matching `.if`"
+        ]
+    );
+    assert_eq!(
+        messages(".main start\n.ifdef\n.text\nstart:\n    hlt\n"),
+        ["`.ifdef` expects a constant name", "`.if` is never closed"]
+    );
+    assert_eq!(
+        messages(".main start\n.equ LEVEL 2\n.text\nstart:\n    hlt\n"),
+        ["constant `LEVEL` is already defined with `-D`"]
+    );
+}
+
+// Writes `files` into a fresh directory under the system temp directory.
+fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = env::temp_dir().join(format!("yucpu-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+
+    for (path, content) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    dir
+}
+
+#[test]
+fn test_include() {
+    let dir = write_files(
+        "include",
+        &[
+            (
+                "main.yuasm",
+                ".main start\n.include \"lib/print.yuasm\"\n.include \"math.yuasm\"\n.text\nstart:\n    jsr print\n    hlt\n",
+            ),
+            (
+                "lib/print.yuasm",
+                ".include \"math.yuasm\"\n.text\nprint:\n    mov r1, double\n    ret\n",
+            ),
+            ("shared/math.yuasm", ".equ double 4\n"),
+        ],
+    );
+
+    let (sources, tokens, diagnostics) =
+        source::load(&dir.join("main.yuasm"), &[dir.join("shared")], &[]).unwrap();
+    assert!(diagnostics.is_empty());
+    assert_eq!(sources.files.len(), 3);
+
+    let parser_res = Parser::new(tokens).parse().unwrap();
+    let (executable, _) = Assembler::new(parser_res, &sources).assemble().unwrap();
+
+    let section = executable.section(SectionKind::Symbols).unwrap();
+    let symbols = SymbolTable::from_bytes(&section.bytes).unwrap();
+
+    assert_eq!(symbols.get("print"), Some(0x4402));
+    assert_eq!(
+        symbols.line(0x4402).unwrap().to_string(),
+        format!("{}:4", dir.join("lib/print.yuasm").display())
+    );
+    assert_eq!(
+        symbols.line(0x4407).unwrap().to_string(),
+        format!("{}:6", dir.join("main.yuasm").display())
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_include_errors() {
+    let dir = write_files(
+        "include-errors",
+        &[
+            (
+                "a.yuasm",
+                ".include \"b.yuasm\"\n.include \"missing.yuasm\"\n",
+            ),
+            ("b.yuasm", ".include \"a.yuasm\"\n"),
+        ],
+    );
+
+    let (sources, _, diagnostics) = source::load(&dir.join("a.yuasm"), &[], &[]).unwrap();
+
+    assert_eq!(
+        diagnostics[0].message,
+        format!("`{}` includes itself", dir.join("a.yuasm").display())
+    );
+    assert_eq!(diagnostics[0].span.clone().unwrap().file, 1);
+    assert_eq!(sources.name(1), dir.join("b.yuasm").display().to_string());
+    assert_eq!(
+        diagnostics[1].message,
+        "cannot find `missing.yuasm` to include"
+    );
+    assert_eq!(diagnostics[1].span.clone().unwrap().line, 2);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_include_in_conditional() {
+    let dir = write_files(
+        "include-conditional",
+        &[(
+            "main.yuasm",
+            ".main start\n.ifdef DEBUG\n.include \"debug_only.yuasm\"\n.endif\n.text\nstart:\n    hlt\n",
+        )],
+    );
+
+    let (sources, tokens, diagnostics) = source::load(&dir.join("main.yuasm"), &[], &[]).unwrap();
+    assert!(diagnostics.is_empty());
+
+    let parser_res = Parser::new(tokens).parse().unwrap();
+    assert!(Assembler::new(parser_res, &sources).assemble().is_ok());
+
+    let defines = [(String::from("DEBUG"), 1)];
+    let (_, _, diagnostics) = source::load(&dir.join("main.yuasm"), &[], &defines).unwrap();
+    assert_eq!(
+        diagnostics[0].message,
+        "cannot find `debug_only.yuasm` to include"
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_linkage_errors() {
+    let diagnostics =
+        assemble(".main start\n.global missing\n.extern start\n.text\nstart:\n    hlt\n")
+            .unwrap_err();
+    let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+
+    assert!(messages.contains(&"global label `missing` is not defined"));
+    assert!(messages.contains(&"`start` is defined in this file, so it can't be `.extern`"));
+
+    let diagnostics =
+        assemble(".main start\n.extern print\n.text\nstart:\n    jsr print\n").unwrap_err();
+
+    assert_eq!(
+        diagnostics[0].message,
+        "`print` is declared `.extern`, but nothing defines it"
+    );
+}
+
+#[test]
+fn test_far_text() {
+    let executable = assemble(
+        ".main start\n.data\ntable: dd helper\n.text\nstart:\n    jsrf helper\n    hlt\n\
+         .far\nhelper:\n    jmp $ + 5\n    retf\n",
+    )
+    .unwrap();
+
+    let data = executable.section(SectionKind::Data).unwrap();
+    let text = executable.section(SectionKind::Text).unwrap();
+    let far = executable.section(SectionKind::FarText).unwrap();
+
+    assert_eq!(data.bytes, vec![0x01, 0x00, 0x00]);
+    assert_eq!(text.bytes, vec![0xA0, 0x08, 0x01, 0x00, 0x00, 0xFE, 0x0C]);
+    assert_eq!(far.load, 0x10000);
+    assert_eq!(far.bytes, vec![0x8E, 0x08, 0x01, 0x00, 0x05, 0xE1, 0x0C]);
+
+    // Without far text the executable keeps the sections it always had.
+    let executable = assemble(".main start\n.text\nstart:\n    hlt\n").unwrap();
+    assert!(executable.section(SectionKind::FarText).is_none());
+}
+
+#[test]
+fn test_program_too_large() {
+    let diagnostics =
+        assemble(".main start\n.text\nstart:\n.rept 600\n    nop\n.endr\n").unwrap_err();
+
+    assert!(diagnostics[0]
+        .message
+        .starts_with("the program is too large: the text section (1200 bytes at 0x04402)"));
+}
+
+#[test]
+fn test_far_text_errors() {
+    let diagnostics = assemble(
+        ".main start\n.int 0x20 helper\n.text\nstart:\n    mov r1, helper\n    hlt\n\
+         .far\nhelper:\n    ret\n",
+    )
+    .unwrap_err();
+
+    assert_eq!(
+        diagnostics[0].message,
+        "the address of far text does not fit in a register"
+    );
+
+    let diagnostics =
+        assemble(".main start\n.int 0x20 helper\n.text\nstart:\n    hlt\n.far\nhelper:\n    ret\n")
+            .unwrap_err();
+
+    assert_eq!(
+        diagnostics[0].message,
+        "interrupt handler `helper` is in far text"
+    );
+    assert_eq!(diagnostics[0].span.clone().unwrap().line, 2);
+
+    // A near call to far text, or from it to near text, would return to the wrong 64K.
+    let diagnostics = assemble(
+        ".main start\n.text\nstart:\n    jsr helper\n    hlt\nnear:\n    ret\n\
+         .far\nhelper:\n    jsr near\n    jsr far\n    retf\nfar:\n    ret\n",
+    )
+    .unwrap_err();
+
+    assert_eq!(diagnostics.len(), 2);
+    assert_eq!(
+        diagnostics[0].message,
+        "`jsr` can't call between near and far text"
+    );
+    assert_eq!(diagnostics[0].span.clone().unwrap().line, 4);
+    assert_eq!(diagnostics[1].span.clone().unwrap().line, 10);
+}
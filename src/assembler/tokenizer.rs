@@ -1,5 +1,9 @@
 use logos::Logos;
 
+use super::diagnostic::{Diagnostic, Span};
+
+pub type TokenInfoType = (Token, String, Span);
+
 #[derive(Logos, Debug, PartialEq, Clone, Copy)]
 pub enum Token {
     #[token(",")]
@@ -8,27 +12,115 @@ pub enum Token {
     #[token(".int")]
     InterruptDefine,
 
+    #[token(".equ")]
+    #[token(".define")]
+    Constant,
+
+    #[token(".global")]
+    Global,
+
+    #[token(".extern")]
+    Extern,
+
+    #[token(".include")]
+    Include,
+
+    #[token(".macro")]
+    MacroStart,
+
+    #[token(".endm")]
+    MacroEnd,
+
+    #[token(".rept")]
+    Repeat,
+
+    #[token(".endr")]
+    RepeatEnd,
+
+    #[token(".if")]
+    If,
+
+    #[token(".ifdef")]
+    IfDefined,
+
+    #[token(".ifndef")]
+    IfNotDefined,
+
+    #[token(".else")]
+    Else,
+
+    #[token(".endif")]
+    EndIf,
+
+    #[token(".error")]
+    UserError,
+
+    #[token(".warning")]
+    UserWarning,
+
     #[token("\n")]
     NewLine,
 
     #[token("db")]
     DefineByte,
 
+    #[token("dw")]
+    DefineWord,
+
+    // A 20-bit address, stored in 3 bytes like an address operand.
+    #[token("dd")]
+    DefineDouble,
+
+    #[token("resb")]
+    ReserveByte,
+
+    #[token("resw")]
+    ReserveWord,
+
+    #[token("times")]
+    Times,
+
+    #[token(".asciz")]
+    Asciz,
+
+    #[token(".align")]
+    Align,
+
     #[token(".text")]
     TextSection,
 
     #[token(".data")]
     DataSection,
 
-    #[regex("(\")([^\"]*)(\")")]
+    // Text linked above 0xFFFF, see FAR_ROM_START.
+    #[token(".far")]
+    FarSection,
+
+    #[regex(r#""([^"\\\n]|\\[^\n])*""#)]
     String,
 
-    #[regex(r"\.[a-zA-Z]+")]
+    #[regex(r"'([^'\\\n]|\\[^\n]|\\x[0-9a-fA-F][0-9a-fA-F])'")]
+    Char,
+
+    // Also a reference to a local label, such as `jmp .loop`, when it isn't the first token on
+    // its line.
+    #[regex(r"\.[a-zA-Z_][a-zA-Z0-9_]*")]
     Metadata,
 
+    // A label scoped to the global label before it, see `labels::resolve`.
+    #[regex(r"\.[a-zA-Z_][a-zA-Z0-9_]*:")]
+    LocalLabel,
+
+    // An anonymous label, referenced as `1f` (the next `1:`) or `1b` (the previous one).
+    #[regex("[0-9]+:")]
+    NumericLabel,
+
+    #[regex("[0-9]+[fb]")]
+    NumericReference,
+
     // #[regex(r"\.[a-zA-Z]+ [a-zA-Z0-9]+ [a-zA-Z0-9]+")]
     // InterruptDefine,
-    #[regex("[a-zA-Z][a-zA-Z0-9]+:")]
+    #[regex(r"[a-zA-Z_][a-zA-Z0-9_]*(\.[a-zA-Z_][a-zA-Z0-9_]*)?:")]
     Label,
 
     #[regex("(R|r)(1|2|3|4|5|6|(PC|pc)|(SP|sp)|(BP|bp))")]
@@ -40,11 +132,167 @@ pub enum Token {
     #[regex(r"\$(0[xX][0-9a-fA-F]+|[0-9]+)")]
     Address,
 
-    #[regex("[a-zA-Z][a-zA-Z0-9]+")]
+    // `print.loop` names the local label `.loop` of `print`.
+    #[regex(r"[a-zA-Z_][a-zA-Z0-9_]*(\.[a-zA-Z_][a-zA-Z0-9_]*)?")]
     Identifier,
 
+    // The address of the current instruction.
+    #[token("$")]
+    Dollar,
+
+    #[token("+")]
+    Plus,
+
+    #[token("-")]
+    Minus,
+
+    #[token("*")]
+    Star,
+
+    #[token("/")]
+    Slash,
+
+    #[token("%")]
+    Percent,
+
+    #[token("<<")]
+    ShiftLeft,
+
+    #[token(">>")]
+    ShiftRight,
+
+    #[token("==")]
+    Equal,
+
+    #[token("!=")]
+    NotEqual,
+
+    #[token("<")]
+    Less,
+
+    #[token("<=")]
+    LessEqual,
+
+    #[token(">")]
+    Greater,
+
+    #[token(">=")]
+    GreaterEqual,
+
+    #[token("&")]
+    Ampersand,
+
+    #[token("|")]
+    Pipe,
+
+    #[token("~")]
+    Tilde,
+
+    #[token("(")]
+    LParen,
+
+    #[token(")")]
+    RParen,
+
+    // Only used by `ld r1, [r2+4]`, see `pseudo`.
+    #[token("[")]
+    LBracket,
+
+    #[token("]")]
+    RBracket,
+
     #[error]
     #[regex(r"[ \t\f]+", logos::skip)]
     #[regex(r";.+", logos::skip)]
     Error,
 }
+
+// `file` is the index of the source in the `SourceMap`, which every span keeps.
+pub fn tokenize(source: &str, file: usize) -> Vec<TokenInfoType> {
+    let mut lex = Token::lexer(source);
+    let mut tokens: Vec<TokenInfoType> = Vec::new();
+
+    let mut line = 1;
+    let mut line_start = 0;
+
+    while let Some(tok) = lex.next() {
+        let range = lex.span();
+
+        let slice = lex.slice();
+
+        // Skipped whitespace and comments never contain a new line, so only tokens need checking.
+        let column = source[line_start..range.start].chars().count() + 1;
+        let span = Span::new(file, line, column, range.clone());
+
+        if let Some(last) = slice.rfind('\n') {
+            line += slice.matches('\n').count();
+            line_start = range.start + last + 1;
+        }
+
+        tokens.push((tok, String::from(slice), span));
+    }
+
+    tokens
+}
+
+/*
+Turns a string or character literal into the bytes it stands for, without the quotes. These
+escapes are understood:
+
+\n \r \t \0 \\ \" \'   the usual characters
+\xHH                   the byte 0xHH
+ */
+pub fn unescape(token: &TokenInfoType) -> Result<Vec<u8>, Diagnostic> {
+    let text = &token.1[1..token.1.len() - 1];
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('r') => '\r',
+                Some('t') => '\t',
+                Some('0') => '\0',
+                Some(c @ ('\\' | '"' | '\'')) => c,
+                Some('x') => {
+                    let digits: String = chars.by_ref().take(2).collect();
+
+                    match u8::from_str_radix(&digits, 16) {
+                        Ok(byte) if digits.len() == 2 && !digits.starts_with('+') => {
+                            bytes.push(byte);
+                            continue;
+                        }
+                        _ => {
+                            return Err(Diagnostic::error(
+                                format!("`\\x{}` is not a valid escape", digits),
+                                Some(token.2.clone()),
+                            )
+                            .with_help("`\\x` must be followed by two hex digits, like `\\x41`"))
+                        }
+                    }
+                }
+                other => {
+                    return Err(Diagnostic::error(
+                        format!("unknown escape `\\{}`", other.unwrap_or_default()),
+                        Some(token.2.clone()),
+                    )
+                    .with_help("the escapes are \\n \\r \\t \\0 \\\\ \\\" \\' and \\xHH"))
+                }
+            },
+            c => c,
+        };
+
+        match u8::try_from(c) {
+            Ok(byte) => bytes.push(byte),
+            Err(_) => {
+                return Err(Diagnostic::error(
+                    format!("character `{}` does not fit in a byte", c),
+                    Some(token.2.clone()),
+                ))
+            }
+        }
+    }
+
+    Ok(bytes)
+}
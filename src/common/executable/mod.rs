@@ -0,0 +1,481 @@
+#[cfg(test)]
+mod tests;
+
+use std::fmt;
+
+pub const MAGIC: [u8; 4] = *b"YUEX";
+pub const VERSION: u16 = 1;
+
+pub const ROM_START: u32 = 0x4402;
+pub const ROM_SIZE: u32 = 0x400;
+// Code that doesn't fit in ROM goes here, above the 16-bit address space.
+pub const FAR_ROM_START: u32 = 0x10000;
+pub const FAR_ROM_SIZE: u32 = 0xF0000;
+pub const IVT_SIZE: usize = 510;
+
+const HEADER_SIZE: usize = 12;
+const SECTION_ENTRY_SIZE: usize = 13;
+const CRC_SIZE: usize = 4;
+const LEGACY_HEADER_SIZE: usize = 6;
+
+/*
+Executable layout, every number is big-endian:
+
+magic        4 bytes   "YUEX"
+version      u16
+sections     u16       number of entries in the section table
+entry        u32       address the PC starts at
+
+Followed by one 13 byte entry per section:
+
+kind         u8        see SectionKind
+load         u32       address the section is loaded at
+offset       u32       offset of the section's bytes from the start of the file
+size         u32
+
+Then the section bytes, and finally a CRC-32 of everything before it.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SectionKind {
+    Data = 1,
+    Text = 2,
+    Ivt = 3,
+    Symbols = 4,
+    Debug = 5,
+    FarText = 6,
+}
+
+impl SectionKind {
+    fn from_u8(value: u8) -> Option<SectionKind> {
+        match value {
+            1 => Some(SectionKind::Data),
+            2 => Some(SectionKind::Text),
+            3 => Some(SectionKind::Ivt),
+            4 => Some(SectionKind::Symbols),
+            5 => Some(SectionKind::Debug),
+            6 => Some(SectionKind::FarText),
+            _ => None,
+        }
+    }
+
+    // Sections that end up in ROM, as opposed to the IVT or metadata for tools.
+    pub fn is_loaded(&self) -> bool {
+        matches!(
+            self,
+            SectionKind::Data | SectionKind::Text | SectionKind::FarText
+        )
+    }
+
+    pub fn is_text(&self) -> bool {
+        matches!(self, SectionKind::Text | SectionKind::FarText)
+    }
+
+    // The part of memory a loaded section has to fit in.
+    pub fn rom(&self) -> (u32, u32) {
+        match self {
+            SectionKind::FarText => (FAR_ROM_START, FAR_ROM_SIZE),
+            _ => (ROM_START, ROM_SIZE),
+        }
+    }
+}
+
+impl fmt::Display for SectionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SectionKind::Data => "data",
+            SectionKind::Text => "text",
+            SectionKind::Ivt => "ivt",
+            SectionKind::Symbols => "symbols",
+            SectionKind::Debug => "debug",
+            SectionKind::FarText => "far text",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExecutableError {
+    Truncated,
+    BadMagic,
+    UnsupportedVersion(u16),
+    ChecksumMismatch {
+        expected: u32,
+        found: u32,
+    },
+    UnknownSection(u8),
+    DuplicateSection(SectionKind),
+    MissingSection(SectionKind),
+    SectionOutOfBounds(SectionKind),
+    InvalidInterruptTable(usize),
+    InvalidLoadAddress {
+        kind: SectionKind,
+        addr: u32,
+        size: usize,
+    },
+    OverlappingSections(SectionKind, SectionKind),
+    EntryOutsideText(u32),
+}
+
+impl fmt::Display for ExecutableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecutableError::Truncated => {
+                write!(f, "the file ends before the end of the executable")
+            }
+            ExecutableError::BadMagic => write!(
+                f,
+                "not a YuCPU executable, use --legacy to load a binary from an older assembler"
+            ),
+            ExecutableError::UnsupportedVersion(version) => write!(
+                f,
+                "executable format version {} is not supported (expected {})",
+                version, VERSION
+            ),
+            ExecutableError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "checksum mismatch, the file is corrupt (expected 0x{:08X}, found 0x{:08X})",
+                expected, found
+            ),
+            ExecutableError::UnknownSection(kind) => write!(f, "unknown section kind {}", kind),
+            ExecutableError::DuplicateSection(kind) => {
+                write!(f, "the {} section appears more than once", kind)
+            }
+            ExecutableError::MissingSection(kind) => write!(f, "there is no {} section", kind),
+            ExecutableError::SectionOutOfBounds(kind) => {
+                write!(f, "the {} section extends past the end of the file", kind)
+            }
+            ExecutableError::InvalidInterruptTable(size) => write!(
+                f,
+                "the interrupt table is {} bytes long (expected {})",
+                size, IVT_SIZE
+            ),
+            ExecutableError::InvalidLoadAddress { kind, addr, size } => {
+                let (start, rom_size) = kind.rom();
+
+                write!(
+                    f,
+                    "the {} section ({} bytes at 0x{:05X}) runs outside ROM (0x{:04X} - 0x{:04X})",
+                    kind,
+                    size,
+                    addr,
+                    start,
+                    start + rom_size - 1
+                )
+            }
+            ExecutableError::OverlappingSections(a, b) => {
+                write!(f, "the {} and {} sections overlap", a, b)
+            }
+            ExecutableError::EntryOutsideText(entry) => write!(
+                f,
+                "the entry point 0x{:05X} is outside of the text sections",
+                entry
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Section {
+    pub kind: SectionKind,
+    pub load: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl Section {
+    pub fn new(kind: SectionKind, load: u32, bytes: Vec<u8>) -> Section {
+        Section { kind, load, bytes }
+    }
+
+    // One past the last byte, or nothing when that's past the end of the address space.
+    pub fn end(&self) -> Option<u32> {
+        self.load.checked_add(u32::try_from(self.bytes.len()).ok()?)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Executable {
+    pub entry: u32,
+    pub sections: Vec<Section>,
+}
+
+// Everything the machine needs to start running a program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadedProgram {
+    pub entry: u32,
+    pub rom: Vec<u8>,
+    // Starts at FAR_ROM_START, empty unless the program has far text.
+    pub far_rom: Vec<u8>,
+    pub ivt: [u8; IVT_SIZE],
+}
+
+// CRC-32 (IEEE), the same checksum zip and png use.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+
+    for byte in bytes {
+        crc ^= *byte as u32;
+
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+impl Executable {
+    pub fn new(entry: u32) -> Executable {
+        Executable {
+            entry,
+            sections: Vec::new(),
+        }
+    }
+
+    pub fn with_section(mut self, section: Section) -> Executable {
+        self.sections.push(section);
+        self
+    }
+
+    pub fn section(&self, kind: SectionKind) -> Option<&Section> {
+        self.sections.iter().find(|section| section.kind == kind)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+
+        output.extend_from_slice(&MAGIC);
+        output.extend_from_slice(&VERSION.to_be_bytes());
+        output.extend_from_slice(&(self.sections.len() as u16).to_be_bytes());
+        output.extend_from_slice(&self.entry.to_be_bytes());
+
+        let mut offset = HEADER_SIZE + self.sections.len() * SECTION_ENTRY_SIZE;
+
+        for section in &self.sections {
+            output.push(section.kind as u8);
+            output.extend_from_slice(&section.load.to_be_bytes());
+            output.extend_from_slice(&(offset as u32).to_be_bytes());
+            output.extend_from_slice(&(section.bytes.len() as u32).to_be_bytes());
+
+            offset += section.bytes.len();
+        }
+
+        for section in &self.sections {
+            output.extend_from_slice(&section.bytes);
+        }
+
+        let crc = crc32(&output);
+        output.extend_from_slice(&crc.to_be_bytes());
+
+        output
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Executable, ExecutableError> {
+        if bytes.len() < MAGIC.len() || bytes[..MAGIC.len()] != MAGIC {
+            return Err(ExecutableError::BadMagic);
+        }
+
+        if bytes.len() < HEADER_SIZE + CRC_SIZE {
+            return Err(ExecutableError::Truncated);
+        }
+
+        let version = read_u16(bytes, 4);
+
+        if version != VERSION {
+            return Err(ExecutableError::UnsupportedVersion(version));
+        }
+
+        let (body, crc) = bytes.split_at(bytes.len() - CRC_SIZE);
+        let expected = read_u32(crc, 0);
+        let found = crc32(body);
+
+        if expected != found {
+            return Err(ExecutableError::ChecksumMismatch { expected, found });
+        }
+
+        let count = read_u16(body, 6) as usize;
+        let entry = read_u32(body, 8);
+
+        if body.len() < HEADER_SIZE + count * SECTION_ENTRY_SIZE {
+            return Err(ExecutableError::Truncated);
+        }
+
+        let mut executable = Executable::new(entry);
+
+        for i in 0..count {
+            let table = HEADER_SIZE + i * SECTION_ENTRY_SIZE;
+
+            let kind = match SectionKind::from_u8(body[table]) {
+                Some(kind) => kind,
+                None => return Err(ExecutableError::UnknownSection(body[table])),
+            };
+
+            if executable.section(kind).is_some() {
+                return Err(ExecutableError::DuplicateSection(kind));
+            }
+
+            let load = read_u32(body, table + 1);
+            let offset = read_u32(body, table + 5) as usize;
+            let size = read_u32(body, table + 9) as usize;
+
+            let section_bytes = match offset.checked_add(size) {
+                Some(end) if end <= body.len() => &body[offset..end],
+                _ => return Err(ExecutableError::SectionOutOfBounds(kind)),
+            };
+
+            executable
+                .sections
+                .push(Section::new(kind, load, section_bytes.to_vec()));
+        }
+
+        executable.validate()?;
+
+        Ok(executable)
+    }
+
+    // Reads the original format: the entry point, text size and data size as big-endian u16s,
+    // followed by the data section, the text section and the interrupt table.
+    pub fn parse_legacy(bytes: &[u8]) -> Result<Executable, ExecutableError> {
+        if bytes.len() < LEGACY_HEADER_SIZE {
+            return Err(ExecutableError::Truncated);
+        }
+
+        let entry = read_u16(bytes, 0) as u32;
+        let text_size = read_u16(bytes, 2) as usize;
+        let data_size = read_u16(bytes, 4) as usize;
+
+        let program = &bytes[LEGACY_HEADER_SIZE..];
+
+        if program.len() < data_size + text_size {
+            return Err(ExecutableError::Truncated);
+        }
+
+        let ivt = &program[data_size + text_size..];
+
+        if ivt.len() < IVT_SIZE {
+            return Err(ExecutableError::MissingSection(SectionKind::Ivt));
+        }
+
+        let executable = Executable::new(entry)
+            .with_section(Section::new(
+                SectionKind::Data,
+                ROM_START,
+                program[..data_size].to_vec(),
+            ))
+            .with_section(Section::new(
+                SectionKind::Text,
+                ROM_START + data_size as u32,
+                program[data_size..data_size + text_size].to_vec(),
+            ))
+            .with_section(Section::new(SectionKind::Ivt, 0, ivt[..IVT_SIZE].to_vec()));
+
+        executable.validate()?;
+
+        Ok(executable)
+    }
+
+    pub fn validate(&self) -> Result<(), ExecutableError> {
+        if self.section(SectionKind::Text).is_none() {
+            return Err(ExecutableError::MissingSection(SectionKind::Text));
+        }
+
+        match self.section(SectionKind::Ivt) {
+            Some(ivt) if ivt.bytes.len() != IVT_SIZE => {
+                return Err(ExecutableError::InvalidInterruptTable(ivt.bytes.len()))
+            }
+            Some(_) => (),
+            None => return Err(ExecutableError::MissingSection(SectionKind::Ivt)),
+        }
+
+        let mut loaded: Vec<(&Section, u32)> = Vec::new();
+
+        for section in self.sections.iter().filter(|s| s.kind.is_loaded()) {
+            let (start, size) = section.kind.rom();
+
+            match section.end() {
+                Some(end) if section.load >= start && end <= start + size => {
+                    loaded.push((section, end))
+                }
+                _ => {
+                    return Err(ExecutableError::InvalidLoadAddress {
+                        kind: section.kind,
+                        addr: section.load,
+                        size: section.bytes.len(),
+                    })
+                }
+            }
+        }
+
+        for (i, (a, a_end)) in loaded.iter().enumerate() {
+            for (b, b_end) in &loaded[i + 1..] {
+                if a.load < *b_end && b.load < *a_end {
+                    return Err(ExecutableError::OverlappingSections(a.kind, b.kind));
+                }
+            }
+        }
+
+        if !loaded.iter().any(|(section, end)| {
+            section.kind.is_text() && (section.load..*end).contains(&self.entry)
+        }) {
+            return Err(ExecutableError::EntryOutsideText(self.entry));
+        }
+
+        Ok(())
+    }
+
+    // Lays the data and text sections out in a ROM image starting at ROM_START, and far text in
+    // one starting at FAR_ROM_START. Gaps between sections are filled with zeros.
+    pub fn load(&self) -> Result<LoadedProgram, ExecutableError> {
+        self.validate()?;
+
+        let image = |far: bool| {
+            let start = if far { FAR_ROM_START } else { ROM_START };
+            let sections: Vec<&Section> = self
+                .sections
+                .iter()
+                .filter(|s| s.kind.is_loaded() && (s.kind == SectionKind::FarText) == far)
+                .collect();
+
+            let end = sections
+                .iter()
+                .filter_map(|s| s.end())
+                .max()
+                .unwrap_or(start);
+            let mut rom = vec![0; (end - start) as usize];
+
+            for section in sections {
+                let offset = (section.load - start) as usize;
+                rom[offset..offset + section.bytes.len()].copy_from_slice(&section.bytes);
+            }
+
+            rom
+        };
+
+        let mut ivt = [0; IVT_SIZE];
+
+        if let Some(section) = self.section(SectionKind::Ivt) {
+            ivt.copy_from_slice(&section.bytes);
+        }
+
+        Ok(LoadedProgram {
+            entry: self.entry,
+            rom: image(false),
+            far_rom: image(true),
+            ivt,
+        })
+    }
+}
@@ -0,0 +1,165 @@
+use super::{
+    crc32, Executable, ExecutableError, Section, SectionKind, FAR_ROM_START, IVT_SIZE, ROM_SIZE,
+    ROM_START,
+};
+
+fn executable() -> Executable {
+    let mut ivt = vec![0; IVT_SIZE];
+    ivt[2] = 0x44;
+    ivt[3] = 0x04;
+
+    Executable::new(0x4404)
+        .with_section(Section::new(SectionKind::Data, ROM_START, vec![0x48, 0x69]))
+        .with_section(Section::new(
+            SectionKind::Text,
+            ROM_START + 2,
+            vec![0x00, 0x00, 0x05, 0xFE, 0x0C],
+        ))
+        .with_section(Section::new(SectionKind::Ivt, 0, ivt))
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+#[test]
+fn test_round_trip() {
+    let bytes = executable().to_bytes();
+
+    assert_eq!(&bytes[..4], b"YUEX");
+    assert_eq!(Executable::parse(&bytes).unwrap(), executable());
+}
+
+#[test]
+fn test_load() {
+    let program = executable().load().unwrap();
+
+    assert_eq!(program.entry, 0x4404);
+    assert_eq!(program.rom, vec![0x48, 0x69, 0x00, 0x00, 0x05, 0xFE, 0x0C]);
+    assert_eq!(&program.ivt[2..4], &[0x44, 0x04]);
+    assert!(program.far_rom.is_empty());
+}
+
+#[test]
+fn test_load_far_text() {
+    let mut executable = executable().with_section(Section::new(
+        SectionKind::FarText,
+        FAR_ROM_START + 2,
+        vec![0xFE, 0x0C],
+    ));
+    executable.entry = FAR_ROM_START + 2;
+
+    let program = executable.load().unwrap();
+
+    assert_eq!(program.entry, 0x10002);
+    assert_eq!(program.rom.len(), 7);
+    assert_eq!(program.far_rom, vec![0x00, 0x00, 0xFE, 0x0C]);
+
+    executable.sections[3].load = ROM_START + 7;
+    assert_eq!(
+        executable.validate().unwrap_err(),
+        ExecutableError::InvalidLoadAddress {
+            kind: SectionKind::FarText,
+            addr: ROM_START + 7,
+            size: 2
+        }
+    );
+
+    // The end of a section past the top of the address space mustn't wrap around.
+    executable.sections[3].load = u32::MAX;
+    assert_eq!(
+        executable.validate().unwrap_err(),
+        ExecutableError::InvalidLoadAddress {
+            kind: SectionKind::FarText,
+            addr: u32::MAX,
+            size: 2
+        }
+    );
+}
+
+#[test]
+fn test_parse_errors() {
+    let mut bytes = executable().to_bytes();
+
+    assert_eq!(
+        Executable::parse(&bytes[1..]).unwrap_err(),
+        ExecutableError::BadMagic
+    );
+
+    bytes[20] ^= 0xFF;
+
+    assert!(matches!(
+        Executable::parse(&bytes).unwrap_err(),
+        ExecutableError::ChecksumMismatch { .. }
+    ));
+
+    let mut bytes = executable().to_bytes();
+    bytes[5] = 2;
+
+    assert_eq!(
+        Executable::parse(&bytes).unwrap_err(),
+        ExecutableError::UnsupportedVersion(2)
+    );
+}
+
+#[test]
+fn test_validate() {
+    let mut bad = executable();
+    bad.entry = 0x4402;
+    assert_eq!(
+        bad.validate().unwrap_err(),
+        ExecutableError::EntryOutsideText(0x4402)
+    );
+
+    let mut bad = executable();
+    bad.sections[1].load = ROM_START + 1;
+    assert_eq!(
+        bad.validate().unwrap_err(),
+        ExecutableError::OverlappingSections(SectionKind::Data, SectionKind::Text)
+    );
+
+    let mut bad = executable();
+    bad.sections[0].load = 0x0401;
+    assert_eq!(
+        bad.validate().unwrap_err(),
+        ExecutableError::InvalidLoadAddress {
+            kind: SectionKind::Data,
+            addr: 0x0401,
+            size: 2
+        }
+    );
+
+    let mut bad = executable();
+    bad.sections.pop();
+    assert_eq!(
+        bad.validate().unwrap_err(),
+        ExecutableError::MissingSection(SectionKind::Ivt)
+    );
+
+    // A section too big for ROM is turned away before anything is loaded.
+    let mut bad = executable();
+    bad.sections[1].bytes = vec![0; ROM_SIZE as usize];
+    let bytes = bad.to_bytes();
+    assert_eq!(
+        Executable::parse(&bytes).and_then(|executable| executable.load()),
+        Err(ExecutableError::InvalidLoadAddress {
+            kind: SectionKind::Text,
+            addr: ROM_START + 2,
+            size: ROM_SIZE as usize
+        })
+    );
+}
+
+#[test]
+fn test_parse_legacy() {
+    let mut bytes = vec![0x44, 0x04, 0x00, 0x05, 0x00, 0x02, 0x48, 0x69];
+    bytes.extend_from_slice(&[0x00, 0x00, 0x05, 0xFE, 0x0C]);
+    bytes.extend_from_slice(&executable().sections[2].bytes);
+
+    assert_eq!(Executable::parse_legacy(&bytes).unwrap(), executable());
+    assert_eq!(
+        Executable::parse_legacy(&bytes[..20]).unwrap_err(),
+        ExecutableError::MissingSection(SectionKind::Ivt)
+    );
+}
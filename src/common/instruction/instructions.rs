@@ -1,229 +1,144 @@
 use super::opcode::AddressingMode;
 use super::opcode::Opcode;
-use crate::vcpu::cpu::Dump;
+use crate::vcpu::cpu::CpuFault;
 use crate::vcpu::cpu::Flags;
 use crate::vcpu::cpu::CPU;
-use crate::vcpu::device::map::DeviceMapResult;
-use crate::vcpu::device::DeviceResponse;
 
-pub type InstructionFunction = fn(&mut CPU);
-pub type InstructionInfo = (Opcode, AddressingMode, InstructionFunction, u8);
+pub type InstructionFunction = fn(&mut CPU) -> Result<(), CpuFault>;
+// Opcode, addressing mode, how it runs, how many arguments it takes and its cost in cycles.
+pub type InstructionInfo = (Opcode, AddressingMode, InstructionFunction, u8, u8);
 
-pub fn mov_immediate(cpu: &mut CPU) {
+pub fn mov_immediate(cpu: &mut CPU) -> Result<(), CpuFault> {
     let register: u8 = ((0xF0 & cpu.ir) >> 4) as u8;
-    *cpu.decode_register(register) = cpu.dr;
+    *cpu.decode_register(register)? = cpu.dr;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn mov_register(cpu: &mut CPU) {
+pub fn mov_register(cpu: &mut CPU) -> Result<(), CpuFault> {
     let register: u8 = ((0xF0 & cpu.ir) >> 4) as u8;
-    *cpu.decode_register(register) = *cpu.decode_register(cpu.dr as u8);
+    *cpu.decode_register(register)? = *cpu.decode_register(cpu.dr as u8)?;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn ld_register(cpu: &mut CPU) {
-    let address = *cpu.decode_register(cpu.dr as u8);
+pub fn ld_register(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let address = *cpu.decode_register(cpu.dr as u8)?;
 
-    let res = match cpu.map.read(address as u32) {
-        DeviceMapResult::Ok(res) => res,
-        DeviceMapResult::NoDevices => panic!("No devices attached. Could not read any values."),
-        DeviceMapResult::Error(err) => {
-            if err == DeviceResponse::WriteOnly {
-                panic!("Device write only. Could not read value.");
-            } else {
-                panic!("Unknown error. Could not read value.");
-            }
-        }
-    };
+    let res = cpu.read(address as u32)?;
 
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) = res;
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? = res;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn ld_address(cpu: &mut CPU) {
+pub fn ld_address(cpu: &mut CPU) -> Result<(), CpuFault> {
     let address: u32 = if cpu.flags.contains(Flags::D) {
         u32::from_be_bytes([0x00, cpu.ad, ((cpu.dr & 0xFF00) >> 8) as u8, cpu.dr as u8])
     } else {
         cpu.dr as u32
     };
 
-    let res = match cpu.map.read(address) {
-        DeviceMapResult::Ok(res) => res,
-        DeviceMapResult::NoDevices => panic!("No devices attached. Could not read any values."),
-        DeviceMapResult::Error(err) => {
-            if err == DeviceResponse::WriteOnly {
-                panic!("Device write only. Could not read value.");
-            } else {
-                panic!("Unknown error. Could not read value.");
-            }
-        }
-    };
+    let res = cpu.read(address)?;
 
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) = res;
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? = res;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn ldb_register(cpu: &mut CPU) {
-    let address = *cpu.decode_register(cpu.dr as u8);
+pub fn ldb_register(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let address = *cpu.decode_register(cpu.dr as u8)?;
 
-    let res = match cpu.map.read_byte(address as u32) {
-        DeviceMapResult::Ok(res) => res,
-        DeviceMapResult::NoDevices => panic!("No devices attached. Could not read any values."),
-        DeviceMapResult::Error(err) => {
-            if err == DeviceResponse::WriteOnly {
-                panic!("Device write only. Could not read value.");
-            } else {
-                panic!("Unknown error. Could not read value.");
-            }
-        }
-    };
+    let res = cpu.read_byte(address as u32)?;
 
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) = res as u16;
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? = res as u16;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn ldb_address(cpu: &mut CPU) {
+pub fn ldb_address(cpu: &mut CPU) -> Result<(), CpuFault> {
     let address: u32 = if cpu.flags.contains(Flags::D) {
         u32::from_be_bytes([0x00, cpu.ad, ((cpu.dr & 0xFF00) >> 8) as u8, cpu.dr as u8])
     } else {
         cpu.dr as u32
     };
 
-    let res = match cpu.map.read_byte(address) {
-        DeviceMapResult::Ok(res) => res,
-        DeviceMapResult::NoDevices => panic!("No devices attached. Could not read any values."),
-        DeviceMapResult::Error(err) => {
-            if err == DeviceResponse::WriteOnly {
-                panic!("Device write only. Could not read value.");
-            } else {
-                panic!("Unknown error. Could not read value.");
-            }
-        }
-    };
+    let res = cpu.read_byte(address)?;
 
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) = res as u16;
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? = res as u16;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn psh_immediate(cpu: &mut CPU) {
+pub fn psh_immediate(cpu: &mut CPU) -> Result<(), CpuFault> {
     // println!("Pushing 0x{:x} at address 0x{:x}", cpu.dr, cpu.sp);
-    match cpu.map.write(cpu.sp as u32, cpu.dr) {
-        DeviceMapResult::Ok(_) => (),
-        DeviceMapResult::NoDevices => panic!("No devices attached. Could not write any values."),
-        DeviceMapResult::Error(err) => {
-            if err == DeviceResponse::ReadOnly {
-                panic!("Device read only. Could not write value.");
-            } else {
-                panic!("Unknown error. Could not write value.");
-            }
-        }
-    };
-
-    cpu.sp += 2;
+    cpu.push(cpu.dr)?;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn psh_register(cpu: &mut CPU) {
-    let value = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8);
+pub fn psh_register(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let value = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)?;
     // println!("Register {} = {}", ((0xF0 & cpu.ir) >> 4) as u8, value);
     // println!("Pushing at address 0x{:X}", cpu.sp);
-    match cpu.map.write(cpu.sp as u32, value) {
-        DeviceMapResult::Ok(_) => (),
-        DeviceMapResult::NoDevices => panic!("No devices attached. Could not write any values."),
-        DeviceMapResult::Error(err) => {
-            if err == DeviceResponse::ReadOnly {
-                panic!("Device read only. Could not write value.");
-            } else {
-                panic!("Unknown error. Could not write value.");
-            }
-        }
-    };
-
-    cpu.sp += 2;
+    cpu.push(value)?;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn psh_address(cpu: &mut CPU) {
+pub fn psh_address(cpu: &mut CPU) -> Result<(), CpuFault> {
     let address: u32 = if cpu.flags.contains(Flags::D) {
         u32::from_be_bytes([0x00, cpu.ad, ((cpu.dr & 0xFF00) >> 8) as u8, cpu.dr as u8])
     } else {
         cpu.dr as u32
     };
 
-    let value = match cpu.map.read(address) {
-        DeviceMapResult::Ok(res) => res,
-        DeviceMapResult::NoDevices => panic!("No devices attached. Could not read any values."),
-        DeviceMapResult::Error(err) => {
-            if err == DeviceResponse::WriteOnly {
-                panic!("Device write only. Could not read value.");
-            } else {
-                panic!("Unknown error. Could not read value.");
-            }
-        }
-    };
-
-    match cpu.map.write(cpu.sp as u32, value) {
-        DeviceMapResult::Ok(_) => (),
-        DeviceMapResult::NoDevices => panic!("No devices attached. Could not write any values."),
-        DeviceMapResult::Error(err) => {
-            if err == DeviceResponse::ReadOnly {
-                panic!("Device read only. Could not write value.");
-            } else {
-                panic!("Unknown error. Could not write value.");
-            }
-        }
-    };
+    let value = cpu.read(address)?;
 
-    cpu.sp += 2;
+    cpu.push(value)?;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn pop(cpu: &mut CPU) {
-    cpu.sp -= 2;
+pub fn pop(cpu: &mut CPU) -> Result<(), CpuFault> {
+    cpu.pop()?;
     cpu.advance();
-}
 
-pub fn pop_register(cpu: &mut CPU) {
-    cpu.sp -= 2;
+    Ok(())
+}
 
-    let value = match cpu.map.read(cpu.sp as u32) {
-        DeviceMapResult::Ok(val) => val,
-        DeviceMapResult::NoDevices => panic!("No devices attached. Could not read any values."),
-        DeviceMapResult::Error(err) => {
-            if err == DeviceResponse::WriteOnly {
-                panic!("Device write only. Could not read value.");
-            } else {
-                panic!("Unknown error. Could not read value.");
-            }
-        }
-    };
+pub fn pop_register(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let value = cpu.pop()?;
 
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) = value;
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? = value;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn st_register(cpu: &mut CPU) {
-    let value = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8);
+pub fn st_register(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let value = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)?;
 
-    let address = *cpu.decode_register(cpu.dr as u8) as u32;
+    let address = *cpu.decode_register(cpu.dr as u8)? as u32;
 
-    match cpu.map.write(address, value) {
-        DeviceMapResult::Ok(_) => (),
-        DeviceMapResult::NoDevices => panic!("No devices attached. Could not write any values."),
-        DeviceMapResult::Error(err) => {
-            if err == DeviceResponse::ReadOnly {
-                panic!("Device read only. Could not write value.");
-            } else {
-                panic!("Unknown error. Could not write value.");
-            }
-        }
-    };
+    cpu.write(address, value)?;
 
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn st_address(cpu: &mut CPU) {
-    let value = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8);
+pub fn st_address(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let value = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)?;
 
     let address: u32 = if cpu.flags.contains(Flags::D) {
         u32::from_be_bytes([0x00, cpu.ad, ((cpu.dr & 0xFF00) >> 8) as u8, cpu.dr as u8])
@@ -231,46 +146,27 @@ pub fn st_address(cpu: &mut CPU) {
         cpu.dr as u32
     };
 
-    match cpu.map.write(address, value) {
-        DeviceMapResult::Ok(_) => (),
-        DeviceMapResult::NoDevices => panic!("No devices attached. Could not write any values."),
-        DeviceMapResult::Error(err) => {
-            if err == DeviceResponse::ReadOnly {
-                panic!("Device read only. Could not write value.");
-            } else {
-                panic!("Unknown error. Could not write value.");
-            }
-        }
-    };
+    cpu.write(address, value)?;
 
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn stl_register(cpu: &mut CPU) {
-    let value = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) as u8;
+pub fn stl_register(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let value = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? as u8;
 
-    let address = *cpu.decode_register(cpu.dr as u8) as u32;
+    let address = *cpu.decode_register(cpu.dr as u8)? as u32;
 
-    match cpu.map.write_byte(address, value) {
-        DeviceMapResult::Ok(_) => (),
-        DeviceMapResult::NoDevices => panic!(
-            "No devices attached. Could not write any values to address 0x{:x}.",
-            address
-        ),
-        DeviceMapResult::Error(err) => {
-            if err == DeviceResponse::ReadOnly {
-                panic!("Device read only. Could not write value.");
-            } else {
-                panic!("Unknown error. Could not write value.");
-            }
-        }
-    };
+    cpu.write_byte(address, value)?;
 
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn stl_address(cpu: &mut CPU) {
-    let value = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) as u8;
+pub fn stl_address(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let value = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? as u8;
 
     let address: u32 = if cpu.flags.contains(Flags::D) {
         u32::from_be_bytes([0x00, cpu.ad, ((cpu.dr & 0xFF00) >> 8) as u8, cpu.dr as u8])
@@ -280,43 +176,27 @@ pub fn stl_address(cpu: &mut CPU) {
 
     // println!("Storing val {} in address {}", value, address);
 
-    match cpu.map.write_byte(address, value) {
-        DeviceMapResult::Ok(_) => (),
-        DeviceMapResult::NoDevices => panic!("No devices attached. Could not write any values."),
-        DeviceMapResult::Error(err) => {
-            if err == DeviceResponse::ReadOnly {
-                panic!("Device read only. Could not write value.");
-            } else {
-                panic!("Unknown error. Could not write value.");
-            }
-        }
-    };
+    cpu.write_byte(address, value)?;
 
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn sth_register(cpu: &mut CPU) {
-    let value = ((*cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)) >> 8) as u8;
+pub fn sth_register(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let value = ((*cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)?) >> 8) as u8;
 
-    let address = *cpu.decode_register(cpu.dr as u8) as u32;
+    let address = *cpu.decode_register(cpu.dr as u8)? as u32;
 
-    match cpu.map.write_byte(address, value) {
-        DeviceMapResult::Ok(_) => (),
-        DeviceMapResult::NoDevices => panic!("No devices attached. Could not write any values."),
-        DeviceMapResult::Error(err) => {
-            if err == DeviceResponse::ReadOnly {
-                panic!("Device read only. Could not write value.");
-            } else {
-                panic!("Unknown error. Could not write value.");
-            }
-        }
-    };
+    cpu.write_byte(address, value)?;
 
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn sth_address(cpu: &mut CPU) {
-    let value = ((*cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)) >> 8) as u8;
+pub fn sth_address(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let value = ((*cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)?) >> 8) as u8;
 
     let address: u32 = if cpu.flags.contains(Flags::D) {
         u32::from_be_bytes([0x00, cpu.ad, ((cpu.dr & 0xFF00) >> 8) as u8, cpu.dr as u8])
@@ -324,19 +204,11 @@ pub fn sth_address(cpu: &mut CPU) {
         cpu.dr as u32
     };
 
-    match cpu.map.write_byte(address, value) {
-        DeviceMapResult::Ok(_) => (),
-        DeviceMapResult::NoDevices => panic!("No devices attached. Could not write any values."),
-        DeviceMapResult::Error(err) => {
-            if err == DeviceResponse::ReadOnly {
-                panic!("Device read only. Could not write value.");
-            } else {
-                panic!("Unknown error. Could not write value.");
-            }
-        }
-    };
+    cpu.write_byte(address, value)?;
 
     cpu.advance();
+
+    Ok(())
 }
 
 fn compare(cpu: &mut CPU, val1: u16, val2: u16) {
@@ -368,99 +240,92 @@ fn compare(cpu: &mut CPU, val1: u16, val2: u16) {
     // }
 }
 
-pub fn cmp_immediate(cpu: &mut CPU) {
-    cpu.dump(Dump::All);
-    let val1 = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8);
+pub fn cmp_immediate(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let val1 = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)?;
     let val2 = cpu.dr;
 
     compare(cpu, val1, val2);
 
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn cmp_register(cpu: &mut CPU) {
-    let val1 = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8);
-    let val2 = *cpu.decode_register(cpu.dr as u8);
+pub fn cmp_register(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let val1 = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)?;
+    let val2 = *cpu.decode_register(cpu.dr as u8)?;
 
     compare(cpu, val1, val2);
 
     cpu.advance();
+
+    Ok(())
 }
 
-fn branch_flag_set(cpu: &mut CPU, flag: Flags) {
+fn branch_flag_set(cpu: &mut CPU, flag: Flags) -> Result<(), CpuFault> {
     if cpu.flags.contains(flag) {
-        cpu.pc = if cpu.flags.contains(Flags::D) {
-            (cpu.dr << 4) | ((cpu.ad as u16) & 0xF)
-        } else {
-            cpu.dr
-        };
-        return;
+        cpu.jump(cpu.target());
+        return Ok(());
     }
 
     cpu.advance();
+
+    Ok(())
 }
 
-fn branch_flag_not_set(cpu: &mut CPU, flag: Flags) {
+fn branch_flag_not_set(cpu: &mut CPU, flag: Flags) -> Result<(), CpuFault> {
     if !cpu.flags.contains(flag) {
-        cpu.pc = if cpu.flags.contains(Flags::D) {
-            (cpu.dr << 4) | ((cpu.ad as u16) & 0xF)
-        } else {
-            cpu.dr
-        };
-        return;
+        cpu.jump(cpu.target());
+        return Ok(());
     }
 
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn beq(cpu: &mut CPU) {
-    branch_flag_set(cpu, Flags::Z);
+pub fn beq(cpu: &mut CPU) -> Result<(), CpuFault> {
+    branch_flag_set(cpu, Flags::Z)
 }
 
-pub fn bgt(cpu: &mut CPU) {
-    branch_flag_set(cpu, Flags::G);
+pub fn bgt(cpu: &mut CPU) -> Result<(), CpuFault> {
+    branch_flag_set(cpu, Flags::G)
 }
 
-pub fn blt(cpu: &mut CPU) {
-    branch_flag_set(cpu, Flags::L);
+pub fn blt(cpu: &mut CPU) -> Result<(), CpuFault> {
+    branch_flag_set(cpu, Flags::L)
 }
 
-pub fn bof(cpu: &mut CPU) {
-    branch_flag_set(cpu, Flags::O);
+pub fn bof(cpu: &mut CPU) -> Result<(), CpuFault> {
+    branch_flag_set(cpu, Flags::O)
 }
 
-pub fn bne(cpu: &mut CPU) {
-    branch_flag_not_set(cpu, Flags::Z);
+pub fn bne(cpu: &mut CPU) -> Result<(), CpuFault> {
+    branch_flag_not_set(cpu, Flags::Z)
 }
 
-pub fn jmp(cpu: &mut CPU) {
-    cpu.pc = if cpu.flags.contains(Flags::D) {
-        (cpu.dr << 4) | ((cpu.ad as u16) & 0xF)
-    } else {
-        cpu.dr
-    };
+pub fn jmp(cpu: &mut CPU) -> Result<(), CpuFault> {
+    cpu.jump(cpu.target());
+
+    Ok(())
 }
 
-pub fn jsr(cpu: &mut CPU) {
-    match cpu.map.write(cpu.sp as u32, cpu.pc + cpu.is as u16) {
-        DeviceMapResult::Ok(_) => (),
-        DeviceMapResult::NoDevices => panic!("No devices attached. Could not write any values."),
-        DeviceMapResult::Error(err) => {
-            if err == DeviceResponse::ReadOnly {
-                panic!("Device read only. Could not write value.");
-            } else {
-                panic!("Unknown error. Could not write value.");
-            }
-        }
-    };
+// Near calls only push the low 16 bits of the return address, so `ret` returns into the 64K the
+// subroutine runs in. Calls that cross into or out of far text take `jsrf` and `retf`.
+pub fn jsr(cpu: &mut CPU) -> Result<(), CpuFault> {
+    cpu.push(cpu.return_address() as u16)?;
 
-    cpu.sp += 2;
+    cpu.jump(cpu.target());
 
-    cpu.pc = if cpu.flags.contains(Flags::D) {
-        (cpu.dr << 4) | ((cpu.ad as u16) & 0xF)
-    } else {
-        cpu.dr
-    };
+    Ok(())
+}
+
+pub fn jsrf(cpu: &mut CPU) -> Result<(), CpuFault> {
+    cpu.push_address(cpu.return_address())?;
+
+    cpu.jump(cpu.target());
+
+    Ok(())
 }
 
 fn add(cpu: &mut CPU, val1: u16, val2: u16) -> u16 {
@@ -475,24 +340,28 @@ fn add(cpu: &mut CPU, val1: u16, val2: u16) -> u16 {
     result
 }
 
-pub fn add_immediate(cpu: &mut CPU) {
-    let val1 = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8);
+pub fn add_immediate(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let val1 = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)?;
     let val2 = cpu.dr;
 
     let result = add(cpu, val1, val2);
 
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) = result;
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? = result;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn add_register(cpu: &mut CPU) {
-    let val1 = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8);
-    let val2 = *cpu.decode_register(cpu.dr as u8);
+pub fn add_register(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let val1 = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)?;
+    let val2 = *cpu.decode_register(cpu.dr as u8)?;
 
     let result = add(cpu, val1, val2);
 
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) = result;
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? = result;
     cpu.advance();
+
+    Ok(())
 }
 
 fn sub(cpu: &mut CPU, val1: u16, val2: u16) -> u16 {
@@ -507,122 +376,136 @@ fn sub(cpu: &mut CPU, val1: u16, val2: u16) -> u16 {
     result
 }
 
-pub fn sub_immediate(cpu: &mut CPU) {
-    let val1 = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8);
+pub fn sub_immediate(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let val1 = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)?;
     let val2 = cpu.dr;
 
     let result = sub(cpu, val1, val2);
 
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) = result;
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? = result;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn sub_register(cpu: &mut CPU) {
-    let val1 = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8);
-    let val2 = *cpu.decode_register(cpu.dr as u8);
+pub fn sub_register(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let val1 = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)?;
+    let val2 = *cpu.decode_register(cpu.dr as u8)?;
 
     let result = sub(cpu, val1, val2);
 
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) = result;
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? = result;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn ret(cpu: &mut CPU) {
-    cpu.sp -= 2;
-    let addr = match cpu.map.read(cpu.sp as u32) {
-        DeviceMapResult::Ok(addr) => addr,
-        DeviceMapResult::NoDevices => panic!("No devices attached. Could not read any values."),
-        DeviceMapResult::Error(err) => {
-            if err == DeviceResponse::ReadOnly {
-                panic!("Device read only. Could not read value.");
-            } else {
-                panic!("Unknown error. Could not read value.");
-            }
-        }
-    };
+pub fn ret(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let addr = cpu.pop()?;
+    cpu.jump(((cpu.cs as u32) << 16) | addr as u32);
 
-    cpu.pc = addr;
+    Ok(())
 }
 
-pub fn int_immediate(cpu: &mut CPU) {
-    let addr = cpu.dr * 2;
-    // println!("Read addr {}", addr);
+pub fn retf(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let addr = cpu.pop_address()?;
+    cpu.jump(addr);
 
-    let jump_addr = match cpu.map.read(addr as u32) {
-        DeviceMapResult::Ok(value) => value,
-        DeviceMapResult::NoDevices => panic!("No devices attached. Could not read any values."),
-        DeviceMapResult::Error(err) => {
-            if err == DeviceResponse::ReadOnly {
-                panic!("Device read only. Could not read value.");
-            } else {
-                panic!("Unknown error. Could not read value.");
-            }
-        }
-    };
+    Ok(())
+}
+
+pub fn int_immediate(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let addr = cpu.dr as u32 * 2;
+    // println!("Read addr {}", addr);
 
-    cpu.push_registers();
+    let jump_addr = cpu.read(addr)?;
 
-    cpu.pc = jump_addr;
+    cpu.interrupt(jump_addr as u32)
 }
 
-pub fn and_immediate(cpu: &mut CPU) {
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) =
-        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) & cpu.dr;
+pub fn and_immediate(cpu: &mut CPU) -> Result<(), CpuFault> {
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? =
+        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? & cpu.dr;
     cpu.advance();
+
+    Ok(())
 }
-pub fn and_register(cpu: &mut CPU) {
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) =
-        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) & *cpu.decode_register(cpu.dr as u8);
+pub fn and_register(cpu: &mut CPU) -> Result<(), CpuFault> {
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? =
+        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? & *cpu.decode_register(cpu.dr as u8)?;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn or_immediate(cpu: &mut CPU) {
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) =
-        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) | cpu.dr;
+pub fn or_immediate(cpu: &mut CPU) -> Result<(), CpuFault> {
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? =
+        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? | cpu.dr;
     cpu.advance();
+
+    Ok(())
 }
-pub fn or_register(cpu: &mut CPU) {
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) =
-        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) | *cpu.decode_register(cpu.dr as u8);
+pub fn or_register(cpu: &mut CPU) -> Result<(), CpuFault> {
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? =
+        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? | *cpu.decode_register(cpu.dr as u8)?;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn xor_immediate(cpu: &mut CPU) {
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) =
-        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) ^ cpu.dr;
+pub fn xor_immediate(cpu: &mut CPU) -> Result<(), CpuFault> {
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? =
+        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? ^ cpu.dr;
     cpu.advance();
+
+    Ok(())
 }
-pub fn xor_register(cpu: &mut CPU) {
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) =
-        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) ^ *cpu.decode_register(cpu.dr as u8);
+pub fn xor_register(cpu: &mut CPU) -> Result<(), CpuFault> {
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? =
+        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? ^ *cpu.decode_register(cpu.dr as u8)?;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn lsh_immediate(cpu: &mut CPU) {
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) =
-        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) << cpu.dr;
+pub fn lsh_immediate(cpu: &mut CPU) -> Result<(), CpuFault> {
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? =
+        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? << cpu.dr;
     cpu.advance();
+
+    Ok(())
 }
-pub fn lsh_register(cpu: &mut CPU) {
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) =
-        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) << *cpu.decode_register(cpu.dr as u8);
+pub fn lsh_register(cpu: &mut CPU) -> Result<(), CpuFault> {
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? = *cpu
+        .decode_register(((0xF0 & cpu.ir) >> 4) as u8)?
+        << *cpu.decode_register(cpu.dr as u8)?;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn rsh_immediate(cpu: &mut CPU) {
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) =
-        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) >> cpu.dr;
+pub fn rsh_immediate(cpu: &mut CPU) -> Result<(), CpuFault> {
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? =
+        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? >> cpu.dr;
     cpu.advance();
+
+    Ok(())
 }
-pub fn rsh_register(cpu: &mut CPU) {
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) =
-        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) >> *cpu.decode_register(cpu.dr as u8);
+pub fn rsh_register(cpu: &mut CPU) -> Result<(), CpuFault> {
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? = *cpu
+        .decode_register(((0xF0 & cpu.ir) >> 4) as u8)?
+        >> *cpu.decode_register(cpu.dr as u8)?;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn rei(cpu: &mut CPU) {
-    println!("Returning from interrupt.");
-    cpu.pop_registers();
+pub fn rei(cpu: &mut CPU) -> Result<(), CpuFault> {
+    if cpu.debug_mode {
+        println!("Returning from interrupt.");
+    }
+
+    cpu.pop_registers()
 }
 
 fn mul(cpu: &mut CPU, val1: u16, val2: u16) -> u16 {
@@ -637,69 +520,103 @@ fn mul(cpu: &mut CPU, val1: u16, val2: u16) -> u16 {
     result
 }
 
-pub fn mul_immediate(cpu: &mut CPU) {
-    let val1 = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8);
+pub fn mul_immediate(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let val1 = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)?;
     let val2 = cpu.dr;
 
     let result = mul(cpu, val1, val2);
 
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) = result;
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? = result;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn mul_register(cpu: &mut CPU) {
-    let val1 = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8);
-    let val2 = *cpu.decode_register(cpu.dr as u8);
+pub fn mul_register(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let val1 = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)?;
+    let val2 = *cpu.decode_register(cpu.dr as u8)?;
 
     let result = mul(cpu, val1, val2);
 
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) = result;
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? = result;
     cpu.advance();
+
+    Ok(())
+}
+
+fn modulo(val1: u16, val2: u16) -> Result<u16, CpuFault> {
+    match val1.checked_rem(val2) {
+        Some(result) => Ok(result),
+        None => Err(CpuFault::DivideByZero),
+    }
 }
 
-pub fn mod_immediate(cpu: &mut CPU) {
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) =
-        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) % cpu.dr;
+pub fn mod_immediate(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let val1 = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)?;
+    let val2 = cpu.dr;
+
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? = modulo(val1, val2)?;
     cpu.advance();
+
+    Ok(())
 }
-pub fn mod_register(cpu: &mut CPU) {
-    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) =
-        *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8) % *cpu.decode_register(cpu.dr as u8);
+
+pub fn mod_register(cpu: &mut CPU) -> Result<(), CpuFault> {
+    let val1 = *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)?;
+    let val2 = *cpu.decode_register(cpu.dr as u8)?;
+
+    *cpu.decode_register(((0xF0 & cpu.ir) >> 4) as u8)? = modulo(val1, val2)?;
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn ble(cpu: &mut CPU) {
+pub fn ble(cpu: &mut CPU) -> Result<(), CpuFault> {
     // cpu.dump(Dump::All);
     if cpu.flags.contains(Flags::L) && cpu.flags.contains(Flags::Z) {
-        cpu.pc = if cpu.flags.contains(Flags::D) {
-            (cpu.dr << 4) | ((cpu.ad as u16) & 0xF)
-        } else {
-            cpu.dr
-        };
-        return;
+        cpu.jump(cpu.target());
+        return Ok(());
     }
 
     cpu.advance();
+
+    Ok(())
 }
 
-pub fn bge(cpu: &mut CPU) {
+pub fn bge(cpu: &mut CPU) -> Result<(), CpuFault> {
     // cpu.dump(Dump::All);
     if cpu.flags.contains(Flags::G) && cpu.flags.contains(Flags::Z) {
-        cpu.pc = if cpu.flags.contains(Flags::D) {
-            (cpu.dr << 4) | ((cpu.ad as u16) & 0xF)
-        } else {
-            cpu.dr
-        };
-        return;
+        cpu.jump(cpu.target());
+        return Ok(());
     }
 
     cpu.advance();
+
+    Ok(())
+}
+
+pub fn cli(cpu: &mut CPU) -> Result<(), CpuFault> {
+    cpu.flags.remove(Flags::I);
+    cpu.advance();
+
+    Ok(())
+}
+
+pub fn sti(cpu: &mut CPU) -> Result<(), CpuFault> {
+    cpu.flags.insert(Flags::I);
+    cpu.advance();
+
+    Ok(())
 }
 
-pub fn hlt(cpu: &mut CPU) {
+pub fn hlt(cpu: &mut CPU) -> Result<(), CpuFault> {
     cpu.running = false;
+
+    Ok(())
 }
 
-pub fn nop(cpu: &mut CPU) {
-    cpu.advance()
+pub fn nop(cpu: &mut CPU) -> Result<(), CpuFault> {
+    cpu.advance();
+
+    Ok(())
 }
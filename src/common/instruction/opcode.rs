@@ -1,14 +1,14 @@
 #![allow(clippy::unusual_byte_groupings)]
 
-use std::collections::HashMap;
-
 use super::instructions::*;
 
 use core::fmt::Debug;
 use strum::IntoEnumIterator;
 use strum_macros::{EnumIter, EnumString};
 
-pub type InstructionMap = HashMap<u8, InstructionInfo>;
+pub type DecodeTable = [Option<InstructionInfo>; 256];
+
+pub static DECODE_TABLE: DecodeTable = Instruction::decode_table();
 
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString)]
@@ -44,6 +44,10 @@ pub enum Opcode {
     MOD = 0b011011,
     BGE = 0b011100,
     BLE = 0b011101,
+    CLI = 0b011110,
+    STI = 0b011111,
+    JSRF = 0b100000,
+    RETF = 0b100001,
     HLT = 0b111110,
     NOP = 0b111111,
 }
@@ -60,6 +64,7 @@ pub struct Instruction {
     pub opcode: Opcode,
     pub mode: AddressingMode,
     pub exec: InstructionFunction,
+    pub cycles: u8,
 }
 
 impl Debug for Instruction {
@@ -67,6 +72,7 @@ impl Debug for Instruction {
         f.debug_struct("Instruction")
             .field("opcode", &self.opcode)
             .field("mode", &self.mode)
+            .field("cycles", &self.cycles)
             .finish()
     }
 }
@@ -79,252 +85,130 @@ pub enum InstructionError {
 pub type InstructionResult = Result<Instruction, InstructionError>;
 
 impl Instruction {
-    pub fn hashmap() -> InstructionMap {
-        let mut map: InstructionMap = HashMap::new();
-        map.insert(
-            Self::create_opcode(Opcode::MOV, AddressingMode::Immediate),
-            (Opcode::MOV, AddressingMode::Immediate, mov_immediate, 2),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::MOV, AddressingMode::Register),
-            (Opcode::MOV, AddressingMode::Register, mov_register, 2),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::MOV, AddressingMode::Direct),
-            (Opcode::MOV, AddressingMode::Direct, mov_immediate, 2),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::LD, AddressingMode::Register),
-            (Opcode::LD, AddressingMode::Register, ld_register, 2),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::LD, AddressingMode::Direct),
-            (Opcode::LD, AddressingMode::Direct, ld_address, 2),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::LDB, AddressingMode::Register),
-            (Opcode::LDB, AddressingMode::Register, ldb_register, 2),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::LDB, AddressingMode::Direct),
-            (Opcode::LDB, AddressingMode::Direct, ldb_address, 2),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::PSH, AddressingMode::Immediate),
-            (Opcode::PSH, AddressingMode::Immediate, psh_immediate, 1),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::PSH, AddressingMode::Register),
-            (Opcode::PSH, AddressingMode::Register, psh_register, 1),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::PSH, AddressingMode::Direct),
-            (Opcode::PSH, AddressingMode::Direct, psh_address, 1),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::POP, AddressingMode::Register),
-            (Opcode::POP, AddressingMode::Register, pop_register, 1),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::POP, AddressingMode::Discard),
-            (Opcode::POP, AddressingMode::Discard, pop, 0),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::ST, AddressingMode::Register),
-            (Opcode::ST, AddressingMode::Register, st_register, 2),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::ST, AddressingMode::Direct),
-            (Opcode::ST, AddressingMode::Direct, st_address, 2),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::STL, AddressingMode::Register),
-            (Opcode::STL, AddressingMode::Register, stl_register, 2),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::STL, AddressingMode::Direct),
-            (Opcode::STL, AddressingMode::Direct, stl_address, 2),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::STH, AddressingMode::Register),
-            (Opcode::STH, AddressingMode::Register, sth_register, 2),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::STH, AddressingMode::Direct),
-            (Opcode::STH, AddressingMode::Direct, sth_address, 2),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::CMP, AddressingMode::Immediate),
-            (Opcode::CMP, AddressingMode::Immediate, cmp_immediate, 2),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::CMP, AddressingMode::Register),
-            (Opcode::CMP, AddressingMode::Register, cmp_register, 2),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::BEQ, AddressingMode::Direct),
-            (Opcode::BEQ, AddressingMode::Direct, beq, 1),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::BGT, AddressingMode::Direct),
-            (Opcode::BGT, AddressingMode::Direct, bgt, 1),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::BLT, AddressingMode::Direct),
-            (Opcode::BLT, AddressingMode::Direct, blt, 1),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::BOF, AddressingMode::Direct),
-            (Opcode::BOF, AddressingMode::Direct, bof, 1),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::BNE, AddressingMode::Direct),
-            (Opcode::BNE, AddressingMode::Direct, bne, 1),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::JMP, AddressingMode::Direct),
-            (Opcode::JMP, AddressingMode::Direct, jmp, 1),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::JSR, AddressingMode::Direct),
-            (Opcode::JSR, AddressingMode::Direct, jsr, 1),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::ADD, AddressingMode::Immediate),
-            (Opcode::ADD, AddressingMode::Immediate, add_immediate, 2),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::ADD, AddressingMode::Register),
-            (Opcode::ADD, AddressingMode::Register, add_register, 2),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::SUB, AddressingMode::Immediate),
-            (Opcode::SUB, AddressingMode::Immediate, sub_immediate, 2),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::SUB, AddressingMode::Register),
-            (Opcode::SUB, AddressingMode::Register, sub_register, 2),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::RET, AddressingMode::Discard),
-            (Opcode::RET, AddressingMode::Discard, ret, 0),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::INT, AddressingMode::Immediate),
-            (Opcode::INT, AddressingMode::Immediate, int_immediate, 1),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::REI, AddressingMode::Discard),
-            (Opcode::REI, AddressingMode::Discard, rei, 0),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::AND, AddressingMode::Immediate),
-            (Opcode::AND, AddressingMode::Direct, and_immediate, 2),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::AND, AddressingMode::Register),
-            (Opcode::AND, AddressingMode::Register, and_register, 2),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::OR, AddressingMode::Immediate),
-            (Opcode::OR, AddressingMode::Direct, or_immediate, 2),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::OR, AddressingMode::Register),
-            (Opcode::OR, AddressingMode::Register, or_register, 2),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::XOR, AddressingMode::Immediate),
-            (Opcode::XOR, AddressingMode::Direct, xor_immediate, 2),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::XOR, AddressingMode::Register),
-            (Opcode::XOR, AddressingMode::Register, xor_register, 2),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::LSH, AddressingMode::Immediate),
-            (Opcode::LSH, AddressingMode::Direct, lsh_immediate, 2),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::AND, AddressingMode::Register),
-            (Opcode::LSH, AddressingMode::Register, lsh_register, 2),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::RSH, AddressingMode::Immediate),
-            (Opcode::RSH, AddressingMode::Direct, rsh_immediate, 2),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::RSH, AddressingMode::Register),
-            (Opcode::RSH, AddressingMode::Register, rsh_register, 2),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::MUL, AddressingMode::Immediate),
-            (Opcode::MUL, AddressingMode::Immediate, mul_immediate, 2),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::MUL, AddressingMode::Register),
-            (Opcode::MUL, AddressingMode::Register, mul_register, 2),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::MOD, AddressingMode::Immediate),
-            (Opcode::MOD, AddressingMode::Direct, mod_immediate, 2),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::MOD, AddressingMode::Register),
-            (Opcode::MOD, AddressingMode::Register, mod_register, 2),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::BGE, AddressingMode::Direct),
-            (Opcode::BNE, AddressingMode::Direct, bge, 1),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::BLE, AddressingMode::Direct),
-            (Opcode::BNE, AddressingMode::Direct, ble, 1),
-        );
-
-        map.insert(
-            Self::create_opcode(Opcode::HLT, AddressingMode::Discard),
-            (Opcode::HLT, AddressingMode::Discard, hlt, 0),
-        );
-        map.insert(
-            Self::create_opcode(Opcode::NOP, AddressingMode::Discard),
-            (Opcode::NOP, AddressingMode::Discard, nop, 0),
-        );
-
-        map
+    /*
+    Built at compile time so decoding an instruction is a single index into `DECODE_TABLE`. The
+    last column is what the instruction costs in clock cycles: 2 for most of them, more for longer
+    encodings and memory accesses, and the most for multiplying, dividing and interrupts.
+     */
+    pub const fn decode_table() -> DecodeTable {
+        macro_rules! entry {
+            ($table:ident, $opcode:ident, $mode:ident, $exec:ident, $args:expr, $cycles:expr) => {
+                $table[Self::create_opcode(Opcode::$opcode, AddressingMode::$mode) as usize] =
+                    Some((
+                        Opcode::$opcode,
+                        AddressingMode::$mode,
+                        $exec,
+                        $args,
+                        $cycles,
+                    ));
+            };
+        }
+
+        let mut table: DecodeTable = [None; 256];
+
+        entry!(table, MOV, Immediate, mov_immediate, 2, 2);
+        entry!(table, MOV, Register, mov_register, 2, 2);
+        entry!(table, MOV, Direct, mov_immediate, 2, 3);
+
+        entry!(table, LD, Register, ld_register, 2, 3);
+        entry!(table, LD, Direct, ld_address, 2, 4);
+
+        entry!(table, LDB, Register, ldb_register, 2, 3);
+        entry!(table, LDB, Direct, ldb_address, 2, 4);
+
+        entry!(table, PSH, Immediate, psh_immediate, 1, 3);
+        entry!(table, PSH, Register, psh_register, 1, 3);
+        entry!(table, PSH, Direct, psh_address, 1, 4);
+
+        entry!(table, POP, Register, pop_register, 1, 3);
+        entry!(table, POP, Discard, pop, 0, 2);
+
+        entry!(table, ST, Register, st_register, 2, 3);
+        entry!(table, ST, Direct, st_address, 2, 4);
+
+        entry!(table, STL, Register, stl_register, 2, 3);
+        entry!(table, STL, Direct, stl_address, 2, 4);
+
+        entry!(table, STH, Register, sth_register, 2, 3);
+        entry!(table, STH, Direct, sth_address, 2, 4);
+
+        entry!(table, CMP, Immediate, cmp_immediate, 2, 2);
+        entry!(table, CMP, Register, cmp_register, 2, 2);
+
+        entry!(table, BEQ, Direct, beq, 1, 3);
+        entry!(table, BGT, Direct, bgt, 1, 3);
+        entry!(table, BLT, Direct, blt, 1, 3);
+        entry!(table, BOF, Direct, bof, 1, 3);
+        entry!(table, BNE, Direct, bne, 1, 3);
+
+        entry!(table, JMP, Direct, jmp, 1, 3);
+        entry!(table, JSR, Direct, jsr, 1, 5);
+
+        entry!(table, ADD, Immediate, add_immediate, 2, 2);
+        entry!(table, ADD, Register, add_register, 2, 2);
+
+        entry!(table, SUB, Immediate, sub_immediate, 2, 2);
+        entry!(table, SUB, Register, sub_register, 2, 2);
+
+        entry!(table, RET, Discard, ret, 0, 4);
+
+        entry!(table, INT, Immediate, int_immediate, 1, 8);
+
+        entry!(table, REI, Discard, rei, 0, 8);
+
+        entry!(table, AND, Immediate, and_immediate, 2, 2);
+        entry!(table, AND, Register, and_register, 2, 2);
+
+        entry!(table, OR, Immediate, or_immediate, 2, 2);
+        entry!(table, OR, Register, or_register, 2, 2);
+
+        entry!(table, XOR, Immediate, xor_immediate, 2, 2);
+        entry!(table, XOR, Register, xor_register, 2, 2);
+
+        entry!(table, LSH, Immediate, lsh_immediate, 2, 2);
+        entry!(table, LSH, Register, lsh_register, 2, 2);
+
+        entry!(table, RSH, Immediate, rsh_immediate, 2, 2);
+        entry!(table, RSH, Register, rsh_register, 2, 2);
+
+        entry!(table, MUL, Immediate, mul_immediate, 2, 6);
+        entry!(table, MUL, Register, mul_register, 2, 6);
+
+        entry!(table, MOD, Immediate, mod_immediate, 2, 10);
+        entry!(table, MOD, Register, mod_register, 2, 10);
+
+        entry!(table, BGE, Direct, bge, 1, 3);
+        entry!(table, BLE, Direct, ble, 1, 3);
+
+        entry!(table, CLI, Discard, cli, 0, 1);
+        entry!(table, STI, Discard, sti, 0, 1);
+
+        entry!(table, JSRF, Direct, jsrf, 1, 6);
+        entry!(table, RETF, Discard, retf, 0, 5);
+
+        entry!(table, HLT, Discard, hlt, 0, 1);
+        entry!(table, NOP, Discard, nop, 0, 1);
+
+        table
+    }
+
+    pub fn lookup(opcode: u8) -> Option<&'static InstructionInfo> {
+        DECODE_TABLE[opcode as usize].as_ref()
+    }
+
+    // Every valid opcode byte in ascending order.
+    pub fn entries() -> impl Iterator<Item = (u8, &'static InstructionInfo)> {
+        DECODE_TABLE
+            .iter()
+            .enumerate()
+            .filter_map(|(code, info)| info.as_ref().map(|info| (code as u8, info)))
     }
 
     pub fn get_variants(opcode: Opcode) -> Vec<AddressingMode> {
-        let map = Instruction::hashmap();
         let mut res: Vec<AddressingMode> = Vec::new();
 
         for mode in AddressingMode::iter() {
-            let code = map.get(&Instruction::create_opcode(opcode, mode));
-
-            if code.is_some() {
+            if Instruction::lookup(Instruction::create_opcode(opcode, mode)).is_some() {
                 res.push(mode);
             }
         }
@@ -341,8 +225,7 @@ impl Instruction {
     // }
 
     pub fn from_opcode(opcode: &u8) -> InstructionResult {
-        let binding = Instruction::hashmap();
-        let result: &InstructionInfo = match binding.get(opcode) {
+        let result: &InstructionInfo = match Instruction::lookup(*opcode) {
             Some(val) => val,
             None => return Err(InstructionError::InvalidOpcode),
         };
@@ -351,10 +234,11 @@ impl Instruction {
             opcode: result.0,
             mode: result.1,
             exec: result.2,
+            cycles: result.4,
         })
     }
 
-    pub fn create_opcode(opcode: Opcode, mode: AddressingMode) -> u8 {
+    pub const fn create_opcode(opcode: Opcode, mode: AddressingMode) -> u8 {
         let num_code = opcode as u8;
         let mode_code = mode as u8;
 
@@ -37,7 +37,7 @@ fn test_from_opcode_instruction() {
 #[test]
 #[should_panic]
 fn test_from_opcode_instruction_fail() {
-    match Instruction::from_opcode(&(0b101_00000 as u8)) {
+    match Instruction::from_opcode(&0b101_00010) {
         Ok(res) => res,
         Err(_) => panic!("Opcode does not exist."),
     };
@@ -45,7 +45,9 @@ fn test_from_opcode_instruction_fail() {
 
 #[test]
 fn test_mov_immediate_byte() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x00, 0x00, 0xAB], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x00, 0x00, 0xAB], 0x0000, 3).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -54,33 +56,34 @@ fn test_mov_immediate_byte() {
     cpu.map = map;
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0xAB);
 }
 
 #[test]
 fn test_mov_immediate_word() {
-    let rom = Arc::new(Mutex::new(Rom::new(
-        vec![0x00, 0x04, 0xAB, 0xCD],
-        0x0000,
-        4,
-    )));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x00, 0x04, 0xAB, 0xCD], 0x0000, 4).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
 
     let mut cpu = CPU::new(0x0000, 0xFFFF, false);
+    cpu.map = map;
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0xABCD);
 }
 
 #[test]
 fn test_mov_register() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x40, 0x00, 0x1], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x40, 0x00, 0x1], 0x0000, 3).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -90,7 +93,7 @@ fn test_mov_register() {
     cpu.r2 = 0xABCD;
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0xABCD);
     assert_eq!(cpu.r2, 0xABCD);
@@ -98,11 +101,9 @@ fn test_mov_register() {
 
 #[test]
 fn test_ld_register() {
-    let rom = Arc::new(Mutex::new(Rom::new(
-        vec![0x41, 0x00, 0x1, 0xAB, 0xCD],
-        0x0000,
-        5,
-    )));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x41, 0x00, 0x1, 0xAB, 0xCD], 0x0000, 5).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -112,18 +113,16 @@ fn test_ld_register() {
     cpu.r2 = 0x0003;
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0xABCD);
 }
 
 #[test]
 fn test_ld_address() {
-    let rom = Arc::new(Mutex::new(Rom::new(
-        vec![0x81, 0x00, 0x03, 0xAB, 0xCD],
-        0x0000,
-        5,
-    )));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x81, 0x00, 0x03, 0xAB, 0xCD], 0x0000, 5).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -132,14 +131,16 @@ fn test_ld_address() {
     cpu.map = map;
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0xABCD);
 }
 
 #[test]
 fn test_ldb_register() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x42, 0x00, 0x1, 0xCD], 0x0000, 4)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x42, 0x00, 0x1, 0xCD], 0x0000, 4).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -149,18 +150,16 @@ fn test_ldb_register() {
     cpu.r2 = 0x0003;
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0xCD);
 }
 
 #[test]
 fn test_ldb_address() {
-    let rom = Arc::new(Mutex::new(Rom::new(
-        vec![0x82, 0x00, 0x03, 0xCD],
-        0x0000,
-        4,
-    )));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x82, 0x00, 0x03, 0xCD], 0x0000, 4).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -169,14 +168,16 @@ fn test_ldb_address() {
     cpu.map = map;
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0xCD);
 }
 
 #[test]
 fn test_cmp_immediate_eq() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x08, 0x20, 0x5], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x08, 0x20, 0x5], 0x0000, 3).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -188,7 +189,7 @@ fn test_cmp_immediate_eq() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert!(cpu.flags.contains(Flags::Z));
     assert!(!cpu.flags.contains(Flags::G));
@@ -197,7 +198,9 @@ fn test_cmp_immediate_eq() {
 
 #[test]
 fn test_cmp_immediate_lt() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x08, 0x20, 0x5], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x08, 0x20, 0x5], 0x0000, 3).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -209,7 +212,7 @@ fn test_cmp_immediate_lt() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert!(cpu.flags.contains(Flags::L));
     assert!(!cpu.flags.contains(Flags::Z));
@@ -218,7 +221,9 @@ fn test_cmp_immediate_lt() {
 
 #[test]
 fn test_cmp_immediate_gt() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x08, 0x20, 0x5], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x08, 0x20, 0x5], 0x0000, 3).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -230,7 +235,7 @@ fn test_cmp_immediate_gt() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert!(cpu.flags.contains(Flags::G));
     assert!(!cpu.flags.contains(Flags::L));
@@ -239,7 +244,9 @@ fn test_cmp_immediate_gt() {
 
 #[test]
 fn test_cmp_register_eq() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x48, 0x20, 0x0], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x48, 0x20, 0x0], 0x0000, 3).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -252,7 +259,7 @@ fn test_cmp_register_eq() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert!(cpu.flags.contains(Flags::Z));
     assert!(!cpu.flags.contains(Flags::G));
@@ -261,7 +268,9 @@ fn test_cmp_register_eq() {
 
 #[test]
 fn test_cmp_register_lt() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x48, 0x20, 0x0], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x48, 0x20, 0x0], 0x0000, 3).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -274,7 +283,7 @@ fn test_cmp_register_lt() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert!(cpu.flags.contains(Flags::L));
     assert!(!cpu.flags.contains(Flags::Z));
@@ -283,7 +292,9 @@ fn test_cmp_register_lt() {
 
 #[test]
 fn test_cmp_register_gt() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x48, 0x20, 0x0], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x48, 0x20, 0x0], 0x0000, 3).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -296,7 +307,7 @@ fn test_cmp_register_gt() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert!(cpu.flags.contains(Flags::G));
     assert!(!cpu.flags.contains(Flags::L));
@@ -304,11 +315,9 @@ fn test_cmp_register_gt() {
 }
 
 fn test_branch_flag_is_set(opcode: u8, flag: Flags) {
-    let rom = Arc::new(Mutex::new(Rom::new(
-        vec![opcode, 0x00, 0x05, 0xFF, 0x0C, 0xFF, 0x0C],
-        0x0000,
-        7,
-    )));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![opcode, 0x00, 0x05, 0xFF, 0x0C, 0xFF, 0x0C], 0x0000, 7).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -319,17 +328,15 @@ fn test_branch_flag_is_set(opcode: u8, flag: Flags) {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.pc, 0x05);
 }
 
 fn test_no_branch_flag_is_not_set(opcode: u8, flag: Flags) {
-    let rom = Arc::new(Mutex::new(Rom::new(
-        vec![opcode, 0x00, 0x05, 0xFF, 0x0C, 0xFF, 0x0C],
-        0x0000,
-        7,
-    )));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![opcode, 0x00, 0x05, 0xFF, 0x0C, 0xFF, 0x0C], 0x0000, 7).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -340,17 +347,15 @@ fn test_no_branch_flag_is_not_set(opcode: u8, flag: Flags) {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.pc, 0x03);
 }
 
 fn test_branch_flag_if_not_set(opcode: u8, flag: Flags) {
-    let rom = Arc::new(Mutex::new(Rom::new(
-        vec![opcode, 0x00, 0x05, 0xFF, 0x0C, 0xFF, 0x0C],
-        0x0000,
-        7,
-    )));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![opcode, 0x00, 0x05, 0xFF, 0x0C, 0xFF, 0x0C], 0x0000, 7).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -361,17 +366,15 @@ fn test_branch_flag_if_not_set(opcode: u8, flag: Flags) {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.pc, 0x05);
 }
 
 fn test_no_branch_flag_is_set(opcode: u8, flag: Flags) {
-    let rom = Arc::new(Mutex::new(Rom::new(
-        vec![opcode, 0x00, 0x05, 0xFF, 0x0C, 0xFF, 0x0C],
-        0x0000,
-        7,
-    )));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![opcode, 0x00, 0x05, 0xFF, 0x0C, 0xFF, 0x0C], 0x0000, 7).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -382,7 +385,7 @@ fn test_no_branch_flag_is_set(opcode: u8, flag: Flags) {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.pc, 0x03);
 }
@@ -439,11 +442,9 @@ fn test_bne_is_set() {
 
 #[test]
 fn test_jmp() {
-    let rom = Arc::new(Mutex::new(Rom::new(
-        vec![0x8E, 0x00, 0x05, 0xFF, 0x0C, 0xFF, 0x0C],
-        0x0000,
-        7,
-    )));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x8E, 0x00, 0x05, 0xFF, 0x0C, 0xFF, 0x0C], 0x0000, 7).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -453,18 +454,16 @@ fn test_jmp() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.pc, 0x05);
 }
 
 #[test]
 fn test_hlt() {
-    let rom = Arc::new(Mutex::new(Rom::new(
-        vec![0xFE, 0x0C, 0xFF, 0x0C],
-        0x0000,
-        4,
-    )));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0xFE, 0x0C, 0xFF, 0x0C], 0x0000, 4).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -474,19 +473,17 @@ fn test_hlt() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.pc, 0x0000);
-    assert_eq!(cpu.running, false);
+    assert!(!cpu.running);
 }
 
 #[test]
 fn test_nop() {
-    let rom = Arc::new(Mutex::new(Rom::new(
-        vec![0xFF, 0x0C, 0xFE, 0x0C],
-        0x0000,
-        4,
-    )));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0xFF, 0x0C, 0xFE, 0x0C], 0x0000, 4).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -496,14 +493,16 @@ fn test_nop() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.pc, 0x0002);
 }
 
 #[test]
 fn test_add_immediate_normal() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x10, 0x00, 0x5], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x10, 0x00, 0x5], 0x0000, 3).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -515,7 +514,7 @@ fn test_add_immediate_normal() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0x7);
     assert!(!cpu.flags.contains(Flags::O));
@@ -523,11 +522,9 @@ fn test_add_immediate_normal() {
 
 #[test]
 fn test_add_immediate_overflow() {
-    let rom = Arc::new(Mutex::new(Rom::new(
-        vec![0x10, 0x04, 0xFF, 0xFE],
-        0x0000,
-        4,
-    )));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x10, 0x04, 0xFF, 0xFE], 0x0000, 4).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -539,7 +536,7 @@ fn test_add_immediate_overflow() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0x00); // Wraps the value when a overflow occurs.
     assert!(cpu.flags.contains(Flags::O));
@@ -547,7 +544,9 @@ fn test_add_immediate_overflow() {
 
 #[test]
 fn test_add_register_normal() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x50, 0x00, 0x1], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x50, 0x00, 0x1], 0x0000, 3).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -560,7 +559,7 @@ fn test_add_register_normal() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0x7);
     assert!(!cpu.flags.contains(Flags::O));
@@ -568,7 +567,9 @@ fn test_add_register_normal() {
 
 #[test]
 fn test_add_register_overflow() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x50, 0x00, 0x01], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x50, 0x00, 0x01], 0x0000, 3).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -581,7 +582,7 @@ fn test_add_register_overflow() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0x00); // Wraps the value when a overflow occurs.
     assert!(cpu.flags.contains(Flags::O));
@@ -589,7 +590,9 @@ fn test_add_register_overflow() {
 
 #[test]
 fn test_sub_immediate_normal() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x11, 0x00, 0x2], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x11, 0x00, 0x2], 0x0000, 3).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -601,7 +604,7 @@ fn test_sub_immediate_normal() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0x3);
     assert!(!cpu.flags.contains(Flags::O));
@@ -609,7 +612,9 @@ fn test_sub_immediate_normal() {
 
 #[test]
 fn test_sub_immediate_overflow() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x11, 0x00, 0x01], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x11, 0x00, 0x01], 0x0000, 3).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -621,7 +626,7 @@ fn test_sub_immediate_overflow() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0xFFFF); // Wraps the value when a overflow occurs.
     assert!(cpu.flags.contains(Flags::O));
@@ -629,7 +634,9 @@ fn test_sub_immediate_overflow() {
 
 #[test]
 fn test_sub_register_normal() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x51, 0x00, 0x1], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x51, 0x00, 0x1], 0x0000, 3).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -642,7 +649,7 @@ fn test_sub_register_normal() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0x3);
     assert!(!cpu.flags.contains(Flags::O));
@@ -650,7 +657,9 @@ fn test_sub_register_normal() {
 
 #[test]
 fn test_sub_register_overflow() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x51, 0x00, 0x01], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x51, 0x00, 0x01], 0x0000, 3).unwrap(),
+    ));
 
     let mut map = DeviceMap::new();
     map.add(rom);
@@ -663,7 +672,7 @@ fn test_sub_register_overflow() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0xFFFF); // Wraps the value when a overflow occurs.
     assert!(cpu.flags.contains(Flags::O));
@@ -671,7 +680,9 @@ fn test_sub_register_overflow() {
 
 #[test]
 fn test_psh_immediate() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x03, 0x00, 0x05], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x03, 0x00, 0x05], 0x0000, 3).unwrap(),
+    ));
     let ram = Arc::new(Mutex::new(Ram::new(0x07, 0x20)));
 
     let mut map = DeviceMap::new();
@@ -683,7 +694,7 @@ fn test_psh_immediate() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.sp, 0x09);
     assert_eq!(
@@ -698,7 +709,7 @@ fn test_psh_immediate() {
 
 #[test]
 fn test_psh_register() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x43, 0x0C], 0x0000, 2)));
+    let rom = Arc::new(Mutex::new(Rom::new(vec![0x43, 0x0C], 0x0000, 2).unwrap()));
     let ram = Arc::new(Mutex::new(Ram::new(0x07, 0x20)));
 
     let mut map = DeviceMap::new();
@@ -711,7 +722,7 @@ fn test_psh_register() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.sp, 0x09);
     assert_eq!(
@@ -726,7 +737,9 @@ fn test_psh_register() {
 
 #[test]
 fn test_psh_address() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x83, 0x00, 0x1E], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x83, 0x00, 0x1E], 0x0000, 3).unwrap(),
+    ));
     let ram = Arc::new(Mutex::new(Ram::new(0x07, 0x20)));
     {
         let mut locked_ram = ram.lock().unwrap();
@@ -743,7 +756,7 @@ fn test_psh_address() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.sp, 0x09);
     assert_eq!(
@@ -758,7 +771,7 @@ fn test_psh_address() {
 
 #[test]
 fn test_pop_register() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x44, 0x0C], 0x0000, 2)));
+    let rom = Arc::new(Mutex::new(Rom::new(vec![0x44, 0x0C], 0x0000, 2).unwrap()));
     let ram = Arc::new(Mutex::new(Ram::new(0x07, 0x20)));
     {
         let mut locked_ram = ram.lock().unwrap();
@@ -775,7 +788,7 @@ fn test_pop_register() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0xABCD);
     assert_eq!(cpu.sp, 0x07);
@@ -783,7 +796,7 @@ fn test_pop_register() {
 
 #[test]
 fn test_pop() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0xC4, 0x0C], 0x0000, 2)));
+    let rom = Arc::new(Mutex::new(Rom::new(vec![0xC4, 0x0C], 0x0000, 2).unwrap()));
     let ram = Arc::new(Mutex::new(Ram::new(0x07, 0x20)));
     {
         let mut locked_ram = ram.lock().unwrap();
@@ -800,14 +813,16 @@ fn test_pop() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.sp, 0x07);
 }
 
 #[test]
 fn test_st_register() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x45, 0x00, 0x01], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x45, 0x00, 0x01], 0x0000, 3).unwrap(),
+    ));
     let ram = Arc::new(Mutex::new(Ram::new(0x07, 0x20)));
 
     let mut map = DeviceMap::new();
@@ -821,7 +836,7 @@ fn test_st_register() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0xD07);
     assert_eq!(
@@ -836,7 +851,9 @@ fn test_st_register() {
 
 #[test]
 fn test_st_address() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x85, 0x00, 0x07], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x85, 0x00, 0x07], 0x0000, 3).unwrap(),
+    ));
     let ram = Arc::new(Mutex::new(Ram::new(0x07, 0x20)));
 
     let mut map = DeviceMap::new();
@@ -849,7 +866,7 @@ fn test_st_address() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0xD07);
     assert_eq!(
@@ -864,7 +881,9 @@ fn test_st_address() {
 
 #[test]
 fn test_stl_register() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x46, 0x00, 0x01], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x46, 0x00, 0x01], 0x0000, 3).unwrap(),
+    ));
     let ram = Arc::new(Mutex::new(Ram::new(0x07, 0x20)));
 
     let mut map = DeviceMap::new();
@@ -878,7 +897,7 @@ fn test_stl_register() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0xD07);
     assert_eq!(
@@ -893,7 +912,9 @@ fn test_stl_register() {
 
 #[test]
 fn test_stl_address() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x86, 0x00, 0x07], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x86, 0x00, 0x07], 0x0000, 3).unwrap(),
+    ));
     let ram = Arc::new(Mutex::new(Ram::new(0x07, 0x20)));
 
     let mut map = DeviceMap::new();
@@ -906,7 +927,7 @@ fn test_stl_address() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0xD07);
     assert_eq!(
@@ -921,7 +942,9 @@ fn test_stl_address() {
 
 #[test]
 fn test_sth_register() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x47, 0x00, 0x01], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x47, 0x00, 0x01], 0x0000, 3).unwrap(),
+    ));
     let ram = Arc::new(Mutex::new(Ram::new(0x07, 0x20)));
 
     let mut map = DeviceMap::new();
@@ -935,7 +958,7 @@ fn test_sth_register() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0xD07);
     assert_eq!(
@@ -950,7 +973,9 @@ fn test_sth_register() {
 
 #[test]
 fn test_sth_address() {
-    let rom = Arc::new(Mutex::new(Rom::new(vec![0x87, 0x00, 0x07], 0x0000, 3)));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x87, 0x00, 0x07], 0x0000, 3).unwrap(),
+    ));
     let ram = Arc::new(Mutex::new(Ram::new(0x07, 0x20)));
 
     let mut map = DeviceMap::new();
@@ -963,7 +988,7 @@ fn test_sth_address() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.r1, 0xD07);
     assert_eq!(
@@ -978,11 +1003,9 @@ fn test_sth_address() {
 
 #[test]
 fn test_jsr() {
-    let rom = Arc::new(Mutex::new(Rom::new(
-        vec![0x8F, 0x00, 0x05, 0xFF, 0x0C, 0xFF, 0x0C],
-        0x0000,
-        7,
-    )));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x8F, 0x00, 0x05, 0xFF, 0x0C, 0xFF, 0x0C], 0x0000, 7).unwrap(),
+    ));
     let ram = Arc::new(Mutex::new(Ram::new(0x07, 0x20)));
 
     let mut map = DeviceMap::new();
@@ -995,7 +1018,7 @@ fn test_jsr() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.pc, 0x05);
     assert_eq!(
@@ -1010,11 +1033,9 @@ fn test_jsr() {
 
 #[test]
 fn test_ret() {
-    let rom = Arc::new(Mutex::new(Rom::new(
-        vec![0xD2, 0x00, 0xFF, 0x0C, 0xFE, 0x0C],
-        0x0000,
-        6,
-    )));
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0xD2, 0x00, 0xFF, 0x0C, 0xFE, 0x0C], 0x0000, 6).unwrap(),
+    ));
     let ram = Arc::new(Mutex::new(Ram::new(0x06, 0x20)));
     {
         let mut locked_ram = ram.lock().unwrap();
@@ -1030,7 +1051,101 @@ fn test_ret() {
 
     let mut pins = cpu.pins;
 
-    pins = cpu.tick(pins);
+    pins = cpu.tick(pins).unwrap();
 
     assert_eq!(cpu.pc, 0x04);
 }
+
+#[test]
+fn test_far_jsr_ret() {
+    // 00000: jsrf $0x10000 / 10000: retf
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0xA0, 0x08, 0x01, 0x00, 0x00], 0x0000, 5).unwrap(),
+    ));
+    let far_rom = Arc::new(Mutex::new(Rom::new(vec![0xE1, 0x0C], 0x10000, 2).unwrap()));
+    let ram = Arc::new(Mutex::new(Ram::new(0x20, 0x40)));
+
+    let mut map = DeviceMap::new();
+    map.add(rom);
+    map.add(far_rom);
+    map.add(ram);
+
+    let mut cpu = CPU::new(0x0000, 0x20, false);
+    cpu.map = map;
+    cpu.stack_base = 0x20;
+
+    let mut pins = cpu.pins;
+
+    pins = cpu.tick(pins).unwrap();
+
+    assert_eq!(cpu.program_counter(), 0x10000);
+    assert_eq!(cpu.sp, 0x24);
+
+    pins = cpu.tick(pins).unwrap();
+
+    assert_eq!(cpu.program_counter(), 0x05);
+    assert_eq!(cpu.sp, 0x20);
+}
+
+#[test]
+fn test_far_jmp() {
+    // 1FFFC: jmp $0x00002 / 00002: jmp $0x1FFFC
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x8E, 0x04, 0x00, 0x02], 0x1FFFC, 4).unwrap(),
+    ));
+    let near_rom = Arc::new(Mutex::new(
+        Rom::new(vec![0x00, 0x00, 0x8E, 0x08, 0x01, 0xFF, 0xFC], 0x0000, 7).unwrap(),
+    ));
+
+    let mut map = DeviceMap::new();
+    map.add(rom);
+    map.add(near_rom);
+
+    let mut cpu = CPU::new(0xFFFC, 0x0000, false);
+    cpu.cs = 1;
+    cpu.map = map;
+
+    let mut pins = cpu.pins;
+
+    pins = cpu.tick(pins).unwrap();
+    assert_eq!(cpu.program_counter(), 0x00002);
+
+    pins = cpu.tick(pins).unwrap();
+    assert_eq!(cpu.program_counter(), 0x1FFFC);
+}
+
+#[test]
+fn test_cli_sti() {
+    let rom = Arc::new(Mutex::new(
+        Rom::new(vec![0xDE, 0x0C, 0xDF, 0x0C], 0x0000, 4).unwrap(),
+    ));
+
+    let mut map = DeviceMap::new();
+    map.add(rom);
+
+    let mut cpu = CPU::new(0x0000, 0xFFFF, false);
+    cpu.map = map;
+
+    let mut pins = cpu.pins;
+
+    assert!(cpu.flags.contains(Flags::I));
+
+    pins = cpu.tick(pins).unwrap();
+    assert!(!cpu.flags.contains(Flags::I));
+    assert_eq!(cpu.pc, 0x0002);
+
+    pins = cpu.tick(pins).unwrap();
+    assert!(cpu.flags.contains(Flags::I));
+    assert_eq!(cpu.pc, 0x0004);
+}
+
+#[test]
+fn test_decode_table_entries() {
+    for (code, (opcode, mode, _, _, cycles)) in Instruction::entries() {
+        assert_eq!(Instruction::create_opcode(*opcode, *mode), code);
+        assert!(*cycles > 0);
+    }
+
+    assert_eq!(Instruction::entries().count(), 56);
+    assert!(Instruction::lookup(0xA2).is_none());
+}
@@ -1,2 +1,5 @@
+pub mod executable;
 pub mod hex;
 pub mod instruction;
+pub mod object;
+pub mod symbols;
@@ -0,0 +1,389 @@
+#[cfg(test)]
+mod tests;
+
+use std::fmt;
+
+use super::executable::{crc32, SectionKind};
+use super::symbols::SourceLine;
+
+pub const MAGIC: [u8; 4] = *b"YUOB";
+pub const VERSION: u16 = 3;
+
+const CRC_SIZE: usize = 4;
+
+/*
+Object files hold one assembled module before it is given an address. Every number is
+big-endian, and strings are a u16 length followed by UTF-8:
+
+magic         4 bytes   "YUOB"
+version       u16
+entry         string    label named by `.main`, empty when there isn't one
+data align    u32       the data section has to start on a multiple of this
+data          u32 size, then the bytes
+text          u32 size, then the bytes
+far text      u32 size, then the bytes
+symbols       u16 count, then per symbol: name string, section u8, offset u32, global u8
+externs       u16 count, then the names
+relocations   u32 count, then per relocation: section u8, offset u32, size u8, target, addend i32
+interrupts    u16 count, then per interrupt: number u8, handler string
+lines         u32 count, then per line: section u8, offset u32, file string, line u32
+
+A relocation target is either 0 followed by a section u8 (the start of that section in this
+object), or 1 followed by a symbol name. The file ends with a CRC-32 of everything before it.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelocationTarget {
+    Section(SectionKind),
+    Symbol(String),
+}
+
+impl fmt::Display for RelocationTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RelocationTarget::Section(kind) => write!(f, "the {} section", kind),
+            RelocationTarget::Symbol(name) => write!(f, "`{}`", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub section: SectionKind,
+    pub offset: u32,
+    pub global: bool,
+}
+
+/*
+How much of the address a relocation fills in:
+
+Word     2 bytes   the address as a big-endian word, it has to be below 0x10000
+Double   3 bytes   the top 4 bits of the address in a byte, then the low 16 bits as a word
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocationSize {
+    Word = 0,
+    Double = 1,
+}
+
+impl RelocationSize {
+    pub fn width(&self) -> usize {
+        match self {
+            RelocationSize::Word => 2,
+            RelocationSize::Double => 3,
+        }
+    }
+}
+
+// The bytes at `offset` in `section` are replaced with the address of `target` plus `addend`
+// when the object is linked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub section: SectionKind,
+    pub offset: u32,
+    pub size: RelocationSize,
+    pub target: RelocationTarget,
+    pub addend: i32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ObjectError {
+    Truncated,
+    BadMagic,
+    UnsupportedVersion(u16),
+    ChecksumMismatch { expected: u32, found: u32 },
+    InvalidSection(u8),
+    InvalidRelocationSize(u8),
+    InvalidString,
+    TrailingBytes,
+}
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjectError::Truncated => write!(f, "the file ends before the end of the object"),
+            ObjectError::BadMagic => write!(f, "not a YuCPU object file"),
+            ObjectError::UnsupportedVersion(version) => write!(
+                f,
+                "object format version {} is not supported (expected {})",
+                version, VERSION
+            ),
+            ObjectError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "checksum mismatch, the file is corrupt (expected 0x{:08X}, found 0x{:08X})",
+                expected, found
+            ),
+            ObjectError::InvalidSection(kind) => {
+                write!(f, "section kind {} can't hold symbols or relocations", kind)
+            }
+            ObjectError::InvalidRelocationSize(size) => {
+                write!(f, "unknown relocation size {}", size)
+            }
+            ObjectError::InvalidString => write!(f, "a name is not valid UTF-8"),
+            ObjectError::TrailingBytes => write!(f, "unexpected bytes after the line table"),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ObjectFile {
+    pub entry: Option<String>,
+    pub data: Vec<u8>,
+    // Zero is treated the same as 1, no alignment.
+    pub data_align: u32,
+    pub text: Vec<u8>,
+    // Code from `.far`, which is linked above 0xFFFF.
+    pub far_text: Vec<u8>,
+    pub symbols: Vec<Symbol>,
+    pub externs: Vec<String>,
+    pub relocations: Vec<Relocation>,
+    pub interrupts: Vec<(u8, String)>,
+    pub lines: Vec<(SectionKind, u32, SourceLine)>,
+}
+
+fn section_from_u8(value: u8) -> Result<SectionKind, ObjectError> {
+    match value {
+        1 => Ok(SectionKind::Data),
+        2 => Ok(SectionKind::Text),
+        6 => Ok(SectionKind::FarText),
+        _ => Err(ObjectError::InvalidSection(value)),
+    }
+}
+
+fn relocation_size_from_u8(value: u8) -> Result<RelocationSize, ObjectError> {
+    match value {
+        0 => Ok(RelocationSize::Word),
+        1 => Ok(RelocationSize::Double),
+        _ => Err(ObjectError::InvalidRelocationSize(value)),
+    }
+}
+
+fn write_string(output: &mut Vec<u8>, string: &str) {
+    output.extend_from_slice(&(string.len() as u16).to_be_bytes());
+    output.extend_from_slice(string.as_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, count: usize) -> Result<&[u8], ObjectError> {
+        match self.pos.checked_add(count) {
+            Some(end) if end <= self.bytes.len() => {
+                let bytes = &self.bytes[self.pos..end];
+                self.pos = end;
+                Ok(bytes)
+            }
+            _ => Err(ObjectError::Truncated),
+        }
+    }
+
+    fn u8(&mut self) -> Result<u8, ObjectError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ObjectError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, ObjectError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn string(&mut self) -> Result<String, ObjectError> {
+        let len = self.u16()? as usize;
+
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| ObjectError::InvalidString)
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, ObjectError> {
+        let len = self.u32()? as usize;
+
+        Ok(self.take(len)?.to_vec())
+    }
+}
+
+impl ObjectFile {
+    pub fn new() -> ObjectFile {
+        ObjectFile::default()
+    }
+
+    pub fn section(&self, kind: SectionKind) -> &[u8] {
+        match kind {
+            SectionKind::Data => &self.data,
+            SectionKind::Text => &self.text,
+            SectionKind::FarText => &self.far_text,
+            _ => &[],
+        }
+    }
+
+    pub fn symbol(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+
+        output.extend_from_slice(&MAGIC);
+        output.extend_from_slice(&VERSION.to_be_bytes());
+        write_string(&mut output, self.entry.as_deref().unwrap_or(""));
+        output.extend_from_slice(&self.data_align.to_be_bytes());
+
+        for section in [&self.data, &self.text, &self.far_text] {
+            output.extend_from_slice(&(section.len() as u32).to_be_bytes());
+            output.extend_from_slice(section);
+        }
+
+        output.extend_from_slice(&(self.symbols.len() as u16).to_be_bytes());
+
+        for symbol in &self.symbols {
+            write_string(&mut output, &symbol.name);
+            output.push(symbol.section as u8);
+            output.extend_from_slice(&symbol.offset.to_be_bytes());
+            output.push(symbol.global as u8);
+        }
+
+        output.extend_from_slice(&(self.externs.len() as u16).to_be_bytes());
+
+        for name in &self.externs {
+            write_string(&mut output, name);
+        }
+
+        output.extend_from_slice(&(self.relocations.len() as u32).to_be_bytes());
+
+        for relocation in &self.relocations {
+            output.push(relocation.section as u8);
+            output.extend_from_slice(&relocation.offset.to_be_bytes());
+            output.push(relocation.size as u8);
+
+            match &relocation.target {
+                RelocationTarget::Section(kind) => {
+                    output.push(0);
+                    output.push(*kind as u8);
+                }
+                RelocationTarget::Symbol(name) => {
+                    output.push(1);
+                    write_string(&mut output, name);
+                }
+            }
+
+            output.extend_from_slice(&relocation.addend.to_be_bytes());
+        }
+
+        output.extend_from_slice(&(self.interrupts.len() as u16).to_be_bytes());
+
+        for (interrupt, handler) in &self.interrupts {
+            output.push(*interrupt);
+            write_string(&mut output, handler);
+        }
+
+        output.extend_from_slice(&(self.lines.len() as u32).to_be_bytes());
+
+        for (section, offset, line) in &self.lines {
+            output.push(*section as u8);
+            output.extend_from_slice(&offset.to_be_bytes());
+            write_string(&mut output, &line.file);
+            output.extend_from_slice(&(line.line as u32).to_be_bytes());
+        }
+
+        let crc = crc32(&output);
+        output.extend_from_slice(&crc.to_be_bytes());
+
+        output
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<ObjectFile, ObjectError> {
+        if bytes.len() < MAGIC.len() || bytes[..MAGIC.len()] != MAGIC {
+            return Err(ObjectError::BadMagic);
+        }
+
+        if bytes.len() < MAGIC.len() + 2 + CRC_SIZE {
+            return Err(ObjectError::Truncated);
+        }
+
+        let (body, crc) = bytes.split_at(bytes.len() - CRC_SIZE);
+        let mut reader = Reader {
+            bytes: body,
+            pos: MAGIC.len(),
+        };
+
+        let version = reader.u16()?;
+
+        if version != VERSION {
+            return Err(ObjectError::UnsupportedVersion(version));
+        }
+
+        let expected = u32::from_be_bytes([crc[0], crc[1], crc[2], crc[3]]);
+        let found = crc32(body);
+
+        if expected != found {
+            return Err(ObjectError::ChecksumMismatch { expected, found });
+        }
+
+        let mut object = ObjectFile::new();
+
+        let entry = reader.string()?;
+        object.entry = Some(entry).filter(|entry| !entry.is_empty());
+        object.data_align = reader.u32()?;
+        object.data = reader.bytes()?;
+        object.text = reader.bytes()?;
+        object.far_text = reader.bytes()?;
+
+        for _ in 0..reader.u16()? {
+            object.symbols.push(Symbol {
+                name: reader.string()?,
+                section: section_from_u8(reader.u8()?)?,
+                offset: reader.u32()?,
+                global: reader.u8()? != 0,
+            });
+        }
+
+        for _ in 0..reader.u16()? {
+            object.externs.push(reader.string()?);
+        }
+
+        for _ in 0..reader.u32()? {
+            let section = section_from_u8(reader.u8()?)?;
+            let offset = reader.u32()?;
+            let size = relocation_size_from_u8(reader.u8()?)?;
+
+            let target = match reader.u8()? {
+                0 => RelocationTarget::Section(section_from_u8(reader.u8()?)?),
+                _ => RelocationTarget::Symbol(reader.string()?),
+            };
+
+            object.relocations.push(Relocation {
+                section,
+                offset,
+                size,
+                target,
+                addend: reader.u32()? as i32,
+            });
+        }
+
+        for _ in 0..reader.u16()? {
+            object.interrupts.push((reader.u8()?, reader.string()?));
+        }
+
+        for _ in 0..reader.u32()? {
+            let section = section_from_u8(reader.u8()?)?;
+            let offset = reader.u32()?;
+            let file = reader.string()?;
+            let line = reader.u32()? as usize;
+
+            object
+                .lines
+                .push((section, offset, SourceLine { file, line }));
+        }
+
+        if reader.pos != body.len() {
+            return Err(ObjectError::TrailingBytes);
+        }
+
+        Ok(object)
+    }
+}
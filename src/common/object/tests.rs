@@ -0,0 +1,102 @@
+use super::{ObjectError, ObjectFile, Relocation, RelocationSize, RelocationTarget, Symbol};
+use crate::common::{executable::SectionKind, symbols::SourceLine};
+
+fn object() -> ObjectFile {
+    ObjectFile {
+        entry: Some(String::from("start")),
+        data: vec![0x48, 0x69],
+        data_align: 4,
+        text: vec![0x80, 0x04, 0x00, 0x00, 0x8F, 0x04, 0x00, 0x00],
+        far_text: vec![0x8F, 0x08, 0x00, 0x00, 0x00],
+        symbols: vec![
+            Symbol {
+                name: String::from("msg"),
+                section: SectionKind::Data,
+                offset: 0,
+                global: false,
+            },
+            Symbol {
+                name: String::from("start"),
+                section: SectionKind::Text,
+                offset: 0,
+                global: true,
+            },
+            Symbol {
+                name: String::from("far"),
+                section: SectionKind::FarText,
+                offset: 0,
+                global: false,
+            },
+        ],
+        externs: vec![String::from("print")],
+        relocations: vec![
+            Relocation {
+                section: SectionKind::Text,
+                offset: 2,
+                size: RelocationSize::Word,
+                target: RelocationTarget::Section(SectionKind::Data),
+                addend: 1,
+            },
+            Relocation {
+                section: SectionKind::Text,
+                offset: 6,
+                size: RelocationSize::Word,
+                target: RelocationTarget::Symbol(String::from("print")),
+                addend: -2,
+            },
+            Relocation {
+                section: SectionKind::FarText,
+                offset: 2,
+                size: RelocationSize::Double,
+                target: RelocationTarget::Section(SectionKind::FarText),
+                addend: 0,
+            },
+        ],
+        interrupts: vec![(0x03, String::from("print"))],
+        lines: vec![(
+            SectionKind::Text,
+            0,
+            SourceLine {
+                file: String::from("main.yuasm"),
+                line: 4,
+            },
+        )],
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    let bytes = object().to_bytes();
+
+    assert_eq!(&bytes[..4], b"YUOB");
+    assert_eq!(ObjectFile::parse(&bytes).unwrap(), object());
+    assert_eq!(
+        ObjectFile::parse(&ObjectFile::new().to_bytes()).unwrap(),
+        ObjectFile::new()
+    );
+}
+
+#[test]
+fn test_parse_errors() {
+    let mut bytes = object().to_bytes();
+
+    assert_eq!(
+        ObjectFile::parse(b"YUEX\x00\x01").unwrap_err(),
+        ObjectError::BadMagic
+    );
+
+    bytes[12] ^= 0xFF;
+
+    assert!(matches!(
+        ObjectFile::parse(&bytes).unwrap_err(),
+        ObjectError::ChecksumMismatch { .. }
+    ));
+
+    let mut bytes = object().to_bytes();
+    bytes[5] = 4;
+
+    assert_eq!(
+        ObjectFile::parse(&bytes).unwrap_err(),
+        ObjectError::UnsupportedVersion(4)
+    );
+}
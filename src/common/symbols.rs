@@ -0,0 +1,183 @@
+use std::{collections::BTreeMap, fmt, ops::Range};
+
+use super::executable::Executable;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SymbolError {
+    InvalidLine(usize),
+    InvalidAddress(usize),
+    InvalidEncoding,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    pub file: String,
+    pub line: usize,
+}
+
+impl fmt::Display for SourceLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/*
+Symbol files are plain text, one entry per line:
+
+04402 main
+0440a print
+04402 examples/box.yuasm:12
+
+The address is in hex. An entry ending in `:<line>` maps the instruction at that address back to
+the source line it was assembled from, anything else names a label. Lines starting with `;` are
+comments.
+ */
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SymbolTable {
+    pub symbols: BTreeMap<String, u32>,
+    pub lines: BTreeMap<u32, SourceLine>,
+    // Where the program's sections are loaded, which isn't part of the file. When it's known,
+    // addresses are only described relative to a label in the same section.
+    pub sections: Vec<Range<u32>>,
+}
+
+impl SymbolTable {
+    pub fn new() -> SymbolTable {
+        SymbolTable {
+            symbols: BTreeMap::new(),
+            lines: BTreeMap::new(),
+            sections: Vec::new(),
+        }
+    }
+
+    pub fn with_sections(mut self, executable: &Executable) -> SymbolTable {
+        self.sections = executable
+            .sections
+            .iter()
+            .filter(|section| section.kind.is_loaded())
+            .filter_map(|section| Some(section.load..section.end()?))
+            .collect();
+
+        self
+    }
+
+    pub fn parse(source: &str) -> Result<SymbolTable, SymbolError> {
+        let mut table = SymbolTable::new();
+
+        for (i, line) in source.lines().enumerate() {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            let (addr, rest) = match line.split_once(char::is_whitespace) {
+                Some((addr, rest)) => (addr, rest.trim()),
+                None => return Err(SymbolError::InvalidLine(i + 1)),
+            };
+
+            let addr = match u32::from_str_radix(addr.trim_start_matches("0x"), 16) {
+                Ok(addr) => addr,
+                Err(_) => return Err(SymbolError::InvalidAddress(i + 1)),
+            };
+
+            // File names may contain spaces, symbol names can't.
+            if let Some((file, line)) = rest.rsplit_once(':') {
+                match line.parse::<usize>() {
+                    Ok(line) => table.insert_line(addr, file, line),
+                    Err(_) => return Err(SymbolError::InvalidLine(i + 1)),
+                }
+            } else if rest.contains(char::is_whitespace) {
+                return Err(SymbolError::InvalidLine(i + 1));
+            } else {
+                table.insert(rest, addr);
+            }
+        }
+
+        Ok(table)
+    }
+
+    // Reads the symbols section embedded in an executable.
+    pub fn from_bytes(bytes: &[u8]) -> Result<SymbolTable, SymbolError> {
+        match std::str::from_utf8(bytes) {
+            Ok(source) => SymbolTable::parse(source),
+            Err(_) => Err(SymbolError::InvalidEncoding),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty() && self.lines.is_empty()
+    }
+
+    pub fn insert_line(&mut self, addr: u32, file: &str, line: usize) {
+        self.lines.insert(
+            addr,
+            SourceLine {
+                file: file.to_string(),
+                line,
+            },
+        );
+    }
+
+    pub fn line(&self, addr: u32) -> Option<&SourceLine> {
+        self.lines.get(&addr)
+    }
+
+    pub fn insert(&mut self, name: &str, addr: u32) {
+        self.symbols.insert(name.to_string(), addr);
+    }
+
+    pub fn get(&self, name: &str) -> Option<u32> {
+        self.symbols.get(name).copied()
+    }
+
+    // Maps each address to a single name. When several symbols share an address the first one
+    // alphabetically wins.
+    pub fn by_address(&self) -> BTreeMap<u32, String> {
+        let mut res = BTreeMap::new();
+
+        for (name, addr) in &self.symbols {
+            res.entry(*addr).or_insert_with(|| name.clone());
+        }
+
+        res
+    }
+
+    // Formats `addr` relative to the closest symbol at or before it, e.g. `print+0x6`.
+    pub fn describe(&self, addr: u32) -> Option<String> {
+        let by_address = self.by_address();
+        let (base, name) = by_address.range(..=addr).next_back()?;
+
+        if !self.sections.is_empty()
+            && !self
+                .sections
+                .iter()
+                .any(|section| section.contains(base) && section.contains(&addr))
+        {
+            return None;
+        }
+
+        if *base == addr {
+            Some(name.clone())
+        } else {
+            Some(format!("{}+0x{:X}", name, addr - base))
+        }
+    }
+}
+
+impl fmt::Display for SymbolTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut symbols: Vec<(&String, &u32)> = self.symbols.iter().collect();
+        symbols.sort_by_key(|(name, addr)| (**addr, *name));
+
+        for (name, addr) in symbols {
+            writeln!(f, "{:05x} {}", addr, name)?;
+        }
+
+        for (addr, line) in &self.lines {
+            writeln!(f, "{:05x} {}", addr, line)?;
+        }
+
+        Ok(())
+    }
+}
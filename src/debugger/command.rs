@@ -0,0 +1,151 @@
+use super::DebuggerError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    Address(u32),
+    Symbol(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Break(Location),
+    Delete(Location),
+    Breakpoints,
+    Step(u64),
+    Continue,
+    Finish,
+    Registers,
+    Examine { count: usize, location: Location },
+    Set { register: u8, value: u16 },
+    Disassemble(usize),
+    Help,
+    Quit,
+}
+
+pub const HELP: &str = "\
+break <label|addr>     Stop when the PC reaches a label or address (b)
+delete <label|addr>    Remove a breakpoint (d)
+breakpoints            List breakpoints (bl)
+step [n]               Execute n instructions, 1 by default (s)
+continue               Run until a breakpoint or HLT (c)
+finish                 Run until the current subroutine returns (f)
+regs                   Show the registers and flags (r)
+x/<n> <label|addr>     Examine n bytes of memory, 16 by default
+set <reg> <value>      Change a register, e.g. `set r1 0x10`
+disas [n]              Disassemble n instructions from the PC, 5 by default, and 2 before it
+help                   Show this message (h)
+quit                   Leave the debugger (q)
+
+Addresses are decimal, or hex with a `0x` or `$0x` prefix. Pressing enter repeats the last command.";
+
+pub fn parse_number(text: &str) -> Option<u32> {
+    let text = text.strip_prefix('$').unwrap_or(text);
+
+    match text.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse::<u32>().ok(),
+    }
+}
+
+pub fn parse_register(text: &str) -> Option<u8> {
+    match text.to_lowercase().as_str() {
+        "r1" => Some(0),
+        "r2" => Some(1),
+        "r3" => Some(2),
+        "r4" => Some(3),
+        "r5" => Some(4),
+        "r6" => Some(5),
+        "pc" => Some(6),
+        "sp" => Some(7),
+        "bp" => Some(8),
+        _ => None,
+    }
+}
+
+fn parse_location(text: Option<&&str>) -> Result<Location, DebuggerError> {
+    let text = match text {
+        Some(text) => *text,
+        None => return Err(DebuggerError::MissingArgument("a label or address")),
+    };
+
+    if text.starts_with('$') || text.starts_with(|c: char| c.is_ascii_digit()) {
+        return match parse_number(text) {
+            Some(addr) => Ok(Location::Address(addr)),
+            None => Err(DebuggerError::InvalidNumber(text.to_string())),
+        };
+    }
+
+    Ok(Location::Symbol(text.to_string()))
+}
+
+fn parse_count(text: Option<&&str>, default: u32) -> Result<u32, DebuggerError> {
+    match text {
+        Some(text) => match parse_number(text) {
+            Some(count) => Ok(count),
+            None => Err(DebuggerError::InvalidNumber(text.to_string())),
+        },
+        None => Ok(default),
+    }
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, DebuggerError> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let name = match words.first() {
+            Some(name) => *name,
+            None => return Err(DebuggerError::EmptyCommand),
+        };
+
+        let args = &words[1..];
+
+        if let Some(count) = name.strip_prefix("x/") {
+            let count = match parse_number(count) {
+                Some(count) => count as usize,
+                None => return Err(DebuggerError::InvalidNumber(count.to_string())),
+            };
+
+            return Ok(Command::Examine {
+                count,
+                location: parse_location(args.first())?,
+            });
+        }
+
+        match name {
+            "break" | "b" => Ok(Command::Break(parse_location(args.first())?)),
+            "delete" | "d" => Ok(Command::Delete(parse_location(args.first())?)),
+            "breakpoints" | "bl" => Ok(Command::Breakpoints),
+            "step" | "s" => Ok(Command::Step(parse_count(args.first(), 1)? as u64)),
+            "continue" | "c" => Ok(Command::Continue),
+            "finish" | "f" => Ok(Command::Finish),
+            "regs" | "r" => Ok(Command::Registers),
+            "x" => Ok(Command::Examine {
+                count: 16,
+                location: parse_location(args.first())?,
+            }),
+            "set" => {
+                let register = match args.first() {
+                    Some(register) => match parse_register(register) {
+                        Some(register) => register,
+                        None => return Err(DebuggerError::InvalidRegister(register.to_string())),
+                    },
+                    None => return Err(DebuggerError::MissingArgument("a register")),
+                };
+
+                let value = match args.get(1) {
+                    Some(value) => match parse_number(value) {
+                        Some(value) if value <= 0xFFFF => value as u16,
+                        _ => return Err(DebuggerError::InvalidNumber(value.to_string())),
+                    },
+                    None => return Err(DebuggerError::MissingArgument("a value")),
+                };
+
+                Ok(Command::Set { register, value })
+            }
+            "disas" => Ok(Command::Disassemble(parse_count(args.first(), 5)? as usize)),
+            "help" | "h" => Ok(Command::Help),
+            "quit" | "q" => Ok(Command::Quit),
+            _ => Err(DebuggerError::UnknownCommand(name.to_string())),
+        }
+    }
+}
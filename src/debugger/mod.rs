@@ -0,0 +1,469 @@
+#[cfg(test)]
+mod tests;
+
+pub mod command;
+
+use std::{
+    collections::BTreeSet,
+    fmt,
+    io::{self, BufRead, Write},
+};
+
+use crate::{
+    common::{
+        instruction::opcode::{Instruction, Opcode},
+        symbols::SymbolTable,
+    },
+    disassembler::{decode, Disassembler},
+    vcpu::{
+        cpu::{CpuFault, Flags, ADDRESS_SPACE},
+        device::map::DeviceMapResult,
+        machine::Machine,
+    },
+};
+
+use self::command::{Command, Location, HELP};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DebuggerError {
+    EmptyCommand,
+    UnknownCommand(String),
+    MissingArgument(&'static str),
+    InvalidNumber(String),
+    InvalidRegister(String),
+    UnknownSymbol(String),
+    PastEndOfMemory,
+}
+
+impl fmt::Display for DebuggerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DebuggerError::EmptyCommand => write!(f, "no command given"),
+            DebuggerError::UnknownCommand(name) => {
+                write!(f, "unknown command `{}`, try `help`", name)
+            }
+            DebuggerError::MissingArgument(what) => write!(f, "expected {}", what),
+            DebuggerError::InvalidNumber(text) => write!(f, "`{}` is not a valid number", text),
+            DebuggerError::InvalidRegister(text) => {
+                write!(f, "`{}` is not a register (r1 - r6, pc, sp, bp)", text)
+            }
+            DebuggerError::UnknownSymbol(name) => {
+                write!(f, "no symbol named `{}` is loaded", name)
+            }
+            DebuggerError::PastEndOfMemory => write!(
+                f,
+                "the range runs past the end of memory at 0x{:05X}",
+                ADDRESS_SPACE - 1
+            ),
+        }
+    }
+}
+
+// How many instructions `disas` shows before the PC.
+const DISAS_BEFORE: usize = 2;
+
+// How far back `disas` looks for a label or line to decode from.
+const DISAS_REACH: u32 = 0x100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Stepped,
+    Breakpoint(u32),
+    Returned,
+    Halted,
+    Fault(CpuFault),
+}
+
+pub struct Debugger {
+    pub machine: Machine,
+    pub breakpoints: BTreeSet<u32>,
+    pub symbols: SymbolTable,
+}
+
+impl Debugger {
+    pub fn new(machine: Machine, symbols: SymbolTable) -> Debugger {
+        Debugger {
+            machine,
+            breakpoints: BTreeSet::new(),
+            symbols,
+        }
+    }
+
+    pub fn pc(&self) -> u32 {
+        self.machine.cpu.program_counter()
+    }
+
+    pub fn resolve(&self, location: &Location) -> Result<u32, DebuggerError> {
+        match location {
+            Location::Address(addr) => Ok(*addr),
+            Location::Symbol(name) => match self.symbols.get(name) {
+                Some(addr) => Ok(addr),
+                None => Err(DebuggerError::UnknownSymbol(name.clone())),
+            },
+        }
+    }
+
+    // `0x4408 <print+0x6>` when a symbol covers the address, `0x4408` otherwise.
+    pub fn describe(&self, addr: u32) -> String {
+        match self.symbols.describe(addr) {
+            Some(name) => format!("0x{:04X} <{}>", addr, name),
+            None => format!("0x{:04X}", addr),
+        }
+    }
+
+    pub fn step(&mut self, count: u64) -> Stop {
+        for i in 0..count {
+            if !self.machine.cpu.running {
+                return Stop::Halted;
+            }
+
+            if let Err(fault) = self.machine.step() {
+                return Stop::Fault(fault);
+            }
+
+            // Only the instructions after the first one can stop on a breakpoint, otherwise a step
+            // from a breakpoint would never get anywhere.
+            if i + 1 < count && self.breakpoints.contains(&self.pc()) {
+                return Stop::Breakpoint(self.pc());
+            }
+        }
+
+        if self.machine.cpu.running {
+            Stop::Stepped
+        } else {
+            Stop::Halted
+        }
+    }
+
+    pub fn resume(&mut self) -> Stop {
+        loop {
+            if !self.machine.cpu.running {
+                return Stop::Halted;
+            }
+
+            if let Err(fault) = self.machine.step() {
+                return Stop::Fault(fault);
+            }
+
+            if self.breakpoints.contains(&self.pc()) {
+                return Stop::Breakpoint(self.pc());
+            }
+        }
+    }
+
+    /*
+    Runs until the current subroutine returns. Calls and returns are counted as they run, so the
+    subroutine can push what it likes. An interrupt or fault taken in place of the instruction at
+    pc pushes more than a return address, so it is told apart by how far sp moved.
+     */
+    pub fn finish(&mut self) -> Stop {
+        let mut depth = 0_u32;
+
+        loop {
+            if !self.machine.cpu.running {
+                return Stop::Halted;
+            }
+
+            let sp = self.machine.cpu.sp as i32;
+            let opcode = self
+                .read_byte(self.pc())
+                .and_then(Instruction::lookup)
+                .map(|info| info.0);
+
+            if let Err(fault) = self.machine.step() {
+                return Stop::Fault(fault);
+            }
+
+            match (opcode, self.machine.cpu.sp as i32 - sp) {
+                (Some(Opcode::JSR), 2) | (Some(Opcode::JSRF), 4) => depth += 1,
+                (Some(Opcode::RET), -2) | (Some(Opcode::RETF), -4) => {
+                    if depth == 0 {
+                        return Stop::Returned;
+                    }
+
+                    depth -= 1;
+                }
+                _ => (),
+            }
+
+            if self.breakpoints.contains(&self.pc()) {
+                return Stop::Breakpoint(self.pc());
+            }
+        }
+    }
+
+    fn read_byte(&self, addr: u32) -> Option<u8> {
+        match self.machine.cpu.map.peek(addr) {
+            DeviceMapResult::Ok(byte) => Some(byte),
+            _ => None,
+        }
+    }
+
+    pub fn registers(&self) -> String {
+        let cpu = &self.machine.cpu;
+
+        let flags: Vec<&str> = [
+            (Flags::Z, "Z"),
+            (Flags::O, "O"),
+            (Flags::L, "L"),
+            (Flags::G, "G"),
+            (Flags::D, "D"),
+            (Flags::I, "I"),
+        ]
+        .iter()
+        .filter(|(flag, _)| cpu.flags.contains(*flag))
+        .map(|(_, name)| *name)
+        .collect();
+
+        format!(
+            "r1 0x{:04X}  r2 0x{:04X}  r3 0x{:04X}\n\
+             r4 0x{:04X}  r5 0x{:04X}  r6 0x{:04X}\n\
+             pc 0x{:04X}  sp 0x{:04X}  bp 0x{:04X}\n\
+             flags [{}]  cycles {}",
+            cpu.r1,
+            cpu.r2,
+            cpu.r3,
+            cpu.r4,
+            cpu.r5,
+            cpu.r6,
+            cpu.program_counter(),
+            cpu.sp,
+            cpu.bp,
+            flags.join(" "),
+            self.machine.cpu.cycles
+        )
+    }
+
+    pub fn examine(&self, addr: u32, count: usize) -> Result<String, DebuggerError> {
+        let end = u32::try_from(count)
+            .ok()
+            .and_then(|count| addr.checked_add(count));
+
+        if end.is_none_or(|end| end > ADDRESS_SPACE) {
+            return Err(DebuggerError::PastEndOfMemory);
+        }
+
+        let mut lines = Vec::new();
+
+        for row in (0..count).step_by(8) {
+            let row_addr = addr + row as u32;
+            let bytes: Vec<String> = (row..count.min(row + 8))
+                .map(|i| match self.read_byte(addr + i as u32) {
+                    Some(byte) => format!("{:02X}", byte),
+                    None => String::from("??"),
+                })
+                .collect();
+
+            lines.push(format!("{}: {}", self.describe(row_addr), bytes.join(" ")));
+        }
+
+        Ok(lines.join("\n"))
+    }
+
+    // The addresses of up to `count` instructions right before `addr`. Instructions can only be
+    // decoded from their first byte, so this decodes forward from a label or line before `addr`,
+    // and only trusts the ones that land on it.
+    fn instructions_before(&self, addr: u32, count: usize) -> Vec<u32> {
+        let anchors: BTreeSet<u32> = self
+            .symbols
+            .lines
+            .keys()
+            .chain(self.symbols.symbols.values())
+            .copied()
+            .filter(|anchor| *anchor < addr && addr - anchor <= DISAS_REACH)
+            .collect();
+
+        let mut found = Vec::new();
+
+        for anchor in anchors.into_iter().rev() {
+            if found.len() >= count {
+                break;
+            }
+
+            match self.decode_between(anchor, addr) {
+                Some(starts) if starts.len() > found.len() => found = starts,
+                _ => (),
+            }
+        }
+
+        found.split_off(found.len().saturating_sub(count))
+    }
+
+    fn decode_between(&self, from: u32, to: u32) -> Option<Vec<u32>> {
+        let mut starts = Vec::new();
+        let mut addr = from;
+
+        while addr < to {
+            let instruction = decode(&self.machine.read_bytes(addr, 5), addr).ok()?;
+            starts.push(addr);
+            addr += instruction.len() as u32;
+        }
+
+        (addr == to).then_some(starts)
+    }
+
+    // `before` instructions before the PC when they can be found, then `count` from the PC.
+    pub fn disassemble(&self, before: usize, count: usize) -> String {
+        let labels = self.symbols.by_address();
+        let before = self.instructions_before(self.pc(), before);
+        let mut lines = Vec::new();
+        let mut addr = before.first().copied().unwrap_or(self.pc());
+
+        for _ in 0..before.len() + count {
+            let bytes = self.machine.read_bytes(addr, 5);
+
+            let marker = match (addr == self.pc(), self.breakpoints.contains(&addr)) {
+                (true, true) => "*>",
+                (true, false) => "=>",
+                (false, true) => "* ",
+                (false, false) => "  ",
+            };
+
+            let instruction = match decode(&bytes, addr) {
+                Ok(instruction) => instruction,
+                Err(_) => {
+                    lines.push(format!("{} {}: ??", marker, self.describe(addr)));
+                    break;
+                }
+            };
+
+            let mut line = format!(
+                "{} {}: {}",
+                marker,
+                self.describe(addr),
+                Disassembler::format_instruction(&instruction, &labels)
+            );
+
+            if let Some(source) = self.symbols.line(addr) {
+                line += &format!(" ({})", source);
+            }
+
+            lines.push(line);
+
+            addr += instruction.len() as u32;
+        }
+
+        lines.join("\n")
+    }
+
+    fn report(&mut self, stop: Stop) -> String {
+        let reason = match stop {
+            Stop::Stepped => String::new(),
+            Stop::Breakpoint(addr) => format!("Breakpoint hit at {}\n", self.describe(addr)),
+            Stop::Returned => String::from("Returned from subroutine\n"),
+            Stop::Halted => {
+                return format!(
+                    "Program halted at {} after {} cycles",
+                    self.describe(self.pc()),
+                    self.machine.cpu.cycles
+                )
+            }
+            Stop::Fault(fault) => format!("CPU fault: {}\n", fault),
+        };
+
+        reason + &self.disassemble(0, 1)
+    }
+
+    pub fn execute(&mut self, command: Command) -> Result<String, DebuggerError> {
+        let output = match command {
+            Command::Break(location) => {
+                let addr = self.resolve(&location)?;
+                self.breakpoints.insert(addr);
+                format!("Breakpoint set at {}", self.describe(addr))
+            }
+            Command::Delete(location) => {
+                let addr = self.resolve(&location)?;
+
+                if self.breakpoints.remove(&addr) {
+                    format!("Breakpoint at {} removed", self.describe(addr))
+                } else {
+                    format!("No breakpoint at {}", self.describe(addr))
+                }
+            }
+            Command::Breakpoints => {
+                if self.breakpoints.is_empty() {
+                    String::from("No breakpoints")
+                } else {
+                    self.breakpoints
+                        .iter()
+                        .map(|addr| self.describe(*addr))
+                        .collect::<Vec<String>>()
+                        .join("\n")
+                }
+            }
+            Command::Step(count) => {
+                let stop = self.step(count);
+                self.report(stop)
+            }
+            Command::Continue => {
+                let stop = self.resume();
+                self.report(stop)
+            }
+            Command::Finish => {
+                let stop = self.finish();
+                self.report(stop)
+            }
+            Command::Registers => self.registers(),
+            Command::Examine { count, location } => {
+                let addr = self.resolve(&location)?;
+                self.examine(addr, count)?
+            }
+            Command::Set { register, value } => {
+                if let Ok(register) = self.machine.cpu.decode_register(register) {
+                    *register = value;
+                }
+
+                self.registers()
+            }
+            Command::Disassemble(count) => self.disassemble(DISAS_BEFORE, count),
+            Command::Help => String::from(HELP),
+            Command::Quit => String::new(),
+        };
+
+        Ok(output)
+    }
+
+    pub fn repl(&mut self) {
+        let stdin = io::stdin();
+        let mut last_line = String::new();
+
+        println!("Entry point {}", self.describe(self.pc()));
+        println!("{}", self.disassemble(0, 1));
+
+        loop {
+            print!("(ydb) ");
+            io::stdout().flush().unwrap();
+
+            let mut line = String::new();
+
+            match stdin.lock().read_line(&mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => (),
+            }
+
+            if line.trim().is_empty() {
+                line = last_line.clone();
+            } else {
+                last_line = line.clone();
+            }
+
+            let command = match Command::parse(&line) {
+                Ok(command) => command,
+                Err(DebuggerError::EmptyCommand) => continue,
+                Err(error) => {
+                    eprintln!("error: {}", error);
+                    continue;
+                }
+            };
+
+            if command == Command::Quit {
+                break;
+            }
+
+            match self.execute(command) {
+                Ok(output) => println!("{}", output),
+                Err(error) => eprintln!("error: {}", error),
+            }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use crate::{
+    common::{
+        executable::{Executable, Section, SectionKind},
+        symbols::SymbolTable,
+    },
+    vcpu::machine::Machine,
+};
+
+use super::{
+    command::{Command, Location},
+    Debugger, DebuggerError, Stop,
+};
+
+// start: jsr sub / hlt
+// sub:   mov r1, 7 / ret
+fn debugger() -> Debugger {
+    let program = vec![
+        0x8F, 0x04, 0x44, 0x08, 0xFE, 0x0C, 0x00, 0x00, 0x07, 0xD2, 0x0C,
+    ];
+
+    let symbols = SymbolTable::parse("04402 start\n04408 sub\n").unwrap();
+
+    Debugger::new(
+        Machine::new(program, [0; 510], 0x4402, false).unwrap(),
+        symbols,
+    )
+}
+
+#[test]
+fn test_parse_commands() {
+    assert_eq!(
+        Command::parse("b sub").unwrap(),
+        Command::Break(Location::Symbol(String::from("sub")))
+    );
+    assert_eq!(
+        Command::parse("break $0x4408").unwrap(),
+        Command::Break(Location::Address(0x4408))
+    );
+    assert_eq!(
+        Command::parse("x/4 0x4402").unwrap(),
+        Command::Examine {
+            count: 4,
+            location: Location::Address(0x4402)
+        }
+    );
+    assert_eq!(
+        Command::parse("set r2 0x10").unwrap(),
+        Command::Set {
+            register: 1,
+            value: 0x10
+        }
+    );
+    assert_eq!(Command::parse("step 3").unwrap(), Command::Step(3));
+    assert_eq!(
+        Command::parse("set r9 1").unwrap_err(),
+        DebuggerError::InvalidRegister(String::from("r9"))
+    );
+}
+
+#[test]
+fn test_symbol_table() {
+    let symbols = SymbolTable::parse("; comment\n04402 start\n0440a print\n").unwrap();
+
+    assert_eq!(symbols.get("print"), Some(0x440A));
+    assert_eq!(symbols.describe(0x4410).unwrap(), "print+0x6");
+    assert_eq!(symbols.describe(0x4000), None);
+    assert!(SymbolTable::parse("zz start").is_err());
+
+    // Past the end of the text section isn't part of the last label.
+    let executable = Executable::new(0x4402).with_section(Section::new(
+        SectionKind::Text,
+        0x4402,
+        [0xFF, 0x0C].repeat(12),
+    ));
+    let symbols = symbols.with_sections(&executable);
+
+    assert_eq!(symbols.describe(0x4410).unwrap(), "print+0x6");
+    assert_eq!(symbols.describe(0x4D20), None);
+}
+
+#[test]
+fn test_symbol_lines() {
+    let source = "04402 start\n04408 sub\n04402 my prog.yuasm:3\n04408 my prog.yuasm:7\n";
+    let symbols = SymbolTable::parse(source).unwrap();
+
+    assert_eq!(symbols.line(0x4408).unwrap().line, 7);
+    assert_eq!(symbols.line(0x4408).unwrap().file, "my prog.yuasm");
+    assert_eq!(symbols.to_string(), source);
+    assert!(SymbolTable::parse("04402 a.yuasm:x").is_err());
+
+    let mut debugger = debugger();
+    debugger.symbols = symbols;
+
+    assert_eq!(
+        debugger.disassemble(0, 1),
+        "=> 0x4402 <start>: jsr sub (my prog.yuasm:3)"
+    );
+}
+
+#[test]
+fn test_breakpoint_on_label() {
+    let mut debugger = debugger();
+
+    debugger
+        .execute(Command::Break(Location::Symbol(String::from("sub"))))
+        .unwrap();
+
+    assert_eq!(debugger.resume(), Stop::Breakpoint(0x4408));
+    assert_eq!(debugger.resume(), Stop::Halted);
+    assert_eq!(debugger.machine.cpu.r1, 7);
+}
+
+#[test]
+fn test_finish() {
+    let mut debugger = debugger();
+
+    assert_eq!(debugger.step(1), Stop::Stepped);
+    assert_eq!(debugger.pc(), 0x4408);
+
+    assert_eq!(debugger.finish(), Stop::Returned);
+    assert_eq!(debugger.pc(), 0x4406);
+}
+
+#[test]
+fn test_finish_after_push() {
+    // start: jsr sub / hlt
+    // sub:   psh r1 / jsr inner / pop r1 / ret
+    // inner: ret
+    let program = vec![
+        0x8F, 0x04, 0x44, 0x08, 0xFE, 0x0C, 0x43, 0x0C, 0x8F, 0x04, 0x44, 0x12, 0x44, 0x0C, 0xD2,
+        0x0C, 0xD2, 0x0C,
+    ];
+
+    let mut debugger = Debugger::new(
+        Machine::new(program, [0; 510], 0x4402, false).unwrap(),
+        SymbolTable::new(),
+    );
+
+    assert_eq!(debugger.step(2), Stop::Stepped);
+    assert_eq!(debugger.pc(), 0x440A);
+
+    // The pop brings sp back below where it was, but the subroutine hasn't returned yet.
+    assert_eq!(debugger.finish(), Stop::Returned);
+    assert_eq!(debugger.pc(), 0x4406);
+}
+
+#[test]
+fn test_examine_and_disassemble() {
+    let mut debugger = debugger();
+
+    assert_eq!(
+        debugger.examine(0x4402, 4),
+        Ok(String::from("0x4402 <start>: 8F 04 44 08"))
+    );
+    assert_eq!(
+        debugger.disassemble(2, 2),
+        "=> 0x4402 <start>: jsr sub\n   0x4406 <start+0x4>: hlt"
+    );
+
+    // The instructions before the PC are found by decoding from `start`.
+    debugger.step(2);
+    assert_eq!(
+        debugger.disassemble(2, 1),
+        "   0x4406 <start+0x4>: hlt\n   0x4408 <sub>: mov r1, 0x7\n=> 0x440B <sub+0x3>: ret"
+    );
+}
+
+#[test]
+fn test_examine_past_end_of_memory() {
+    let debugger = debugger();
+
+    assert!(debugger
+        .examine(0xFFFF8, 8)
+        .unwrap()
+        .ends_with(": ?? ?? ?? ?? ?? ?? ?? ??"));
+    assert_eq!(
+        debugger.examine(0xFFFF8, 16),
+        Err(DebuggerError::PastEndOfMemory)
+    );
+    assert_eq!(
+        debugger.examine(u32::MAX, 16),
+        Err(DebuggerError::PastEndOfMemory)
+    );
+}
+
+#[test]
+fn test_unknown_symbol() {
+    let mut debugger = debugger();
+
+    assert_eq!(
+        debugger
+            .execute(Command::Break(Location::Symbol(String::from("nope"))))
+            .unwrap_err(),
+        DebuggerError::UnknownSymbol(String::from("nope"))
+    );
+}
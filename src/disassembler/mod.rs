@@ -0,0 +1,596 @@
+#[cfg(test)]
+mod tests;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::assembler::pseudo::SCRATCH;
+use crate::common::{
+    executable::{Executable, SectionKind, FAR_ROM_START, IVT_SIZE},
+    instruction::opcode::{AddressingMode, Instruction, Opcode},
+    symbols::{SymbolError, SymbolTable},
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DisassemblerError {
+    MissingText,
+    MissingInterruptTable,
+    InvalidSymbols(SymbolError),
+    InvalidOpcode(u8),
+    TruncatedInstruction(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Register(u8),
+    Number(u16),
+    Address(u32),
+}
+
+#[derive(Debug, Clone)]
+pub struct DisassembledInstruction {
+    pub addr: u32,
+    pub bytes: Vec<u8>,
+    pub opcode: Opcode,
+    pub args: Vec<Operand>,
+}
+
+impl DisassembledInstruction {
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    pub fn is_branch(&self) -> bool {
+        matches!(
+            self.opcode,
+            Opcode::BEQ
+                | Opcode::BGT
+                | Opcode::BLT
+                | Opcode::BOF
+                | Opcode::BNE
+                | Opcode::BGE
+                | Opcode::BLE
+                | Opcode::JMP
+                | Opcode::JSR
+                | Opcode::JSRF
+        )
+    }
+
+    pub fn target(&self) -> Option<u32> {
+        if !self.is_branch() {
+            return None;
+        }
+
+        match self.args.first() {
+            Some(Operand::Address(addr)) => Some(*addr),
+            _ => None,
+        }
+    }
+}
+
+pub fn register_name(reg: u8) -> String {
+    match reg {
+        0..=5 => format!("r{}", reg + 1),
+        6 => String::from("pc"),
+        7 => String::from("sp"),
+        8 => String::from("bp"),
+        _ => format!("r?{}", reg),
+    }
+}
+
+pub fn mnemonic(opcode: Opcode) -> String {
+    format!("{:?}", opcode).to_lowercase()
+}
+
+// Decodes a single instruction from the start of `bytes`, which live at `addr` in memory.
+pub fn decode(bytes: &[u8], addr: u32) -> Result<DisassembledInstruction, DisassemblerError> {
+    if bytes.len() < 2 {
+        return Err(DisassemblerError::TruncatedInstruction(addr));
+    }
+
+    let (opcode, mode, _, arg_count, _) = match Instruction::lookup(bytes[0]) {
+        Some(info) => *info,
+        None => return Err(DisassemblerError::InvalidOpcode(bytes[0])),
+    };
+
+    let meta = bytes[1];
+    let len = match (meta & 0b0000_1100) >> 2 {
+        0b00 => 3,
+        0b01 => 4,
+        0b10 => 5,
+        _ => 2,
+    };
+
+    if bytes.len() < len {
+        return Err(DisassemblerError::TruncatedInstruction(addr));
+    }
+
+    let data: u32 = match len {
+        3 => bytes[2] as u32,
+        4 => u16::from_be_bytes([bytes[2], bytes[3]]) as u32,
+        5 => (((bytes[2] & 0xF) as u32) << 16) | u16::from_be_bytes([bytes[3], bytes[4]]) as u32,
+        _ => 0,
+    };
+
+    let operand = match mode {
+        AddressingMode::Immediate => Operand::Number(data as u16),
+        AddressingMode::Register => Operand::Register(data as u8),
+        AddressingMode::Direct => Operand::Address(data),
+        AddressingMode::Discard => Operand::Number(0),
+    };
+
+    let args = match arg_count {
+        0 => vec![],
+        1 => match mode {
+            AddressingMode::Register => vec![Operand::Register(meta >> 4)],
+            _ => vec![operand],
+        },
+        _ => vec![Operand::Register(meta >> 4), operand],
+    };
+
+    Ok(DisassembledInstruction {
+        addr,
+        bytes: bytes[..len].to_vec(),
+        opcode,
+        args,
+    })
+}
+
+pub struct Disassembler {
+    pub start_index: u32,
+    pub data_start: u32,
+    pub data: Vec<u8>,
+    pub text_start: u32,
+    pub text: Vec<u8>,
+    pub far_start: u32,
+    pub far_text: Vec<u8>,
+    pub interrupts: BTreeMap<u8, u16>,
+    pub symbols: SymbolTable,
+    // Print the pseudo-instructions the assembler expands, see `assembler::pseudo`.
+    pub resugar: bool,
+}
+
+impl Disassembler {
+    pub fn new(executable: &Executable) -> Result<Disassembler, DisassemblerError> {
+        let text = match executable.section(SectionKind::Text) {
+            Some(text) => text,
+            None => return Err(DisassemblerError::MissingText),
+        };
+
+        let ivt = match executable.section(SectionKind::Ivt) {
+            Some(ivt) if ivt.bytes.len() == IVT_SIZE => ivt,
+            _ => return Err(DisassemblerError::MissingInterruptTable),
+        };
+
+        let (data_start, data) = match executable.section(SectionKind::Data) {
+            Some(data) => (data.load, data.bytes.clone()),
+            None => (text.load, Vec::new()),
+        };
+
+        let (far_start, far_text) = match executable.section(SectionKind::FarText) {
+            Some(far) => (far.load, far.bytes.clone()),
+            None => (FAR_ROM_START, Vec::new()),
+        };
+
+        let symbols = match executable.section(SectionKind::Symbols) {
+            Some(section) => match SymbolTable::from_bytes(&section.bytes) {
+                Ok(symbols) => symbols,
+                Err(error) => return Err(DisassemblerError::InvalidSymbols(error)),
+            },
+            None => SymbolTable::new(),
+        };
+
+        let mut interrupts = BTreeMap::new();
+
+        for (i, entry) in ivt.bytes.chunks(2).enumerate() {
+            let addr = u16::from_be_bytes([entry[0], entry[1]]);
+
+            if addr != 0 {
+                interrupts.insert(i as u8, addr);
+            }
+        }
+
+        Ok(Disassembler {
+            start_index: executable.entry,
+            data_start,
+            data,
+            text_start: text.load,
+            text: text.bytes.clone(),
+            far_start,
+            far_text,
+            interrupts,
+            symbols,
+            resugar: false,
+        })
+    }
+
+    // Decodes the text section, then the far text section. Bytes that aren't a valid instruction
+    // are returned as errors and skipped one at a time so the rest of the section can still be
+    // read.
+    pub fn instructions(&self) -> Vec<Result<DisassembledInstruction, (u32, u8)>> {
+        let mut res = Vec::new();
+
+        for (start, text) in [
+            (self.text_start, &self.text),
+            (self.far_start, &self.far_text),
+        ] {
+            let mut offset = 0;
+
+            while offset < text.len() {
+                let addr = start + offset as u32;
+
+                match decode(&text[offset..], addr) {
+                    Ok(instruction) => {
+                        offset += instruction.len();
+                        res.push(Ok(instruction));
+                    }
+                    Err(_) => {
+                        res.push(Err((addr, text[offset])));
+                        offset += 1;
+                    }
+                }
+            }
+        }
+
+        res
+    }
+
+    fn in_text(&self, addr: u32) -> bool {
+        (self.text_start..self.text_start + self.text.len() as u32).contains(&addr)
+            || (self.far_start..self.far_start + self.far_text.len() as u32).contains(&addr)
+    }
+
+    fn text_labels(&self, instructions: &[DisassembledInstruction]) -> BTreeMap<u32, String> {
+        let starts: BTreeSet<u32> = instructions.iter().map(|i| i.addr).collect();
+        let mut labels: BTreeMap<u32, String> = BTreeMap::new();
+
+        for instruction in instructions {
+            if let Some(target) = instruction.target() {
+                if starts.contains(&target) && !labels.contains_key(&target) {
+                    let prefix = if matches!(instruction.opcode, Opcode::JSR | Opcode::JSRF) {
+                        "sub"
+                    } else {
+                        "loc"
+                    };
+
+                    labels.insert(target, format!("{}{:04x}", prefix, target));
+                }
+            }
+        }
+
+        for (irq, addr) in &self.interrupts {
+            let addr = *addr as u32;
+
+            if starts.contains(&addr) {
+                labels.insert(addr, format!("irq{:02x}", irq));
+            }
+        }
+
+        if self.in_text(self.start_index) {
+            labels.insert(self.start_index, String::from("start"));
+        }
+
+        // Every instruction has to belong to a label, so the text sections always open with one.
+        labels
+            .entry(self.text_start)
+            .or_insert_with(|| String::from("text"));
+
+        if !self.far_text.is_empty() {
+            labels
+                .entry(self.far_start)
+                .or_insert_with(|| String::from("far"));
+        }
+
+        labels
+    }
+
+    fn data_refs(&self, instructions: &[DisassembledInstruction]) -> BTreeSet<u32> {
+        let data_range = self.data_start..self.data_start + self.data.len() as u32;
+        let mut refs = BTreeSet::new();
+
+        for instruction in instructions {
+            if instruction.is_branch() || instruction.len() != 4 {
+                continue;
+            }
+
+            if let Some(Operand::Address(addr)) = instruction.args.last() {
+                if data_range.contains(addr) {
+                    refs.insert(*addr);
+                }
+            }
+        }
+
+        refs
+    }
+
+    fn format_operand(
+        instruction: &DisassembledInstruction,
+        operand: &Operand,
+        labels: &BTreeMap<u32, String>,
+    ) -> String {
+        match operand {
+            Operand::Register(reg) => register_name(*reg),
+            Operand::Number(num) => format!("0x{:X}", num),
+            // Only where the label would assemble to the same bytes, 20-bit addresses are only
+            // used for labels in far text.
+            Operand::Address(addr) => match labels.get(addr) {
+                Some(label) if instruction.len() == 4 || *addr > 0xFFFF => label.clone(),
+                _ => format!("$0x{:X}", addr),
+            },
+        }
+    }
+
+    pub fn format_instruction(
+        instruction: &DisassembledInstruction,
+        labels: &BTreeMap<u32, String>,
+    ) -> String {
+        let args: Vec<String> = instruction
+            .args
+            .iter()
+            .map(|arg| Self::format_operand(instruction, arg, labels))
+            .collect();
+
+        if args.is_empty() {
+            mnemonic(instruction.opcode)
+        } else {
+            format!("{} {}", mnemonic(instruction.opcode), args.join(", "))
+        }
+    }
+
+    /*
+    Matches the start of `run` against the sequences pseudo-instructions expand to, returning the
+    pseudo-instruction and how many instructions it covers. Numbers have to be stored the way the
+    assembler would store them, so the output still assembles to the same bytes.
+     */
+    fn sugar(
+        run: &[&DisassembledInstruction],
+        labels: &BTreeMap<u32, String>,
+    ) -> Option<(String, usize)> {
+        use Operand::{Number, Register};
+
+        let first = run[0];
+        let canonical = |instruction: &DisassembledInstruction| match instruction.args.last() {
+            Some(Number(n)) => instruction.len() == if *n > 0xFF { 4 } else { 3 },
+            _ => true,
+        };
+        let operands = |instructions: &[&DisassembledInstruction]| {
+            let operands: Vec<String> = instructions
+                .iter()
+                .map(|i| Self::format_operand(i, &i.args[0], labels))
+                .collect();
+
+            operands.join(", ")
+        };
+
+        // `ld r1, [r2+4]`, which works out the address in the scratch register.
+        if let (Opcode::MOV, [Register(SCRATCH), Register(base)], Some(offset), Some(load)) =
+            (first.opcode, first.args.as_slice(), run.get(1), run.get(2))
+        {
+            let sign = match offset.opcode {
+                Opcode::ADD => Some("+"),
+                Opcode::SUB => Some("-"),
+                _ => None,
+            };
+
+            if let (
+                Some(sign),
+                [Register(SCRATCH), amount],
+                Opcode::LD,
+                [Register(dest), Register(SCRATCH)],
+            ) = (
+                sign,
+                offset.args.as_slice(),
+                load.opcode,
+                load.args.as_slice(),
+            ) {
+                if *base != SCRATCH && canonical(offset) {
+                    let text = format!(
+                        "ld {}, [{}{}{}]",
+                        register_name(*dest),
+                        register_name(*base),
+                        sign,
+                        Self::format_operand(offset, amount, labels)
+                    );
+
+                    return Some((text, 3));
+                }
+            }
+        }
+
+        let same = |opcode: Opcode| {
+            run.iter()
+                .take_while(|i| i.opcode == opcode && matches!(i.args.as_slice(), [_]))
+                .count()
+        };
+
+        match (first.opcode, first.args.as_slice()) {
+            (Opcode::XOR, [Register(reg), Number(0xFFFF)]) if canonical(first) => {
+                let negated = run.get(1).is_some_and(|next| {
+                    next.opcode == Opcode::ADD
+                        && next.args == [Register(*reg), Number(1)]
+                        && canonical(next)
+                });
+
+                if negated {
+                    Some((format!("neg {}", register_name(*reg)), 2))
+                } else {
+                    Some((format!("not {}", register_name(*reg)), 1))
+                }
+            }
+            (Opcode::ADD, [Register(reg), Number(1)]) if canonical(first) => {
+                Some((format!("inc {}", register_name(*reg)), 1))
+            }
+            (Opcode::SUB, [Register(reg), Number(1)]) if canonical(first) => {
+                Some((format!("dec {}", register_name(*reg)), 1))
+            }
+            (Opcode::MOV, [Register(reg), Number(0)]) if canonical(first) => {
+                Some((format!("clr {}", register_name(*reg)), 1))
+            }
+            (Opcode::MOV, [Register(reg), addr @ Operand::Address(_)]) => Some((
+                format!(
+                    "la {}, {}",
+                    register_name(*reg),
+                    Self::format_operand(first, addr, labels)
+                ),
+                1,
+            )),
+            (Opcode::JSR, [target]) => Some((
+                format!("call {}", Self::format_operand(first, target, labels)),
+                1,
+            )),
+            (Opcode::PSH, _) if same(Opcode::PSH) > 1 => {
+                let count = same(Opcode::PSH);
+                Some((format!("push {}", operands(&run[..count])), count))
+            }
+            (Opcode::POP, [Register(_)]) if same(Opcode::POP) > 1 => {
+                let count = same(Opcode::POP);
+                let mut popped = run[..count].to_vec();
+                popped.reverse();
+
+                Some((format!("pop {}", operands(&popped)), count))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn disassemble(&self) -> String {
+        let decoded = self.instructions();
+        let instructions: Vec<DisassembledInstruction> =
+            decoded.iter().flatten().cloned().collect();
+
+        let mut labels = self.text_labels(&instructions);
+
+        let mut data_refs = self.data_refs(&instructions);
+        data_refs.extend(self.symbols.symbols.values());
+        let mut data_lines: Vec<(u32, Vec<u8>)> = Vec::new();
+
+        for (i, byte) in self.data.iter().enumerate() {
+            let addr = self.data_start + i as u32;
+
+            match data_lines.last_mut() {
+                Some((_, bytes)) if bytes.len() < 8 && !data_refs.contains(&addr) => {
+                    bytes.push(*byte)
+                }
+                _ => data_lines.push((addr, vec![*byte])),
+            }
+        }
+
+        for (addr, _) in &data_lines {
+            labels.insert(*addr, format!("data{:04x}", addr));
+        }
+
+        // Real label names win over generated ones wherever they start a line of output.
+        let starts: BTreeSet<u32> = decoded
+            .iter()
+            .map(|result| match result {
+                Ok(instruction) => instruction.addr,
+                Err((addr, _)) => *addr,
+            })
+            .chain(data_lines.iter().map(|(addr, _)| *addr))
+            .collect();
+
+        for (addr, name) in self.symbols.by_address() {
+            if starts.contains(&addr) {
+                labels.insert(addr, name);
+            }
+        }
+
+        let mut out = String::new();
+
+        if let Some(label) = labels.get(&self.start_index) {
+            out += &format!(".main {}\n", label);
+        } else {
+            out += &format!(
+                "; Entry point $0x{:X} is outside the text section\n",
+                self.start_index
+            );
+        }
+
+        for (irq, addr) in &self.interrupts {
+            match labels.get(&(*addr as u32)) {
+                Some(label) => out += &format!(".int 0x{:02X} {}\n", irq, label),
+                None => out += &format!("; .int 0x{:02X} $0x{:X}\n", irq, addr),
+            }
+        }
+
+        out += "\n.text\n";
+
+        let mut i = 0;
+
+        while i < decoded.len() {
+            let result = &decoded[i];
+            let addr = match result {
+                Ok(instruction) => instruction.addr,
+                Err((addr, _)) => *addr,
+            };
+
+            if addr == self.far_start && !self.far_text.is_empty() {
+                out += "\n.far\n";
+            }
+
+            if let Some(label) = labels.get(&addr) {
+                out += &format!("\n{}:\n", label);
+            }
+
+            // A pseudo-instruction never spans a label, since something may jump into the middle.
+            let run: Vec<&DisassembledInstruction> = decoded[i..]
+                .iter()
+                .map_while(|result| result.as_ref().ok())
+                .enumerate()
+                .take_while(|(j, instruction)| *j == 0 || !labels.contains_key(&instruction.addr))
+                .map(|(_, instruction)| instruction)
+                .collect();
+
+            let sugar = if self.resugar && !run.is_empty() {
+                Self::sugar(&run, &labels)
+            } else {
+                None
+            };
+
+            let (text, bytes, count) = match (result, sugar) {
+                (Ok(_), Some((text, count))) => {
+                    let bytes = run[..count]
+                        .iter()
+                        .flat_map(|instruction| instruction.bytes.clone())
+                        .collect();
+
+                    (text, bytes, count)
+                }
+                (Ok(instruction), None) => (
+                    Self::format_instruction(instruction, &labels),
+                    instruction.bytes.clone(),
+                    1,
+                ),
+                (Err((_, byte)), _) => (String::from("; invalid opcode"), vec![*byte], 1),
+            };
+
+            i += count;
+
+            let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+
+            let location = match self.symbols.describe(addr) {
+                Some(name) => format!("{:05X} <{}>", addr, name),
+                None => format!("{:05X}", addr),
+            };
+
+            out += &format!("    {:<24} ; {}: {}", text, location, hex.join(" "));
+
+            if let Some(source) = self.symbols.line(addr) {
+                out += &format!(" ({})", source);
+            }
+
+            out += "\n";
+        }
+
+        if !data_lines.is_empty() {
+            out += "\n.data\n\n";
+
+            for (addr, bytes) in &data_lines {
+                let values: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+
+                out += &format!("{}: db {}\n", labels[addr], values.join(", "));
+            }
+        }
+
+        out
+    }
+}
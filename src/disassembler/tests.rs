@@ -0,0 +1,181 @@
+use crate::common::{
+    executable::{Executable, Section, SectionKind, ROM_START},
+    instruction::opcode::Opcode,
+    symbols::SymbolTable,
+};
+
+use super::{decode, Disassembler, DisassemblerError, Operand};
+
+// Builds a binary in the original header format and reads it back.
+fn image(start: u16, data: &[u8], text: &[u8], ivt: &[(u8, u16)]) -> Executable {
+    let mut bytes = Vec::new();
+
+    bytes.extend_from_slice(&start.to_be_bytes());
+    bytes.extend_from_slice(&(text.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(data);
+    bytes.extend_from_slice(text);
+
+    let mut table = [0_u8; 510];
+
+    for (irq, addr) in ivt {
+        table[*irq as usize * 2] = (addr >> 8) as u8;
+        table[*irq as usize * 2 + 1] = *addr as u8;
+    }
+
+    bytes.extend_from_slice(&table);
+    Executable::parse_legacy(&bytes).unwrap()
+}
+
+#[test]
+fn test_decode_two_args_word() {
+    let instruction = decode(&[0x00, 0x14, 0x04, 0x01], 0x4402).unwrap();
+
+    assert_eq!(instruction.opcode, Opcode::MOV);
+    assert_eq!(
+        instruction.args,
+        vec![Operand::Register(1), Operand::Number(0x0401)]
+    );
+    assert_eq!(instruction.len(), 4);
+}
+
+#[test]
+fn test_decode_one_register() {
+    let instruction = decode(&[0x43, 0x2C], 0x4402).unwrap();
+
+    assert_eq!(instruction.opcode, Opcode::PSH);
+    assert_eq!(instruction.args, vec![Operand::Register(2)]);
+    assert_eq!(instruction.len(), 2);
+}
+
+#[test]
+fn test_decode_far_address() {
+    let instruction = decode(&[0x8E, 0x08, 0x01, 0x23, 0x45], 0x4402).unwrap();
+
+    assert_eq!(instruction.opcode, Opcode::JMP);
+    assert_eq!(instruction.target(), Some(0x12345));
+    assert_eq!(instruction.len(), 5);
+}
+
+#[test]
+fn test_decode_invalid_opcode() {
+    assert_eq!(
+        decode(&[0xA2, 0x0C], 0x4402).unwrap_err(),
+        DisassemblerError::InvalidOpcode(0xA2)
+    );
+}
+
+#[test]
+fn test_missing_interrupt_table() {
+    assert_eq!(
+        Disassembler::new(&Executable::new(0x4402).with_section(Section::new(
+            SectionKind::Text,
+            ROM_START,
+            vec![0xFE, 0x0C]
+        )))
+        .err(),
+        Some(DisassemblerError::MissingInterruptTable)
+    );
+}
+
+#[test]
+fn test_disassemble_labels() {
+    // start: jsr sub / hlt, sub: ret, with interrupt 1 pointing at the hlt.
+    let text = [0x8F, 0x04, 0x44, 0x08, 0xFE, 0x0C, 0xD2, 0x0C];
+    let bytes = image(0x4402, &[], &text, &[(1, 0x4406)]);
+
+    let output = Disassembler::new(&bytes).unwrap().disassemble();
+
+    assert!(output.contains(".main start\n"));
+    assert!(output.contains(".int 0x01 irq01\n"));
+    assert!(output.contains("\nstart:\n    jsr sub4408"));
+    assert!(output.contains("\nirq01:\n    hlt"));
+    assert!(output.contains("\nsub4408:\n    ret"));
+}
+
+#[test]
+fn test_disassemble_data() {
+    // mov r1, data4402 / hlt
+    let text = [0x80, 0x04, 0x44, 0x02, 0xFE, 0x0C];
+    let bytes = image(0x4404, &[0x48, 0x69], &text, &[]);
+
+    let output = Disassembler::new(&bytes).unwrap().disassemble();
+
+    assert!(output.contains("mov r1, data4402"));
+    assert!(output.contains("\ndata4402: db 0x48, 0x69\n"));
+}
+
+#[test]
+fn test_disassemble_with_symbols() {
+    let text = [0x80, 0x04, 0x44, 0x02, 0xFE, 0x0C];
+    let mut disassembler = Disassembler::new(&image(0x4404, &[0x48, 0x69], &text, &[])).unwrap();
+
+    disassembler.symbols =
+        SymbolTable::parse("04402 msg\n04404 main\n04408 main.yuasm:4\n").unwrap();
+
+    let output = disassembler.disassemble();
+
+    assert!(output.contains(".main main\n"));
+    assert!(output.contains("mov r1, msg"));
+    assert!(output.contains("; 04408 <main+0x4>: FE 0C (main.yuasm:4)\n"));
+    assert!(output.contains("\nmsg: db 0x48, 0x69\n"));
+}
+
+#[test]
+fn test_disassemble_far_text() {
+    // start: jsrf far / hlt, far: retf
+    let executable = image(
+        0x4402,
+        &[],
+        &[0xA0, 0x08, 0x01, 0x00, 0x00, 0xFE, 0x0C],
+        &[],
+    )
+    .with_section(Section::new(
+        SectionKind::FarText,
+        0x10000,
+        vec![0xE1, 0x0C],
+    ));
+
+    let output = Disassembler::new(&executable).unwrap().disassemble();
+
+    assert!(output.contains("\nstart:\n    jsrf sub10000"));
+    assert!(output.contains("\n.far\n\nsub10000:\n    retf"));
+}
+
+#[test]
+fn test_resugar() {
+    // inc r1 / neg r2 / push r1, r2 / pop r2 / irq01: pop r1 / ld r1, [r3+4] / hlt
+    let text = [
+        0x10, 0x00, 0x01, 0x17, 0x14, 0xFF, 0xFF, 0x10, 0x10, 0x01, 0x43, 0x0C, 0x43, 0x1C, 0x44,
+        0x1C, 0x44, 0x0C, 0x40, 0x50, 0x02, 0x10, 0x50, 0x04, 0x41, 0x00, 0x05, 0xFE, 0x0C,
+    ];
+    let mut disassembler = Disassembler::new(&image(0x4402, &[], &text, &[(1, 0x4412)])).unwrap();
+
+    assert!(disassembler
+        .disassemble()
+        .contains("\nstart:\n    add r1, 0x1 "));
+
+    disassembler.resugar = true;
+    let output = disassembler.disassemble();
+    let lines: Vec<&str> = output
+        .lines()
+        .map(|line| line.split(';').next().unwrap().trim_end())
+        .collect();
+
+    assert_eq!(
+        lines[5..],
+        [
+            "start:",
+            "    inc r1",
+            "    neg r2",
+            "    push r1, r2",
+            "    pop r2",
+            "",
+            "irq01:",
+            "    pop r1",
+            "    ld r1, [r3+0x4]",
+            "    hlt",
+        ]
+    );
+    assert!(output.contains("; 04405: 17 14 FF FF 10 10 01\n"));
+}
@@ -0,0 +1,376 @@
+#[cfg(test)]
+mod tests;
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::common::{
+    executable::{
+        Executable, ExecutableError, Section, SectionKind, FAR_ROM_START, IVT_SIZE, ROM_START,
+    },
+    object::{ObjectFile, RelocationSize, RelocationTarget},
+    symbols::SymbolTable,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LinkError {
+    DuplicateSymbol {
+        name: String,
+        first: String,
+        second: String,
+    },
+    UndefinedSymbol {
+        name: String,
+        object: String,
+    },
+    MissingEntry,
+    DuplicateEntry {
+        first: String,
+        second: String,
+    },
+    DuplicateInterrupt {
+        interrupt: u8,
+        first: String,
+        second: String,
+    },
+    // `object` and `index` locate the relocation, so the assembler can point at the operand.
+    RelocationOutOfRange {
+        object: usize,
+        index: usize,
+        value: i64,
+        bits: u32,
+    },
+    RelocationOutOfBounds {
+        object: String,
+        offset: u32,
+    },
+    // The interrupt table only holds 16-bit addresses.
+    FarInterruptHandler {
+        interrupt: u8,
+        handler: String,
+        addr: u32,
+    },
+    // The linked program doesn't pass the checks the loader makes, usually because it's too big.
+    InvalidExecutable(ExecutableError),
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::DuplicateSymbol {
+                name,
+                first,
+                second,
+            } => write!(
+                f,
+                "symbol `{}` is defined in both `{}` and `{}`",
+                name, first, second
+            ),
+            LinkError::UndefinedSymbol { name, object } => {
+                write!(f, "undefined symbol `{}`, used in `{}`", name, object)
+            }
+            LinkError::MissingEntry => write!(f, "none of the objects have a `.main` label"),
+            LinkError::DuplicateEntry { first, second } => write!(
+                f,
+                "both `{}` and `{}` choose the main label, only one object can",
+                first, second
+            ),
+            LinkError::DuplicateInterrupt {
+                interrupt,
+                first,
+                second,
+            } => write!(
+                f,
+                "interrupt 0x{:02X} has a handler in both `{}` and `{}`",
+                interrupt, first, second
+            ),
+            LinkError::RelocationOutOfRange { value, bits, .. } => write!(
+                f,
+                "relocated address 0x{:X} does not fit in {} bits",
+                value, bits
+            ),
+            LinkError::RelocationOutOfBounds { object, offset } => write!(
+                f,
+                "`{}` has a relocation at 0x{:X}, past the end of its section",
+                object, offset
+            ),
+            LinkError::FarInterruptHandler {
+                interrupt,
+                handler,
+                addr,
+            } => write!(
+                f,
+                "the handler for interrupt 0x{:02X}, `{}`, is at 0x{:05X} but handlers have to be below 0x10000",
+                interrupt, handler, addr
+            ),
+            LinkError::InvalidExecutable(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+/*
+Links objects into an executable. Every object's data is placed first, in the order the objects
+were given and padded to the alignment each one asks for, followed by every object's text:
+
+ROM_START       data of a.o, data of b.o, ..., text of a.o, text of b.o, ...
+FAR_ROM_START   far text of a.o, far text of b.o, ...
+
+Symbols are looked up in the object that uses them first, then among the `.global` symbols of
+every object.
+ */
+pub struct Linker {
+    objects: Vec<(String, ObjectFile)>,
+}
+
+struct Layout {
+    data: Vec<u32>,
+    text: Vec<u32>,
+    far_text: Vec<u32>,
+    globals: HashMap<String, (usize, u32)>,
+}
+
+impl Layout {
+    fn base(&self, object: usize, kind: SectionKind) -> u32 {
+        match kind {
+            SectionKind::Data => self.data[object],
+            SectionKind::FarText => self.far_text[object],
+            _ => self.text[object],
+        }
+    }
+}
+
+impl Linker {
+    pub fn new(objects: Vec<(String, ObjectFile)>) -> Linker {
+        Linker { objects }
+    }
+
+    fn layout(&self, errors: &mut Vec<LinkError>) -> Layout {
+        let mut layout = Layout {
+            data: Vec::new(),
+            text: Vec::new(),
+            far_text: Vec::new(),
+            globals: HashMap::new(),
+        };
+
+        let mut addr = ROM_START;
+
+        for (_, object) in &self.objects {
+            addr = addr.next_multiple_of(object.data_align.max(1));
+            layout.data.push(addr);
+            addr += object.data.len() as u32;
+        }
+
+        for (_, object) in &self.objects {
+            layout.text.push(addr);
+            addr += object.text.len() as u32;
+        }
+
+        let mut addr = FAR_ROM_START;
+
+        for (_, object) in &self.objects {
+            layout.far_text.push(addr);
+            addr += object.far_text.len() as u32;
+        }
+
+        for (i, (name, object)) in self.objects.iter().enumerate() {
+            for symbol in object.symbols.iter().filter(|symbol| symbol.global) {
+                let addr = layout.base(i, symbol.section) + symbol.offset;
+
+                match layout.globals.get(&symbol.name) {
+                    Some((first, _)) => errors.push(LinkError::DuplicateSymbol {
+                        name: symbol.name.clone(),
+                        first: self.objects[*first].0.clone(),
+                        second: name.clone(),
+                    }),
+                    None => {
+                        layout.globals.insert(symbol.name.clone(), (i, addr));
+                    }
+                }
+            }
+        }
+
+        layout
+    }
+
+    fn resolve(&self, layout: &Layout, object: usize, name: &str) -> Result<u32, LinkError> {
+        if let Some(symbol) = self.objects[object].1.symbol(name) {
+            return Ok(layout.base(object, symbol.section) + symbol.offset);
+        }
+
+        match layout.globals.get(name) {
+            Some((_, addr)) => Ok(*addr),
+            None => Err(LinkError::UndefinedSymbol {
+                name: name.to_string(),
+                object: self.objects[object].0.clone(),
+            }),
+        }
+    }
+
+    pub fn link(&self) -> Result<Executable, Vec<LinkError>> {
+        let mut errors = Vec::new();
+        let layout = self.layout(&mut errors);
+
+        let mut data = Vec::new();
+        let mut text = Vec::new();
+        let mut far_text = Vec::new();
+        let mut symbols = SymbolTable::new();
+        let mut entry: Option<(usize, u32)> = None;
+        let mut handlers: HashMap<u8, (usize, u32)> = HashMap::new();
+
+        for (i, (name, object)) in self.objects.iter().enumerate() {
+            let mut sections = [
+                object.data.clone(),
+                object.text.clone(),
+                object.far_text.clone(),
+            ];
+
+            for (index, relocation) in object.relocations.iter().enumerate() {
+                let target = match &relocation.target {
+                    RelocationTarget::Section(kind) => Ok(layout.base(i, *kind)),
+                    RelocationTarget::Symbol(symbol) => self.resolve(&layout, i, symbol),
+                };
+
+                let value = match target {
+                    Ok(addr) => addr as i64 + relocation.addend as i64,
+                    Err(error) => {
+                        errors.push(error);
+                        continue;
+                    }
+                };
+
+                let bits = match relocation.size {
+                    RelocationSize::Word => 16,
+                    RelocationSize::Double => 20,
+                };
+
+                if !(0..1 << bits).contains(&value) {
+                    errors.push(LinkError::RelocationOutOfRange {
+                        object: i,
+                        index,
+                        value,
+                        bits,
+                    });
+                    continue;
+                }
+
+                let bytes = match relocation.section {
+                    SectionKind::Data => &mut sections[0],
+                    SectionKind::FarText => &mut sections[2],
+                    _ => &mut sections[1],
+                };
+
+                let offset = relocation.offset as usize;
+                let value = (value as u32).to_be_bytes();
+
+                match bytes.get_mut(offset..offset + relocation.size.width()) {
+                    Some(field) => field.copy_from_slice(&value[4 - field.len()..]),
+                    None => errors.push(LinkError::RelocationOutOfBounds {
+                        object: name.clone(),
+                        offset: relocation.offset,
+                    }),
+                }
+            }
+
+            let [object_data, object_text, object_far_text] = sections;
+            // Pads up to where the object's data was aligned to.
+            data.resize((layout.data[i] - layout.data[0]) as usize, 0);
+            data.extend(object_data);
+            text.extend(object_text);
+            far_text.extend(object_far_text);
+
+            for symbol in &object.symbols {
+                symbols.insert(&symbol.name, layout.base(i, symbol.section) + symbol.offset);
+            }
+
+            for (section, offset, line) in &object.lines {
+                symbols.insert_line(layout.base(i, *section) + offset, &line.file, line.line);
+            }
+
+            if let Some(main) = &object.entry {
+                match entry {
+                    Some((first, _)) => errors.push(LinkError::DuplicateEntry {
+                        first: self.objects[first].0.clone(),
+                        second: name.clone(),
+                    }),
+                    None => match self.resolve(&layout, i, main) {
+                        Ok(addr) => entry = Some((i, addr)),
+                        Err(error) => errors.push(error),
+                    },
+                }
+            }
+
+            for (interrupt, handler) in &object.interrupts {
+                if let Some((first, _)) = handlers.get(interrupt) {
+                    errors.push(LinkError::DuplicateInterrupt {
+                        interrupt: *interrupt,
+                        first: self.objects[*first].0.clone(),
+                        second: name.clone(),
+                    });
+                    continue;
+                }
+
+                match self.resolve(&layout, i, handler) {
+                    Ok(addr) if addr > 0xFFFF => errors.push(LinkError::FarInterruptHandler {
+                        interrupt: *interrupt,
+                        handler: handler.clone(),
+                        addr,
+                    }),
+                    Ok(addr) => {
+                        handlers.insert(*interrupt, (i, addr));
+                    }
+                    Err(error) => errors.push(error),
+                }
+            }
+        }
+
+        if entry.is_none() && errors.is_empty() {
+            errors.push(LinkError::MissingEntry);
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut ivt = vec![0; IVT_SIZE];
+
+        for (interrupt, (_, addr)) in handlers {
+            let offset = interrupt as usize * 2;
+
+            // The table has room for 255 entries, so 0xFF can't have a handler.
+            if let Some(slot) = ivt.get_mut(offset..offset + 2) {
+                slot.copy_from_slice(&(addr as u16).to_be_bytes());
+            }
+        }
+
+        let data_start = layout.data.first().copied().unwrap_or(ROM_START);
+        let text_start = layout.text.first().copied().unwrap_or(ROM_START);
+
+        let mut executable = Executable::new(entry.map_or(0, |(_, addr)| addr))
+            .with_section(Section::new(SectionKind::Data, data_start, data))
+            .with_section(Section::new(SectionKind::Text, text_start, text));
+
+        // Programs without far code keep the same sections they always had.
+        if !far_text.is_empty() {
+            executable = executable.with_section(Section::new(
+                SectionKind::FarText,
+                FAR_ROM_START,
+                far_text,
+            ));
+        }
+
+        let executable = executable
+            .with_section(Section::new(SectionKind::Ivt, 0, ivt))
+            .with_section(Section::new(
+                SectionKind::Symbols,
+                0,
+                symbols.to_string().into_bytes(),
+            ));
+
+        // Nothing should be written out that `run` would refuse to load.
+        executable
+            .validate()
+            .map_err(|error| vec![LinkError::InvalidExecutable(error)])?;
+
+        Ok(executable)
+    }
+}
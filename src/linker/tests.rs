@@ -0,0 +1,241 @@
+use crate::assembler::{parser::Parser, source::SourceMap, tokenizer::tokenize, Assembler};
+use crate::common::{
+    executable::{ExecutableError, SectionKind, FAR_ROM_START, ROM_START},
+    object::{ObjectFile, RelocationSize, RelocationTarget},
+    symbols::SymbolTable,
+};
+
+use super::{LinkError, Linker};
+
+fn object(name: &str, source: &str) -> (String, ObjectFile) {
+    let mut sources = SourceMap::new();
+    sources.add(name, source);
+
+    let parser_res = Parser::new(tokenize(source, 0)).parse().unwrap();
+    let (object, _) = Assembler::new(parser_res, &sources)
+        .assemble_object()
+        .unwrap();
+
+    (name.to_string(), object)
+}
+
+const MAIN: &str = "\
+.main start
+.extern print
+.data
+msg: db \"Hi\", 0
+.text
+start:
+    mov r1, msg
+    jsr print
+    hlt
+";
+
+const PRINT: &str = "\
+.global print
+.text
+print:
+    ret
+";
+
+#[test]
+fn test_link() {
+    let (name, main) = object("main.yuasm", MAIN);
+
+    assert_eq!(main.externs, vec![String::from("print")]);
+    assert!(main
+        .relocations
+        .iter()
+        .any(|r| r.target == RelocationTarget::Symbol(String::from("print"))));
+    assert!(main
+        .relocations
+        .iter()
+        .any(|r| r.target == RelocationTarget::Section(SectionKind::Data)));
+
+    let executable = Linker::new(vec![(name, main), object("print.yuasm", PRINT)])
+        .link()
+        .unwrap();
+
+    let data = executable.section(SectionKind::Data).unwrap();
+    let text = executable.section(SectionKind::Text).unwrap();
+    let symbols =
+        SymbolTable::from_bytes(&executable.section(SectionKind::Symbols).unwrap().bytes).unwrap();
+
+    let print = symbols.get("print").unwrap();
+
+    assert_eq!(data.load, ROM_START);
+    assert_eq!(data.bytes, b"Hi\0");
+    assert_eq!(text.load, ROM_START + 3);
+    assert_eq!(executable.entry, text.load);
+    assert_eq!(symbols.get("msg"), Some(ROM_START));
+    // Both objects' text follows the data, in the order the objects were given.
+    assert_eq!(print, text.load + text.bytes.len() as u32 - 2);
+    // `mov r1, msg` and `jsr print` have their operands filled in.
+    assert_eq!(&text.bytes[2..4], &(ROM_START as u16).to_be_bytes());
+    assert_eq!(&text.bytes[6..8], &(print as u16).to_be_bytes());
+}
+
+#[test]
+fn test_link_interrupts() {
+    let handler = ".global tick\n.int 0x20 tick\n.text\ntick:\n    rei\n";
+    let executable = Linker::new(vec![
+        object("main.yuasm", ".main start\n.text\nstart:\n    hlt\n"),
+        object("tick.yuasm", handler),
+    ])
+    .link()
+    .unwrap();
+
+    let ivt = &executable.section(SectionKind::Ivt).unwrap().bytes;
+
+    assert_eq!(&ivt[0x40..0x42], &(ROM_START as u16 + 2).to_be_bytes());
+
+    let errors = Linker::new(vec![
+        object("main.yuasm", ".main start\n.text\nstart:\n    hlt\n"),
+        object("a.yuasm", handler),
+        object("b.yuasm", ".int 0x20 other\n.text\nother:\n    rei\n"),
+    ])
+    .link()
+    .unwrap_err();
+
+    assert_eq!(
+        errors,
+        vec![LinkError::DuplicateInterrupt {
+            interrupt: 0x20,
+            first: String::from("a.yuasm"),
+            second: String::from("b.yuasm"),
+        }]
+    );
+}
+
+#[test]
+fn test_link_errors() {
+    let errors = Linker::new(vec![object("main.yuasm", MAIN)])
+        .link()
+        .unwrap_err();
+
+    assert_eq!(
+        errors,
+        vec![LinkError::UndefinedSymbol {
+            name: String::from("print"),
+            object: String::from("main.yuasm"),
+        }]
+    );
+
+    let errors = Linker::new(vec![
+        object("main.yuasm", MAIN),
+        object("a.yuasm", PRINT),
+        object("b.yuasm", PRINT),
+    ])
+    .link()
+    .unwrap_err();
+
+    assert_eq!(
+        errors,
+        vec![LinkError::DuplicateSymbol {
+            name: String::from("print"),
+            first: String::from("a.yuasm"),
+            second: String::from("b.yuasm"),
+        }]
+    );
+
+    let errors = Linker::new(vec![object("print.yuasm", PRINT)])
+        .link()
+        .unwrap_err();
+
+    assert_eq!(errors, vec![LinkError::MissingEntry]);
+
+    // Each object fits in ROM on its own, but not both of them.
+    let nops = "    nop\n".repeat(300);
+    let errors = Linker::new(vec![
+        object(
+            "main.yuasm",
+            &format!(".main start\n.text\nstart:\n{}", nops),
+        ),
+        object("more.yuasm", &format!(".text\nmore:\n{}", nops)),
+    ])
+    .link()
+    .unwrap_err();
+
+    assert!(matches!(
+        errors[..],
+        [LinkError::InvalidExecutable(
+            ExecutableError::InvalidLoadAddress { .. }
+        )]
+    ));
+}
+
+#[test]
+fn test_link_alignment() {
+    let executable = Linker::new(vec![
+        object(
+            "main.yuasm",
+            ".main start\n.data\nbyte: db 1\n.text\nstart:\n    hlt\n",
+        ),
+        object("table.yuasm", ".data\n.align 8\ntable: dw 2\n"),
+    ])
+    .link()
+    .unwrap();
+
+    let data = executable.section(SectionKind::Data).unwrap();
+    let symbols =
+        SymbolTable::from_bytes(&executable.section(SectionKind::Symbols).unwrap().bytes).unwrap();
+    let table = symbols.get("table").unwrap();
+
+    assert_eq!(table & 7, 0);
+    assert_eq!(data.bytes[0], 1);
+    assert_eq!(data.bytes[(table - data.load) as usize..], [0x00, 0x02]);
+    assert!(data.bytes[1..(table - data.load) as usize]
+        .iter()
+        .all(|byte| *byte == 0));
+}
+
+#[test]
+fn test_link_far_text() {
+    let (name, main) = object(
+        "main.yuasm",
+        ".main start\n.text\nstart:\n    jsrf helper\n    hlt\n.far\nhelper:\n    retf\n",
+    );
+
+    assert!(main
+        .relocations
+        .iter()
+        .any(|r| r.size == RelocationSize::Double
+            && r.target == RelocationTarget::Section(SectionKind::FarText)));
+
+    let executable = Linker::new(vec![
+        (name, main),
+        object("more.yuasm", ".far\nother:\n    retf\n"),
+    ])
+    .link()
+    .unwrap();
+
+    let far = executable.section(SectionKind::FarText).unwrap();
+    let symbols =
+        SymbolTable::from_bytes(&executable.section(SectionKind::Symbols).unwrap().bytes).unwrap();
+
+    assert_eq!(far.load, FAR_ROM_START);
+    assert_eq!(far.bytes, vec![0xE1, 0x0C, 0xE1, 0x0C]);
+    assert_eq!(symbols.get("helper"), Some(FAR_ROM_START));
+    assert_eq!(symbols.get("other"), Some(FAR_ROM_START + 2));
+
+    // Another object can't tell the label is far, so its operand is only a word.
+    let errors = Linker::new(vec![
+        object(
+            "main.yuasm",
+            ".main start\n.extern helper\n.text\nstart:\n    jsrf helper\n",
+        ),
+        object("far.yuasm", ".global helper\n.far\nhelper:\n    retf\n"),
+    ])
+    .link()
+    .unwrap_err();
+
+    assert_eq!(
+        errors,
+        vec![LinkError::RelocationOutOfRange {
+            object: 0,
+            index: 0,
+            value: FAR_ROM_START as i64,
+            bits: 16,
+        }]
+    );
+}
@@ -1,18 +1,29 @@
 mod assembler;
 pub mod common;
+mod debugger;
+mod disassembler;
+mod linker;
 mod vcpu;
 
-use assembler::parser::{Parser, TokenInfoType};
-use assembler::tokenizer::Token;
+use assembler::diagnostic::Diagnostic;
+use assembler::parser::Parser;
+use assembler::source::{self, SourceMap};
 use assembler::Assembler;
 use clap::{Parser as ClapParser, Subcommand};
-use itertools::Itertools;
-use logos::Logos;
-use std::fs::{self, File};
-use std::io::{BufRead, Read};
-use std::path::PathBuf;
+use common::executable::{Executable, LoadedProgram, SectionKind};
+use common::object::ObjectFile;
+use common::symbols::SymbolTable;
+use debugger::Debugger;
+use disassembler::Disassembler;
+use linker::Linker;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::str::FromStr;
+use std::time::Instant;
+use vcpu::device::disk::{build_image, DiskFile, SECTOR_SIZE};
+use vcpu::machine::Machine;
+use vcpu::serial::SerialConfig;
 
 use common::instruction::opcode::{AddressingMode, Instruction, Opcode};
 
@@ -34,6 +45,104 @@ enum Commands {
 
         #[arg(short, long)]
         output: PathBuf,
+
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "Also write the label addresses and line table to a symbol file."
+        )]
+        symbols: Option<PathBuf>,
+
+        #[arg(long, help = "Leave the symbol table out of the binary.")]
+        strip: bool,
+
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "Also write a listing of every source line with its address and bytes."
+        )]
+        listing: Option<PathBuf>,
+
+        #[arg(
+            short = 'I',
+            long = "include-dir",
+            value_name = "DIR",
+            help = "Search DIR for files named by `.include`. Can be given more than once."
+        )]
+        include_dirs: Vec<PathBuf>,
+
+        #[arg(
+            short = 'D',
+            long = "define",
+            value_name = "NAME[=VALUE]",
+            value_parser = parse_define,
+            help = "Define a constant before assembling, as if by `.equ`. VALUE defaults to 1. Can be given more than once."
+        )]
+        defines: Vec<(String, i64)>,
+
+        #[arg(
+            short = 'c',
+            long,
+            conflicts_with_all = ["symbols", "strip"],
+            help = "Write a relocatable object file to pass to `link` instead of an executable."
+        )]
+        object: bool,
+    },
+
+    #[command(
+        arg_required_else_help = true,
+        about = "Link object files from `assemble --object` into an executable."
+    )]
+    Link {
+        #[arg(
+            required = true,
+            help = "Object files, laid out in the order they are given."
+        )]
+        inputs: Vec<PathBuf>,
+
+        #[arg(short, long)]
+        output: PathBuf,
+
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "Also write the label addresses and line table to a symbol file."
+        )]
+        symbols: Option<PathBuf>,
+
+        #[arg(long, help = "Leave the symbol table out of the binary.")]
+        strip: bool,
+    },
+
+    #[command(
+        arg_required_else_help = true,
+        about = "Disassemble a YuCPU binary back into assembly."
+    )]
+    Disassemble {
+        #[arg(short, long)]
+        input: PathBuf,
+
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        #[arg(
+            short,
+            long,
+            help = "Symbol file used to resolve labels. Defaults to the symbols embedded in the binary, then the input with a .sym extension."
+        )]
+        symbols: Option<PathBuf>,
+
+        #[arg(
+            long,
+            help = "Read a binary made before the versioned executable format."
+        )]
+        legacy: bool,
+
+        #[arg(
+            long,
+            help = "Show instruction sequences the assembler expands pseudo-instructions into, such as `add r1, 1`, as the pseudo-instruction (`inc r1`)."
+        )]
+        resugar: bool,
     },
 
     #[command(arg_required_else_help = true, about = "Run the YuCPU PC.")]
@@ -43,6 +152,146 @@ enum Commands {
 
         #[arg(short)]
         debug_mode: bool,
+
+        #[arg(long, help = "Run without opening a window.")]
+        headless: bool,
+
+        #[arg(
+            long,
+            requires = "headless",
+            help = "Stop after this many clock cycles."
+        )]
+        max_cycles: Option<u64>,
+
+        #[arg(
+            long,
+            requires = "headless",
+            default_value = "r1",
+            value_parser = parse_exit_register,
+            help = "Register used as the exit status once the program halts. 124 means the cycle limit was hit and 125 a CPU fault, a value that would be read as either of those, or doesn't fit in an exit status, exits with 123."
+        )]
+        exit_register: u8,
+
+        #[arg(
+            long,
+            requires = "headless",
+            help = "Print the VGA text buffer to stdout once the program stops."
+        )]
+        dump_vga: bool,
+
+        #[arg(
+            long,
+            value_name = "PORT",
+            conflicts_with = "headless",
+            help = "Run without a window and wait for gdb to attach on this localhost port."
+        )]
+        gdb: Option<u16>,
+
+        #[arg(
+            long,
+            requires = "headless",
+            help = "Print every instruction to stderr before it runs."
+        )]
+        trace: bool,
+
+        #[arg(
+            long,
+            value_name = "HZ",
+            value_parser = clap::value_parser!(u64).range(1..),
+            help = "Run at this many clock cycles per second instead of as fast as possible."
+        )]
+        clock_hz: Option<u64>,
+
+        #[arg(
+            long,
+            value_name = "BACKEND",
+            value_parser = SerialConfig::parse,
+            help = "Connect the UART to `stdio`, `file:PATH` (output only) or `unix:PATH`, a socket that is waited on for a client before the program starts."
+        )]
+        serial: Option<SerialConfig>,
+
+        #[arg(
+            long,
+            value_name = "IMAGE",
+            help = "Attach a disk image made with `mkdisk`. Sectors the program writes are saved to it."
+        )]
+        disk: Option<PathBuf>,
+
+        #[arg(
+            short,
+            long,
+            help = "Symbol file used to resolve labels. Defaults to the symbols embedded in the binary, then the input with a .sym extension."
+        )]
+        symbols: Option<PathBuf>,
+
+        #[arg(
+            long,
+            help = "Read a binary made before the versioned executable format."
+        )]
+        legacy: bool,
+    },
+
+    #[command(
+        arg_required_else_help = true,
+        about = "Measure how many instructions per second the emulator runs."
+    )]
+    Bench {
+        #[arg(short, long)]
+        input: PathBuf,
+
+        #[arg(long, default_value_t = 10_000_000)]
+        cycles: u64,
+
+        #[arg(
+            long,
+            help = "Read a binary made before the versioned executable format."
+        )]
+        legacy: bool,
+    },
+
+    #[command(
+        arg_required_else_help = true,
+        about = "Step through a YuCPU binary in an interactive debugger."
+    )]
+    Debug {
+        #[arg(short, long)]
+        input: PathBuf,
+
+        #[arg(
+            short,
+            long,
+            help = "Symbol file used to resolve labels. Defaults to the symbols embedded in the binary, then the input with a .sym extension."
+        )]
+        symbols: Option<PathBuf>,
+
+        #[arg(
+            long,
+            help = "Read a binary made before the versioned executable format."
+        )]
+        legacy: bool,
+    },
+
+    #[command(
+        arg_required_else_help = true,
+        about = "Create a disk image for `run --disk`."
+    )]
+    Mkdisk {
+        #[arg(short, long)]
+        output: PathBuf,
+
+        #[arg(
+            long,
+            default_value_t = 0,
+            help = "Pad the image to at least this many 512 byte sectors."
+        )]
+        sectors: u16,
+
+        #[arg(
+            value_name = "FILE[@SECTOR]",
+            value_parser = parse_disk_file,
+            help = "Files to copy in. Each starts on a new sector, right after the one before it unless a sector is given."
+        )]
+        files: Vec<(PathBuf, Option<u16>)>,
     },
 
     #[command(
@@ -58,95 +307,553 @@ enum Commands {
     Instruction { instruction: String },
 }
 
+fn parse_exit_register(value: &str) -> Result<u8, String> {
+    match value.to_lowercase().as_str() {
+        "r1" => Ok(0),
+        "r2" => Ok(1),
+        "r3" => Ok(2),
+        "r4" => Ok(3),
+        "r5" => Ok(4),
+        "r6" => Ok(5),
+        _ => Err(String::from(
+            "expected a general purpose register (r1 - r6)",
+        )),
+    }
+}
+
+// Reads `PATH@SECTOR` or `PATH` for `mkdisk`.
+fn parse_disk_file(value: &str) -> Result<(PathBuf, Option<u16>), String> {
+    match value.rsplit_once('@') {
+        Some((path, sector)) => match sector.parse() {
+            Ok(sector) => Ok((PathBuf::from(path), Some(sector))),
+            Err(_) => Err(format!("`{sector}` is not a sector number")),
+        },
+        None => Ok((PathBuf::from(value), None)),
+    }
+}
+
+// Reads `NAME=VALUE` or `NAME` for `assemble -D`.
+fn parse_define(value: &str) -> Result<(String, i64), String> {
+    let (name, number) = value.split_once('=').unwrap_or((value, "1"));
+
+    let mut chars = name.chars();
+    let valid_name = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+    if !valid_name {
+        return Err(format!("`{}` is not a valid constant name", name));
+    }
+
+    let (negative, digits) = match number.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, number),
+    };
+
+    let magnitude = match digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => digits.parse::<u32>(),
+    };
+
+    match magnitude {
+        Ok(magnitude) if negative => Ok((name.to_string(), -(magnitude as i64))),
+        Ok(magnitude) => Ok((name.to_string(), magnitude as i64)),
+        Err(_) => Err(format!("`{}` is not a number", number)),
+    }
+}
+
+// Reads and validates an executable, exiting with an error message when it can't be loaded.
+fn read_executable(input: &Path, legacy: bool) -> Executable {
+    let bytes = match fs::read(input) {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            eprintln!(
+                "Unable to read input file \"{}\".\n{error}",
+                input.display()
+            );
+            exit(1);
+        }
+    };
+
+    let executable = if legacy {
+        Executable::parse_legacy(&bytes)
+    } else {
+        Executable::parse(&bytes)
+    };
+
+    match executable {
+        Ok(executable) => executable,
+        Err(error) => {
+            eprintln!(
+                "Input file \"{}\" is not a valid YuCPU binary: {}",
+                input.display(),
+                error
+            );
+            exit(1);
+        }
+    }
+}
+
+fn load_program(input: &Path, executable: &Executable) -> LoadedProgram {
+    match executable.load() {
+        Ok(program) => program,
+        Err(error) => {
+            eprintln!("Unable to load \"{}\": {}", input.display(), error);
+            exit(1);
+        }
+    }
+}
+
+fn load_machine(input: &Path, program: LoadedProgram, debug_mode: bool) -> Machine {
+    match Machine::load(program, debug_mode) {
+        Ok(machine) => machine,
+        Err(error) => {
+            eprintln!("Unable to load \"{}\": {}", input.display(), error);
+            exit(1);
+        }
+    }
+}
+
+// Picks the symbols given on the command line, then the ones embedded in the binary, then a
+// .sym file next to the input. Having none at all is fine.
+fn load_symbols(input: &Path, path: Option<PathBuf>, executable: &Executable) -> SymbolTable {
+    let sidecar = input.with_extension("sym");
+
+    let path = match path {
+        Some(path) => path,
+        None => match executable.section(SectionKind::Symbols) {
+            Some(section) => match SymbolTable::from_bytes(&section.bytes) {
+                Ok(symbols) => return symbols,
+                Err(error) => {
+                    eprintln!(
+                        "Invalid symbols embedded in \"{}\": {:?}",
+                        input.display(),
+                        error
+                    );
+                    exit(1);
+                }
+            },
+            None if sidecar.exists() => sidecar,
+            None => return SymbolTable::new(),
+        },
+    };
+
+    let source = match fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(error) => {
+            eprintln!(
+                "Unable to read symbol file \"{}\".\n{error}",
+                path.display()
+            );
+            exit(1);
+        }
+    };
+
+    match SymbolTable::parse(&source) {
+        Ok(symbols) => symbols,
+        Err(error) => {
+            eprintln!("Invalid symbol file \"{}\": {:?}", path.display(), error);
+            exit(1);
+        }
+    }
+}
+
+// Writes the symbol file when asked to, then serializes the executable.
+fn executable_bytes(mut executable: Executable, symbols: Option<PathBuf>, strip: bool) -> Vec<u8> {
+    if let Some(path) = symbols {
+        if let Some(section) = executable.section(SectionKind::Symbols) {
+            if let Err(error) = fs::write(path, &section.bytes) {
+                eprintln!("Unable to write symbol file.\n{error}");
+                exit(1);
+            }
+        }
+    }
+
+    if strip {
+        executable
+            .sections
+            .retain(|section| section.kind != SectionKind::Symbols);
+    }
+
+    executable.to_bytes()
+}
+
+// Prints every diagnostic, followed by a summary line when any of them are errors.
+fn report_diagnostics(diagnostics: &[Diagnostic], sources: &SourceMap) {
+    for diagnostic in diagnostics {
+        eprintln!("{}", diagnostic.render(sources));
+    }
+
+    let errors = diagnostics.iter().filter(|d| d.is_error()).count();
+
+    if errors > 0 {
+        eprintln!(
+            "{}",
+            Diagnostic::error(
+                format!(
+                    "could not assemble `{}` due to {} previous error{}",
+                    sources.name(0),
+                    errors,
+                    if errors == 1 { "" } else { "s" }
+                ),
+                None,
+            )
+            .render_title()
+        );
+    }
+}
+
 fn main() {
     let args = Args::parse();
 
     match args.command {
-        Commands::Assemble { input, output } => {
+        Commands::Assemble {
+            input,
+            output,
+            symbols,
+            strip,
+            listing,
+            include_dirs,
+            defines,
+            object,
+        } => {
             if !input.as_path().exists() {
                 eprintln!("Input file \"{:?}\" does not exist.", input);
             }
 
-            let mut input_content: String = String::from("");
-
-            let file = match File::open(&input) {
-                Err(why) => panic!("Opening file \"{:?}\" failed!\n\n{}", input, why),
-                Ok(file) => file,
+            let (sources, tokens, diagnostics) = match source::load(&input, &include_dirs, &defines)
+            {
+                Err(why) => {
+                    eprintln!("Opening file \"{:?}\" failed!\n\n{}", input, why);
+                    exit(1);
+                }
+                Ok(res) => res,
             };
 
-            let buffer = std::io::BufReader::new(file);
+            if diagnostics.iter().any(|diagnostic| diagnostic.is_error()) {
+                report_diagnostics(&diagnostics, &sources);
+                exit(1);
+            }
+
+            let mut parser = Parser::new(tokens);
 
-            for line in buffer.lines().flatten() {
-                input_content.push_str(&format!("{}\n", line));
+            for (name, value) in &defines {
+                parser.define(name, *value);
             }
 
-            let mut lex = Token::lexer(&input_content);
-            let mut tokens: Vec<TokenInfoType> = Vec::new();
+            let parser_res = match parser.parse() {
+                Ok(res) => res,
+                Err(diagnostics) => {
+                    report_diagnostics(&diagnostics, &sources);
+                    exit(1);
+                }
+            };
+
+            report_diagnostics(&parser_res.warnings, &sources);
 
-            loop {
-                let tok_option = lex.next();
-                let slice = lex.slice();
+            let assembler = Assembler::new(parser_res, &sources);
 
-                if tok_option.is_none() {
-                    break;
+            let (bytes, assembly_listing) = if object {
+                match assembler.assemble_object() {
+                    Ok((object, listing)) => (object.to_bytes(), listing),
+                    Err(diagnostics) => {
+                        report_diagnostics(&diagnostics, &sources);
+                        exit(1);
+                    }
+                }
+            } else {
+                match assembler.assemble() {
+                    Ok((executable, listing)) => {
+                        (executable_bytes(executable, symbols, strip), listing)
+                    }
+                    Err(diagnostics) => {
+                        report_diagnostics(&diagnostics, &sources);
+                        exit(1);
+                    }
                 }
+            };
 
-                let tok = tok_option.unwrap();
+            if let Some(path) = listing {
+                if let Err(error) = fs::write(path, assembly_listing.render(&sources)) {
+                    eprintln!("Unable to write listing file.\n{error}");
+                    exit(1);
+                }
+            }
 
-                tokens.push((tok, String::from(slice)));
+            if let Err(error) = fs::write(output, bytes) {
+                eprintln!("Unable to write output file.\n{error}");
+                exit(1);
             }
+        }
+        Commands::Link {
+            inputs,
+            output,
+            symbols,
+            strip,
+        } => {
+            let mut objects = Vec::new();
+
+            for input in &inputs {
+                let bytes = match fs::read(input) {
+                    Ok(bytes) => bytes,
+                    Err(error) => {
+                        eprintln!(
+                            "Unable to read object file \"{}\".\n{}",
+                            input.display(),
+                            error
+                        );
+                        exit(1);
+                    }
+                };
 
-            let mut parser = Parser::new(tokens);
-            let parser_res = parser.parse();
+                match ObjectFile::parse(&bytes) {
+                    Ok(object) => objects.push((input.display().to_string(), object)),
+                    Err(error) => {
+                        eprintln!("Invalid object file \"{}\": {}", input.display(), error);
+                        exit(1);
+                    }
+                }
+            }
 
-            let assembler = Assembler::new(parser_res);
-            let bytecode = assembler.assemble();
+            let executable = match Linker::new(objects).link() {
+                Ok(executable) => executable,
+                Err(errors) => {
+                    for error in errors {
+                        eprintln!(
+                            "{}",
+                            Diagnostic::error(error.to_string(), None).render_title()
+                        );
+                    }
+                    exit(1);
+                }
+            };
 
-            match fs::write(output, bytecode) {
-                Ok(file) => file,
+            if let Err(error) = fs::write(output, executable_bytes(executable, symbols, strip)) {
+                eprintln!("Unable to write output file.\n{error}");
+                exit(1);
+            }
+        }
+        Commands::Disassemble {
+            input,
+            output,
+            symbols,
+            legacy,
+            resugar,
+        } => {
+            let executable = read_executable(&input, legacy);
+
+            let mut disassembler = match Disassembler::new(&executable) {
+                Ok(disassembler) => disassembler,
                 Err(error) => {
-                    eprintln!("Unable to write output file.\n{error}");
+                    eprintln!(
+                        "Input file \"{:?}\" is not a valid YuCPU binary.\n{:?}",
+                        input, error
+                    );
                     exit(1);
                 }
             };
+
+            disassembler.symbols = load_symbols(&input, symbols, &executable);
+            disassembler.resugar = resugar;
+
+            let source = disassembler.disassemble();
+
+            match output {
+                Some(output) => {
+                    if let Err(error) = fs::write(output, source) {
+                        eprintln!("Unable to write output file.\n{error}");
+                        exit(1);
+                    }
+                }
+                None => print!("{}", source),
+            }
         }
-        Commands::Run { input, debug_mode } => {
-            // Check if the input file exists
+        Commands::Run {
+            input,
+            debug_mode,
+            headless,
+            max_cycles,
+            exit_register,
+            dump_vga,
+            gdb,
+            trace,
+            clock_hz,
+            serial,
+            disk,
+            symbols,
+            legacy,
+        } => {
+            let executable = read_executable(&input, legacy);
+            let program = load_program(&input, &executable);
+            let symbols = load_symbols(&input, symbols, &executable).with_sections(&executable);
+
+            if debug_mode {
+                dbg!(program.entry);
+                dbg!(program.rom.len());
+                dbg!(&program.rom);
+            }
 
-            if !input.as_path().exists() {
-                eprintln!("Input file \"{:?}\" does not exist.", input);
+            let mut machine = load_machine(&input, program, debug_mode);
+
+            if let Some(hz) = clock_hz {
+                machine.set_clock_hz(hz);
+            }
+
+            if let Some(serial) = serial {
+                match serial.open() {
+                    Ok(backend) => machine.uart.lock().unwrap().backend = backend,
+                    Err(error) => {
+                        eprintln!("Unable to open the serial port.\n{error}");
+                        exit(1);
+                    }
+                }
             }
 
-            let mut file = File::open(&input).unwrap();
+            if let Some(disk) = disk {
+                let attached = fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(&disk)
+                    .and_then(|image| machine.disk.lock().unwrap().attach(image));
+
+                if let Err(error) = attached {
+                    eprintln!(
+                        "Unable to attach disk image \"{}\".\n{error}",
+                        disk.display()
+                    );
+                    exit(1);
+                }
+            }
 
-            let mut buf_start_index = [0_u8; 2];
-            file.read_exact(&mut buf_start_index).unwrap();
-            let start_index: u16 = u16::from_be_bytes(buf_start_index);
+            if let Some(port) = gdb {
+                if let Err(error) = vcpu::run_gdb(machine, port) {
+                    eprintln!("GDB connection failed.\n{error}");
+                    exit(1);
+                }
 
-            let mut buf_text_size = [0_u8; 2];
-            file.read_exact(&mut buf_text_size).unwrap();
-            let text_size = u16::from_be_bytes(buf_text_size);
+                return;
+            }
 
-            let mut buf_data_size = [0_u8; 2];
-            file.read_exact(&mut buf_data_size).unwrap();
-            let data_size = u16::from_be_bytes(buf_data_size);
+            if !headless {
+                vcpu::run(machine, debug_mode, symbols);
+                return;
+            }
 
-            dbg!(start_index);
-            dbg!(text_size);
-            dbg!(data_size);
+            let options = vcpu::HeadlessOptions {
+                max_cycles,
+                exit_register,
+                dump_vga,
+                trace,
+                symbols,
+            };
 
-            let mut program = vec![0; (text_size + data_size) as usize];
-            file.read_exact(program.as_mut_slice()).unwrap();
-            dbg!(&program);
+            let result = vcpu::run_headless(machine, options);
 
-            let mut ivt_buf = [0; 510];
-            file.read_exact(&mut ivt_buf).unwrap();
+            match result.reason {
+                vcpu::StopReason::Halted => {}
+                vcpu::StopReason::CycleLimit => {
+                    eprintln!("Program did not halt within {} cycles.", result.cycles);
+                }
+                vcpu::StopReason::Fault(fault) => {
+                    eprintln!("CPU fault at 0x{:04X}: {}", result.pc, fault);
+                }
+            }
 
-            vcpu::run(program, ivt_buf, start_index, debug_mode);
+            exit(result.exit_status());
         }
-        Commands::OpcodeTable => {
-            let hashmap = common::instruction::opcode::Instruction::hashmap();
+        Commands::Debug {
+            input,
+            symbols,
+            legacy,
+        } => {
+            let executable = read_executable(&input, legacy);
+            let program = load_program(&input, &executable);
+            let symbols = load_symbols(&input, symbols, &executable).with_sections(&executable);
+
+            let machine = load_machine(&input, program, false);
+            Debugger::new(machine, symbols).repl();
+        }
+        Commands::Bench {
+            input,
+            cycles,
+            legacy,
+        } => {
+            let executable = read_executable(&input, legacy);
+            let machine = load_machine(&input, load_program(&input, &executable), false);
+
+            let options = vcpu::HeadlessOptions {
+                max_cycles: Some(cycles),
+                exit_register: 0,
+                dump_vga: false,
+                trace: false,
+                symbols: SymbolTable::new(),
+            };
+
+            let start = Instant::now();
+            let result = vcpu::run_headless(machine, options);
+            let elapsed = start.elapsed().as_secs_f64();
+
+            if result.reason == vcpu::StopReason::Halted {
+                println!("Program halted early.");
+            }
+
+            println!(
+                "Executed {} instructions ({} cycles) in {:.3}s ({:.0} instructions/s, {:.2} MHz)",
+                result.instructions,
+                result.cycles,
+                elapsed,
+                result.instructions as f64 / elapsed,
+                result.cycles as f64 / elapsed / 1_000_000.0
+            );
+        }
+        Commands::Mkdisk {
+            output,
+            sectors,
+            files,
+        } => {
+            let files: Vec<DiskFile> = files
+                .into_iter()
+                .map(|(path, sector)| match fs::read(&path) {
+                    Ok(data) => DiskFile {
+                        name: path.display().to_string(),
+                        sector,
+                        data,
+                    },
+                    Err(error) => {
+                        eprintln!("Unable to read input file \"{}\".\n{error}", path.display());
+                        exit(1);
+                    }
+                })
+                .collect();
+
+            let image = match build_image(&files, sectors) {
+                Ok(image) => image,
+                Err(error) => {
+                    eprintln!("Unable to build disk image: {error}");
+                    exit(1);
+                }
+            };
+
+            if let Err(error) = fs::write(&output, &image.bytes) {
+                eprintln!("Unable to write output file.\n{error}");
+                exit(1);
+            }
+
+            for (file, (first, count)) in files.iter().zip(image.files) {
+                println!("{:>5} {:>5}  {}", first, count, file.name);
+            }
 
+            println!(
+                "Wrote {} sectors to {}",
+                image.bytes.len() / SECTOR_SIZE,
+                output.display()
+            );
+        }
+        Commands::OpcodeTable => {
             let mut table = String::from("|     ");
 
             for i in 0x00..0x10 {
@@ -164,16 +871,7 @@ fn main() {
             for i in 0x00..0x10 {
                 table += &format!("| 0x{:01X} ", i);
                 for j in 0x00..0x10 {
-                    let mut get: Option<common::instruction::instructions::InstructionInfo> = None;
-
-                    for key in hashmap.keys().sorted() {
-                        if key == &(((i << 4) | j) as u8) {
-                            get = Some(hashmap[key]);
-                            break;
-                        }
-                    }
-
-                    match get {
+                    match Instruction::lookup(((i << 4) | j) as u8) {
                         Some(value) => {
                             let value_type = match value.1 {
                                 AddressingMode::Immediate => "V",
@@ -183,10 +881,11 @@ fn main() {
                             };
 
                             table += &format!(
-                                "| 0x{:02X}<br/>{:?} {} ",
+                                "| 0x{:02X}<br/>{:?} {}<br/>{} cycles ",
                                 (i << 4) | j,
                                 value.0,
-                                value_type
+                                value_type,
+                                value.4
                             );
                         }
                         None => {
@@ -226,9 +925,12 @@ fn main() {
 
             for mode in variants {
                 let final_opcode = Instruction::create_opcode(opcode, mode);
+                let cycles = Instruction::lookup(final_opcode).unwrap().4;
+
                 println!("    - {:?} ({:02b})", mode, mode as u8);
                 println!("        - {:08b}", final_opcode);
                 println!("        - 0x{:02x}", final_opcode);
+                println!("        - {} cycles", cycles);
             }
         }
     }
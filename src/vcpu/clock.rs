@@ -0,0 +1,41 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+// The speed devices are tuned for when `--clock-hz` isn't given.
+pub const DEFAULT_CLOCK_HZ: u64 = 1_000_000;
+
+// Sleeping for less than this costs more than it saves.
+const MIN_SLEEP: Duration = Duration::from_millis(2);
+
+/*
+Holds the emulated clock to real time. The CPU runs as fast as the host allows until it gets ahead,
+then sleeps until real time catches up, so the speed evens out to `hz` without a sleep between
+every instruction.
+ */
+pub struct Clock {
+    pub hz: u64,
+    start: Instant,
+    start_cycles: u64,
+}
+
+impl Clock {
+    pub fn new(hz: u64, cycles: u64) -> Self {
+        Self {
+            hz,
+            start: Instant::now(),
+            start_cycles: cycles,
+        }
+    }
+
+    // Waits until the CPU is due to have run `cycles` cycles.
+    pub fn wait(&self, cycles: u64) {
+        let due = Duration::from_secs_f64((cycles - self.start_cycles) as f64 / self.hz as f64);
+        let elapsed = self.start.elapsed();
+
+        if due > elapsed + MIN_SLEEP {
+            thread::sleep(due - elapsed);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::{fs, sync::mpsc::Sender};
+use std::{fmt, fs, sync::mpsc::Sender};
 
 use bitflags::bitflags;
 
@@ -10,6 +10,12 @@ use crate::{
 use super::device::map::{DeviceMap, DeviceMapResult};
 use crate::common::instruction::opcode::Instruction;
 
+// Addresses are 20 bits wide.
+pub const ADDRESS_SPACE: u32 = 0x100000;
+
+// What it costs to take an IRQ or vector a fault, on top of the instruction that was running.
+pub const INTERRUPT_CYCLES: u64 = 8;
+
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct Flags: u32 {
@@ -18,7 +24,9 @@ bitflags! {
         const L = 0b01000000;
         const G = 0b00100000;
         const D = 0b10000000;
-        const FLAGS = Self::Z.bits() | Self::O.bits() | Self::L.bits() | Self::G.bits() | Self::D.bits();
+        // Interrupts are enabled. Cleared when one is taken, `rei` puts it back.
+        const I = 0b00000100;
+        const FLAGS = Self::Z.bits() | Self::O.bits() | Self::L.bits() | Self::G.bits() | Self::D.bits() | Self::I.bits();
     }
 }
 
@@ -66,6 +74,54 @@ pub enum Dump {
     Stats,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuFault {
+    InvalidOpcode(u8),
+    BusError(u32),
+    ProtectionFault(u32),
+    InvalidRegister(u8),
+    StackUnderflow,
+    DivideByZero,
+}
+
+impl CpuFault {
+    // The IVT entry a guest program can install to handle this fault itself.
+    pub fn vector(&self) -> u8 {
+        match self {
+            CpuFault::DivideByZero => 0xF9,
+            CpuFault::InvalidOpcode(_) => 0xFA,
+            CpuFault::BusError(_) => 0xFB,
+            CpuFault::ProtectionFault(_) => 0xFC,
+            CpuFault::InvalidRegister(_) => 0xFD,
+            CpuFault::StackUnderflow => 0xFE,
+        }
+    }
+}
+
+impl fmt::Display for CpuFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuFault::InvalidOpcode(opcode) => write!(f, "invalid opcode 0x{:02X}", opcode),
+            CpuFault::BusError(addr) => write!(f, "no device mapped at 0x{:04X}", addr),
+            CpuFault::ProtectionFault(addr) => {
+                write!(f, "access not permitted at 0x{:04X}", addr)
+            }
+            CpuFault::InvalidRegister(reg) => write!(f, "invalid register {}", reg),
+            CpuFault::StackUnderflow => write!(f, "stack underflow"),
+            CpuFault::DivideByZero => write!(f, "divide by zero"),
+        }
+    }
+}
+
+fn map_fault<T>(result: DeviceMapResult<T>, addr: u32) -> Result<T, CpuFault> {
+    match result {
+        DeviceMapResult::Ok(value) => Ok(value),
+        DeviceMapResult::NoDevices => Err(CpuFault::BusError(addr)),
+        DeviceMapResult::Error(DeviceResponse::InvalidAddress) => Err(CpuFault::BusError(addr)),
+        DeviceMapResult::Error(_) => Err(CpuFault::ProtectionFault(addr)),
+    }
+}
+
 pub struct DebugInfo {
     pub r1: u16,
     pub r2: u16,
@@ -73,7 +129,7 @@ pub struct DebugInfo {
     pub r4: u16,
     pub r5: u16,
     pub r6: u16,
-    pub pc: u16,
+    pub pc: u32,
     pub sp: u16,
     pub bp: u16,
     pub flags: Flags,
@@ -90,6 +146,9 @@ pub struct CPU {
     pub r6: u16,
     pub sp: u16,
     pub pc: u16,
+    // The top 4 bits of the 20-bit program counter. `pc` is the register programs see, and writing
+    // to it stays inside the current 64K, while jumps and calls set both.
+    pub cs: u8,
     pub bp: u16,
     pub flags: Flags,
     pub ir: u16,
@@ -98,6 +157,9 @@ pub struct CPU {
     pub is: u8,
     pub pins: Pins,
     pub map: DeviceMap,
+    pub stack_base: u16,
+    // Clock cycles since the CPU was created.
+    pub cycles: u64,
     pub running: bool,
     pub debug_mode: bool,
     pub debug_tx: Option<Sender<DebugInfo>>,
@@ -116,14 +178,18 @@ impl CPU {
             r6: 0,
             sp,
             pc,
+            cs: 0,
             bp: 0,
-            flags: Flags::empty(),
+            // Programs written before `cli` and `sti` expect their IRQs without asking for them.
+            flags: Flags::I,
             ir: 0,
             dr: 0,
             ad: 0,
             is: 0,
             pins: Pins::new(),
             map: DeviceMap::new(),
+            stack_base: 0,
+            cycles: 0,
             running: true,
             debug_mode,
             debug_tx: None,
@@ -132,111 +198,77 @@ impl CPU {
 }
 
 impl CPU {
-    pub fn tick(&mut self, mut pins: Pins) -> Pins {
-        if let IrqPin::On(irq) = pins.irq {
-            let jump_addr = match self.map.read((irq * 2) as u32) {
-                DeviceMapResult::Ok(value) => value,
-                DeviceMapResult::NoDevices => {
-                    panic!("No devices attached. Could not read any values.")
-                }
-                DeviceMapResult::Error(err) => {
-                    if err == DeviceResponse::ReadOnly {
-                        panic!("Device read only. Could not read value.");
-                    } else {
-                        panic!("Unknown error. Could not read value.");
-                    }
-                }
-            };
+    pub fn tick(&mut self, mut pins: Pins) -> Result<Pins, CpuFault> {
+        // While interrupts are disabled, the IRQ stays on the pins until `sti`.
+        match pins.irq {
+            IrqPin::On(irq) if self.flags.contains(Flags::I) => {
+                pins.irq = IrqPin::Off;
 
-            if jump_addr != 0 {
-                self.push_registers();
-                self.pc = jump_addr;
+                let jump_addr = self.read(irq as u32 * 2)?;
 
-                pins.irq = IrqPin::Off;
-                return pins;
-            } else {
-                pins.irq = IrqPin::Off;
+                if jump_addr != 0 {
+                    self.interrupt(jump_addr as u32)?;
+                    self.cycles += INTERRUPT_CYCLES;
+
+                    return Ok(pins);
+                }
             }
+            _ => (),
         }
 
-        self.flags.set(Flags::D, false);
         pins.rw = ReadWrite::Read;
 
-        self.ir = match self.map.read(self.pc as u32) {
-            DeviceMapResult::Ok(data) => data,
-            DeviceMapResult::NoDevices => {
-                panic!("No devices attached. Could not get instruction info.")
-            }
-            DeviceMapResult::Error(err) => {
-                if err == DeviceResponse::WriteOnly {
-                    panic!("Device write only. Could not read value.");
-                } else {
-                    panic!("Unknown error. Could not read value.");
-                }
-            }
-        };
+        let pc = self.program_counter();
+
+        if let Err(fault) = self.execute() {
+            self.jump(pc);
+            self.is = 0;
+            self.vector_fault(fault)?;
+        }
+
+        // Only the window listens for these, headless and gdb runs have no receiver.
+        if let (true, Some(tx)) = (self.debug_mode, &self.debug_tx) {
+            tx.send(DebugInfo {
+                r1: self.r1,
+                r2: self.r2,
+                r3: self.r3,
+                r4: self.r4,
+                r5: self.r5,
+                r6: self.r6,
+                pc: self.program_counter(),
+                sp: self.sp,
+                bp: self.bp,
+                flags: self.flags,
+                pins,
+            })
+            .unwrap();
+        }
+
+        self.is = 0;
+        self.ir = 0;
+        self.dr = 0;
+
+        Ok(pins)
+    }
+
+    fn execute(&mut self) -> Result<(), CpuFault> {
+        self.flags.set(Flags::D, false);
+
+        let pc = self.program_counter();
+        self.ir = self.read(pc)?;
 
         match (0xC & self.ir) >> 2 {
             0b00 => {
-                self.dr = match self.map.read_byte((self.pc + 2) as u32) {
-                    DeviceMapResult::Ok(data) => data as u16,
-                    DeviceMapResult::NoDevices => {
-                        panic!("No devices attached. Could not get instruction data.")
-                    }
-                    DeviceMapResult::Error(err) => {
-                        if err == DeviceResponse::WriteOnly {
-                            panic!("Device write only. Could not read value.");
-                        } else {
-                            panic!("Unknown error. Could not read value.");
-                        }
-                    }
-                };
+                self.dr = self.read_byte(pc + 2)? as u16;
                 self.is = 3;
             }
             0b01 => {
-                self.dr = match self.map.read((self.pc + 2) as u32) {
-                    DeviceMapResult::Ok(data) => data,
-                    DeviceMapResult::NoDevices => {
-                        panic!("No devices attached. Could not get instruction data.")
-                    }
-                    DeviceMapResult::Error(err) => {
-                        if err == DeviceResponse::WriteOnly {
-                            panic!("Device write only. Could not read value.");
-                        } else {
-                            panic!("Unknown error. Could not read value.");
-                        }
-                    }
-                };
+                self.dr = self.read(pc + 2)?;
                 self.is = 4;
             }
             0b10 => {
-                self.ad = match self.map.read_byte((self.pc + 2) as u32) {
-                    DeviceMapResult::Ok(data) => data & 0xF,
-                    DeviceMapResult::NoDevices => {
-                        panic!("No devices attached. Could not get instruction data.")
-                    }
-                    DeviceMapResult::Error(err) => {
-                        if err == DeviceResponse::WriteOnly {
-                            panic!("Device write only. Could not read value.");
-                        } else {
-                            panic!("Unknown error. Could not read value.");
-                        }
-                    }
-                };
-
-                self.dr = match self.map.read((self.pc + 3) as u32) {
-                    DeviceMapResult::Ok(data) => data,
-                    DeviceMapResult::NoDevices => {
-                        panic!("No devices attached. Could not get instruction data.")
-                    }
-                    DeviceMapResult::Error(err) => {
-                        if err == DeviceResponse::WriteOnly {
-                            panic!("Device write only. Could not read value.");
-                        } else {
-                            panic!("Unknown error. Could not read value.");
-                        }
-                    }
-                };
+                self.ad = self.read_byte(pc + 2)? & 0xF;
+                self.dr = self.read(pc + 3)?;
 
                 self.flags.set(Flags::D, true);
 
@@ -245,25 +277,23 @@ impl CPU {
             0b11 => {
                 self.is = 2;
             }
-            _ => panic!("Data match was over 2 bytes..."),
+            _ => unreachable!(),
         };
 
-        let mask = 0xFF00;
+        let opcode = (self.ir >> 8) as u8;
 
-        let res = match Instruction::from_opcode(&(((mask & self.ir) >> 8) as u8)) {
+        let res = match Instruction::from_opcode(&opcode) {
             Ok(data) => data,
-            Err(error) => match error {
-                InstructionError::InvalidOpcode => {
-                    panic!("Invalid opcode: 0x{:x}", &(((mask & self.ir) >> 8) as u8))
-                }
-            },
+            Err(InstructionError::InvalidOpcode) => return Err(CpuFault::InvalidOpcode(opcode)),
         };
 
-        if res.opcode != Opcode::HLT {
+        self.cycles += res.cycles as u64;
+
+        if self.debug_mode && res.opcode != Opcode::HLT {
             println!("Running {:?} with addr mode {:?}.", res.opcode, res.mode);
             println!(
                 "Opcode {:08b} ir {} dr {} ad {}",
-                (mask & self.ir),
+                (0xFF00 & self.ir),
                 self.ir,
                 self.dr,
                 self.ad,
@@ -272,149 +302,170 @@ impl CPU {
             println!("Executing instruction...");
         }
 
-        (res.exec)(self);
+        (res.exec)(self)
+    }
 
-        if self.debug_mode {
-            let tx = self.debug_tx.as_ref().unwrap();
+    /*
+    Faults are handled by the guest when it installed a handler in the fault's IVT entry. The
+    registers are pushed like any other interrupt, with the PC still pointing at the faulting
+    instruction. Without a handler, or when the fault happens while vectoring, it goes back to
+    the host.
+     */
+    fn vector_fault(&mut self, fault: CpuFault) -> Result<(), CpuFault> {
+        let handler = match self.read(fault.vector() as u32 * 2) {
+            Ok(handler) if handler != 0 => handler,
+            _ => return Err(fault),
+        };
 
-            tx.send(DebugInfo {
-                r1: self.r1,
-                r2: self.r2,
-                r3: self.r3,
-                r4: self.r4,
-                r5: self.r5,
-                r6: self.r6,
-                pc: self.pc,
-                sp: self.sp,
-                bp: self.bp,
-                flags: self.flags,
-                pins,
-            })
-            .unwrap();
+        if self.interrupt(handler as u32).is_err() {
+            return Err(fault);
         }
 
-        self.is = 0;
-        self.ir = 0;
-        self.dr = 0;
+        self.cycles += INTERRUPT_CYCLES;
 
-        pins
+        Ok(())
     }
 
-    pub fn advance(&mut self) {
-        self.pc += self.is as u16;
+    // Saves the registers and flags for `rei`, then runs the handler with interrupts disabled.
+    pub fn interrupt(&mut self, handler: u32) -> Result<(), CpuFault> {
+        self.push_registers()?;
+        self.flags.remove(Flags::I);
+        self.jump(handler);
+
+        Ok(())
     }
 
-    #[allow(unused_variables, clippy::needless_return)]
-    pub fn decode_register(&mut self, reg: u8) -> &mut u16 {
-        return match reg {
-            0 => &mut self.r1,
-            1 => &mut self.r2,
-            2 => &mut self.r3,
-            3 => &mut self.r4,
-            4 => &mut self.r5,
-            5 => &mut self.r6,
-            6 => &mut self.pc,
-            7 => &mut self.sp,
-            8 => &mut self.bp,
-            _ => panic!("Invalid register {}", reg),
-        };
+    pub fn read(&mut self, addr: u32) -> Result<u16, CpuFault> {
+        map_fault(self.map.read(addr), addr)
     }
 
-    pub fn push_registers(&mut self) {
-        for reg in 0..6 {
-            let decoded_reg = *self.decode_register(reg);
+    pub fn read_byte(&mut self, addr: u32) -> Result<u8, CpuFault> {
+        map_fault(self.map.read_byte(addr), addr)
+    }
 
-            match self.map.write(self.sp as u32, decoded_reg) {
-                DeviceMapResult::Ok(_) => (),
-                DeviceMapResult::NoDevices => {
-                    panic!("No devices attached. Could not read any values.")
-                }
-                DeviceMapResult::Error(err) => {
-                    if err == DeviceResponse::ReadOnly {
-                        panic!("Device read only. Could not read value.");
-                    } else {
-                        panic!("Unknown error. Could not read value.");
-                    }
-                }
-            };
+    pub fn write(&mut self, addr: u32, value: u16) -> Result<(), CpuFault> {
+        map_fault(self.map.write(addr, value), addr)
+    }
+
+    pub fn write_byte(&mut self, addr: u32, value: u8) -> Result<(), CpuFault> {
+        map_fault(self.map.write_byte(addr, value), addr)
+    }
 
-            self.sp += 2;
+    pub fn push(&mut self, value: u16) -> Result<(), CpuFault> {
+        self.write(self.sp as u32, value)?;
+        self.sp += 2;
+
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Result<u16, CpuFault> {
+        if self.sp < self.stack_base.saturating_add(2) {
+            return Err(CpuFault::StackUnderflow);
         }
 
-        match self.map.write(self.sp as u32, self.flags.bits() as u16) {
-            DeviceMapResult::Ok(_) => (),
-            DeviceMapResult::NoDevices => panic!("No devices attached. Could not read any values."),
-            DeviceMapResult::Error(err) => {
-                if err == DeviceResponse::ReadOnly {
-                    panic!("Device read only. Could not read value.");
-                } else {
-                    panic!("Unknown error. Could not read value.");
-                }
-            }
-        };
+        self.sp -= 2;
+        self.read(self.sp as u32)
+    }
 
-        self.sp += 2;
+    pub fn program_counter(&self) -> u32 {
+        ((self.cs as u32) << 16) | self.pc as u32
+    }
 
-        match self.map.write(self.sp as u32, self.pc + self.is as u16) {
-            DeviceMapResult::Ok(_) => (),
-            DeviceMapResult::NoDevices => panic!("No devices attached. Could not read any values."),
-            DeviceMapResult::Error(err) => {
-                if err == DeviceResponse::ReadOnly {
-                    panic!("Device read only. Could not read value.");
-                } else {
-                    panic!("Unknown error. Could not read value.");
-                }
-            }
-        };
+    pub fn jump(&mut self, addr: u32) {
+        self.cs = ((addr >> 16) & 0xF) as u8;
+        self.pc = addr as u16;
+    }
 
-        self.sp += 2;
+    // Where a jump or call goes: the 20-bit address when the operand had one, otherwise a 16-bit
+    // address, which is always in the first 64K.
+    pub fn target(&self) -> u32 {
+        if self.flags.contains(Flags::D) {
+            ((self.ad as u32 & 0xF) << 16) | self.dr as u32
+        } else {
+            self.dr as u32
+        }
     }
 
-    pub fn pop_registers(&mut self) {
-        // self.dump(Dump::All);
-        self.sp -= 2;
-        self.pc = match self.map.read(self.sp as u32) {
-            DeviceMapResult::Ok(val) => val,
-            DeviceMapResult::NoDevices => panic!("No devices attached. Could not read any values."),
-            DeviceMapResult::Error(err) => {
-                if err == DeviceResponse::WriteOnly {
-                    panic!("Device write only. Could not read value.");
-                } else {
-                    panic!("Unknown error. Could not read value.");
-                }
+    pub fn advance(&mut self) {
+        self.jump(self.program_counter() + self.is as u32);
+    }
+
+    // Where execution carries on after the current instruction.
+    pub fn return_address(&self) -> u32 {
+        self.program_counter() + self.is as u32
+    }
+
+    // Return addresses take two words: the top 4 bits, then the low 16 bits.
+    pub fn push_address(&mut self, addr: u32) -> Result<(), CpuFault> {
+        self.push((addr >> 16) as u16 & 0xF)?;
+        self.push(addr as u16)
+    }
+
+    // Undoes `push_address`, leaving the stack alone when only one word is left.
+    pub fn pop_address(&mut self) -> Result<u32, CpuFault> {
+        let sp = self.sp;
+        let low = self.pop()?;
+
+        match self.pop() {
+            Ok(high) => Ok(((high as u32 & 0xF) << 16) | low as u32),
+            Err(fault) => {
+                self.sp = sp;
+                Err(fault)
             }
-        };
+        }
+    }
 
-        self.sp -= 2;
-        self.flags = Flags::from_bits(match self.map.read(self.sp as u32) {
-            DeviceMapResult::Ok(val) => val as u32,
-            DeviceMapResult::NoDevices => panic!("No devices attached. Could not read any values."),
-            DeviceMapResult::Error(err) => {
-                if err == DeviceResponse::ReadOnly {
-                    panic!("Device read only. Could not read value.");
-                } else {
-                    panic!("Unknown error. Could not read value.");
+    pub fn decode_register(&mut self, reg: u8) -> Result<&mut u16, CpuFault> {
+        match reg {
+            0 => Ok(&mut self.r1),
+            1 => Ok(&mut self.r2),
+            2 => Ok(&mut self.r3),
+            3 => Ok(&mut self.r4),
+            4 => Ok(&mut self.r5),
+            5 => Ok(&mut self.r6),
+            6 => Ok(&mut self.pc),
+            7 => Ok(&mut self.sp),
+            8 => Ok(&mut self.bp),
+            _ => Err(CpuFault::InvalidRegister(reg)),
+        }
+    }
+
+    pub fn push_registers(&mut self) -> Result<(), CpuFault> {
+        for reg in 0..6 {
+            let value = *self.decode_register(reg)?;
+            self.push(value)?;
+        }
+
+        // The flags only use the low byte, so the top of the return address goes above them.
+        let ret = self.return_address();
+
+        self.push(self.flags.bits() as u16 | ((ret >> 8) as u16 & 0x0F00))?;
+        self.push(ret as u16)
+    }
+
+    // Undoes `push_registers`. Nothing is changed unless the whole frame could be read back.
+    pub fn pop_registers(&mut self) -> Result<(), CpuFault> {
+        let sp = self.sp;
+        let mut frame = [0_u16; 8];
+
+        for value in frame.iter_mut().rev() {
+            match self.pop() {
+                Ok(popped) => *value = popped,
+                Err(fault) => {
+                    self.sp = sp;
+                    return Err(fault);
                 }
             }
-        })
-        .unwrap();
+        }
 
-        for reg in (0..6).rev() {
-            self.sp -= 2;
-            *self.decode_register(reg) = match self.map.read(self.sp as u32) {
-                DeviceMapResult::Ok(val) => val,
-                DeviceMapResult::NoDevices => {
-                    panic!("No devices attached. Could not read any values.")
-                }
-                DeviceMapResult::Error(err) => {
-                    if err == DeviceResponse::WriteOnly {
-                        panic!("Device write only. Could not read value.");
-                    } else {
-                        panic!("Unknown error. Could not read value.");
-                    }
-                }
-            };
+        for reg in 0..6 {
+            *self.decode_register(reg)? = frame[reg as usize];
         }
+
+        self.flags = Flags::from_bits_truncate(frame[6] as u32 & 0xFF);
+        self.jump((((frame[6] as u32) & 0x0F00) << 8) | frame[7] as u32);
+
+        Ok(())
     }
 
     pub fn dump(&self, dump_type: Dump) {
@@ -459,7 +510,7 @@ Flags:
     LT  : {}
     OvrF: {}
     DWord: {}",
-                self.pc,
+                self.program_counter(),
                 self.sp,
                 self.r1,
                 self.r2,
@@ -1,7 +1,11 @@
 pub mod bios;
+pub mod disk;
 pub mod map;
+pub mod pic;
 pub mod ram;
 pub mod rom;
+pub mod timer;
+pub mod uart;
 pub mod vga;
 
 #[derive(PartialEq, Eq, Debug)]
@@ -14,11 +18,106 @@ pub enum DeviceResponse<T> {
 }
 
 pub trait Device {
-    fn read(&self, addr: u32) -> DeviceResponse<u16>;
-    fn read_byte(&self, addr: u32) -> DeviceResponse<u8>;
+    // Reads can change the device, like taking a byte out of a FIFO.
+    fn read(&mut self, addr: u32) -> DeviceResponse<u16>;
+    fn read_byte(&mut self, addr: u32) -> DeviceResponse<u8>;
     fn write(&mut self, addr: u32, value: u16) -> DeviceResponse<()>;
     fn write_byte(&mut self, addr: u32, value: u8) -> DeviceResponse<()>;
     fn get_name(&self) -> String;
     fn set_name(&mut self, name: String);
     fn get_memory(&self) -> Vec<u8>;
+
+    // Like `read_byte`, but never changes the device, for the debugger, GDB and DMA.
+    fn peek(&self, addr: u32) -> DeviceResponse<u8>;
+
+    // Called after every instruction with the cycles it took. Returns the IRQ line to raise, if
+    // the device wants to interrupt the CPU.
+    fn tick(&mut self, _cycles: u64) -> Option<u8> {
+        None
+    }
+}
+
+/*
+For devices made of word registers, looked up by their offset from the first address. A byte is
+the high or low half of a register, in the same order as memory, and writing one keeps the other
+half. Registers that can't be read are written a byte at a time as if they held 0.
+ */
+pub trait WordRegisters {
+    // The first and last address of the registers.
+    fn bounds(&self) -> (u32, u32);
+    fn peek_register(&self, offset: u32) -> DeviceResponse<u16>;
+    fn set_register(&mut self, offset: u32, value: u16) -> DeviceResponse<()>;
+
+    // Only differs from `peek_register` for registers that change when they're read.
+    fn register(&mut self, offset: u32) -> DeviceResponse<u16> {
+        self.peek_register(offset)
+    }
+
+    fn offset(&self, addr: u32) -> Option<u32> {
+        let (start, end) = self.bounds();
+        (start..=end).contains(&addr).then(|| addr - start)
+    }
+
+    fn read_register(&mut self, addr: u32) -> DeviceResponse<u16> {
+        match self.offset(addr) {
+            None => DeviceResponse::NotMyAddress,
+            Some(offset) if !offset.is_multiple_of(2) => DeviceResponse::InvalidAddress,
+            Some(offset) => self.register(offset),
+        }
+    }
+
+    fn read_register_byte(&mut self, addr: u32) -> DeviceResponse<u8> {
+        let Some(offset) = self.offset(addr) else {
+            return DeviceResponse::NotMyAddress;
+        };
+
+        half(self.register(offset & !1), offset)
+    }
+
+    fn peek_register_byte(&self, addr: u32) -> DeviceResponse<u8> {
+        let Some(offset) = self.offset(addr) else {
+            return DeviceResponse::NotMyAddress;
+        };
+
+        half(self.peek_register(offset & !1), offset)
+    }
+
+    fn write_register(&mut self, addr: u32, value: u16) -> DeviceResponse<()> {
+        match self.offset(addr) {
+            None => DeviceResponse::NotMyAddress,
+            Some(offset) if !offset.is_multiple_of(2) => DeviceResponse::InvalidAddress,
+            Some(offset) => self.set_register(offset, value),
+        }
+    }
+
+    fn write_register_byte(&mut self, addr: u32, value: u8) -> DeviceResponse<()> {
+        let Some(offset) = self.offset(addr) else {
+            return DeviceResponse::NotMyAddress;
+        };
+
+        let word = match self.peek_register(offset & !1) {
+            DeviceResponse::Ok(word) => word,
+            _ => 0,
+        };
+
+        let word = if offset.is_multiple_of(2) {
+            (word & 0x00FF) | ((value as u16) << 8)
+        } else {
+            (word & 0xFF00) | value as u16
+        };
+
+        self.set_register(offset & !1, word)
+    }
+}
+
+fn half(word: DeviceResponse<u16>, offset: u32) -> DeviceResponse<u8> {
+    let DeviceResponse::Ok(word) = word else {
+        return DeviceResponse::WriteOnly;
+    };
+
+    if offset.is_multiple_of(2) {
+        DeviceResponse::Ok((word >> 8) as u8)
+    } else {
+        DeviceResponse::Ok(word as u8)
+    }
 }
@@ -149,7 +149,7 @@ impl BIOS {
 }
 
 impl Device for BIOS {
-    fn read(&self, addr: u32) -> DeviceResponse<u16> {
+    fn read(&mut self, addr: u32) -> DeviceResponse<u16> {
         if addr >= self.start && addr <= self.end {
             for data in &self.bda.memory {
                 let (read_data, read_range, _) = BIOS::read_bda_data(data);
@@ -163,26 +163,8 @@ impl Device for BIOS {
         DeviceResponse::NotMyAddress
     }
 
-    fn read_byte(&self, addr: u32) -> DeviceResponse<u8> {
-        if addr >= self.start && addr <= self.end {
-            let addr = &self.relative(addr);
-
-            for data in &self.bda.memory {
-                let (read_data, read_range, _) = BIOS::read_bda_data(data);
-
-                if read_range.contains(addr) {
-                    if addr % 2 == 0 {
-                        // Upper bytes
-                        return DeviceResponse::Ok((read_data >> 4) as u8);
-                    } else {
-                        // Lower bytes
-                        return DeviceResponse::Ok(read_data as u8);
-                    }
-                }
-            }
-        }
-
-        DeviceResponse::NotMyAddress
+    fn read_byte(&mut self, addr: u32) -> DeviceResponse<u8> {
+        self.peek(addr)
     }
 
     fn write(&mut self, addr: u32, _value: u16) -> DeviceResponse<()> {
@@ -212,4 +194,26 @@ impl Device for BIOS {
     fn get_memory(&self) -> Vec<u8> {
         Vec::new()
     }
+
+    fn peek(&self, addr: u32) -> DeviceResponse<u8> {
+        if addr >= self.start && addr <= self.end {
+            let addr = &self.relative(addr);
+
+            for data in &self.bda.memory {
+                let (read_data, read_range, _) = BIOS::read_bda_data(data);
+
+                if read_range.contains(addr) {
+                    if addr % 2 == 0 {
+                        // Upper bytes
+                        return DeviceResponse::Ok((read_data >> 4) as u8);
+                    } else {
+                        // Lower bytes
+                        return DeviceResponse::Ok(read_data as u8);
+                    }
+                }
+            }
+        }
+
+        DeviceResponse::NotMyAddress
+    }
 }
@@ -0,0 +1,279 @@
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+};
+
+use bitflags::bitflags;
+
+use super::{Device, DeviceResponse, WordRegisters};
+
+pub const SECTOR_SIZE: usize = 512;
+
+// How long a transfer takes, about 2ms at the default clock.
+pub const SECTOR_CYCLES: u64 = 2048;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct DiskStatus: u16 {
+        const BUSY  = 0b01;
+        const ERROR = 0b10;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiskCommand {
+    Read = 1,
+    Write = 2,
+}
+
+/*
+A controller for a disk image on the host, which moves whole sectors to and from guest memory by
+itself. Every register is a word:
+
++0  SECTOR   the sector to transfer
++2  BUFFER   where the sector is read into or written from, 512 bytes long
++4  COMMAND  1 reads SECTOR into BUFFER, 2 writes BUFFER to SECTOR (write only)
++6  STATUS   bit 0 while a transfer is running, bit 1 if the last one failed (read only)
++8  SECTORS  how many sectors the image holds, 0 without one (read only)
+
+A transfer takes SECTOR_CYCLES, and memory is only touched once it's done, when the IRQ is raised
+on the controller's line. Commands given while BUSY is set are ignored. A sector past the end of
+the image, or a buffer that runs into memory that can't be read or written, sets ERROR.
+ */
+pub struct Disk {
+    pub sector: u16,
+    pub buffer: u16,
+    pub status: DiskStatus,
+    pub sectors: u16,
+    pub line: u8,
+    pub image: Option<File>,
+    // The running transfer, and the cycles left until it's done.
+    command: Option<(DiskCommand, u64)>,
+    // A transfer that's done, waiting for the machine to carry it out.
+    finished: Option<DiskCommand>,
+    start: u32,
+    end: u32,
+}
+
+impl Disk {
+    pub fn new(start: u32, line: u8) -> Self {
+        Self {
+            sector: 0,
+            buffer: 0,
+            status: DiskStatus::empty(),
+            sectors: 0,
+            line,
+            image: None,
+            command: None,
+            finished: None,
+            start,
+            end: start + 9,
+        }
+    }
+
+    // Only whole sectors count, anything after the last one is left alone.
+    pub fn attach(&mut self, image: File) -> io::Result<()> {
+        let sectors = image.metadata()?.len() / SECTOR_SIZE as u64;
+
+        self.sectors = u16::try_from(sectors).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("disk images can't be larger than {} sectors", u16::MAX),
+            )
+        })?;
+        self.image = Some(image);
+
+        Ok(())
+    }
+
+    fn seek(&mut self, sector: u16) -> io::Result<&mut File> {
+        if sector >= self.sectors {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "sector is past the end of the disk",
+            ));
+        }
+
+        let image = self
+            .image
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no disk image attached"))?;
+
+        image.seek(SeekFrom::Start(sector as u64 * SECTOR_SIZE as u64))?;
+        Ok(image)
+    }
+
+    pub fn read_sector(&mut self, sector: u16) -> io::Result<[u8; SECTOR_SIZE]> {
+        let mut data = [0_u8; SECTOR_SIZE];
+        self.seek(sector)?.read_exact(&mut data)?;
+
+        Ok(data)
+    }
+
+    pub fn write_sector(&mut self, sector: u16, data: &[u8; SECTOR_SIZE]) -> io::Result<()> {
+        self.seek(sector)?.write_all(data)
+    }
+
+    // The transfer whose time is up, which the machine has to carry out and then `finish`.
+    pub fn take_finished(&mut self) -> Option<DiskCommand> {
+        self.finished.take()
+    }
+
+    pub fn finish(&mut self, ok: bool) {
+        self.status.remove(DiskStatus::BUSY);
+        self.status.set(DiskStatus::ERROR, !ok);
+    }
+
+    fn run(&mut self, command: u16) {
+        if self.status.contains(DiskStatus::BUSY) {
+            return;
+        }
+
+        let command = match command {
+            1 => DiskCommand::Read,
+            2 => DiskCommand::Write,
+            _ => {
+                self.status.insert(DiskStatus::ERROR);
+                return;
+            }
+        };
+
+        self.status = DiskStatus::BUSY;
+        self.command = Some((command, SECTOR_CYCLES));
+    }
+}
+
+impl WordRegisters for Disk {
+    fn bounds(&self) -> (u32, u32) {
+        (self.start, self.end)
+    }
+
+    fn peek_register(&self, offset: u32) -> DeviceResponse<u16> {
+        match offset {
+            0 => DeviceResponse::Ok(self.sector),
+            2 => DeviceResponse::Ok(self.buffer),
+            4 => DeviceResponse::WriteOnly,
+            6 => DeviceResponse::Ok(self.status.bits()),
+            _ => DeviceResponse::Ok(self.sectors),
+        }
+    }
+
+    fn set_register(&mut self, offset: u32, value: u16) -> DeviceResponse<()> {
+        match offset {
+            0 => self.sector = value,
+            2 => self.buffer = value,
+            4 => self.run(value),
+            _ => return DeviceResponse::ReadOnly,
+        }
+
+        DeviceResponse::Ok(())
+    }
+}
+
+impl Device for Disk {
+    fn read(&mut self, addr: u32) -> DeviceResponse<u16> {
+        self.read_register(addr)
+    }
+
+    fn read_byte(&mut self, addr: u32) -> DeviceResponse<u8> {
+        self.read_register_byte(addr)
+    }
+
+    fn write(&mut self, addr: u32, value: u16) -> DeviceResponse<()> {
+        self.write_register(addr, value)
+    }
+
+    fn write_byte(&mut self, addr: u32, value: u8) -> DeviceResponse<()> {
+        self.write_register_byte(addr, value)
+    }
+
+    fn get_name(&self) -> String {
+        String::from("Disk")
+    }
+
+    fn set_name(&mut self, _name: String) {}
+
+    fn get_memory(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn peek(&self, addr: u32) -> DeviceResponse<u8> {
+        self.peek_register_byte(addr)
+    }
+
+    fn tick(&mut self, cycles: u64) -> Option<u8> {
+        if let Some((command, remaining)) = self.command {
+            if cycles >= remaining {
+                self.command = None;
+                self.finished = Some(command);
+            } else {
+                self.command = Some((command, remaining - cycles));
+            }
+        }
+
+        None
+    }
+}
+
+pub struct DiskFile {
+    pub name: String,
+    pub sector: Option<u16>,
+    pub data: Vec<u8>,
+}
+
+pub struct DiskImage {
+    pub bytes: Vec<u8>,
+    // The first sector and the length in sectors of every file, in order.
+    pub files: Vec<(u16, u16)>,
+}
+
+/*
+Lays files out for `mkdisk`. Each one starts on a sector boundary, at the sector it asks for or
+else right after the file before it, and the image is padded to at least `sectors` sectors.
+ */
+pub fn build_image(files: &[DiskFile], sectors: u16) -> Result<DiskImage, String> {
+    let mut image = vec![0_u8; sectors as usize * SECTOR_SIZE];
+    let mut placed: Vec<(u16, u16)> = Vec::new();
+    let mut next = 0_u32;
+
+    for file in files {
+        let first = file.sector.map_or(next, u32::from);
+        let end = first + file.data.len().div_ceil(SECTOR_SIZE) as u32;
+
+        if end > u16::MAX as u32 {
+            return Err(format!(
+                "`{}` doesn't fit, disk images can't be larger than {} sectors",
+                file.name,
+                u16::MAX
+            ));
+        }
+
+        let (first, end) = (first as u16, end as u16);
+
+        if let Some(index) = placed
+            .iter()
+            .position(|&(start, count)| first < start + count && start < end)
+        {
+            return Err(format!(
+                "`{}` at sector {} overlaps `{}`",
+                file.name, first, files[index].name
+            ));
+        }
+
+        let offset = first as usize * SECTOR_SIZE;
+        let size = offset + file.data.len();
+
+        if image.len() < size {
+            image.resize(size.next_multiple_of(SECTOR_SIZE), 0);
+        }
+
+        image[offset..size].copy_from_slice(&file.data);
+        placed.push((first, end - first));
+        next = end as u32;
+    }
+
+    Ok(DiskImage {
+        bytes: image,
+        files: placed,
+    })
+}
@@ -25,27 +25,10 @@ impl DeviceMap {
 
     pub fn read(&mut self, addr: u32) -> DeviceMapResult<u16> {
         for device in &mut self.devices {
-            let dev = device.lock().unwrap();
-            let res = match dev.read(addr) {
-                DeviceResponse::Ok(val) => val,
+            match device.lock().unwrap().read(addr) {
                 DeviceResponse::NotMyAddress => continue,
-                DeviceResponse::ReadOnly => {
-                    panic!("Something went wrong with device {}.\nRead only received on a read action.", dev.get_name());
-                }
-                DeviceResponse::WriteOnly => {
-                    println!("Device is write only.");
-                    return DeviceMapResult::Error(DeviceResponse::WriteOnly);
-                }
-                #[allow(unreachable_patterns)]
-                _ => {
-                    panic!(
-                        "Something wrong wrong with device {}.\nA unknown error received.",
-                        dev.get_name()
-                    );
-                }
-            };
-
-            return DeviceMapResult::Ok(res);
+                response => return DeviceMapResult::from(response),
+            }
         }
 
         DeviceMapResult::NoDevices
@@ -53,27 +36,21 @@ impl DeviceMap {
 
     pub fn read_byte(&mut self, addr: u32) -> DeviceMapResult<u8> {
         for device in &mut self.devices {
-            let dev = device.lock().unwrap();
-            let res = match dev.read_byte(addr) {
-                DeviceResponse::Ok(val) => val,
+            match device.lock().unwrap().read_byte(addr) {
                 DeviceResponse::NotMyAddress => continue,
-                DeviceResponse::ReadOnly => {
-                    panic!("Something went wrong with device {}.\nRead only received on a read action.", dev.get_name());
-                }
-                DeviceResponse::WriteOnly => {
-                    println!("Device is write only.");
-                    return DeviceMapResult::Error(DeviceResponse::WriteOnly);
-                }
-                #[allow(unreachable_patterns)]
-                _ => {
-                    panic!(
-                        "Something wrong wrong with device {}.\nA unknown error received.",
-                        dev.get_name()
-                    );
-                }
-            };
-
-            return DeviceMapResult::Ok(res);
+                response => return DeviceMapResult::from(response),
+            }
+        }
+
+        DeviceMapResult::NoDevices
+    }
+
+    pub fn peek(&self, addr: u32) -> DeviceMapResult<u8> {
+        for device in &self.devices {
+            match device.lock().unwrap().peek(addr) {
+                DeviceResponse::NotMyAddress => continue,
+                response => return DeviceMapResult::from(response),
+            }
         }
 
         DeviceMapResult::NoDevices
@@ -81,26 +58,10 @@ impl DeviceMap {
 
     pub fn write(&mut self, addr: u32, value: u16) -> DeviceMapResult<()> {
         for device in &mut self.devices {
-            let mut dev = device.lock().unwrap();
-            match dev.write(addr, value) {
-                DeviceResponse::Ok(_) => (),
+            match device.lock().unwrap().write(addr, value) {
                 DeviceResponse::NotMyAddress => continue,
-                DeviceResponse::ReadOnly => {
-                    return DeviceMapResult::Error(DeviceResponse::ReadOnly)
-                }
-                DeviceResponse::WriteOnly => {
-                    panic!("Something went wrong with device {}.\nWrite only received on a write action.", dev.get_name());
-                }
-                #[allow(unreachable_patterns)]
-                _ => {
-                    panic!(
-                        "Something wrong wrong with device {}.\nA unknown error received.",
-                        dev.get_name()
-                    );
-                }
-            };
-
-            return DeviceMapResult::Ok(());
+                response => return DeviceMapResult::from(response),
+            }
         }
 
         DeviceMapResult::NoDevices
@@ -108,30 +69,37 @@ impl DeviceMap {
 
     pub fn write_byte(&mut self, addr: u32, value: u8) -> DeviceMapResult<()> {
         for device in &mut self.devices {
-            let mut dev = device.lock().unwrap();
-            match dev.write_byte(addr, value) {
-                DeviceResponse::Ok(_) => (),
+            match device.lock().unwrap().write_byte(addr, value) {
                 DeviceResponse::NotMyAddress => continue,
-                DeviceResponse::ReadOnly => {
-                    return DeviceMapResult::Error(DeviceResponse::ReadOnly);
-                }
-                DeviceResponse::WriteOnly => {
-                    panic!("Something went wrong with device {}.\nWrite only received on a write action.", dev.get_name());
-                }
-                #[allow(unreachable_patterns)]
-                _ => {
-                    panic!(
-                        "Something wrong wrong with device {}.\nA unknown error received.",
-                        dev.get_name()
-                    );
-                }
-            };
-
-            return DeviceMapResult::Ok(());
+                response => return DeviceMapResult::from(response),
+            }
         }
 
         DeviceMapResult::NoDevices
     }
+
+    // Every IRQ line raised during the tick, in the order the devices were added.
+    pub fn tick(&mut self, cycles: u64) -> Vec<u8> {
+        self.devices
+            .iter()
+            .filter_map(|device| device.lock().unwrap().tick(cycles))
+            .collect()
+    }
+}
+
+// Errors are passed on to the CPU, which decides whether they fault.
+impl<T> From<DeviceResponse<T>> for DeviceMapResult<T> {
+    fn from(response: DeviceResponse<T>) -> Self {
+        match response {
+            DeviceResponse::Ok(value) => DeviceMapResult::Ok(value),
+            DeviceResponse::NotMyAddress => DeviceMapResult::NoDevices,
+            DeviceResponse::ReadOnly => DeviceMapResult::Error(DeviceResponse::ReadOnly),
+            DeviceResponse::WriteOnly => DeviceMapResult::Error(DeviceResponse::WriteOnly),
+            DeviceResponse::InvalidAddress => {
+                DeviceMapResult::Error(DeviceResponse::InvalidAddress)
+            }
+        }
+    }
 }
 
 impl Default for DeviceMap {
@@ -0,0 +1,135 @@
+use super::{Device, DeviceResponse, WordRegisters};
+
+/*
+Sits between the devices and the CPU's IRQ pin. Each of the 16 lines is a bit in these words, and
+line 0 has the highest priority:
+
++0  MASK        lines that are ignored while their bit is set
++2  PENDING     lines that raised an IRQ the CPU hasn't taken yet (read only)
++4  IN_SERVICE  lines whose handler is running (read only)
++6  EOI         writing anything ends the highest priority handler in service (write only)
++8  BASE        the vector for line 0, the other lines use the ones after it
+
+A line is only passed on while no line of the same or a higher priority is in service, so a
+handler can only be interrupted by a more important device, and only once it runs `sti`. Handlers
+have to write EOI before `rei`, or their line and every line below it stay blocked.
+ */
+pub struct Pic {
+    pub mask: u16,
+    pub pending: u16,
+    pub in_service: u16,
+    pub base: u8,
+    start: u32,
+    end: u32,
+}
+
+impl Pic {
+    pub fn new(start: u32) -> Self {
+        Self {
+            mask: 0,
+            pending: 0,
+            in_service: 0,
+            base: 0,
+            start,
+            end: start + 9,
+        }
+    }
+
+    pub fn raise(&mut self, line: u8) {
+        self.pending |= 1 << line;
+    }
+
+    // The line that should interrupt the CPU next, if any.
+    pub fn next(&self) -> Option<u8> {
+        let ready = self.pending & !self.mask;
+
+        if ready == 0 {
+            return None;
+        }
+
+        // Both are 16 when no bit is set.
+        let line = ready.trailing_zeros();
+        let serving = self.in_service.trailing_zeros();
+
+        (line < serving).then_some(line as u8)
+    }
+
+    pub fn vector(&self, line: u8) -> u8 {
+        self.base.wrapping_add(line)
+    }
+
+    // The CPU took the IRQ, so its handler is now in service.
+    pub fn acknowledge(&mut self, line: u8) {
+        self.pending &= !(1 << line);
+        self.in_service |= 1 << line;
+    }
+
+    // Forgets an IRQ without running a handler for it.
+    pub fn cancel(&mut self, line: u8) {
+        self.pending &= !(1 << line);
+    }
+
+    pub fn end_of_interrupt(&mut self) {
+        // Clears the lowest bit, which is the highest priority.
+        self.in_service &= self.in_service.wrapping_sub(1);
+    }
+}
+
+impl WordRegisters for Pic {
+    fn bounds(&self) -> (u32, u32) {
+        (self.start, self.end)
+    }
+
+    fn peek_register(&self, offset: u32) -> DeviceResponse<u16> {
+        match offset {
+            0 => DeviceResponse::Ok(self.mask),
+            2 => DeviceResponse::Ok(self.pending),
+            4 => DeviceResponse::Ok(self.in_service),
+            6 => DeviceResponse::WriteOnly,
+            _ => DeviceResponse::Ok(self.base as u16),
+        }
+    }
+
+    fn set_register(&mut self, offset: u32, value: u16) -> DeviceResponse<()> {
+        match offset {
+            0 => self.mask = value,
+            2 | 4 => return DeviceResponse::ReadOnly,
+            6 => self.end_of_interrupt(),
+            _ => self.base = value as u8,
+        }
+
+        DeviceResponse::Ok(())
+    }
+}
+
+impl Device for Pic {
+    fn read(&mut self, addr: u32) -> DeviceResponse<u16> {
+        self.read_register(addr)
+    }
+
+    fn read_byte(&mut self, addr: u32) -> DeviceResponse<u8> {
+        self.read_register_byte(addr)
+    }
+
+    fn write(&mut self, addr: u32, value: u16) -> DeviceResponse<()> {
+        self.write_register(addr, value)
+    }
+
+    fn write_byte(&mut self, addr: u32, value: u8) -> DeviceResponse<()> {
+        self.write_register_byte(addr, value)
+    }
+
+    fn get_name(&self) -> String {
+        String::from("PIC")
+    }
+
+    fn set_name(&mut self, _name: String) {}
+
+    fn get_memory(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn peek(&self, addr: u32) -> DeviceResponse<u8> {
+        self.peek_register_byte(addr)
+    }
+}
@@ -10,7 +10,7 @@ pub struct Ram {
 impl Ram {
     pub fn new(start: u32, end: u32) -> Ram {
         Ram {
-            memory: vec![0; (end - start) as usize],
+            memory: vec![0; (end - start + 1) as usize],
             start,
             end,
             name: String::from("RAM"),
@@ -24,7 +24,11 @@ impl Ram {
 }
 
 impl Device for Ram {
-    fn read(&self, addr: u32) -> DeviceResponse<u16> {
+    fn read(&mut self, addr: u32) -> DeviceResponse<u16> {
+        if addr == self.end {
+            return DeviceResponse::InvalidAddress;
+        }
+
         if addr >= self.start && addr <= self.end {
             let data1 = (self.memory[self.relative(addr)] as u16) << 8;
             let data2 = self.memory[self.relative(addr + 1)] as u16;
@@ -35,20 +39,17 @@ impl Device for Ram {
         DeviceResponse::NotMyAddress
     }
 
-    fn read_byte(&self, addr: u32) -> DeviceResponse<u8> {
-        if addr >= self.start && addr <= self.end {
-            return DeviceResponse::Ok(self.memory[self.relative(addr)]);
-        }
-
-        DeviceResponse::NotMyAddress
+    fn read_byte(&mut self, addr: u32) -> DeviceResponse<u8> {
+        self.peek(addr)
     }
 
     fn write(&mut self, addr: u32, value: u16) -> DeviceResponse<()> {
-        if addr >= self.start && addr <= self.end {
-            if self.name == "Stack" {
-                println!("Saving at addr {}", self.relative(addr));
-            }
+        // A word at the last address would spill into the next device.
+        if addr == self.end {
+            return DeviceResponse::InvalidAddress;
+        }
 
+        if addr >= self.start && addr <= self.end {
             let val1 = (value >> 8) as u8;
             let val2 = value as u8;
             let addr1 = self.relative(addr);
@@ -85,4 +86,12 @@ impl Device for Ram {
     fn get_memory(&self) -> Vec<u8> {
         self.memory.clone()
     }
+
+    fn peek(&self, addr: u32) -> DeviceResponse<u8> {
+        if addr >= self.start && addr <= self.end {
+            return DeviceResponse::Ok(self.memory[self.relative(addr)]);
+        }
+
+        DeviceResponse::NotMyAddress
+    }
 }
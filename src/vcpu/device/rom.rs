@@ -1,5 +1,23 @@
+use std::fmt;
+
 use super::{Device, DeviceResponse};
 
+#[derive(Debug, PartialEq, Eq)]
+pub struct ProgramTooLarge {
+    pub size: usize,
+    pub limit: u32,
+}
+
+impl fmt::Display for ProgramTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the program is {} bytes, but ROM only holds {}",
+            self.size, self.limit
+        )
+    }
+}
+
 pub struct Rom {
     pub memory: Vec<u8>,
     start: u32,
@@ -7,16 +25,19 @@ pub struct Rom {
 }
 
 impl Rom {
-    pub fn new(memory: Vec<u8>, start: u32, limit: u32) -> Rom {
+    pub fn new(memory: Vec<u8>, start: u32, limit: u32) -> Result<Rom, ProgramTooLarge> {
         if memory.len() > limit as usize {
-            panic!("Program too large to fit in ROM");
+            return Err(ProgramTooLarge {
+                size: memory.len(),
+                limit,
+            });
         }
 
-        Rom {
+        Ok(Rom {
             start,
             end: (memory.len() as u32) + start - 1,
             memory,
-        }
+        })
     }
 
     fn relative(&self, addr: u32) -> usize {
@@ -25,7 +46,11 @@ impl Rom {
 }
 
 impl Device for Rom {
-    fn read(&self, addr: u32) -> DeviceResponse<u16> {
+    fn read(&mut self, addr: u32) -> DeviceResponse<u16> {
+        if addr == self.end {
+            return DeviceResponse::InvalidAddress;
+        }
+
         if addr >= self.start && addr <= self.end {
             let data1 = (self.memory[self.relative(addr)] as u16) << 8;
             let data2 = self.memory[self.relative(addr + 1)] as u16;
@@ -35,12 +60,8 @@ impl Device for Rom {
         DeviceResponse::NotMyAddress
     }
 
-    fn read_byte(&self, addr: u32) -> DeviceResponse<u8> {
-        if addr >= self.start && addr <= self.end {
-            return DeviceResponse::Ok(self.memory[self.relative(addr)]);
-        }
-
-        DeviceResponse::NotMyAddress
+    fn read_byte(&mut self, addr: u32) -> DeviceResponse<u8> {
+        self.peek(addr)
     }
 
     fn write(&mut self, addr: u32, _value: u16) -> DeviceResponse<()> {
@@ -70,4 +91,12 @@ impl Device for Rom {
     fn get_memory(&self) -> Vec<u8> {
         self.memory.clone()
     }
+
+    fn peek(&self, addr: u32) -> DeviceResponse<u8> {
+        if addr >= self.start && addr <= self.end {
+            return DeviceResponse::Ok(self.memory[self.relative(addr)]);
+        }
+
+        DeviceResponse::NotMyAddress
+    }
 }
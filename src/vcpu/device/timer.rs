@@ -0,0 +1,130 @@
+use bitflags::bitflags;
+
+use super::{Device, DeviceResponse, WordRegisters};
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct TimerControl: u16 {
+        const ENABLE   = 0b01;
+        const PERIODIC = 0b10;
+    }
+}
+
+/*
+Counts emulated cycles down from a reload value, and raises an IRQ on its line when it gets to 0.
+Every register is a word:
+
++0  RELOAD   cycles between IRQs
++2  COUNT    cycles left until the next one (read only)
++4  CONTROL  bit 0 enables the timer, bit 1 reloads it after each IRQ instead of stopping
+
+Writing CONTROL with the enable bit set (re)starts the count from RELOAD, so a one-shot timer is
+armed again the same way it was started.
+ */
+pub struct Timer {
+    pub reload: u16,
+    pub count: u16,
+    pub control: TimerControl,
+    pub line: u8,
+    start: u32,
+    end: u32,
+}
+
+impl Timer {
+    pub fn new(start: u32, line: u8) -> Self {
+        Self {
+            reload: 0,
+            count: 0,
+            control: TimerControl::empty(),
+            line,
+            start,
+            end: start + 5,
+        }
+    }
+}
+
+impl WordRegisters for Timer {
+    fn bounds(&self) -> (u32, u32) {
+        (self.start, self.end)
+    }
+
+    fn peek_register(&self, offset: u32) -> DeviceResponse<u16> {
+        DeviceResponse::Ok(match offset {
+            0 => self.reload,
+            2 => self.count,
+            _ => self.control.bits(),
+        })
+    }
+
+    fn set_register(&mut self, offset: u32, value: u16) -> DeviceResponse<()> {
+        match offset {
+            0 => self.reload = value,
+            2 => return DeviceResponse::ReadOnly,
+            _ => {
+                self.control = TimerControl::from_bits_truncate(value);
+
+                if self.control.contains(TimerControl::ENABLE) {
+                    self.count = self.reload;
+                }
+            }
+        }
+
+        DeviceResponse::Ok(())
+    }
+}
+
+impl Device for Timer {
+    fn read(&mut self, addr: u32) -> DeviceResponse<u16> {
+        self.read_register(addr)
+    }
+
+    fn read_byte(&mut self, addr: u32) -> DeviceResponse<u8> {
+        self.read_register_byte(addr)
+    }
+
+    fn write(&mut self, addr: u32, value: u16) -> DeviceResponse<()> {
+        self.write_register(addr, value)
+    }
+
+    fn write_byte(&mut self, addr: u32, value: u8) -> DeviceResponse<()> {
+        self.write_register_byte(addr, value)
+    }
+
+    fn get_name(&self) -> String {
+        String::from("Timer")
+    }
+
+    fn set_name(&mut self, _name: String) {}
+
+    fn get_memory(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn peek(&self, addr: u32) -> DeviceResponse<u8> {
+        self.peek_register_byte(addr)
+    }
+
+    // A one-shot timer stops at 0, a periodic one carries the cycles left over into the next count.
+    fn tick(&mut self, cycles: u64) -> Option<u8> {
+        let mut cycles = cycles;
+        let mut fired = false;
+
+        while self.control.contains(TimerControl::ENABLE) && cycles >= self.count as u64 {
+            cycles -= self.count as u64;
+            fired = true;
+
+            if self.control.contains(TimerControl::PERIODIC) && self.reload != 0 {
+                self.count = self.reload;
+            } else {
+                self.control.remove(TimerControl::ENABLE);
+                self.count = 0;
+            }
+        }
+
+        if self.control.contains(TimerControl::ENABLE) {
+            self.count -= cycles as u16;
+        }
+
+        fired.then_some(self.line)
+    }
+}
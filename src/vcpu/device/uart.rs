@@ -0,0 +1,207 @@
+use std::collections::VecDeque;
+
+use bitflags::bitflags;
+
+use super::{Device, DeviceResponse, WordRegisters};
+use crate::vcpu::serial::{Disconnected, SerialBackend};
+
+pub const FIFO_SIZE: usize = 16;
+
+// About 115200 baud at the default clock, with 10 bits on the wire for every byte.
+pub const CYCLES_PER_BYTE: u64 = 87;
+
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct UartStatus: u16 {
+        const RX_READY = 0b01;
+        const TX_FULL  = 0b10;
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct UartControl: u16 {
+        const RX_IRQ = 0b1;
+    }
+}
+
+/*
+A serial port to whatever the host connected it to. Every register is a word:
+
++0  DATA     reading takes the oldest received byte (0 when there is none), writing sends one
++2  STATUS   bit 0 when there is a byte to read, bit 1 while bytes written now would be dropped
++4  CONTROL  bit 0 raises an IRQ on the UART's line whenever a byte arrives
+
+Bytes move between the FIFOs and the host at a fixed rate in cycles, one each way at a time. The
+host is only read while the RX FIFO has room, so input piped in is never lost to a guest that
+reads it slowly. IRQs for bytes that arrive while one is still pending are merged by the PIC, so
+a handler that can fall behind should read until STATUS says there is nothing left.
+ */
+pub struct Uart {
+    pub rx: VecDeque<u8>,
+    pub tx: VecDeque<u8>,
+    pub control: UartControl,
+    pub line: u8,
+    pub backend: Box<dyn SerialBackend + Send>,
+    // Cycles since the last byte was moved.
+    cycles: u64,
+    start: u32,
+    end: u32,
+}
+
+impl Uart {
+    pub fn new(start: u32, line: u8) -> Self {
+        Self {
+            rx: VecDeque::with_capacity(FIFO_SIZE),
+            tx: VecDeque::with_capacity(FIFO_SIZE),
+            control: UartControl::empty(),
+            line,
+            backend: Box::new(Disconnected),
+            cycles: 0,
+            start,
+            end: start + 5,
+        }
+    }
+
+    pub fn status(&self) -> UartStatus {
+        let mut status = UartStatus::empty();
+
+        status.set(UartStatus::RX_READY, !self.rx.is_empty());
+        status.set(UartStatus::TX_FULL, self.tx.len() >= FIFO_SIZE);
+
+        status
+    }
+
+    fn send(&mut self, byte: u8) {
+        if self.tx.len() < FIFO_SIZE {
+            self.tx.push_back(byte);
+        }
+    }
+}
+
+impl WordRegisters for Uart {
+    fn bounds(&self) -> (u32, u32) {
+        (self.start, self.end)
+    }
+
+    fn peek_register(&self, offset: u32) -> DeviceResponse<u16> {
+        DeviceResponse::Ok(match offset {
+            0 => self.rx.front().copied().unwrap_or(0) as u16,
+            2 => self.status().bits(),
+            _ => self.control.bits(),
+        })
+    }
+
+    // Reading DATA takes the byte out of the FIFO.
+    fn register(&mut self, offset: u32) -> DeviceResponse<u16> {
+        match offset {
+            0 => DeviceResponse::Ok(self.rx.pop_front().unwrap_or(0) as u16),
+            _ => self.peek_register(offset),
+        }
+    }
+
+    fn set_register(&mut self, offset: u32, value: u16) -> DeviceResponse<()> {
+        match offset {
+            0 => self.send(value as u8),
+            2 => return DeviceResponse::ReadOnly,
+            _ => self.control = UartControl::from_bits_truncate(value),
+        }
+
+        DeviceResponse::Ok(())
+    }
+
+    // Only the low byte of a register can be read on its own, so `ldb` can take a byte of DATA.
+    fn read_register_byte(&mut self, addr: u32) -> DeviceResponse<u8> {
+        match self.offset(addr) {
+            None => DeviceResponse::NotMyAddress,
+            Some(offset) if offset.is_multiple_of(2) => DeviceResponse::Ok(0),
+            Some(offset) => match self.register(offset & !1) {
+                DeviceResponse::Ok(word) => DeviceResponse::Ok(word as u8),
+                _ => DeviceResponse::WriteOnly,
+            },
+        }
+    }
+
+    fn peek_register_byte(&self, addr: u32) -> DeviceResponse<u8> {
+        match self.offset(addr) {
+            None => DeviceResponse::NotMyAddress,
+            Some(offset) if offset.is_multiple_of(2) => DeviceResponse::Ok(0),
+            Some(offset) => match self.peek_register(offset & !1) {
+                DeviceResponse::Ok(word) => DeviceResponse::Ok(word as u8),
+                _ => DeviceResponse::WriteOnly,
+            },
+        }
+    }
+
+    // Like reads, only the low byte counts, so `stl` can send a byte.
+    fn write_register_byte(&mut self, addr: u32, value: u8) -> DeviceResponse<()> {
+        match self.offset(addr) {
+            None => DeviceResponse::NotMyAddress,
+            Some(offset) if offset.is_multiple_of(2) => DeviceResponse::Ok(()),
+            Some(offset) => self.set_register(offset & !1, value as u16),
+        }
+    }
+}
+
+impl Device for Uart {
+    fn read(&mut self, addr: u32) -> DeviceResponse<u16> {
+        self.read_register(addr)
+    }
+
+    fn read_byte(&mut self, addr: u32) -> DeviceResponse<u8> {
+        self.read_register_byte(addr)
+    }
+
+    fn write(&mut self, addr: u32, value: u16) -> DeviceResponse<()> {
+        self.write_register(addr, value)
+    }
+
+    fn write_byte(&mut self, addr: u32, value: u8) -> DeviceResponse<()> {
+        self.write_register_byte(addr, value)
+    }
+
+    fn get_name(&self) -> String {
+        String::from("UART")
+    }
+
+    fn set_name(&mut self, _name: String) {}
+
+    fn get_memory(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn peek(&self, addr: u32) -> DeviceResponse<u8> {
+        self.peek_register_byte(addr)
+    }
+
+    fn tick(&mut self, cycles: u64) -> Option<u8> {
+        self.cycles += cycles;
+
+        let mut received = false;
+
+        while self.cycles >= CYCLES_PER_BYTE {
+            self.cycles -= CYCLES_PER_BYTE;
+
+            if let Some(byte) = self.tx.pop_front() {
+                self.backend.send(byte);
+            }
+
+            if self.rx.len() < FIFO_SIZE {
+                if let Some(byte) = self.backend.receive() {
+                    self.rx.push_back(byte);
+                    received = true;
+                }
+            }
+        }
+
+        let interrupt = received && self.control.contains(UartControl::RX_IRQ);
+        interrupt.then_some(self.line)
+    }
+}
+
+// A program that halts right after printing would otherwise lose the end of its output.
+impl Drop for Uart {
+    fn drop(&mut self) {
+        for byte in self.tx.drain(..) {
+            self.backend.send(byte);
+        }
+    }
+}
@@ -5,7 +5,13 @@ use std::{
     sync::{mpsc::Receiver, Arc, Mutex},
 };
 
-use crate::vcpu::cpu::{DebugInfo, Flags};
+use crate::{
+    common::symbols::SymbolTable,
+    vcpu::{
+        clock::DEFAULT_CLOCK_HZ,
+        cpu::{DebugInfo, Flags},
+    },
+};
 
 use super::{Device, DeviceResponse};
 use olc_pixel_game_engine as olc;
@@ -17,6 +23,9 @@ pub const CHAR_HEIGHT: i32 = 16;
 pub const SCREEN_WIDTH: i32 = 80;
 pub const SCREEN_HEIGHT: i32 = 25;
 
+// Frames per emulated second.
+pub const FRAME_RATE: u64 = 60;
+
 pub const DEBUG_WIDTH: i32 = 250;
 pub const DEBUG_PADDING: i32 = 5;
 
@@ -124,6 +133,7 @@ pub struct Screen {
     debug_rx: Receiver<DebugInfo>,
     debug_mode: bool,
     keys: Arc<Mutex<VecDeque<KeyEvent>>>,
+    symbols: SymbolTable,
 }
 
 impl Screen {
@@ -132,6 +142,7 @@ impl Screen {
         debug_rx: Receiver<DebugInfo>,
         debug_mode: bool,
         keys: Arc<Mutex<VecDeque<KeyEvent>>>,
+        symbols: SymbolTable,
     ) -> Self {
         let file = match fs::read("resources/AVGA2_8x16.bin") {
             Ok(file) => file,
@@ -147,6 +158,7 @@ impl Screen {
             debug_rx,
             debug_mode,
             keys,
+            symbols,
         }
     }
 
@@ -293,6 +305,14 @@ impl Screen {
             olc::WHITE,
         )
         .unwrap();
+
+        if let Some(name) = self.symbols.describe(debug_info.pc) {
+            olc::draw_string(offset_x, offset_y + 140, &name, olc::WHITE).unwrap();
+        }
+
+        if let Some(source) = self.symbols.line(debug_info.pc) {
+            olc::draw_string(offset_x, offset_y + 150, &source.to_string(), olc::WHITE).unwrap();
+        }
     }
 }
 
@@ -302,7 +322,7 @@ impl olc::Application for Screen {
     }
 
     fn on_user_update(&mut self, _elapsed_time: f32) -> Result<(), olc::Error> {
-        let memory = self.vga.lock().unwrap();
+        let frame = self.vga.lock().unwrap().frame.clone();
 
         let mut lock_keys = self.keys.lock().unwrap();
 
@@ -326,7 +346,7 @@ impl olc::Application for Screen {
         // println!("{:?}", memory.read_byte(655361));
         for y in 0..SCREEN_HEIGHT {
             for x in 0..SCREEN_WIDTH {
-                let character = memory.memory[(y * SCREEN_WIDTH + x) as usize];
+                let character = frame[(y * SCREEN_WIDTH + x) as usize];
 
                 let background = Screen::vga_color_to_pixel(character.background);
                 let color = Screen::vga_color_to_pixel(character.color);
@@ -405,10 +425,19 @@ impl VGACharacter {
     }
 }
 
+/*
+The screen shows `frame`, a copy of `memory` taken every `frame_cycles` cycles, so how often it
+changes follows the emulated clock rather than how fast the host draws. The machine also takes one
+when the CPU stops, so a program that halts before its first frame still shows what it drew.
+ */
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Clone)]
 pub struct VGA {
     pub memory: Vec<VGACharacter>,
+    pub frame: Vec<VGACharacter>,
+    pub frame_cycles: u64,
+    // Cycles since the last frame was taken.
+    cycles: u64,
     start: u32,
     end: u32,
 }
@@ -422,7 +451,10 @@ impl VGA {
         // }
 
         Self {
+            frame: mem.clone(),
             memory: mem,
+            frame_cycles: DEFAULT_CLOCK_HZ / FRAME_RATE,
+            cycles: 0,
             start,
             end: start + (SCREEN_WIDTH * SCREEN_HEIGHT * 2) as u32,
         }
@@ -431,12 +463,42 @@ impl VGA {
     fn relative(&self, addr: u32) -> usize {
         (addr - self.start) as usize
     }
+
+    // The text buffer as plain text, one line per row. Characters outside printable ASCII are
+    // shown as spaces, and trailing blanks are dropped so the output diffs cleanly.
+    pub fn text(&self) -> String {
+        let mut lines: Vec<String> = self
+            .memory
+            .chunks(SCREEN_WIDTH as usize)
+            .map(|row| {
+                row.iter()
+                    .map(|c| match c.character {
+                        0x20..=0x7E => c.character as char,
+                        _ => ' ',
+                    })
+                    .collect::<String>()
+                    .trim_end()
+                    .to_string()
+            })
+            .collect();
+
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+
+    // Shows what's in memory now, without waiting for the next frame.
+    pub fn present(&mut self) {
+        self.frame.clone_from(&self.memory);
+    }
 }
 
 impl Device for VGA {
-    fn read(&self, addr: u32) -> DeviceResponse<u16> {
-        if addr >= self.start && addr <= self.end {
-            let bytes = self.memory[self.relative(addr)].bytes();
+    fn read(&mut self, addr: u32) -> DeviceResponse<u16> {
+        if addr >= self.start && addr < self.end {
+            let bytes = self.memory[self.relative(addr) / 2].bytes();
             let data1 = (bytes[0] as u16) << 8;
             let data2 = bytes[1] as u16;
 
@@ -446,27 +508,16 @@ impl Device for VGA {
         DeviceResponse::NotMyAddress
     }
 
-    fn read_byte(&self, addr: u32) -> DeviceResponse<u8> {
-        if addr >= self.start && addr <= self.end {
-            let relative_addr = self.relative(addr);
-            let bytes = self.memory[relative_addr].bytes();
-
-            if relative_addr % 2 == 0 {
-                return DeviceResponse::Ok(bytes[0]);
-            } else {
-                return DeviceResponse::Ok(bytes[1]);
-            }
-        }
-
-        DeviceResponse::NotMyAddress
+    fn read_byte(&mut self, addr: u32) -> DeviceResponse<u8> {
+        self.peek(addr)
     }
 
     fn write(&mut self, addr: u32, value: u16) -> DeviceResponse<()> {
-        if addr >= self.start && addr <= self.end {
+        if addr >= self.start && addr < self.end {
             let background = ((value & 0xF000) >> 12) as u8;
             let color = ((value & 0x0F00) >> 8) as u8;
             let character = value as u8;
-            let relative_addr = self.relative(addr);
+            let relative_addr = self.relative(addr) / 2;
 
             self.memory[relative_addr].background =
                 VGAColor::iter().nth(background as usize).unwrap();
@@ -480,7 +531,7 @@ impl Device for VGA {
     }
 
     fn write_byte(&mut self, addr: u32, value: u8) -> DeviceResponse<()> {
-        if addr >= self.start && addr <= self.end {
+        if addr >= self.start && addr < self.end {
             let relative_addr = self.relative(addr);
 
             // println!("Relative addr: {}", relative_addr / 2);
@@ -524,4 +575,30 @@ impl Device for VGA {
 
         temp
     }
+
+    fn peek(&self, addr: u32) -> DeviceResponse<u8> {
+        if addr >= self.start && addr < self.end {
+            let relative_addr = self.relative(addr);
+            let bytes = self.memory[relative_addr / 2].bytes();
+
+            if relative_addr % 2 == 0 {
+                return DeviceResponse::Ok(bytes[0]);
+            } else {
+                return DeviceResponse::Ok(bytes[1]);
+            }
+        }
+
+        DeviceResponse::NotMyAddress
+    }
+
+    fn tick(&mut self, cycles: u64) -> Option<u8> {
+        self.cycles += cycles;
+
+        if self.cycles >= self.frame_cycles {
+            self.cycles %= self.frame_cycles;
+            self.present();
+        }
+
+        None
+    }
 }
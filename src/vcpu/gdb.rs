@@ -0,0 +1,470 @@
+use std::{
+    collections::BTreeSet,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use super::{
+    cpu::{CpuFault, Flags, ADDRESS_SPACE},
+    device::map::DeviceMapResult,
+    machine::Machine,
+};
+
+// r1 - r6, pc, sp, bp and flags. pc is sent as 32 bits so gdb sees the whole 20-bit address,
+// with cs on top of the 16-bit pc, the rest are 16 bits wide.
+pub const REGISTER_COUNT: usize = 10;
+const PC: usize = 6;
+
+// How many instructions run between checks for a Ctrl-C from the debugger.
+const INTERRUPT_CHECK_INTERVAL: u64 = 1024;
+
+pub const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.yucpu.core">
+    <flags id="yucpu_flags" size="2">
+      <field name="Z" start="0" end="0"/>
+      <field name="O" start="1" end="1"/>
+      <field name="I" start="2" end="2"/>
+      <field name="G" start="5" end="5"/>
+      <field name="L" start="6" end="6"/>
+      <field name="D" start="7" end="7"/>
+    </flags>
+    <reg name="r1" bitsize="16" type="uint16" regnum="0"/>
+    <reg name="r2" bitsize="16" type="uint16"/>
+    <reg name="r3" bitsize="16" type="uint16"/>
+    <reg name="r4" bitsize="16" type="uint16"/>
+    <reg name="r5" bitsize="16" type="uint16"/>
+    <reg name="r6" bitsize="16" type="uint16"/>
+    <reg name="pc" bitsize="32" type="code_ptr"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="bp" bitsize="16" type="data_ptr"/>
+    <reg name="flags" bitsize="16" type="yucpu_flags"/>
+  </feature>
+</target>
+"#;
+
+pub enum Action {
+    Reply(String),
+    Continue,
+    Close(Option<String>),
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0_u8, |sum, byte| sum.wrapping_add(byte))
+}
+
+pub fn encode_packet(data: &str) -> String {
+    format!("${}#{:02x}", data, checksum(data))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [high, low] => u8::from_str_radix(std::str::from_utf8(&[*high, *low]).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+fn parse_hex(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 16).ok()
+}
+
+// The address `offset` bytes after `addr`, if it's still one the CPU can reach.
+fn offset_address(addr: u32, offset: u32) -> Option<u32> {
+    addr.checked_add(offset)
+        .filter(|addr| *addr < ADDRESS_SPACE)
+}
+
+// Splits `addr,length` as used by the memory and breakpoint packets.
+fn parse_range(text: &str) -> Option<(u32, usize)> {
+    let (addr, len) = text.split_once(',')?;
+
+    Some((parse_hex(addr)?, parse_hex(len)? as usize))
+}
+
+pub struct GdbStub {
+    pub machine: Machine,
+    pub breakpoints: BTreeSet<u32>,
+    pub fault: Option<CpuFault>,
+}
+
+impl GdbStub {
+    pub fn new(machine: Machine) -> GdbStub {
+        GdbStub {
+            machine,
+            breakpoints: BTreeSet::new(),
+            fault: None,
+        }
+    }
+
+    fn register_size(reg: usize) -> Option<usize> {
+        match reg {
+            PC => Some(4),
+            0..REGISTER_COUNT => Some(2),
+            _ => None,
+        }
+    }
+
+    fn read_register(&mut self, reg: usize) -> Option<Vec<u8>> {
+        match reg {
+            PC => Some(self.machine.cpu.program_counter().to_be_bytes().to_vec()),
+            0..=8 => self
+                .machine
+                .cpu
+                .decode_register(reg as u8)
+                .ok()
+                .map(|value| value.to_be_bytes().to_vec()),
+            9 => Some(
+                (self.machine.cpu.flags.bits() as u16)
+                    .to_be_bytes()
+                    .to_vec(),
+            ),
+            _ => None,
+        }
+    }
+
+    fn write_register(&mut self, reg: usize, bytes: &[u8]) -> bool {
+        if Self::register_size(reg) != Some(bytes.len()) {
+            return false;
+        }
+
+        let value = bytes
+            .iter()
+            .fold(0_u32, |value, byte| (value << 8) | *byte as u32);
+
+        match reg {
+            PC => self.machine.cpu.jump(value),
+            0..=8 => match self.machine.cpu.decode_register(reg as u8) {
+                Ok(register) => *register = value as u16,
+                Err(_) => return false,
+            },
+            9 => self.machine.cpu.flags = Flags::from_bits_truncate(value),
+            _ => return false,
+        }
+
+        true
+    }
+
+    // Registers go over the wire in the same big endian order the CPU uses for memory.
+    fn read_registers(&mut self) -> String {
+        (0..REGISTER_COUNT)
+            .map(|reg| to_hex(&self.read_register(reg).unwrap()))
+            .collect()
+    }
+
+    fn write_registers(&mut self, data: &str) -> String {
+        let size: usize = (0..REGISTER_COUNT).filter_map(Self::register_size).sum();
+
+        let bytes = match from_hex(data) {
+            Some(bytes) if bytes.len() == size => bytes,
+            _ => return String::from("E01"),
+        };
+
+        let mut rest = bytes.as_slice();
+
+        for reg in 0..REGISTER_COUNT {
+            let (value, next) = rest.split_at(Self::register_size(reg).unwrap());
+            self.write_register(reg, value);
+            rest = next;
+        }
+
+        String::from("OK")
+    }
+
+    fn read_memory(&self, addr: u32, len: usize) -> String {
+        let mut bytes = Vec::new();
+
+        for i in 0..len.min(ADDRESS_SPACE as usize) as u32 {
+            match offset_address(addr, i).map(|addr| self.machine.cpu.map.peek(addr)) {
+                Some(DeviceMapResult::Ok(byte)) => bytes.push(byte),
+                _ if bytes.is_empty() => return String::from("E01"),
+                // A partial read is allowed, gdb will ask for the rest separately.
+                _ => break,
+            }
+        }
+
+        to_hex(&bytes)
+    }
+
+    fn write_memory(&mut self, addr: u32, data: &str) -> String {
+        let bytes = match from_hex(data) {
+            Some(bytes) => bytes,
+            None => return String::from("E01"),
+        };
+
+        for (i, byte) in bytes.iter().enumerate() {
+            let written = offset_address(addr, i as u32)
+                .map(|addr| self.machine.cpu.map.write_byte(addr, *byte));
+
+            match written {
+                Some(DeviceMapResult::Ok(_)) => (),
+                _ => return String::from("E01"),
+            }
+        }
+
+        String::from("OK")
+    }
+
+    fn read_features(&self, args: &str) -> String {
+        let (annex, range) = match args.split_once(':') {
+            Some(parts) => parts,
+            None => return String::from("E01"),
+        };
+
+        if annex != "target.xml" {
+            return String::from("E00");
+        }
+
+        let (offset, len) = match parse_range(range) {
+            Some(range) => range,
+            None => return String::from("E01"),
+        };
+
+        let offset = (offset as usize).min(TARGET_XML.len());
+        let end = (offset + len).min(TARGET_XML.len());
+        let marker = if end == TARGET_XML.len() { 'l' } else { 'm' };
+
+        format!("{}{}", marker, &TARGET_XML[offset..end])
+    }
+
+    pub fn stop_reply(&self) -> String {
+        // Faults are reported with the signal a host CPU would raise for them.
+        if let Some(fault) = self.fault {
+            return match fault {
+                CpuFault::InvalidOpcode(_) => String::from("S04"),
+                CpuFault::DivideByZero => String::from("S08"),
+                _ => String::from("S0b"),
+            };
+        }
+
+        if self.machine.cpu.running {
+            String::from("S05")
+        } else {
+            format!("W{:02x}", self.machine.cpu.r1 as u8)
+        }
+    }
+
+    pub fn step(&mut self) -> String {
+        if self.machine.cpu.running {
+            self.fault = self.machine.step().err();
+        }
+
+        self.stop_reply()
+    }
+
+    // Runs until a breakpoint or HLT. `interrupted` is polled every so often so the debugger can
+    // stop a program that never reaches either.
+    pub fn resume(&mut self, mut interrupted: impl FnMut() -> bool) -> String {
+        let mut since_check = 0;
+
+        loop {
+            if !self.machine.cpu.running {
+                return self.stop_reply();
+            }
+
+            if let Err(fault) = self.machine.step() {
+                self.fault = Some(fault);
+                return self.stop_reply();
+            }
+
+            if self
+                .breakpoints
+                .contains(&self.machine.cpu.program_counter())
+            {
+                return String::from("T05swbreak:;");
+            }
+
+            since_check += 1;
+
+            if since_check == INTERRUPT_CHECK_INTERVAL {
+                since_check = 0;
+
+                if interrupted() {
+                    return String::from("S02");
+                }
+            }
+        }
+    }
+
+    pub fn handle_packet(&mut self, packet: &str) -> Action {
+        let (command, args) = packet.split_at(packet.len().min(1));
+
+        let reply = match command {
+            "?" => self.stop_reply(),
+            "g" => self.read_registers(),
+            "G" => self.write_registers(args),
+            "p" => match parse_hex(args).and_then(|reg| self.read_register(reg as usize)) {
+                Some(bytes) => to_hex(&bytes),
+                None => String::from("E01"),
+            },
+            "P" => {
+                let written = args.split_once('=').and_then(|(reg, value)| {
+                    Some(self.write_register(parse_hex(reg)? as usize, &from_hex(value)?))
+                });
+
+                match written {
+                    Some(true) => String::from("OK"),
+                    _ => String::from("E01"),
+                }
+            }
+            "m" => match parse_range(args) {
+                Some((addr, len)) => self.read_memory(addr, len),
+                None => String::from("E01"),
+            },
+            "M" => match args.split_once(':') {
+                Some((range, data)) => match parse_range(range) {
+                    Some((addr, _)) => self.write_memory(addr, data),
+                    None => String::from("E01"),
+                },
+                None => String::from("E01"),
+            },
+            "Z" | "z" => {
+                let (kind, range) = args.split_once(',').unwrap_or(("", ""));
+
+                match (kind, parse_range(range)) {
+                    ("0", Some((addr, _))) => {
+                        if command == "Z" {
+                            self.breakpoints.insert(addr);
+                        } else {
+                            self.breakpoints.remove(&addr);
+                        }
+
+                        String::from("OK")
+                    }
+                    // Only software breakpoints are supported.
+                    _ => String::new(),
+                }
+            }
+            "s" => self.step(),
+            "c" => return Action::Continue,
+            "k" => return Action::Close(None),
+            "D" => return Action::Close(Some(String::from("OK"))),
+            "H" => String::from("OK"),
+            "q" => {
+                if let Some(args) = args.strip_prefix("Xfer:features:read:") {
+                    self.read_features(args)
+                } else if args.starts_with("Supported") {
+                    String::from("PacketSize=1000;qXfer:features:read+;swbreak+")
+                } else if args == "Attached" {
+                    String::from("1")
+                } else if args == "C" {
+                    String::from("QC1")
+                } else if args == "fThreadInfo" {
+                    String::from("m1")
+                } else if args == "sThreadInfo" {
+                    String::from("l")
+                } else {
+                    String::new()
+                }
+            }
+            _ => String::new(),
+        };
+
+        Action::Reply(reply)
+    }
+
+    fn send(stream: &mut TcpStream, data: &str) -> io::Result<()> {
+        stream.write_all(encode_packet(data).as_bytes())?;
+        stream.flush()
+    }
+
+    // Reads the next packet, acknowledging it. Returns `None` once the connection is closed.
+    fn receive(stream: &mut TcpStream) -> io::Result<Option<String>> {
+        let mut byte = [0_u8; 1];
+
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+
+            if byte[0] == b'$' {
+                break;
+            }
+
+            // Anything outside a packet is an ack or a stray interrupt, neither need a reply.
+        }
+
+        let mut data = Vec::new();
+
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+
+            if byte[0] == b'#' {
+                break;
+            }
+
+            data.push(byte[0]);
+        }
+
+        let mut sum = [0_u8; 2];
+        stream.read_exact(&mut sum)?;
+
+        let data = String::from_utf8_lossy(&data).to_string();
+
+        let valid = std::str::from_utf8(&sum)
+            .ok()
+            .and_then(|sum| u8::from_str_radix(sum, 16).ok())
+            == Some(checksum(&data));
+
+        if !valid {
+            stream.write_all(b"-")?;
+            return Self::receive(stream);
+        }
+
+        stream.write_all(b"+")?;
+
+        Ok(Some(data))
+    }
+
+    // Checks for a Ctrl-C (0x03) without blocking.
+    fn poll_interrupt(stream: &mut TcpStream) -> bool {
+        let mut byte = [0_u8; 1];
+
+        if stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+
+        let interrupted = matches!(stream.read(&mut byte), Ok(1) if byte[0] == 0x03);
+        let _ = stream.set_nonblocking(false);
+
+        interrupted
+    }
+
+    pub fn serve_connection(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        while let Some(packet) = Self::receive(&mut stream)? {
+            match self.handle_packet(&packet) {
+                Action::Reply(reply) => Self::send(&mut stream, &reply)?,
+                Action::Continue => {
+                    let reply = self.resume(|| Self::poll_interrupt(&mut stream));
+                    Self::send(&mut stream, &reply)?;
+                }
+                Action::Close(reply) => {
+                    if let Some(reply) = reply {
+                        Self::send(&mut stream, &reply)?;
+                    }
+
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Waits for a single debugger to attach and serves it until it detaches.
+    pub fn serve(&mut self, listener: TcpListener) -> io::Result<()> {
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+
+        self.serve_connection(stream)
+    }
+}
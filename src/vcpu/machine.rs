@@ -0,0 +1,255 @@
+use std::sync::{Arc, Mutex};
+
+use super::{
+    clock::Clock,
+    cpu::{CpuFault, Flags, IrqPin, Pins, CPU},
+    device::{
+        self,
+        bios::BIOS,
+        disk::{Disk, DiskCommand, SECTOR_SIZE},
+        map::DeviceMapResult,
+        pic::Pic,
+        rom::{ProgramTooLarge, Rom},
+        timer::Timer,
+        uart::Uart,
+        vga::{FRAME_RATE, VGA},
+        Device,
+    },
+};
+use crate::common::executable::{LoadedProgram, FAR_ROM_SIZE, FAR_ROM_START, ROM_SIZE, ROM_START};
+
+pub const STACK_START: u16 = 0x4803;
+pub const TIMER_START: u32 = 0x4D04;
+pub const PIC_START: u32 = 0x4D10;
+pub const UART_START: u32 = 0x4D20;
+pub const DISK_START: u32 = 0x4D30;
+
+// The PIC lines devices are wired to. With the PIC's default base, these are also their vectors.
+pub const KEYBOARD_IRQ: u8 = 1;
+pub const UART_IRQ: u8 = 4;
+pub const DISK_IRQ: u8 = 6;
+pub const TIMER_IRQ: u8 = 8;
+
+// Everything the CPU needs to run a program: the device map plus handles to the devices the
+// host has to talk to directly.
+pub struct Machine {
+    pub cpu: CPU,
+    pub pins: Pins,
+    pub vga: Arc<Mutex<VGA>>,
+    pub bda: Arc<Mutex<BIOS>>,
+    pub pic: Arc<Mutex<Pic>>,
+    pub uart: Arc<Mutex<Uart>>,
+    pub disk: Arc<Mutex<Disk>>,
+    pub instructions: u64,
+    // Only set when running at a fixed speed, otherwise the CPU runs as fast as it can.
+    pub clock: Option<Clock>,
+}
+
+impl Machine {
+    pub fn new(
+        program: Vec<u8>,
+        ivt_bytes: [u8; 510],
+        start_index: u32,
+        debug_mode: bool,
+    ) -> Result<Self, ProgramTooLarge> {
+        // println!("{:?}", program);
+        let ivt = Arc::new(Mutex::new(device::ram::Ram::new(0x0000, 0x0400)));
+        {
+            let mut l = ivt.lock().unwrap();
+            l.set_name(String::from("IVT"));
+            l.memory[..ivt_bytes.len()].copy_from_slice(&ivt_bytes);
+        }
+
+        let ram = Arc::new(Mutex::new(device::ram::Ram::new(0x0401, 0x4401)));
+        let rom = Arc::new(Mutex::new(Rom::new(program, ROM_START, ROM_SIZE)?));
+
+        let stack = Arc::new(Mutex::new(device::ram::Ram::new(0x4803, 0x4C03)));
+        {
+            let mut l = stack.lock().unwrap();
+            l.set_name(String::from("Stack"));
+        }
+
+        let bda = Arc::new(Mutex::new(BIOS::new(0x4C04)));
+        let vga = Arc::new(Mutex::new(VGA::new(0xA000)));
+        let timer = Arc::new(Mutex::new(Timer::new(TIMER_START, TIMER_IRQ)));
+        let pic = Arc::new(Mutex::new(Pic::new(PIC_START)));
+        let uart = Arc::new(Mutex::new(Uart::new(UART_START, UART_IRQ)));
+        let disk = Arc::new(Mutex::new(Disk::new(DISK_START, DISK_IRQ)));
+
+        let mut map = device::map::DeviceMap::new();
+
+        map.add(Arc::clone(&vga));
+        map.add(ivt);
+        map.add(ram);
+        map.add(rom);
+        map.add(stack);
+        map.add(Arc::clone(&bda));
+        map.add(timer);
+        map.add(Arc::clone(&pic));
+        map.add(Arc::clone(&uart));
+        map.add(Arc::clone(&disk));
+
+        let mut cpu = CPU::new(0, STACK_START, debug_mode);
+        cpu.jump(start_index);
+        cpu.map = map;
+        cpu.stack_base = STACK_START;
+
+        Ok(Self {
+            cpu,
+            pins: Pins::new(),
+            vga,
+            bda,
+            pic,
+            uart,
+            disk,
+            instructions: 0,
+            clock: None,
+        })
+    }
+
+    // Like `new`, but also maps the program's far text when it has any.
+    pub fn load(program: LoadedProgram, debug_mode: bool) -> Result<Self, ProgramTooLarge> {
+        let mut machine = Machine::new(program.rom, program.ivt, program.entry, debug_mode)?;
+
+        if !program.far_rom.is_empty() {
+            let far_rom = Rom::new(program.far_rom, FAR_ROM_START, FAR_ROM_SIZE)?;
+            machine.cpu.map.add(Arc::new(Mutex::new(far_rom)));
+        }
+
+        Ok(machine)
+    }
+
+    // Reads up to `count` bytes without changing any device, stopping early at the first address
+    // nothing is mapped to.
+    pub fn read_bytes(&self, addr: u32, count: u32) -> Vec<u8> {
+        (0..count)
+            .map_while(|i| match self.cpu.map.peek(addr + i) {
+                DeviceMapResult::Ok(byte) => Some(byte),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Runs at `hz` from now on, with the screen refreshed at the same rate it would be in real time.
+    pub fn set_clock_hz(&mut self, hz: u64) {
+        self.clock = Some(Clock::new(hz, self.cpu.cycles));
+        self.vga.lock().unwrap().frame_cycles = (hz / FRAME_RATE).max(1);
+    }
+
+    // Raises an IRQ line on the PIC. One that is already pending isn't raised twice.
+    pub fn raise(&mut self, irq: u8) {
+        self.pic.lock().unwrap().raise(irq);
+    }
+
+    pub fn is_pending(&self, irq: u8) -> bool {
+        self.pic.lock().unwrap().pending & (1 << irq) != 0
+    }
+
+    // Takes the next IRQ from the PIC. One without a handler in the IVT is dropped, so it doesn't
+    // block the lines below it.
+    fn next_irq(&mut self) -> IrqPin {
+        loop {
+            let (line, vector) = {
+                let pic = self.pic.lock().unwrap();
+
+                match pic.next() {
+                    Some(line) => (line, pic.vector(line)),
+                    None => return IrqPin::Off,
+                }
+            };
+
+            let handled = matches!(self.cpu.read(vector as u32 * 2), Ok(handler) if handler != 0);
+            let mut pic = self.pic.lock().unwrap();
+
+            if handled {
+                pic.acknowledge(line);
+                return IrqPin::On(vector);
+            }
+
+            pic.cancel(line);
+        }
+    }
+
+    // Runs one instruction. A fault the guest doesn't handle stops the CPU.
+    pub fn step(&mut self) -> Result<(), CpuFault> {
+        self.instructions += 1;
+
+        if let Some(clock) = &self.clock {
+            clock.wait(self.cpu.cycles);
+        }
+
+        let start = self.cpu.cycles;
+
+        if self.pins.irq == IrqPin::Off && self.cpu.flags.contains(Flags::I) {
+            self.pins.irq = self.next_irq();
+        }
+
+        match self.cpu.tick(self.pins) {
+            Ok(pins) => self.pins = pins,
+            Err(fault) => {
+                self.cpu.running = false;
+                self.vga.lock().unwrap().present();
+                return Err(fault);
+            }
+        }
+
+        if !self.cpu.running {
+            self.vga.lock().unwrap().present();
+        }
+
+        // Devices see the time the instruction took, not how long the host spent on it.
+        for irq in self.cpu.map.tick(self.cpu.cycles - start) {
+            self.raise(irq);
+        }
+
+        self.transfer_disk();
+
+        Ok(())
+    }
+
+    // Carries out a disk transfer once its time is up. The disk is only locked while it's used
+    // itself, since the buffer could run over its own registers.
+    fn transfer_disk(&mut self) {
+        let (command, sector, buffer) = {
+            let mut disk = self.disk.lock().unwrap();
+
+            match disk.take_finished() {
+                Some(command) => (command, disk.sector, disk.buffer as u32),
+                None => return,
+            }
+        };
+
+        let ok = match command {
+            DiskCommand::Read => {
+                let data = self.disk.lock().unwrap().read_sector(sector);
+
+                data.is_ok_and(|data| {
+                    (buffer..).zip(data).all(|(addr, byte)| {
+                        matches!(self.cpu.map.write_byte(addr, byte), DeviceMapResult::Ok(()))
+                    })
+                })
+            }
+            DiskCommand::Write => {
+                let data = self.read_bytes(buffer, SECTOR_SIZE as u32);
+
+                match <[u8; SECTOR_SIZE]>::try_from(data) {
+                    Ok(data) => self
+                        .disk
+                        .lock()
+                        .unwrap()
+                        .write_sector(sector, &data)
+                        .is_ok(),
+                    Err(_) => false,
+                }
+            }
+        };
+
+        let line = {
+            let mut disk = self.disk.lock().unwrap();
+            disk.finish(ok);
+            disk.line
+        };
+
+        self.raise(line);
+    }
+}
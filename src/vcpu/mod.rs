@@ -1,17 +1,30 @@
 #![allow(unused_assignments)]
 
 use std::{
-    collections::VecDeque,
+    collections::{BTreeMap, VecDeque},
+    io,
+    net::TcpListener,
     sync::{atomic::AtomicBool, mpsc, Arc, Mutex},
     thread,
 };
 
 use olc_pixel_game_engine as olc;
 
+#[cfg(test)]
+mod tests;
+
+pub mod clock;
 pub mod cpu;
 pub mod device;
+pub mod gdb;
+pub mod machine;
+pub mod serial;
 
-use crate::vcpu::{cpu::IrqPin, device::bios::KeyboardFlags};
+use crate::{
+    common::symbols::SymbolTable,
+    disassembler::{decode, Disassembler},
+    vcpu::device::bios::KeyboardFlags,
+};
 
 #[allow(unused_imports)]
 use self::{
@@ -22,62 +35,31 @@ use self::{
     },
 };
 use self::{
-    cpu::{DebugInfo, Pins},
+    cpu::{CpuFault, DebugInfo},
     device::vga::KeyEvent,
+    machine::{Machine, KEYBOARD_IRQ},
 };
 
 const SCALE: i32 = 1;
 
 #[allow(unused_variables)]
-pub fn run(program: Vec<u8>, ivt_bytes: [u8; 510], start_index: u16, debug_mode: bool) {
+pub fn run(mut machine: Machine, debug_mode: bool, symbols: SymbolTable) {
     let add_scr_width = if debug_mode { DEBUG_WIDTH } else { 0 };
 
     let keys: Arc<Mutex<VecDeque<KeyEvent>>> = Arc::new(Mutex::new(VecDeque::new()));
     let keys_scr = Arc::clone(&keys);
 
-    // println!("{:?}", program);
-    let ivt = Arc::new(Mutex::new(device::ram::Ram::new(0x0000, 0x0400)));
-    {
-        let mut l = ivt.lock().unwrap();
-        l.set_name(String::from("IVT"));
-        l.memory = ivt_bytes.to_vec();
-    }
-
-    let ram = Arc::new(Mutex::new(device::ram::Ram::new(0x0401, 0x4401)));
-    let rom = Arc::new(Mutex::new(device::rom::Rom::new(program, 0x4402, 0x400)));
-
-    let stack = Arc::new(Mutex::new(device::ram::Ram::new(0x4803, 0x4C03)));
-    {
-        let mut l = stack.lock().unwrap();
-        l.set_name(String::from("Stack"));
-    }
-
-    let bda = Arc::new(Mutex::new(device::bios::BIOS::new(0x4C04)));
-    let bda_map = Arc::clone(&bda);
-
-    let vga = Arc::new(Mutex::new(device::vga::VGA::new(0xA000)));
-    let vga_scr = Arc::clone(&vga);
+    let bda = Arc::clone(&machine.bda);
+    let vga_scr = Arc::clone(&machine.vga);
 
     let (debug_tx, debug_rx) = mpsc::channel::<DebugInfo>();
-    let mut pins = Pins::new();
-    let mut screen = device::vga::Screen::new(vga_scr, debug_rx, debug_mode, keys_scr);
-
-    let mut map = device::map::DeviceMap::new();
-
-    map.add(vga);
-    map.add(ivt);
-    map.add(ram);
-    map.add(rom);
-    map.add(stack);
-    map.add(bda_map);
+    let mut screen = device::vga::Screen::new(vga_scr, debug_rx, debug_mode, keys_scr, symbols);
 
     let running = Arc::new(AtomicBool::new(true));
     let running_screen = Arc::clone(&running);
-    let mut cpu = cpu::CPU::new(start_index, 0x4803, debug_mode);
-    cpu.map = map;
 
     if debug_mode {
-        cpu.debug_tx = Some(debug_tx);
+        machine.cpu.debug_tx = Some(debug_tx);
     }
 
     let vga_thread_builder = thread::Builder::new().name(String::from("VGA"));
@@ -98,10 +80,19 @@ pub fn run(program: Vec<u8>, ivt_bytes: [u8; 510], start_index: u16, debug_mode:
         .unwrap();
 
     loop {
-        pins = cpu.tick(pins);
-        cpu.dump(Dump::All);
+        if machine.cpu.running {
+            if let Err(fault) = machine.step() {
+                eprintln!(
+                    "CPU fault at 0x{:04X}: {}",
+                    machine.cpu.program_counter(),
+                    fault
+                );
+            }
+        }
+
+        machine.cpu.dump(Dump::All);
 
-        if !cpu.running {
+        if !machine.cpu.running {
             running.store(false, std::sync::atomic::Ordering::Release);
         }
 
@@ -112,7 +103,7 @@ pub fn run(program: Vec<u8>, ivt_bytes: [u8; 510], start_index: u16, debug_mode:
         let mut lock_keys = keys.lock().unwrap();
         // println!("Keys: {:?}", lock_keys);
 
-        if lock_keys.len() > 0 && pins.irq == IrqPin::Off {
+        if lock_keys.len() > 0 && !machine.is_pending(KEYBOARD_IRQ) {
             // We have a new key press! We can unwrap since we know the length is greater than one.
             let key_event = lock_keys.pop_back().unwrap();
             let mut lock_bda = bda.lock().unwrap();
@@ -139,7 +130,7 @@ pub fn run(program: Vec<u8>, ivt_bytes: [u8; 510], start_index: u16, debug_mode:
                             .unwrap(),
                         _ => {
                             lock_bda.set_keyboard_buffer(key_to_char(key)).unwrap();
-                            pins.irq = IrqPin::On(1)
+                            machine.raise(KEYBOARD_IRQ)
                         }
                     };
                 }
@@ -149,3 +140,120 @@ pub fn run(program: Vec<u8>, ivt_bytes: [u8; 510], start_index: u16, debug_mode:
 
     vga_thread.join().unwrap();
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Halted,
+    CycleLimit,
+    Fault(CpuFault),
+}
+
+pub struct HeadlessOptions {
+    pub max_cycles: Option<u64>,
+    pub exit_register: u8,
+    pub dump_vga: bool,
+    pub trace: bool,
+    pub symbols: SymbolTable,
+}
+
+pub struct HeadlessResult {
+    pub reason: StopReason,
+    pub pc: u32,
+    pub instructions: u64,
+    pub cycles: u64,
+    pub exit_value: u16,
+}
+
+// Exit statuses `run --headless` keeps for itself, so a program's own can't be mistaken for them.
+pub const EXIT_BAD_STATUS: i32 = 123;
+pub const EXIT_CYCLE_LIMIT: i32 = 124;
+pub const EXIT_FAULT: i32 = 125;
+
+impl HeadlessResult {
+    // The shell only sees the low 8 bits of a status, so a program exiting with 256 would pass.
+    pub fn exit_status(&self) -> i32 {
+        match self.reason {
+            StopReason::Halted => match self.exit_value as i32 {
+                status @ (0..EXIT_BAD_STATUS | 126..=255) => status,
+                _ => EXIT_BAD_STATUS,
+            },
+            StopReason::CycleLimit => EXIT_CYCLE_LIMIT,
+            StopReason::Fault(_) => EXIT_FAULT,
+        }
+    }
+}
+
+// The instruction about to run, e.g. `0x4408 <print+0x6>: mov r1, 0x5 (box.yuasm:12)`.
+pub fn trace_line(
+    machine: &Machine,
+    symbols: &SymbolTable,
+    labels: &BTreeMap<u32, String>,
+) -> String {
+    let pc = machine.cpu.program_counter();
+
+    let mut line = match symbols.describe(pc) {
+        Some(name) => format!("0x{:04X} <{}>: ", pc, name),
+        None => format!("0x{:04X}: ", pc),
+    };
+
+    match decode(&machine.read_bytes(pc, 5), pc) {
+        Ok(instruction) => line += &Disassembler::format_instruction(&instruction, labels),
+        Err(_) => line += "??",
+    }
+
+    if let Some(source) = symbols.line(pc) {
+        line += &format!(" ({})", source);
+    }
+
+    line
+}
+
+pub fn run_headless(mut machine: Machine, options: HeadlessOptions) -> HeadlessResult {
+    let labels = options.symbols.by_address();
+
+    let reason = loop {
+        if !machine.cpu.running {
+            break StopReason::Halted;
+        }
+
+        if let Some(max_cycles) = options.max_cycles {
+            if machine.cpu.cycles >= max_cycles {
+                break StopReason::CycleLimit;
+            }
+        }
+
+        if options.trace {
+            eprintln!("{}", trace_line(&machine, &options.symbols, &labels));
+        }
+
+        if let Err(fault) = machine.step() {
+            break StopReason::Fault(fault);
+        }
+    };
+
+    if options.dump_vga {
+        print!("{}", machine.vga.lock().unwrap().text());
+    }
+
+    HeadlessResult {
+        reason,
+        pc: machine.cpu.program_counter(),
+        instructions: machine.instructions,
+        cycles: machine.cpu.cycles,
+        exit_value: machine
+            .cpu
+            .decode_register(options.exit_register)
+            .map_or(0, |value| *value),
+    }
+}
+
+// Runs the program headless under a GDB remote stub listening on localhost.
+pub fn run_gdb(machine: Machine, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!(
+        "Waiting for gdb on 127.0.0.1:{}",
+        listener.local_addr()?.port()
+    );
+
+    gdb::GdbStub::new(machine).serve(listener)
+}
@@ -0,0 +1,226 @@
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::PathBuf,
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+#[cfg(unix)]
+use std::sync::OnceLock;
+
+#[cfg(unix)]
+use std::os::unix::net::UnixListener;
+
+// The host side of the UART.
+pub trait SerialBackend {
+    // The next byte from the host, if one has arrived. Never blocks.
+    fn receive(&mut self) -> Option<u8>;
+    fn send(&mut self, byte: u8);
+}
+
+// What the UART is connected to when `--serial` isn't given.
+pub struct Disconnected;
+
+impl SerialBackend for Disconnected {
+    fn receive(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn send(&mut self, _byte: u8) {}
+}
+
+/*
+Where `run --serial` connects the UART:
+
+stdio           the terminal, or whatever was piped in and out
+file:PATH       output goes to PATH, nothing comes in
+unix:PATH       a Unix domain socket at PATH, which waits for a client before the program starts
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SerialConfig {
+    Stdio,
+    File(PathBuf),
+    Unix(PathBuf),
+}
+
+impl SerialConfig {
+    pub fn parse(value: &str) -> Result<SerialConfig, String> {
+        if value == "stdio" {
+            return Ok(SerialConfig::Stdio);
+        }
+
+        match value.split_once(':') {
+            Some(("file", path)) if !path.is_empty() => Ok(SerialConfig::File(PathBuf::from(path))),
+            Some(("unix", path)) if !path.is_empty() => Ok(SerialConfig::Unix(PathBuf::from(path))),
+            _ => Err(String::from("expected `stdio`, `file:PATH` or `unix:PATH`")),
+        }
+    }
+
+    pub fn open(&self) -> io::Result<Box<dyn SerialBackend + Send>> {
+        match self {
+            SerialConfig::Stdio => Ok(Box::new(Stdio::new())),
+            SerialConfig::File(path) => Ok(Box::new(Stream {
+                input: None,
+                output: File::create(path)?,
+            })),
+            #[cfg(unix)]
+            SerialConfig::Unix(path) => {
+                // A socket left behind by an earlier run would stop us binding.
+                let _ = std::fs::remove_file(path);
+                let listener = UnixListener::bind(path)?;
+
+                eprintln!("Waiting for a serial connection on {}", path.display());
+                let (stream, _) = listener.accept()?;
+
+                Ok(Box::new(Stream {
+                    input: Some(spawn_reader(stream.try_clone()?)),
+                    output: stream,
+                }))
+            }
+            #[cfg(not(unix))]
+            SerialConfig::Unix(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Unix sockets are only supported on Unix",
+            )),
+        }
+    }
+}
+
+// Reads on a thread of its own, since the readers here can only block.
+fn spawn_reader(mut reader: impl Read + Send + 'static) -> Receiver<u8> {
+    let (tx, rx) = mpsc::channel();
+
+    thread::Builder::new()
+        .name(String::from("Serial"))
+        .spawn(move || {
+            let mut byte = [0_u8; 1];
+
+            while let Ok(1) = reader.read(&mut byte) {
+                if tx.send(byte[0]).is_err() {
+                    break;
+                }
+            }
+        })
+        .unwrap();
+
+    rx
+}
+
+pub struct Stream<W: Write> {
+    input: Option<Receiver<u8>>,
+    output: W,
+}
+
+impl<W: Write> SerialBackend for Stream<W> {
+    fn receive(&mut self) -> Option<u8> {
+        self.input.as_ref()?.try_recv().ok()
+    }
+
+    // A host that went away is treated like a cable that was pulled out.
+    fn send(&mut self, byte: u8) {
+        let _ = self.output.write_all(&[byte]);
+        let _ = self.output.flush();
+    }
+}
+
+/*
+While stdin is a terminal it is put in raw mode, so keys reach the guest as they are typed,
+without echo or waiting for Enter, and put back when the UART is dropped. Ctrl-C still stops the
+emulator, which exits without dropping anything, so it puts the terminal back from the signal
+handler.
+ */
+pub struct Stdio {
+    stream: Stream<io::Stdout>,
+    #[cfg(unix)]
+    saved: Option<libc::termios>,
+}
+
+impl Stdio {
+    pub fn new() -> Self {
+        Self {
+            stream: Stream {
+                input: Some(spawn_reader(io::stdin())),
+                output: io::stdout(),
+            },
+            #[cfg(unix)]
+            saved: raw_mode(),
+        }
+    }
+}
+
+impl Default for Stdio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SerialBackend for Stdio {
+    fn receive(&mut self) -> Option<u8> {
+        self.stream.receive()
+    }
+
+    fn send(&mut self, byte: u8) {
+        self.stream.send(byte)
+    }
+}
+
+impl Drop for Stdio {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(saved) = &self.saved {
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+            }
+        }
+    }
+}
+
+// The terminal settings from before raw mode, for the SIGINT handler.
+#[cfg(unix)]
+static SAVED: OnceLock<libc::termios> = OnceLock::new();
+
+#[cfg(unix)]
+extern "C" fn interrupted(_signal: libc::c_int) {
+    unsafe {
+        if let Some(saved) = SAVED.get() {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+        }
+
+        libc::_exit(130);
+    }
+}
+
+// Returns the settings to restore, or nothing when stdin isn't a terminal.
+#[cfg(unix)]
+fn raw_mode() -> Option<libc::termios> {
+    unsafe {
+        if libc::isatty(libc::STDIN_FILENO) == 0 {
+            return None;
+        }
+
+        let mut saved: libc::termios = std::mem::zeroed();
+
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+            return None;
+        }
+
+        let mut raw = saved;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_iflag &= !(libc::ICRNL | libc::IXON);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+
+        if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+            return None;
+        }
+
+        let _ = SAVED.set(saved);
+        libc::signal(
+            libc::SIGINT,
+            interrupted as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+
+        Some(saved)
+    }
+}
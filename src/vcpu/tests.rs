@@ -0,0 +1,753 @@
+use std::{
+    collections::VecDeque,
+    env, fs,
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    process,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::common::{
+    executable::{LoadedProgram, ROM_SIZE},
+    symbols::SymbolTable,
+};
+
+use super::{
+    cpu::{CpuFault, Flags},
+    device::{
+        disk::{build_image, Disk, DiskCommand, DiskFile, DiskStatus, SECTOR_CYCLES, SECTOR_SIZE},
+        pic::Pic,
+        rom::ProgramTooLarge,
+        timer::Timer,
+        uart::{Uart, CYCLES_PER_BYTE, FIFO_SIZE},
+        vga::{SCREEN_HEIGHT, SCREEN_WIDTH, VGA},
+        Device, DeviceResponse,
+    },
+    gdb::{encode_packet, Action, GdbStub},
+    machine::{Machine, DISK_IRQ, DISK_START, TIMER_IRQ, TIMER_START, UART_IRQ, UART_START},
+    run_headless,
+    serial::{SerialBackend, SerialConfig},
+    HeadlessOptions, StopReason, EXIT_BAD_STATUS, EXIT_CYCLE_LIMIT,
+};
+
+fn options(max_cycles: Option<u64>) -> HeadlessOptions {
+    HeadlessOptions {
+        max_cycles,
+        exit_register: 0,
+        dump_vga: false,
+        trace: false,
+        symbols: SymbolTable::new(),
+    }
+}
+
+#[test]
+fn test_headless_exit_value() {
+    // mov r1, 42 / hlt
+    let program = vec![0x00, 0x00, 0x2A, 0xFE, 0x0C];
+
+    let result = run_headless(
+        Machine::new(program, [0; 510], 0x4402, false).unwrap(),
+        options(Some(100)),
+    );
+
+    assert_eq!(result.reason, StopReason::Halted);
+    assert_eq!(result.exit_value, 42);
+    assert_eq!(result.exit_status(), 42);
+    assert_eq!(result.instructions, 2);
+    assert_eq!(result.cycles, 3);
+}
+
+#[test]
+fn test_headless_exit_status() {
+    // mov r1, 0x100 / hlt
+    let program = vec![0x00, 0x04, 0x01, 0x00, 0xFE, 0x0C];
+
+    let mut result = run_headless(
+        Machine::new(program, [0; 510], 0x4402, false).unwrap(),
+        options(Some(100)),
+    );
+
+    // Neither passes, nor looks like a cycle limit or a fault.
+    assert_eq!(result.exit_value, 0x100);
+    assert_eq!(result.exit_status(), EXIT_BAD_STATUS);
+
+    result.exit_value = 124;
+    assert_eq!(result.exit_status(), EXIT_BAD_STATUS);
+
+    result.exit_value = 200;
+    assert_eq!(result.exit_status(), 200);
+
+    result.reason = StopReason::CycleLimit;
+    assert_eq!(result.exit_status(), EXIT_CYCLE_LIMIT);
+}
+
+#[test]
+fn test_debug_mode_without_window() {
+    // nop / hlt
+    let program = vec![0xFF, 0x0C, 0xFE, 0x0C];
+
+    let result = run_headless(
+        Machine::new(program, [0; 510], 0x4402, true).unwrap(),
+        options(Some(100)),
+    );
+
+    assert_eq!(result.reason, StopReason::Halted);
+}
+
+#[test]
+fn test_headless_cycle_limit() {
+    // loop: jmp loop
+    let program = vec![0x8E, 0x04, 0x44, 0x02];
+
+    let result = run_headless(
+        Machine::new(program, [0; 510], 0x4402, false).unwrap(),
+        options(Some(50)),
+    );
+
+    // The limit is checked between instructions, and each jump takes 3 cycles.
+    assert_eq!(result.reason, StopReason::CycleLimit);
+    assert_eq!(result.instructions, 17);
+    assert_eq!(result.cycles, 51);
+}
+
+#[test]
+fn test_program_too_large_for_rom() {
+    let program = vec![0; ROM_SIZE as usize + 1];
+
+    assert_eq!(
+        Machine::new(program, [0; 510], 0x4402, false).err(),
+        Some(ProgramTooLarge {
+            size: ROM_SIZE as usize + 1,
+            limit: ROM_SIZE
+        })
+    );
+}
+
+#[test]
+fn test_vga_text() {
+    let mut vga = VGA::new(0xA000);
+
+    for (i, c) in "Hi".bytes().enumerate() {
+        vga.memory[i].character = c;
+    }
+
+    vga.memory[SCREEN_WIDTH as usize + 1].character = b'!';
+
+    assert_eq!(vga.text(), "Hi\n !\n");
+}
+
+#[test]
+fn test_vga_addresses() {
+    let mut vga = VGA::new(0xA000);
+    let last = 0xA000 + (SCREEN_WIDTH * SCREEN_HEIGHT * 2) as u32 - 1;
+
+    // Two bytes per character, the colors and then the character.
+    assert_eq!(vga.write(0xA002, 0x1F41), DeviceResponse::Ok(()));
+    assert_eq!(vga.memory[1].character, b'A');
+    assert_eq!(vga.peek(0xA003), DeviceResponse::Ok(b'A'));
+    assert_eq!(vga.read(0xA002), DeviceResponse::Ok(0x1F41));
+
+    assert_eq!(vga.write_byte(last, b'Z'), DeviceResponse::Ok(()));
+    assert_eq!(vga.peek(last), DeviceResponse::Ok(b'Z'));
+    assert_eq!(vga.peek(last + 1), DeviceResponse::NotMyAddress);
+    assert_eq!(vga.read(last + 1), DeviceResponse::NotMyAddress);
+}
+
+#[test]
+fn test_vga_frame_follows_cycles() {
+    let mut vga = VGA::new(0xA000);
+    vga.frame_cycles = 100;
+
+    vga.memory[0].character = b'A';
+    assert_eq!(vga.tick(99), None);
+    assert_eq!(vga.frame[0].character, 0);
+
+    vga.tick(1);
+    assert_eq!(vga.frame[0].character, b'A');
+}
+
+#[test]
+fn test_vga_frame_on_halt() {
+    // mov r1, 'A' / stl r1, $0xA001 / hlt, all well before the first frame
+    let program = vec![0x00, 0x00, 0x41, 0x86, 0x04, 0xA0, 0x01, 0xFE, 0x0C];
+    let mut machine = Machine::new(program, [0; 510], 0x4402, false).unwrap();
+
+    while machine.cpu.running {
+        machine.step().unwrap();
+    }
+
+    assert_eq!(machine.vga.lock().unwrap().frame[0].character, b'A');
+}
+
+#[test]
+fn test_clock_hz_throttles() {
+    // loop: jmp loop
+    let program = vec![0x8E, 0x04, 0x44, 0x02];
+    let mut machine = Machine::new(program, [0; 510], 0x4402, false).unwrap();
+
+    machine.set_clock_hz(10_000);
+
+    let start = Instant::now();
+    let result = run_headless(machine, options(Some(500)));
+
+    assert_eq!(result.reason, StopReason::CycleLimit);
+    assert!(start.elapsed() >= Duration::from_millis(40));
+}
+
+// Sends a packet and returns the reply, checking the acks on both sides.
+fn gdb_request(stream: &mut TcpStream, packet: &str) -> String {
+    stream.write_all(encode_packet(packet).as_bytes()).unwrap();
+
+    let mut byte = [0_u8; 1];
+    stream.read_exact(&mut byte).unwrap();
+    assert_eq!(byte[0], b'+');
+
+    let mut reply = Vec::new();
+
+    loop {
+        stream.read_exact(&mut byte).unwrap();
+
+        if byte[0] == b'#' {
+            break;
+        }
+
+        reply.push(byte[0]);
+    }
+
+    let mut sum = [0_u8; 2];
+    stream.read_exact(&mut sum).unwrap();
+    stream.write_all(b"+").unwrap();
+
+    assert_eq!(reply[0], b'$');
+    String::from_utf8(reply[1..].to_vec()).unwrap()
+}
+
+#[test]
+fn test_gdb_loopback() {
+    // mov r1, 42 / mov r2, 1 / hlt
+    let program = vec![0x00, 0x00, 0x2A, 0x00, 0x10, 0x01, 0xFE, 0x0C];
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+
+    let server = thread::spawn(move || {
+        let machine = Machine::new(program, [0; 510], 0x4402, false).unwrap();
+        GdbStub::new(machine).serve(listener).unwrap();
+    });
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+
+    assert!(gdb_request(&mut stream, "qSupported:swbreak+").contains("qXfer:features:read+"));
+    assert_eq!(gdb_request(&mut stream, "?"), "S05");
+    assert!(gdb_request(&mut stream, "qXfer:features:read:target.xml:0,fff").starts_with("l<?xml"));
+
+    // r1 - r6, then the 32-bit pc 0x4402 and sp 0x4803.
+    assert_eq!(
+        &gdb_request(&mut stream, "g")[..36],
+        "000000000000000000000000000044024803"
+    );
+
+    assert_eq!(gdb_request(&mut stream, "m4402,3"), "00002a");
+    assert_eq!(gdb_request(&mut stream, "M4402,1:ff"), "E01");
+    assert_eq!(gdb_request(&mut stream, "M0401,2:beef"), "OK");
+    assert_eq!(gdb_request(&mut stream, "m401,2"), "beef");
+
+    assert_eq!(gdb_request(&mut stream, "s"), "S05");
+    assert_eq!(gdb_request(&mut stream, "p0"), "002a");
+    assert_eq!(gdb_request(&mut stream, "P1=0007"), "OK");
+    assert_eq!(gdb_request(&mut stream, "p1"), "0007");
+
+    assert_eq!(gdb_request(&mut stream, "Z0,4408,2"), "OK");
+    assert_eq!(gdb_request(&mut stream, "c"), "T05swbreak:;");
+    assert_eq!(gdb_request(&mut stream, "p6"), "00004408");
+    assert_eq!(gdb_request(&mut stream, "P6=4408"), "E01");
+    assert_eq!(gdb_request(&mut stream, "P6=00004408"), "OK");
+    assert_eq!(gdb_request(&mut stream, "z0,4408,2"), "OK");
+    assert_eq!(gdb_request(&mut stream, "c"), "W2a");
+
+    assert_eq!(gdb_request(&mut stream, "D"), "OK");
+    server.join().unwrap();
+}
+
+#[test]
+fn test_unhandled_fault_stops_cpu() {
+    // mov r1, 1 / ret, with nothing on the stack
+    let program = vec![0x00, 0x00, 0x01, 0xD2, 0x0C];
+
+    let result = run_headless(
+        Machine::new(program, [0; 510], 0x4402, false).unwrap(),
+        options(Some(100)),
+    );
+
+    assert_eq!(result.reason, StopReason::Fault(CpuFault::StackUnderflow));
+    assert_eq!(result.pc, 0x4405);
+}
+
+#[test]
+fn test_write_to_rom_faults() {
+    // st r1, $0x4402
+    let program = vec![0x85, 0x04, 0x44, 0x02];
+    let mut machine = Machine::new(program, [0; 510], 0x4402, false).unwrap();
+
+    assert_eq!(machine.step(), Err(CpuFault::ProtectionFault(0x4402)));
+    assert!(!machine.cpu.running);
+}
+
+#[test]
+fn test_fault_vectors_through_ivt() {
+    // 4402: mov r1, 0 / 4405: mod r2, r1 / 4408: hlt
+    // 440A: handler: mov r6, 9 / hlt
+    let program = vec![
+        0x00, 0x00, 0x00, 0x5B, 0x10, 0x00, 0xFE, 0x0C, 0x00, 0x50, 0x09, 0xFE, 0x0C,
+    ];
+
+    let mut ivt = [0_u8; 510];
+    let vector = CpuFault::DivideByZero.vector() as usize * 2;
+    ivt[vector..vector + 2].copy_from_slice(&0x440A_u16.to_be_bytes());
+
+    let mut machine = Machine::new(program, ivt, 0x4402, false).unwrap();
+
+    machine.step().unwrap();
+    machine.step().unwrap();
+
+    // The faulting PC is the last thing pushed.
+    assert_eq!(machine.cpu.pc, 0x440A);
+    assert_eq!(machine.cpu.pop().unwrap(), 0x4405);
+
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.r6, 9);
+}
+
+#[test]
+fn test_far_program() {
+    // 04402: jmp $0x10000 / 10000: mov r1, 7 / hlt
+    let program = LoadedProgram {
+        entry: 0x4402,
+        rom: vec![0x8E, 0x08, 0x01, 0x00, 0x00],
+        far_rom: vec![0x00, 0x00, 0x07, 0xFE, 0x0C],
+        ivt: [0; 510],
+    };
+
+    let result = run_headless(Machine::load(program, false).unwrap(), options(Some(100)));
+
+    assert_eq!(result.reason, StopReason::Halted);
+    assert_eq!(result.exit_value, 7);
+    assert_eq!(result.pc, 0x10003);
+}
+
+#[test]
+fn test_interrupt_frame_keeps_far_pc() {
+    let mut machine = Machine::new(vec![0xFE, 0x0C], [0; 510], 0x4402, false).unwrap();
+
+    machine.cpu.jump(0x12345);
+    machine.cpu.flags = Flags::Z;
+    machine.cpu.push_registers().unwrap();
+
+    machine.cpu.jump(0x4402);
+    machine.cpu.flags = Flags::empty();
+    machine.cpu.pop_registers().unwrap();
+
+    assert_eq!(machine.cpu.program_counter(), 0x12345);
+    assert_eq!(machine.cpu.flags, Flags::Z);
+}
+
+#[test]
+fn test_timer_registers() {
+    let mut timer = Timer::new(0x4D04, 8);
+
+    assert_eq!(timer.write(0x4D04, 3), DeviceResponse::Ok(()));
+    assert_eq!(timer.write(0x4D08, 0b01), DeviceResponse::Ok(()));
+    assert_eq!(timer.read(0x4D06), DeviceResponse::Ok(3));
+    assert_eq!(timer.write(0x4D06, 0), DeviceResponse::ReadOnly);
+    assert_eq!(timer.read(0x4D05), DeviceResponse::InvalidAddress);
+    assert_eq!(timer.read(0x4D0A), DeviceResponse::NotMyAddress);
+
+    // One-shot: fires once, then stays stopped at 0.
+    assert_eq!(timer.tick(2), None);
+    assert_eq!(timer.read_byte(0x4D07), DeviceResponse::Ok(1));
+    assert_eq!(timer.tick(1), Some(8));
+    assert_eq!(timer.tick(10), None);
+    assert_eq!(timer.read(0x4D08), DeviceResponse::Ok(0));
+
+    // Periodic, with the leftover cycles carried over.
+    timer.write(0x4D08, 0b11);
+
+    assert_eq!(timer.tick(7), Some(8));
+    assert_eq!(timer.read(0x4D06), DeviceResponse::Ok(2));
+}
+
+#[test]
+fn test_timer_interrupts_program() {
+    // 4402: loop: jmp loop
+    // 4406: handler: ld r2, $0x0401 / add r2, 1 / st r2, $0x0401 / st r2, $0x4D16 (EOI) / rei
+    let program = vec![
+        0x8E, 0x04, 0x44, 0x02, 0x81, 0x14, 0x04, 0x01, 0x10, 0x10, 0x01, 0x85, 0x14, 0x04, 0x01,
+        0x85, 0x14, 0x4D, 0x16, 0xD4, 0x0C,
+    ];
+
+    let mut ivt = [0_u8; 510];
+    let vector = TIMER_IRQ as usize * 2;
+    ivt[vector..vector + 2].copy_from_slice(&0x4406_u16.to_be_bytes());
+
+    let mut machine = Machine::new(program, ivt, 0x4402, false).unwrap();
+
+    machine.cpu.write(TIMER_START, 100).unwrap();
+    machine.cpu.write(TIMER_START + 4, 0b11).unwrap();
+
+    while machine.cpu.cycles < 1000 {
+        machine.step().unwrap();
+    }
+
+    // The timer counts cycles, not instructions. The IRQ from the 1000th cycle hasn't been taken
+    // yet.
+    assert_eq!(machine.cpu.read(0x0401), Ok(9));
+    assert!(machine.is_pending(TIMER_IRQ));
+    assert_eq!(machine.cpu.program_counter(), 0x4402);
+}
+
+#[test]
+fn test_pic_priorities() {
+    let mut pic = Pic::new(0x4D10);
+
+    pic.raise(8);
+    pic.raise(1);
+    assert_eq!(pic.next(), Some(1));
+    pic.acknowledge(1);
+
+    // Line 8 waits for line 1's handler to finish, line 0 can interrupt it.
+    assert_eq!(pic.next(), None);
+    pic.raise(0);
+    assert_eq!(pic.next(), Some(0));
+
+    assert_eq!(pic.write(0x4D10, 0b1), DeviceResponse::Ok(()));
+    assert_eq!(pic.next(), None);
+    assert_eq!(pic.read(0x4D12), DeviceResponse::Ok(0x0101));
+    assert_eq!(pic.read(0x4D14), DeviceResponse::Ok(0b10));
+
+    assert_eq!(pic.write(0x4D16, 0), DeviceResponse::Ok(()));
+    assert_eq!(pic.read(0x4D14), DeviceResponse::Ok(0));
+    assert_eq!(pic.next(), Some(8));
+
+    assert_eq!(pic.read(0x4D16), DeviceResponse::WriteOnly);
+    assert_eq!(pic.write(0x4D12, 0), DeviceResponse::ReadOnly);
+
+    pic.write_byte(0x4D19, 0x20);
+    assert_eq!(pic.vector(8), 0x28);
+}
+
+#[test]
+fn test_cli_defers_irqs() {
+    // 4402: cli / nop / sti
+    // 4408: loop: jmp loop
+    // 440C: handler: st r1, $0x4D16 (EOI) / rei
+    let program = vec![
+        0xDE, 0x0C, 0xFF, 0x0C, 0xDF, 0x0C, 0x8E, 0x04, 0x44, 0x08, 0x85, 0x04, 0x4D, 0x16, 0xD4,
+        0x0C,
+    ];
+
+    let mut ivt = [0_u8; 510];
+    let vector = TIMER_IRQ as usize * 2;
+    ivt[vector..vector + 2].copy_from_slice(&0x440C_u16.to_be_bytes());
+
+    let mut machine = Machine::new(program, ivt, 0x4402, false).unwrap();
+
+    machine.step().unwrap();
+    machine.raise(TIMER_IRQ);
+    machine.step().unwrap();
+
+    assert_eq!(machine.cpu.pc, 0x4406);
+    assert!(machine.is_pending(TIMER_IRQ));
+
+    machine.step().unwrap();
+    machine.step().unwrap();
+
+    // Taken once `sti` ran, with interrupts disabled again inside the handler.
+    assert_eq!(machine.cpu.pc, 0x440C);
+    assert!(!machine.cpu.flags.contains(Flags::I));
+    assert_eq!(machine.pic.lock().unwrap().in_service, 1 << TIMER_IRQ);
+
+    machine.step().unwrap();
+    assert_eq!(machine.pic.lock().unwrap().in_service, 0);
+
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.pc, 0x4408);
+    assert!(machine.cpu.flags.contains(Flags::I));
+}
+
+// A host the test can type into and read back from.
+#[derive(Clone, Default)]
+struct TestSerial {
+    input: Arc<Mutex<VecDeque<u8>>>,
+    output: Arc<Mutex<Vec<u8>>>,
+}
+
+impl TestSerial {
+    fn new(input: &[u8]) -> Self {
+        let serial = Self::default();
+        serial.input.lock().unwrap().extend(input);
+        serial
+    }
+}
+
+impl SerialBackend for TestSerial {
+    fn receive(&mut self) -> Option<u8> {
+        self.input.lock().unwrap().pop_front()
+    }
+
+    fn send(&mut self, byte: u8) {
+        self.output.lock().unwrap().push(byte);
+    }
+}
+
+#[test]
+fn test_uart_registers() {
+    let serial = TestSerial::new(b"0123456789abcdefXY");
+    let mut uart = Uart::new(0x4D20, 4);
+    uart.backend = Box::new(serial.clone());
+
+    assert_eq!(uart.write(0x4D20, b'h' as u16), DeviceResponse::Ok(()));
+    assert_eq!(uart.write_byte(0x4D21, b'i'), DeviceResponse::Ok(()));
+    assert_eq!(uart.read(0x4D22), DeviceResponse::Ok(0));
+
+    // Nothing moves until a byte's worth of cycles has passed.
+    assert_eq!(uart.tick(CYCLES_PER_BYTE - 1), None);
+    assert!(serial.output.lock().unwrap().is_empty());
+    assert_eq!(uart.tick(1), None);
+    assert_eq!(*serial.output.lock().unwrap(), b"h");
+
+    assert_eq!(uart.read(0x4D22), DeviceResponse::Ok(0b01));
+    assert_eq!(uart.read_byte(0x4D21), DeviceResponse::Ok(b'0'));
+    assert_eq!(uart.read(0x4D20), DeviceResponse::Ok(0));
+
+    // The host is left alone once the RX FIFO is full.
+    uart.tick(CYCLES_PER_BYTE * 20);
+    assert_eq!(uart.rx.len(), FIFO_SIZE);
+    assert_eq!(*serial.input.lock().unwrap(), b"Y");
+    assert_eq!(*serial.output.lock().unwrap(), b"hi");
+
+    // Only a byte arriving raises an IRQ.
+    assert_eq!(uart.write(0x4D24, 0b1), DeviceResponse::Ok(()));
+    assert_eq!(uart.tick(CYCLES_PER_BYTE), None);
+    uart.read(0x4D20);
+    assert_eq!(uart.tick(CYCLES_PER_BYTE), Some(4));
+
+    // Bytes written to a full TX FIFO are dropped.
+    for byte in 0..=FIFO_SIZE as u16 {
+        uart.write(0x4D20, byte);
+    }
+
+    assert_eq!(uart.read(0x4D22), DeviceResponse::Ok(0b11));
+    assert_eq!(uart.tx.len(), FIFO_SIZE);
+
+    assert_eq!(uart.write(0x4D22, 0), DeviceResponse::ReadOnly);
+    assert_eq!(uart.read(0x4D21), DeviceResponse::InvalidAddress);
+    assert_eq!(uart.read(0x4D26), DeviceResponse::NotMyAddress);
+
+    drop(uart);
+    assert_eq!(serial.output.lock().unwrap().len(), 2 + FIFO_SIZE);
+}
+
+#[test]
+fn test_peek_leaves_devices_alone() {
+    // hlt
+    let program = vec![0xFE, 0x0C];
+    let machine = Machine::new(program, [0; 510], 0x4402, false).unwrap();
+    machine.uart.lock().unwrap().rx.extend(b"ab");
+
+    assert_eq!(machine.read_bytes(UART_START, 4), vec![0, b'a', 0, 0b01]);
+
+    let mut stub = GdbStub::new(machine);
+    let reply = stub.handle_packet("m4d20,2");
+    assert!(matches!(reply, Action::Reply(bytes) if bytes == "0061"));
+
+    let mut uart = stub.machine.uart.lock().unwrap();
+    assert_eq!(uart.rx.len(), 2);
+    assert_eq!(uart.read_byte(UART_START + 1), DeviceResponse::Ok(b'a'));
+    assert_eq!(uart.peek(UART_START + 1), DeviceResponse::Ok(b'b'));
+}
+
+#[test]
+fn test_gdb_memory_bounds() {
+    // hlt
+    let program = vec![0xFE, 0x0C];
+    let mut stub = GdbStub::new(Machine::new(program, [0; 510], 0x4402, false).unwrap());
+
+    for packet in ["mffffffff,10", "mfffff,10", "Mffffffff,1:00"] {
+        assert!(matches!(stub.handle_packet(packet), Action::Reply(reply) if reply == "E01"));
+    }
+
+    // Reads stop at the first address nothing is mapped to.
+    let reply = stub.handle_packet("m4d38,ffffffff");
+    assert!(matches!(reply, Action::Reply(bytes) if bytes == "0000"));
+}
+
+#[test]
+fn test_uart_echo_program() {
+    // 4402: loop: jmp loop
+    // 4406: handler: ldb r1, $0x4D21 / stl r1, $0x4D21 / st r1, $0x4D16 (EOI) / rei
+    let program = vec![
+        0x8E, 0x04, 0x44, 0x02, 0x82, 0x14, 0x4D, 0x21, 0x86, 0x14, 0x4D, 0x21, 0x85, 0x14, 0x4D,
+        0x16, 0xD4, 0x0C,
+    ];
+
+    let mut ivt = [0_u8; 510];
+    let vector = UART_IRQ as usize * 2;
+    ivt[vector..vector + 2].copy_from_slice(&0x4406_u16.to_be_bytes());
+
+    let serial = TestSerial::new(b"echo");
+    let mut machine = Machine::new(program, ivt, 0x4402, false).unwrap();
+    machine.uart.lock().unwrap().backend = Box::new(serial.clone());
+    machine.cpu.write(UART_START + 4, 0b1).unwrap();
+
+    while machine.cpu.cycles < CYCLES_PER_BYTE * 10 {
+        machine.step().unwrap();
+    }
+
+    assert_eq!(*serial.output.lock().unwrap(), b"echo");
+    assert_eq!(machine.pic.lock().unwrap().in_service, 0);
+}
+
+#[test]
+fn test_serial_config() {
+    assert_eq!(SerialConfig::parse("stdio"), Ok(SerialConfig::Stdio));
+    assert_eq!(
+        SerialConfig::parse("unix:/tmp/yucpu.sock"),
+        Ok(SerialConfig::Unix("/tmp/yucpu.sock".into()))
+    );
+    assert_eq!(
+        SerialConfig::parse("file:out.txt"),
+        Ok(SerialConfig::File("out.txt".into()))
+    );
+    assert!(SerialConfig::parse("file:").is_err());
+    assert!(SerialConfig::parse("tcp:1234").is_err());
+}
+
+#[test]
+fn test_disk_registers() {
+    let mut disk = Disk::new(0x4D30, 6);
+
+    assert_eq!(disk.write(0x4D30, 3), DeviceResponse::Ok(()));
+    assert_eq!(disk.write(0x4D32, 0x0401), DeviceResponse::Ok(()));
+    assert_eq!(disk.read(0x4D38), DeviceResponse::Ok(0));
+
+    assert_eq!(disk.write_byte(0x4D35, 1), DeviceResponse::Ok(()));
+    assert_eq!(disk.read(0x4D36), DeviceResponse::Ok(0b01));
+
+    // Ignored while the read is running.
+    disk.write(0x4D34, 2);
+
+    disk.tick(SECTOR_CYCLES - 1);
+    assert_eq!(disk.take_finished(), None);
+    disk.tick(1);
+    assert_eq!(disk.take_finished(), Some(DiskCommand::Read));
+
+    disk.finish(false);
+    assert_eq!(disk.status, DiskStatus::ERROR);
+
+    disk.write(0x4D34, 7);
+    assert_eq!(disk.status, DiskStatus::ERROR);
+    assert_eq!(disk.take_finished(), None);
+
+    assert_eq!(disk.read(0x4D34), DeviceResponse::WriteOnly);
+    assert_eq!(disk.write(0x4D36, 0), DeviceResponse::ReadOnly);
+    assert_eq!(disk.write(0x4D38, 0), DeviceResponse::ReadOnly);
+    assert_eq!(disk.read(0x4D3A), DeviceResponse::NotMyAddress);
+
+    disk.set_name(String::from("Disk 2"));
+    assert_eq!(disk.get_name(), "Disk");
+}
+
+fn run_disk_command(machine: &mut Machine, command: u16, sector: u16, buffer: u16) -> u16 {
+    machine.cpu.write(DISK_START, sector).unwrap();
+    machine.cpu.write(DISK_START + 2, buffer).unwrap();
+    machine.cpu.write(DISK_START + 4, command).unwrap();
+
+    while machine.cpu.read(DISK_START + 6) == Ok(DiskStatus::BUSY.bits()) {
+        machine.step().unwrap();
+    }
+
+    machine.cpu.read(DISK_START + 6).unwrap()
+}
+
+#[test]
+fn test_disk_transfers() {
+    let path = env::temp_dir().join(format!("yucpu-disk-{}.img", process::id()));
+
+    let mut image = vec![0_u8; SECTOR_SIZE * 2];
+    image[SECTOR_SIZE..].fill(0xAB);
+    fs::write(&path, &image).unwrap();
+
+    // 4402: loop: jmp loop
+    // 4406: handler: st r1, $0x4D16 (EOI) / rei
+    let program = vec![0x8E, 0x04, 0x44, 0x02, 0x85, 0x14, 0x4D, 0x16, 0xD4, 0x0C];
+
+    let mut ivt = [0_u8; 510];
+    let vector = DISK_IRQ as usize * 2;
+    ivt[vector..vector + 2].copy_from_slice(&0x4406_u16.to_be_bytes());
+
+    let mut machine = Machine::new(program, ivt, 0x4402, false).unwrap();
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&path)
+        .unwrap();
+    machine.disk.lock().unwrap().attach(file).unwrap();
+
+    assert_eq!(machine.cpu.read(DISK_START + 8), Ok(2));
+
+    assert_eq!(run_disk_command(&mut machine, 1, 1, 0x0401), 0);
+    assert_eq!(
+        machine.read_bytes(0x0401, SECTOR_SIZE as u32),
+        image[SECTOR_SIZE..]
+    );
+    assert!(machine.cpu.cycles >= SECTOR_CYCLES);
+
+    // The completion IRQ is taken on the next instruction.
+    assert!(machine.is_pending(DISK_IRQ));
+    machine.step().unwrap();
+    assert_eq!(machine.cpu.pc, 0x4406);
+    machine.step().unwrap();
+    machine.step().unwrap();
+
+    machine.cpu.write(0x0401, 0x1234).unwrap();
+    assert_eq!(run_disk_command(&mut machine, 2, 0, 0x0401), 0);
+    assert_eq!(fs::read(&path).unwrap()[..3], [0x12, 0x34, 0xAB]);
+
+    // Past the end of the disk, and into ROM.
+    assert_eq!(run_disk_command(&mut machine, 1, 2, 0x0401), 0b10);
+    assert_eq!(run_disk_command(&mut machine, 1, 0, 0x4402), 0b10);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_build_image() {
+    let file = |name: &str, sector, len| DiskFile {
+        name: String::from(name),
+        sector,
+        data: vec![name.as_bytes()[0]; len],
+    };
+
+    let image = build_image(
+        &[
+            file("boot", None, 512),
+            file("kernel", None, 513),
+            file("data", Some(8), 1),
+        ],
+        4,
+    )
+    .unwrap();
+
+    assert_eq!(image.files, [(0, 1), (1, 2), (8, 1)]);
+    assert_eq!(image.bytes.len(), 9 * SECTOR_SIZE);
+    assert_eq!(image.bytes[SECTOR_SIZE * 2], b'k');
+    assert_eq!(image.bytes[SECTOR_SIZE * 2 + 1], 0);
+    assert_eq!(image.bytes[SECTOR_SIZE * 8], b'd');
+
+    assert_eq!(build_image(&[], 4).unwrap().bytes.len(), 4 * SECTOR_SIZE);
+    assert_eq!(
+        build_image(&[file("boot", None, 1024), file("fs", Some(1), 1)], 0).err(),
+        Some(String::from("`fs` at sector 1 overlaps `boot`"))
+    );
+}